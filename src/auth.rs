@@ -1,13 +1,23 @@
 use std::env;
-use std::io;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
 use std::path::PathBuf;
 
-use crate::regex;
+use crate::{cli::AuthType, negotiate, regex, utils::to_hex};
+use anyhow::{anyhow, Result};
 use dirs::home_dir;
+use md5::{Digest, Md5};
 use netrc_rs::Netrc;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, Url};
+use sha2::Sha256;
 use std::fs;
 
-pub fn parse_auth(auth: String, host: &str) -> io::Result<(String, Option<String>)> {
+pub fn parse_auth(
+    auth: String,
+    host: &str,
+    password_stdin: bool,
+) -> io::Result<(String, Option<String>)> {
     if let Some(cap) = regex!(r"^([^:]*):$").captures(&auth) {
         Ok((cap[1].to_string(), None))
     } else if let Some(cap) = regex!(r"^(.+?):(.+)$").captures(&auth) {
@@ -15,13 +25,260 @@ pub fn parse_auth(auth: String, host: &str) -> io::Result<(String, Option<String
         let password = cap[2].to_string();
         Ok((username, Some(password)))
     } else {
-        let username = auth;
-        let prompt = format!("http: password for {}@{}: ", username, host);
-        let password = rpassword::read_password_from_tty(Some(&prompt))?;
+        let username = if auth.is_empty() {
+            read_username(host)?
+        } else {
+            auth
+        };
+        let password = if password_stdin {
+            read_password_stdin()?
+        } else {
+            let prompt = format!("http: password for {}@{}: ", username, host);
+            rpassword::read_password_from_tty(Some(&prompt))?
+        };
         Ok((username, Some(password)))
     }
 }
 
+/// Prompt for a username on the terminal. Unlike the password it's echoed.
+fn read_username(host: &str) -> io::Result<String> {
+    let tty = if cfg!(windows) { "CONIN$" } else { "/dev/tty" };
+    let mut tty = BufReader::new(File::open(tty)?);
+    eprint!("http: username for {}: ", host);
+    let mut username = String::new();
+    tty.read_line(&mut username)?;
+    Ok(username.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+/// Read a password for --password-stdin. All of stdin is used, except for a
+/// trailing newline, like `echo` would add.
+fn read_password_stdin() -> io::Result<String> {
+    let mut password = String::new();
+    io::stdin().read_to_string(&mut password)?;
+    if password.ends_with('\n') {
+        password.pop();
+        if password.ends_with('\r') {
+            password.pop();
+        }
+    }
+    Ok(password)
+}
+
+/// A scheme offered in a `WWW-Authenticate` header, with its parameters.
+#[derive(Debug, PartialEq)]
+pub struct Challenge {
+    pub scheme: String,
+    params: Vec<(String, String)>,
+}
+
+impl Challenge {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Parse the challenges in all `WWW-Authenticate` (or `Proxy-Authenticate`)
+/// headers. A header may hold several, e.g. `Negotiate, Basic realm="x"`.
+/// Anything that doesn't parse ends the header, since there's no telling
+/// where the next challenge starts.
+pub fn parse_challenges(headers: &HeaderMap, name: HeaderName) -> Vec<Challenge> {
+    let mut challenges = Vec::new();
+    for value in headers.get_all(name) {
+        if let Ok(value) = value.to_str() {
+            parse_challenge_list(value, &mut challenges);
+        }
+    }
+    challenges
+}
+
+fn parse_challenge_list(mut rest: &str, challenges: &mut Vec<Challenge>) {
+    // Only challenges from this header can take parameters
+    let first = challenges.len();
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        let (name, after) = split_token(rest);
+        if name.is_empty() {
+            return;
+        }
+        let after = after.trim_start();
+        match (challenges.len() > first, after.strip_prefix('=')) {
+            (true, Some(value)) => {
+                let value = value.trim_start();
+                let (value, after) = if let Some(quoted) = value.strip_prefix('"') {
+                    match split_quoted(quoted) {
+                        Some(split) => split,
+                        None => return,
+                    }
+                } else {
+                    let (value, after) = split_token(value);
+                    (value.to_string(), after)
+                };
+                let challenge = challenges.last_mut().unwrap();
+                challenge.params.push((name.to_string(), value));
+                rest = after;
+            }
+            _ => {
+                challenges.push(Challenge {
+                    scheme: name.to_string(),
+                    params: Vec::new(),
+                });
+                rest = skip_token68(after);
+            }
+        }
+    }
+}
+
+fn split_token(text: &str) -> (&str, &str) {
+    let end = text
+        .find(|c: char| !(c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)))
+        .unwrap_or(text.len());
+    (&text[..end], &text[end..])
+}
+
+/// Split off a quoted string, whose opening quote is already gone.
+fn split_quoted(text: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &text[i + 1..])),
+            '\\' => value.push(chars.next()?.1),
+            _ => value.push(c),
+        }
+    }
+    None
+}
+
+/// Skip the opaque token some schemes (like Negotiate) use instead of
+/// parameters. It can end in `=`, so it's only a token68 if nothing else
+/// follows before the next comma.
+fn skip_token68(text: &str) -> &str {
+    let end = text
+        .find(|c: char| !(c.is_ascii_alphanumeric() || "-._~+/".contains(c)))
+        .unwrap_or(text.len());
+    let after = text[end..].trim_start_matches('=').trim_start();
+    if end > 0 && (after.is_empty() || after.starts_with(',')) {
+        after
+    } else {
+        text
+    }
+}
+
+/// Answer a 401 response's challenges with the user's credentials.
+///
+/// With no --auth-type the strongest scheme that can use a password is picked:
+/// Digest, then Basic. Negotiate comes last since it ignores the password and
+/// uses the ticket cache instead. Returns `None` if nothing offered fits.
+pub fn answer_challenges(
+    challenges: &[Challenge],
+    auth_type: Option<AuthType>,
+    username: &str,
+    password: Option<&str>,
+    method: &Method,
+    url: &Url,
+) -> Result<Option<HeaderValue>> {
+    let password = password.unwrap_or("");
+    let find = |scheme: &str| {
+        challenges
+            .iter()
+            .find(|challenge| challenge.scheme.eq_ignore_ascii_case(scheme))
+    };
+    let header = if let Some(challenge) = find("Digest") {
+        let cnonce = format!("{:016x}", rand::random::<u64>());
+        digest_auth(challenge, username, password, method, url, &cnonce)?
+    } else if auth_type == Some(AuthType::digest) {
+        return Ok(None);
+    } else if find("Basic").is_some() {
+        basic_auth(username, password)
+    } else if find("Negotiate").is_some() {
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow!("Negotiate authentication needs a host name"))?;
+        negotiate::authorization(host)?
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(HeaderValue::from_str(&header)?))
+}
+
+pub fn basic_auth(username: &str, password: &str) -> String {
+    format!(
+        "Basic {}",
+        base64::encode(format!("{}:{}", username, password))
+    )
+}
+
+/// Build an `Authorization: Digest` value as described in RFC 7616. Only the
+/// `auth` quality of protection is supported: `auth-int` would need the body
+/// hashed up front, which rules out streaming it.
+fn digest_auth(
+    challenge: &Challenge,
+    username: &str,
+    password: &str,
+    method: &Method,
+    url: &Url,
+    cnonce: &str,
+) -> Result<String> {
+    let realm = challenge.param("realm").unwrap_or("");
+    let nonce = challenge
+        .param("nonce")
+        .ok_or_else(|| anyhow!("The Digest challenge has no nonce"))?;
+    let algorithm = challenge.param("algorithm").unwrap_or("MD5");
+    let hash: fn(&str) -> String = match algorithm.to_ascii_uppercase().as_str() {
+        "MD5" | "MD5-SESS" => |data| to_hex(&Md5::digest(data.as_bytes())),
+        "SHA-256" | "SHA-256-SESS" => |data| to_hex(&Sha256::digest(data.as_bytes())),
+        _ => return Err(anyhow!("Unsupported Digest algorithm {:?}", algorithm)),
+    };
+    let qop = match challenge.param("qop") {
+        Some(qop) if qop.split(',').any(|qop| qop.trim() == "auth") => Some("auth"),
+        Some(qop) => return Err(anyhow!("Unsupported Digest qop {:?}", qop)),
+        None => None,
+    };
+    let uri = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+
+    let mut ha1 = hash(&format!("{}:{}:{}", username, realm, password));
+    if algorithm.to_ascii_uppercase().ends_with("-SESS") {
+        ha1 = hash(&format!("{}:{}:{}", ha1, nonce, cnonce));
+    }
+    let ha2 = hash(&format!("{}:{}", method, uri));
+    let response = match qop {
+        Some(qop) => hash(&format!(
+            "{}:{}:00000001:{}:{}:{}",
+            ha1, nonce, cnonce, qop, ha2
+        )),
+        // RFC 2069, which some old servers still use
+        None => hash(&format!("{}:{}:{}", ha1, nonce, ha2)),
+    };
+
+    let quote = |value: &str| format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""));
+    let mut header = format!(
+        "Digest username={}, realm={}, nonce={}, uri={}, algorithm={}, response={}",
+        quote(username),
+        quote(realm),
+        quote(nonce),
+        quote(&uri),
+        algorithm,
+        quote(&response)
+    );
+    if let Some(qop) = qop {
+        header.push_str(&format!(
+            ", qop={}, nc=00000001, cnonce={}",
+            qop,
+            quote(cnonce)
+        ));
+    }
+    if let Some(opaque) = challenge.param("opaque") {
+        header.push_str(&format!(", opaque={}", quote(opaque)));
+    }
+    Ok(header)
+}
+
 fn get_home_dir() -> Option<PathBuf> {
     #[cfg(target_os = "windows")]
     if let Some(path) = env::var_os("XH_TEST_MODE_WIN_HOME_DIR") {
@@ -85,6 +342,7 @@ pub fn auth_from_netrc(machine: &str, netrc: &str) -> Option<(String, Option<Str
 #[cfg(test)]
 mod tests {
     use super::*;
+    use reqwest::header::WWW_AUTHENTICATE;
 
     #[test]
     fn parsing() {
@@ -95,11 +353,93 @@ mod tests {
             (":", ("", None)),
         ];
         for (input, output) in expected {
-            let (user, pass) = parse_auth(input.to_string(), "").unwrap();
+            let (user, pass) = parse_auth(input.to_string(), "", false).unwrap();
             assert_eq!(output, (user.as_str(), pass.as_deref()));
         }
     }
 
+    #[test]
+    fn challenges() {
+        let mut headers = HeaderMap::new();
+        headers.append(WWW_AUTHENTICATE, HeaderValue::from_static("Negotiate"));
+        headers.append(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static(
+                r#"Digest realm="a \"b\", c", qop="auth,auth-int", nonce=abc, Basic realm=x"#,
+            ),
+        );
+        headers.append(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static("Bearer dG9rZW4=, Foo"),
+        );
+        let challenges = parse_challenges(&headers, WWW_AUTHENTICATE);
+        let schemes: Vec<_> = challenges.iter().map(|c| c.scheme.as_str()).collect();
+        assert_eq!(schemes, ["Negotiate", "Digest", "Basic", "Bearer", "Foo"]);
+        assert_eq!(challenges[1].param("realm"), Some(r#"a "b", c"#));
+        assert_eq!(challenges[1].param("QOP"), Some("auth,auth-int"));
+        assert_eq!(challenges[1].param("nonce"), Some("abc"));
+        assert_eq!(challenges[2].param("realm"), Some("x"));
+        assert_eq!(challenges[3].params, []);
+    }
+
+    #[test]
+    fn negotiate_challenges() {
+        // A server's final token comes back as a token68, and a bare
+        // `Negotiate` may share a header with other schemes
+        let mut headers = HeaderMap::new();
+        headers.append(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static("Negotiate oRQwEqADCgEAoQsGCSqGSIb3EgECAg==, Basic realm=x"),
+        );
+        headers.append(WWW_AUTHENTICATE, HeaderValue::from_static("negotiate"));
+        let challenges = parse_challenges(&headers, WWW_AUTHENTICATE);
+        let schemes: Vec<_> = challenges.iter().map(|c| c.scheme.as_str()).collect();
+        assert_eq!(schemes, ["Negotiate", "Basic", "negotiate"]);
+        assert_eq!(challenges[0].params, []);
+        assert_eq!(challenges[1].param("realm"), Some("x"));
+    }
+
+    #[test]
+    fn digest() {
+        // The examples from RFC 7616, section 3.9.1
+        for (algorithm, response) in &[
+            ("MD5", "8ca523f5e9506fed4657c9700eebdbec"),
+            (
+                "SHA-256",
+                "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1",
+            ),
+        ] {
+            let challenge = Challenge {
+                scheme: "Digest".to_string(),
+                params: vec![
+                    ("realm".to_string(), "http-auth@example.org".to_string()),
+                    ("qop".to_string(), "auth, auth-int".to_string()),
+                    ("algorithm".to_string(), algorithm.to_string()),
+                    (
+                        "nonce".to_string(),
+                        "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v".to_string(),
+                    ),
+                    (
+                        "opaque".to_string(),
+                        "FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS".to_string(),
+                    ),
+                ],
+            };
+            let header = digest_auth(
+                &challenge,
+                "Mufasa",
+                "Circle of Life",
+                &Method::GET,
+                &Url::parse("http://www.example.org/dir/index.html").unwrap(),
+                "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+            )
+            .unwrap();
+            assert!(header.contains(&format!("response=\"{}\"", response)));
+            assert!(header.contains("uri=\"/dir/index.html\""));
+            assert!(header.contains("qop=auth, nc=00000001"));
+        }
+    }
+
     #[test]
     fn netrc() {
         let good_netrc = "machine example.com\nlogin user\npassword pass";
@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, stdin, stdout, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::blocking::{Client, Request};
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::auth::parse_auth;
+use crate::cli::{self, parse_method, ByteSize, Cli};
+use crate::config::Credential;
+use crate::request::{authenticate, build_client, build_request, replace_with_json_body};
+use crate::request_items::{RequestItem, RequestItems};
+use crate::runtime::Runtime;
+use crate::stats::Stats;
+use crate::url::construct_url;
+use crate::utils::{BodyTooLarge, SizeLimit};
+use crate::{blame_deadline, error_exit_code, error_outcome, status_exit_code};
+
+/// A single line of an --input file.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Spec {
+    method: Option<String>,
+    url: String,
+    #[serde(default)]
+    items: Vec<String>,
+}
+
+fn read_lines(input: &Path) -> Result<Vec<String>> {
+    if input == Path::new("-") {
+        Ok(stdin().lock().lines().collect::<io::Result<_>>()?)
+    } else {
+        let file = File::open(input)
+            .with_context(|| format!("Failed to open the input file: {}", input.display()))?;
+        Ok(BufReader::new(file).lines().collect::<io::Result<_>>()?)
+    }
+}
+
+/// Read a --urls-from file: a URL on each line, optionally preceded by a method.
+pub fn read_urls(input: &Path) -> Result<Vec<(Option<Method>, String)>> {
+    let mut urls = Vec::new();
+    for (index, line) in read_lines(input)?.iter().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            [url] => urls.push((None, url.to_string())),
+            [method, url] if parse_method(method).is_some() => {
+                urls.push((parse_method(method), url.to_string()))
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Line {} of {}: expected [METHOD] URL, found {:?}",
+                    index + 1,
+                    input.display(),
+                    line
+                ))
+            }
+        }
+    }
+    Ok(urls)
+}
+
+/// Send every request in `input`, `args.parallel` at a time, and print a
+/// line of JSON for each one.
+pub fn run(
+    args: &Cli,
+    input: &Path,
+    credentials: &HashMap<String, Credential>,
+    runtime: &Runtime,
+) -> Result<i32> {
+    let lines = read_lines(input)?;
+
+    // Parse credentials once, so we don't prompt for a password for every line
+    let auth = match &args.auth {
+        Some(auth) => Some(parse_auth(auth.clone(), "<host>", args.password_stdin)?),
+        None => None,
+    };
+    let proxy_auth = match &args.proxy_auth {
+        Some(auth) => Some(parse_auth(auth.clone(), "<proxy>", false)?),
+        None => None,
+    };
+    let client = build_client(args, true, proxy_auth.as_ref(), None)?;
+
+    let (sender, receiver) = mpsc::channel();
+    let mut specs = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Spec>(line) {
+            Ok(spec) => specs.push((index + 1, spec)),
+            Err(err) => sender.send((index + 1, Err(err.into())))?,
+        }
+    }
+    let mut jobs = Vec::new();
+    for round in 0..args.repeat.unwrap_or(1) {
+        for (line_number, spec) in &specs {
+            match to_request(args, &client, auth.clone(), credentials, spec.clone()) {
+                Ok(request) => jobs.push((*line_number, request)),
+                // It would fail the same way in every round
+                Err(err) if round == 0 => sender.send((*line_number, Err(err)))?,
+                Err(_) => {}
+            }
+        }
+    }
+
+    let max_body_size = args.max_body_size.map(|ByteSize(size)| size);
+    let timeout = args.timeout.as_ref().and_then(|t| t.as_duration());
+    let cutoff = runtime.cutoff;
+    let jobs = Arc::new(Mutex::new(jobs.into_iter()));
+    let workers: Vec<_> = (0..args.parallel.unwrap_or(1))
+        .map(|_| {
+            let jobs = Arc::clone(&jobs);
+            let client = client.clone();
+            let sender = sender.clone();
+            thread::spawn(move || loop {
+                // The lock has to be released before sending the request
+                let job = jobs.lock().unwrap().next();
+                let (line_number, mut request) = match job {
+                    Some(job) => job,
+                    None => break,
+                };
+                let result = match cutoff.map(|cutoff| cutoff.limit(timeout)) {
+                    Some(Err(err)) => Err(err.into()),
+                    Some(Ok(timeout)) => {
+                        *request.timeout_mut() = Some(timeout);
+                        execute(&client, request, max_body_size)
+                    }
+                    None => execute(&client, request, max_body_size),
+                };
+                if sender.send((line_number, result)).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect();
+    drop(sender);
+
+    let mut stats = args.repeat.map(|_| Stats::default());
+    let mut exit_code = 0;
+    let stdout = stdout();
+    let mut stdout = stdout.lock();
+    for (line_number, result) in receiver {
+        let mut report = json!({ "line": line_number });
+        match result {
+            Ok(outcome) => {
+                exit_code =
+                    exit_code.max(status_exit_code(args, &runtime.exit_codes, outcome.status));
+                report["method"] = json!(outcome.method);
+                report["url"] = json!(outcome.url);
+                report["status"] = json!(outcome.status);
+                report["size"] = json!(outcome.size);
+                report["time"] = json!(outcome.time);
+                if let Some(stats) = &mut stats {
+                    stats.add_response(outcome.status, outcome.time);
+                }
+            }
+            Err(err) => {
+                let err = blame_deadline(err, runtime.cutoff);
+                exit_code = error_exit_code(&err, &runtime.exit_codes);
+                if let Some(stats) = &mut stats {
+                    stats.add_error(
+                        &error_outcome(&err).map_or_else(|| "other".to_string(), |o| o.to_string()),
+                    );
+                }
+                report["error"] = json!(format!("{:#}", err));
+                report["error_kind"] =
+                    json!(error_outcome(&err).map_or("other", cli::Outcome::kind));
+            }
+        }
+        writeln!(stdout, "{}", report)?;
+    }
+
+    for worker in workers {
+        worker
+            .join()
+            .map_err(|_| anyhow!("A request thread panicked"))?;
+    }
+    if let Some(stats) = stats {
+        if args.quiet < 2 {
+            eprintln!("{}", stats);
+        }
+        if let Some(path) = &args.metrics_file {
+            fs::write(path, stats.to_prometheus())
+                .with_context(|| format!("Failed to write the metrics to {}", path.display()))?;
+        }
+    }
+    Ok(exit_code)
+}
+
+fn to_request(
+    args: &Cli,
+    client: &Client,
+    auth: Option<(String, Option<String>)>,
+    credentials: &HashMap<String, Credential>,
+    spec: Spec,
+) -> Result<Request> {
+    let items = spec
+        .items
+        .iter()
+        .map(|item| item.parse::<RequestItem>())
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut request_items = RequestItems::new(items);
+    request_items.expand(args)?;
+    let (headers, headers_to_unset) = request_items.headers()?;
+    let url = construct_url(
+        &spec.url,
+        args.default_scheme.as_deref(),
+        &request_items.query()?,
+        &request_items.query_to_unset(),
+        args.encode_set,
+    )?;
+    let mut body = request_items.body(args.request_type, &args.part_header)?;
+    if let Some(json_body) = &args.json_body {
+        body = replace_with_json_body(body, json_body)?;
+    }
+    let method = match spec.method {
+        Some(method) => Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+            .with_context(|| format!("Invalid method: {}", method))?,
+        None => body.pick_method(),
+    };
+
+    let request_builder = build_request(client, args, method, url.clone(), body)?;
+    // Requests aren't retried here, so credentials can't wait for a challenge
+    let mut request = authenticate(request_builder, args, auth, true, credentials, &url)?
+        .headers(headers)
+        .build()?;
+    for header in &headers_to_unset {
+        request.headers_mut().remove(header);
+    }
+    Ok(request)
+}
+
+struct Outcome {
+    method: String,
+    url: String,
+    status: u16,
+    size: u64,
+    time: f64,
+}
+
+fn execute(client: &Client, request: Request, max_size: Option<u64>) -> Result<Outcome> {
+    let method = request.method().to_string();
+    let url = request.url().to_string();
+    let start = Instant::now();
+    let response = client.execute(request)?;
+    let status = response.status().as_u16();
+    if let (Some(limit), Some(length)) = (max_size, response.content_length()) {
+        if length > limit {
+            return Err(BodyTooLarge(limit).into());
+        }
+    }
+    let size = io::copy(&mut SizeLimit::new(response, max_size), &mut io::sink())?;
+    Ok(Outcome {
+        method,
+        url,
+        status,
+        size,
+        time: start.elapsed().as_secs_f64(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spec_parsing() {
+        assert_eq!(
+            serde_json::from_str::<Spec>(r#"{"url": "example.org"}"#).unwrap(),
+            Spec {
+                method: None,
+                url: "example.org".to_string(),
+                items: vec![],
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<Spec>(
+                r#"{"method": "post", "url": "example.org", "items": ["a=b"]}"#
+            )
+            .unwrap(),
+            Spec {
+                method: Some("post".to_string()),
+                url: "example.org".to_string(),
+                items: vec!["a=b".to_string()],
+            }
+        );
+        assert!(serde_json::from_str::<Spec>(r#"{"method": "get"}"#).is_err());
+        assert!(serde_json::from_str::<Spec>(r#"{"url": "a", "headers": {}}"#).is_err());
+    }
+
+    #[test]
+    fn url_list() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            "# comment\nexample.org/a\n\n  delete http://example.org/b  "
+        )
+        .unwrap();
+        assert_eq!(
+            read_urls(file.path()).unwrap(),
+            vec![
+                (None, "example.org/a".to_string()),
+                (Some(Method::DELETE), "http://example.org/b".to_string())
+            ]
+        );
+
+        writeln!(file, "example.org/c x-trace:1").unwrap();
+        assert!(read_urls(file.path()).is_err());
+    }
+}
@@ -2,13 +2,14 @@ use std::{
     fmt,
     io::{self, stdout, LineWriter, Stdout, Write},
     path::Path,
+    process::{Child, ChildStdin, Stdio},
 };
 
 use termcolor::{Ansi, ColorChoice, StandardStream, WriteColor};
 
 use crate::{
-    cli::Pretty,
-    utils::{test_default_color, test_pretend_term},
+    cli::{ColorMode, Pretty},
+    utils::{parse_command, test_default_color, test_pretend_term},
 };
 
 pub enum Buffer {
@@ -20,6 +21,10 @@ pub enum Buffer {
     Redirect(Ansi<Stdout>),
     Stdout(StandardStream),
     Stderr(StandardStream),
+    Pager(Pager),
+    /// Another buffer, with --max-lines, --wrap and --line-numbers applied
+    /// to bodies
+    Lines(Box<LineControl>),
 }
 
 impl Buffer {
@@ -28,18 +33,24 @@ impl Buffer {
         output: Option<&Path>,
         is_stdout_tty: bool,
         pretty: Option<Pretty>,
+        color: Option<ColorMode>,
+        pager: Option<&str>,
     ) -> io::Result<Self> {
-        let color_choice = match pretty {
-            None if test_default_color() => ColorChoice::AlwaysAnsi,
-            None => ColorChoice::Auto,
-            Some(pretty) if pretty.color() => ColorChoice::Always,
-            Some(..) => ColorChoice::Never,
+        let color_choice = match (pretty, color) {
+            (_, Some(ColorMode::always)) => ColorChoice::Always,
+            (_, Some(ColorMode::never)) => ColorChoice::Never,
+            (None, _) if test_default_color() => ColorChoice::AlwaysAnsi,
+            (None, _) => ColorChoice::Auto,
+            (Some(pretty), _) if pretty.color() => ColorChoice::Always,
+            (Some(..), _) => ColorChoice::Never,
         };
         Ok(if download {
             Buffer::Stderr(StandardStream::stderr(color_choice))
         } else if let Some(output) = output {
             let file = std::fs::File::create(&output)?;
             Buffer::File(Ansi::new(LineWriter::new(file)))
+        } else if let (true, Some(command)) = (is_stdout_tty, pager) {
+            Buffer::Pager(Pager::spawn(command)?)
         } else if is_stdout_tty {
             Buffer::Stdout(StandardStream::stdout(color_choice))
         } else {
@@ -47,13 +58,70 @@ impl Buffer {
         })
     }
 
+    /// Apply line controls to bodies printed into this buffer.
+    pub fn with_line_control(
+        self,
+        max_lines: Option<usize>,
+        wrap: Option<usize>,
+        line_numbers: bool,
+    ) -> Self {
+        if max_lines.is_none() && wrap.is_none() && !line_numbers {
+            return self;
+        }
+        Buffer::Lines(Box::new(LineControl {
+            inner: self,
+            active: false,
+            max_lines,
+            wrap,
+            line_numbers,
+            line: 0,
+            column: 0,
+            at_line_start: true,
+            blank_lines: 0,
+            truncated: false,
+        }))
+    }
+
+    /// Start or stop treating what's printed as a body.
+    ///
+    /// When a body ends a note is added if lines were left out.
+    pub fn set_body(&mut self, active: bool) -> io::Result<()> {
+        if let Buffer::Lines(lines) = self {
+            if !active && lines.truncated {
+                let note = format!("[output truncated after {} lines]\n", lines.line - 1);
+                lines.inner.write_all(note.as_bytes())?;
+            } else if !active {
+                // Blank lines at the very end are the separator after the body
+                let blank = vec![b'\n'; lines.blank_lines];
+                lines.inner.write_all(&blank)?;
+            }
+            lines.active = active;
+            lines.line = 0;
+            lines.column = 0;
+            lines.at_line_start = true;
+            lines.blank_lines = 0;
+            lines.truncated = false;
+        }
+        Ok(())
+    }
+
     pub fn is_terminal(&self) -> bool {
-        matches!(self, Buffer::Stdout(..) | Buffer::Stderr(..))
-            || (matches!(self, Buffer::Redirect(..)) && test_pretend_term())
+        match self {
+            Buffer::Lines(lines) => lines.inner.is_terminal(),
+            _ => {
+                matches!(
+                    self,
+                    Buffer::Stdout(..) | Buffer::Stderr(..) | Buffer::Pager(..)
+                ) || (matches!(self, Buffer::Redirect(..)) && test_pretend_term())
+            }
+        }
     }
 
     pub fn is_redirect(&self) -> bool {
-        matches!(self, Buffer::Redirect(..))
+        match self {
+            Buffer::Lines(lines) => lines.inner.is_redirect(),
+            _ => matches!(self, Buffer::Redirect(..)),
+        }
     }
 
     #[inline]
@@ -84,6 +152,8 @@ impl Buffer {
             Buffer::File(file) => file,
             Buffer::Stdout(stream) | Buffer::Stderr(stream) => stream,
             Buffer::Redirect(stream) => stream,
+            Buffer::Pager(pager) => &pager.stdin,
+            Buffer::Lines(lines) => &lines.inner,
         }
     }
 
@@ -92,6 +162,8 @@ impl Buffer {
             Buffer::File(file) => file,
             Buffer::Stdout(stream) | Buffer::Stderr(stream) => stream,
             Buffer::Redirect(stream) => stream,
+            Buffer::Pager(pager) => &mut pager.stdin,
+            Buffer::Lines(lines) => &mut lines.inner,
         }
     }
 }
@@ -103,6 +175,8 @@ impl Write for Buffer {
             Buffer::File(file) => file.write(buf),
             Buffer::Stdout(stream) | Buffer::Stderr(stream) => stream.write(buf),
             Buffer::Redirect(stream) => stream.write(buf),
+            Buffer::Pager(pager) => pager.write(buf),
+            Buffer::Lines(lines) => lines.write_all(buf).map(|()| buf.len()),
         }
     }
 
@@ -116,6 +190,8 @@ impl Write for Buffer {
             Buffer::File(file) => file.write_all(buf),
             Buffer::Stdout(stream) | Buffer::Stderr(stream) => stream.write_all(buf),
             Buffer::Redirect(stream) => stream.write_all(buf),
+            Buffer::Pager(pager) => pager.write_all(buf),
+            Buffer::Lines(lines) => lines.write_all(buf),
         }
     }
 }
@@ -133,6 +209,8 @@ impl WriteColor for Buffer {
             Buffer::File(file) => file.set_color(spec),
             Buffer::Stdout(stream) | Buffer::Stderr(stream) => stream.set_color(spec),
             Buffer::Redirect(stream) => stream.set_color(spec),
+            Buffer::Pager(pager) => pager.stdin.set_color(spec),
+            Buffer::Lines(lines) => lines.inner.set_color(spec),
         }
     }
 
@@ -153,7 +231,141 @@ impl fmt::Debug for Buffer {
             Buffer::Stderr(..) => "Stderr",
             Buffer::Stdout(..) => "Stdout",
             Buffer::Redirect(..) => "Redirect",
+            Buffer::Pager(..) => "Pager",
+            Buffer::Lines(..) => "Lines",
         };
         write!(f, "{}(..)", text)
     }
 }
+
+/// Limits, wraps and numbers the lines of bodies on their way to another buffer.
+///
+/// Colors go straight to the inner buffer, so they don't take up columns.
+pub struct LineControl {
+    inner: Buffer,
+    active: bool,
+    max_lines: Option<usize>,
+    wrap: Option<usize>,
+    line_numbers: bool,
+    /// The number of the current line, or 0 before the first one
+    line: usize,
+    column: usize,
+    at_line_start: bool,
+    /// Blank lines that are held back until we know more of the body follows
+    blank_lines: usize,
+    truncated: bool,
+}
+
+impl LineControl {
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        if !self.active {
+            return self.inner.write_all(buf);
+        }
+        if self.truncated {
+            return Ok(());
+        }
+        let mut out = Vec::with_capacity(buf.len());
+        for &byte in buf {
+            if self.at_line_start {
+                if byte == b'\n' {
+                    self.blank_lines += 1;
+                    continue;
+                }
+                while self.blank_lines > 0 {
+                    self.blank_lines -= 1;
+                    if !self.start_line(&mut out) {
+                        break;
+                    }
+                    out.push(b'\n');
+                }
+                if self.truncated || !self.start_line(&mut out) {
+                    break;
+                }
+                self.at_line_start = false;
+                self.column = 0;
+            }
+            if byte == b'\n' {
+                self.at_line_start = true;
+                out.push(byte);
+                continue;
+            }
+            // Only the first byte of a UTF-8 character takes up a column
+            let starts_char = byte & 0xc0 != 0x80;
+            if starts_char {
+                if self.wrap == Some(self.column) {
+                    out.push(b'\n');
+                    if self.line_numbers {
+                        out.extend(b"      | ");
+                    }
+                    self.column = 0;
+                }
+                self.column += 1;
+            }
+            out.push(byte);
+        }
+        self.inner.write_all(&out)
+    }
+
+    /// Count a new line and add its number, or return false if it's one too many.
+    fn start_line(&mut self, out: &mut Vec<u8>) -> bool {
+        self.line += 1;
+        if self.max_lines.map_or(false, |max| self.line > max) {
+            self.truncated = true;
+            return false;
+        }
+        if self.line_numbers {
+            out.extend(format!("{:>5} | ", self.line).bytes());
+        }
+        true
+    }
+}
+
+/// A pager process that we feed our output to.
+pub struct Pager {
+    // Fields are dropped in order, so the pipe is closed (and the pager sees
+    // the end of its input) before we wait for the user to quit it
+    stdin: Ansi<LineWriter<ChildStdin>>,
+    _child: WaitOnDrop,
+}
+
+impl Pager {
+    fn spawn(command: &str) -> io::Result<Self> {
+        let mut child = parse_command(command)?
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("Failed to start pager {:?}: {}", command, err),
+                )
+            })?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        Ok(Pager {
+            stdin: Ansi::new(LineWriter::new(stdin)),
+            _child: WaitOnDrop(child),
+        })
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.stdin.write(buf) {
+            // The user quit the pager before seeing everything, that's fine
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(buf.len()),
+            result => result,
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self.stdin.write_all(buf) {
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            result => result,
+        }
+    }
+}
+
+struct WaitOnDrop(Child);
+
+impl Drop for WaitOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.wait();
+    }
+}
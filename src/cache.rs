@@ -0,0 +1,560 @@
+// --cache: a private HTTP cache on disk, following RFC 9111, so exploring an
+// API doesn't send it the same requests over and over
+//
+// Only responses to GET are stored, each as a JSON file named after a hash of
+// the URL and the body next to it. A stored response that's still fresh is
+// used without sending anything. A stale one is revalidated with
+// If-None-Match or If-Modified-Since, and used again if the server answers
+// 304 Not Modified.
+//
+// Partial responses (206) aren't stored, and only one response is kept for
+// each URL, so a Vary header that doesn't match just means a miss. Requests
+// with Authorization or Cookie headers only share responses marked public,
+// since the URL alone doesn't say whose response it was.
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use reqwest::blocking::{Request, Response};
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, AGE, AUTHORIZATION, CACHE_CONTROL, COOKIE, DATE, ETAG,
+    EXPIRES, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, PRAGMA, VARY,
+};
+use reqwest::{ResponseBuilderExt, StatusCode, Url, Version};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tempfile::NamedTempFile;
+
+use crate::utils::{to_hex, SizeLimit};
+
+/// Statuses that may be stored without an explicit expiration time, and then
+/// get a heuristic one (RFC 9110, section 15.1).
+const HEURISTICALLY_CACHEABLE: &[u16] = &[200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+/// A response as it's kept on disk.
+#[derive(Debug, Serialize, Deserialize)]
+struct Stored {
+    url: String,
+    status: u16,
+    version: String,
+    headers: Vec<(String, String)>,
+    /// The request headers named by Vary, as they were sent
+    vary: Vec<(String, Option<String>)>,
+    /// When the request was sent and the response arrived, in seconds since
+    /// the epoch
+    request_time: u64,
+    response_time: u64,
+}
+
+/// A stored response that can be used for a request, if it's fresh. The body
+/// is in a file of its own next to `path`.
+pub struct Entry {
+    path: PathBuf,
+    stored: Stored,
+}
+
+impl Cache {
+    pub fn new(dir: Option<PathBuf>) -> Result<Cache> {
+        let dir = match dir {
+            Some(dir) => dir,
+            None => dirs::cache_dir()
+                .context("Couldn't find a cache directory, pass --cache=DIR")?
+                .join(env!("CARGO_PKG_NAME")),
+        };
+        Ok(Cache { dir })
+    }
+
+    fn path(&self, url: &Url) -> PathBuf {
+        let hash = Sha256::digest(url.as_str().as_bytes());
+        self.dir.join(format!("{}.json", to_hex(&hash)))
+    }
+
+    /// The stored response for a GET request, if there's one that was sent
+    /// with the same headers that Vary names, and that may be shared with it
+    /// if it has credentials.
+    pub fn lookup(&self, request: &Request) -> Result<Option<Entry>> {
+        let path = self.path(request.url());
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).with_context(|| format!("Failed to read {:?}", path)),
+        };
+        // A damaged entry is as good as none, and gets replaced
+        let stored: Stored = match serde_json::from_str(&text) {
+            Ok(stored) => stored,
+            Err(_) => return Ok(None),
+        };
+        let entry = Entry { path, stored };
+        let matches = entry
+            .stored
+            .vary
+            .iter()
+            .all(|(name, value)| header_text(request.headers(), name) == *value);
+        let shareable = !has_credentials(request.headers()) || is_public(&entry.headers());
+        Ok(if matches && shareable && entry.body_path().exists() {
+            Some(entry)
+        } else {
+            None
+        })
+    }
+
+    /// Store the response to a GET request if that's allowed, and give it
+    /// back. A stored response has its body read into memory.
+    pub fn store(
+        &self,
+        url: &Url,
+        request_headers: &HeaderMap,
+        request_time: SystemTime,
+        response: Response,
+        max_body_size: Option<u64>,
+    ) -> Result<Response> {
+        if !storable(request_headers, response.status(), response.headers()) {
+            return Ok(response);
+        }
+        let vary = response
+            .headers()
+            .get_all(VARY)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let name = name.to_ascii_lowercase();
+                let value = header_text(request_headers, &name);
+                (name, value)
+            })
+            .collect();
+        let stored = Stored {
+            url: response.url().to_string(),
+            status: response.status().as_u16(),
+            version: format!("{:?}", response.version()),
+            headers: to_pairs(response.headers()),
+            vary,
+            request_time: seconds(request_time),
+            response_time: seconds(SystemTime::now()),
+        };
+        let mut body = Vec::new();
+        SizeLimit::new(response, max_body_size).read_to_end(&mut body)?;
+
+        let entry = Entry {
+            path: self.path(url),
+            stored,
+        };
+        write_atomically(&self.dir, &entry.body_path(), &body)?;
+        entry.save(&self.dir)?;
+        entry.response(None)
+    }
+
+    /// Forget the response for a URL, after a request that may have changed
+    /// what's there, like a successful PUT.
+    pub fn invalidate(&self, url: &Url) -> Result<()> {
+        let path = self.path(url);
+        for path in &[path.with_extension("body"), path] {
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => {
+                    return Err(err).with_context(|| format!("Failed to remove {:?}", path));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Entry {
+    /// How old the response is, counting from when the origin server sent it
+    /// (RFC 9111, section 4.2.3).
+    pub fn age(&self, now: SystemTime) -> Duration {
+        let headers = self.headers();
+        let stored = &self.stored;
+        let date = http_date(&headers, DATE).unwrap_or(stored.response_time);
+        let apparent_age = stored.response_time.saturating_sub(date);
+        let age_value = headers
+            .get(AGE)
+            .and_then(|value| value.to_str().ok()?.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        let response_delay = stored.response_time.saturating_sub(stored.request_time);
+        let corrected_initial_age = apparent_age.max(age_value + response_delay);
+        let resident_time = seconds(now).saturating_sub(stored.response_time);
+        Duration::from_secs(corrected_initial_age + resident_time)
+    }
+
+    /// How long the response stays fresh (RFC 9111, section 4.2.1).
+    fn freshness_lifetime(&self) -> Duration {
+        let headers = self.headers();
+        let directives = directives(&headers);
+        if let Some(max_age) = directive_seconds(&directives, "max-age") {
+            return Duration::from_secs(max_age);
+        }
+        let date = http_date(&headers, DATE).unwrap_or(self.stored.response_time);
+        if headers.contains_key(EXPIRES) {
+            // An invalid date means it already expired
+            let expires = http_date(&headers, EXPIRES).unwrap_or(0);
+            return Duration::from_secs(expires.saturating_sub(date));
+        }
+        match http_date(&headers, LAST_MODIFIED) {
+            Some(last_modified)
+                if HEURISTICALLY_CACHEABLE.contains(&self.stored.status)
+                    || directives.iter().any(|(name, _)| name == "public") =>
+            {
+                // A tenth of the time since it last changed, like browsers do
+                Duration::from_secs(date.saturating_sub(last_modified) / 10)
+            }
+            _ => Duration::from_secs(0),
+        }
+    }
+
+    /// Whether the response can be used for a request with these headers
+    /// without asking the server.
+    pub fn is_fresh(&self, request_headers: &HeaderMap, now: SystemTime) -> bool {
+        let age = self.age(now);
+        let request = directives(request_headers);
+        let pragma_no_cache = request_headers
+            .get_all(PRAGMA)
+            .iter()
+            .any(|value| value.as_bytes().eq_ignore_ascii_case(b"no-cache"));
+        if pragma_no_cache || request.iter().any(|(name, _)| name == "no-cache") {
+            return false;
+        }
+        if let Some(max_age) = directive_seconds(&request, "max-age") {
+            if age > Duration::from_secs(max_age) {
+                return false;
+            }
+        }
+        let response = directives(&self.headers());
+        !response.iter().any(|(name, _)| name == "no-cache") && self.freshness_lifetime() > age
+    }
+
+    /// Ask the server whether the stale response may be used again. Returns
+    /// false if it can't be asked, because the response has no validators or
+    /// the request already has conditions of its own.
+    pub fn add_validators(&self, request: &mut Request) -> bool {
+        let headers = self.headers();
+        if request.headers().contains_key(IF_NONE_MATCH)
+            || request.headers().contains_key(IF_MODIFIED_SINCE)
+        {
+            return false;
+        }
+        let mut added = false;
+        if let Some(etag) = headers.get(ETAG) {
+            request.headers_mut().insert(IF_NONE_MATCH, etag.clone());
+            added = true;
+        }
+        if let Some(last_modified) = headers.get(LAST_MODIFIED) {
+            request
+                .headers_mut()
+                .insert(IF_MODIFIED_SINCE, last_modified.clone());
+            added = true;
+        }
+        added
+    }
+
+    /// Update the response with the headers of a 304 Not Modified that
+    /// revalidated it (RFC 9111, section 4.3.4), and give it back.
+    pub fn freshen(
+        mut self,
+        cache: &Cache,
+        not_modified: &Response,
+        request_time: SystemTime,
+    ) -> Result<Response> {
+        let mut headers = self.headers();
+        for name in not_modified.headers().keys() {
+            // These describe the 304 itself, not the stored body
+            if name == "content-length" || name == "content-encoding" {
+                continue;
+            }
+            headers.remove(name);
+            for value in not_modified.headers().get_all(name) {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+        self.stored.headers = to_pairs(&headers);
+        self.stored.request_time = seconds(request_time);
+        self.stored.response_time = seconds(SystemTime::now());
+        self.save(&cache.dir)?;
+        self.response(None)
+    }
+
+    /// The stored response, with an Age header if it's used without asking
+    /// the server.
+    pub fn response(&self, age: Option<Duration>) -> Result<Response> {
+        let version = match self.stored.version.as_str() {
+            "HTTP/0.9" => Version::HTTP_09,
+            "HTTP/1.0" => Version::HTTP_10,
+            "HTTP/2.0" => Version::HTTP_2,
+            "HTTP/3.0" => Version::HTTP_3,
+            _ => Version::HTTP_11,
+        };
+        let mut builder = http::Response::builder()
+            .status(StatusCode::from_u16(self.stored.status)?)
+            .version(version)
+            .url(Url::parse(&self.stored.url)?);
+        let mut headers = self.headers();
+        if let Some(age) = age {
+            headers.insert(AGE, HeaderValue::from(age.as_secs()));
+        }
+        if let Some(builder_headers) = builder.headers_mut() {
+            *builder_headers = headers;
+        }
+        let body_path = self.body_path();
+        let body =
+            fs::read(&body_path).with_context(|| format!("Failed to read {:?}", body_path))?;
+        Ok(Response::from(builder.body(body)?))
+    }
+
+    fn body_path(&self) -> PathBuf {
+        self.path.with_extension("body")
+    }
+
+    fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.stored.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                headers.append(name, value);
+            }
+        }
+        headers
+    }
+
+    /// Write the entry's metadata. The body only changes when it's stored.
+    fn save(&self, dir: &Path) -> Result<()> {
+        write_atomically(
+            dir,
+            &self.path,
+            serde_json::to_string(&self.stored)?.as_bytes(),
+        )
+    }
+}
+
+/// Write a file through a temporary one, so it's never half written. Each
+/// write gets its own, in case another xh is writing the same entry.
+fn write_atomically(dir: &Path, path: &Path, contents: &[u8]) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    let mut temp = NamedTempFile::new_in(dir)
+        .with_context(|| format!("Failed to create a file in {:?}", dir))?;
+    temp.write_all(contents)
+        .with_context(|| format!("Failed to write {:?}", temp.path()))?;
+    temp.persist(path)
+        .with_context(|| format!("Failed to write {:?}", path))?;
+    Ok(())
+}
+
+/// Whether a response to a GET request may be stored (RFC 9111, section 3).
+fn storable(request_headers: &HeaderMap, status: StatusCode, headers: &HeaderMap) -> bool {
+    let request = directives(request_headers);
+    let response = directives(headers);
+    let no_store = |directives: &[(String, Option<String>)]| {
+        directives.iter().any(|(name, _)| name == "no-store")
+    };
+    if no_store(&request) || no_store(&response) {
+        return false;
+    }
+    // Another request to the URL may not have the right to see it
+    if has_credentials(request_headers) && !is_public(headers) {
+        return false;
+    }
+    let vary_all = headers
+        .get_all(VARY)
+        .iter()
+        .any(|value| value.to_str().map_or(false, |value| value.trim() == "*"));
+    // A 304 only comes back for conditions the user added themselves
+    if vary_all || status == StatusCode::PARTIAL_CONTENT || status == StatusCode::NOT_MODIFIED {
+        return false;
+    }
+    response
+        .iter()
+        .any(|(name, _)| name == "max-age" || name == "public" || name == "private")
+        || headers.contains_key(EXPIRES)
+        || HEURISTICALLY_CACHEABLE.contains(&status.as_u16())
+}
+
+/// Whether a request says who's asking, so that the response may be meant for
+/// them alone.
+fn has_credentials(request_headers: &HeaderMap) -> bool {
+    request_headers.contains_key(AUTHORIZATION) || request_headers.contains_key(COOKIE)
+}
+
+/// Whether the response says it may be stored and shared even when the
+/// request had credentials.
+fn is_public(headers: &HeaderMap) -> bool {
+    directives(headers).iter().any(|(name, _)| name == "public")
+}
+
+/// The Cache-Control directives, with lowercase names and unquoted values.
+fn directives(headers: &HeaderMap) -> Vec<(String, Option<String>)> {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter(|directive| !directive.trim().is_empty())
+        .map(|directive| match directive.find('=') {
+            Some(index) => (
+                directive[..index].trim().to_ascii_lowercase(),
+                Some(directive[index + 1..].trim().trim_matches('"').to_string()),
+            ),
+            None => (directive.trim().to_ascii_lowercase(), None),
+        })
+        .collect()
+}
+
+fn directive_seconds(directives: &[(String, Option<String>)], name: &str) -> Option<u64> {
+    directives
+        .iter()
+        .find(|(directive, _)| directive == name)
+        .and_then(|(_, value)| value.as_ref()?.parse().ok())
+}
+
+/// All the values of a header, joined like they would be in one field.
+fn header_text(headers: &HeaderMap, name: &str) -> Option<String> {
+    let values: Vec<String> = headers
+        .get_all(name)
+        .iter()
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+        .collect();
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(", "))
+    }
+}
+
+fn to_pairs(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect()
+}
+
+fn http_date(headers: &HeaderMap, name: HeaderName) -> Option<u64> {
+    let value = headers.get(name)?.to_str().ok()?;
+    let date = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    u64::try_from(date.timestamp()).ok()
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(headers: &[(&str, &str)]) -> Entry {
+        Entry {
+            path: PathBuf::new(),
+            stored: Stored {
+                url: "http://example.com/".to_string(),
+                status: 200,
+                version: "HTTP/1.1".to_string(),
+                headers: headers
+                    .iter()
+                    .map(|&(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+                vary: Vec::new(),
+                // Sun, 06 Nov 1994 08:49:37 GMT, with a second on the way
+                request_time: 784_111_776,
+                response_time: 784_111_777,
+            },
+        }
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn freshness() {
+        let date = ("date", "Sun, 06 Nov 1994 08:49:37 GMT");
+        let max_age = entry(&[date, ("cache-control", "max-age=60"), ("age", "10")]);
+        assert_eq!(max_age.age(at(784_111_777 + 20)), Duration::from_secs(31));
+        assert!(max_age.is_fresh(&HeaderMap::new(), at(784_111_777 + 20)));
+        assert!(!max_age.is_fresh(&HeaderMap::new(), at(784_111_777 + 50)));
+
+        let mut request = HeaderMap::new();
+        request.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        assert!(!max_age.is_fresh(&request, at(784_111_777)));
+
+        // A tenth of the ten days since it last changed
+        let heuristic = entry(&[date, ("last-modified", "Thu, 27 Oct 1994 08:49:37 GMT")]);
+        assert_eq!(heuristic.freshness_lifetime(), Duration::from_secs(86_400));
+
+        let expired = entry(&[date, ("expires", "0")]);
+        assert!(!expired.is_fresh(&HeaderMap::new(), at(784_111_777)));
+    }
+
+    #[test]
+    fn storability() {
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut headers = HeaderMap::new();
+            for &(name, value) in pairs {
+                headers.append(name, HeaderValue::from_static(value));
+            }
+            headers
+        };
+        let none = HeaderMap::new();
+        assert!(storable(&none, StatusCode::OK, &none));
+        assert!(!storable(&none, StatusCode::CREATED, &none));
+        assert!(storable(
+            &none,
+            StatusCode::CREATED,
+            &headers(&[("cache-control", "max-age=5")])
+        ));
+        assert!(!storable(
+            &none,
+            StatusCode::OK,
+            &headers(&[("cache-control", "private, no-store")])
+        ));
+        assert!(!storable(
+            &headers(&[("cache-control", "no-store")]),
+            StatusCode::OK,
+            &none
+        ));
+        assert!(!storable(&none, StatusCode::OK, &headers(&[("vary", "*")])));
+
+        // Responses to requests with credentials have to say they're public
+        for credentials in &[("authorization", "Basic eDp5"), ("cookie", "a=b")] {
+            let request = headers(&[*credentials]);
+            assert!(!storable(
+                &request,
+                StatusCode::OK,
+                &headers(&[("cache-control", "max-age=5")])
+            ));
+            assert!(storable(
+                &request,
+                StatusCode::OK,
+                &headers(&[("cache-control", "public, max-age=5")])
+            ));
+        }
+    }
+
+    #[test]
+    fn atomic_writes_leave_no_temporary_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("entry.json");
+        write_atomically(dir.path(), &path, b"old").unwrap();
+        write_atomically(dir.path(), &path, b"new").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,166 @@
+// Summarize a server's certificate for --verbose
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::IpAddr;
+
+use chrono::{SecondsFormat, TimeZone, Utc};
+use x509_parser::{
+    certificate::X509Certificate,
+    extensions::GeneralName,
+    oid_registry::{
+        OID_EC_P256, OID_KEY_TYPE_EC_PUBLIC_KEY, OID_NIST_EC_P384, OID_NIST_EC_P521,
+        OID_PKCS1_RSAENCRYPTION, OID_SIG_ED25519, OID_SIG_ED448, OID_X509_COMMON_NAME,
+        OID_X509_COUNTRY_NAME, OID_X509_LOCALITY_NAME, OID_X509_ORGANIZATIONAL_UNIT,
+        OID_X509_ORGANIZATION_NAME, OID_X509_STATE_OR_PROVINCE_NAME,
+    },
+    parse_x509_certificate,
+    public_key::PublicKey,
+    x509::SubjectPublicKeyInfo,
+    x509::X509Name,
+};
+
+/// The parts of an X.509 certificate that are interesting when debugging.
+#[derive(Debug, PartialEq)]
+pub struct Certificate {
+    pub subject: String,
+    pub issuer: String,
+    pub names: Vec<String>,
+    pub not_after: String,
+    pub key: String,
+}
+
+impl Certificate {
+    /// Summarize a DER-encoded certificate, or return `None` if it can't be
+    /// made sense of.
+    pub fn from_der(der: &[u8]) -> Option<Certificate> {
+        let (_, certificate) = parse_x509_certificate(der).ok()?;
+        let not_after = Utc
+            .timestamp_opt(certificate.validity().not_after.timestamp(), 0)
+            .single()?
+            .to_rfc3339_opts(SecondsFormat::Secs, true);
+        Some(Certificate {
+            subject: format_name(certificate.subject()),
+            issuer: format_name(certificate.issuer()),
+            names: alt_names(&certificate)?,
+            not_after,
+            key: format_key(certificate.public_key()),
+        })
+    }
+}
+
+impl fmt::Display for Certificate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "subject: {}", self.subject)?;
+        writeln!(f, "issuer: {}", self.issuer)?;
+        if !self.names.is_empty() {
+            writeln!(f, "names: {}", self.names.join(", "))?;
+        }
+        writeln!(f, "expires: {}", self.not_after)?;
+        write!(f, "key: {}", self.key)
+    }
+}
+
+/// Format a distinguished name like `CN=example.org, O=Example, C=US`.
+fn format_name(name: &X509Name) -> String {
+    let known = [
+        (OID_X509_COMMON_NAME, "CN"),
+        (OID_X509_ORGANIZATIONAL_UNIT, "OU"),
+        (OID_X509_ORGANIZATION_NAME, "O"),
+        (OID_X509_LOCALITY_NAME, "L"),
+        (OID_X509_STATE_OR_PROVINCE_NAME, "ST"),
+        (OID_X509_COUNTRY_NAME, "C"),
+    ];
+    let mut parts = Vec::new();
+    for attribute in name.iter_rdn().flat_map(|rdn| rdn.iter()) {
+        if let Some((_, key)) = known.iter().find(|(oid, _)| oid == attribute.attr_type()) {
+            let value = match attribute.as_str() {
+                Ok(value) => value.to_string(),
+                Err(_) => String::from_utf8_lossy(attribute.as_slice()).into_owned(),
+            };
+            parts.push(format!("{}={}", key, value));
+        }
+    }
+    // Names are stored from the least to the most specific part
+    parts.reverse();
+    parts.join(", ")
+}
+
+fn format_key(info: &SubjectPublicKeyInfo) -> String {
+    let algorithm = &info.algorithm.algorithm;
+    if *algorithm == OID_PKCS1_RSAENCRYPTION {
+        if let Ok(PublicKey::RSA(key)) = info.parsed() {
+            return format!("RSA {}", key.key_size());
+        }
+    } else if *algorithm == OID_KEY_TYPE_EC_PUBLIC_KEY {
+        let curve = info
+            .algorithm
+            .parameters
+            .as_ref()
+            .and_then(|parameters| parameters.as_oid().ok());
+        return match curve {
+            Some(oid) if oid == OID_EC_P256 => "EC P-256",
+            Some(oid) if oid == OID_NIST_EC_P384 => "EC P-384",
+            Some(oid) if oid == OID_NIST_EC_P521 => "EC P-521",
+            _ => "EC",
+        }
+        .to_string();
+    } else if *algorithm == OID_SIG_ED25519 {
+        return "Ed25519".to_string();
+    } else if *algorithm == OID_SIG_ED448 {
+        return "Ed448".to_string();
+    }
+    "unknown".to_string()
+}
+
+/// Find the DNS names and IP addresses in the subjectAltName extension.
+fn alt_names(certificate: &X509Certificate) -> Option<Vec<String>> {
+    let extension = match certificate.subject_alternative_name().ok()? {
+        Some(extension) => extension,
+        None => return Some(Vec::new()),
+    };
+    let mut names = Vec::new();
+    for name in &extension.value.general_names {
+        match name {
+            GeneralName::DNSName(name) => names.push(name.to_string()),
+            GeneralName::IPAddress(bytes) => {
+                let address = match bytes.len() {
+                    4 => IpAddr::from(<[u8; 4]>::try_from(*bytes).ok()?),
+                    16 => IpAddr::from(<[u8; 16]>::try_from(*bytes).ok()?),
+                    _ => continue,
+                };
+                names.push(address.to_string());
+            }
+            _ => {}
+        }
+    }
+    Some(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn certificate_summary() {
+        let pem = pem::parse(include_str!(
+            "../tests/fixtures/certs/wildcard-self-signed.pem"
+        ))
+        .unwrap();
+        let certificate = Certificate::from_der(&pem.contents).unwrap();
+        let name = "CN=*.badssl.com, O=BadSSL, L=San Francisco, ST=California, C=US";
+        assert_eq!(
+            certificate,
+            Certificate {
+                subject: name.to_string(),
+                issuer: name.to_string(),
+                names: vec!["*.badssl.com".to_string(), "badssl.com".to_string()],
+                not_after: "2018-08-08T21:17:05Z".to_string(),
+                key: "RSA 2048".to_string(),
+            }
+        );
+
+        assert_eq!(Certificate::from_der(b"\x30\x03\x02\x01"), None);
+        assert_eq!(Certificate::from_der(&pem.contents[..100]), None);
+    }
+}
@@ -1,18 +1,24 @@
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::env;
 use std::ffi::OsString;
 use std::fmt;
 use std::io::Write;
 use std::mem;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::Duration;
 
-use reqwest::{Method, Url};
+use encoding_rs::Encoding;
+use reqwest::header::{HeaderName, HeaderValue};
+use reqwest::{Method, StatusCode, Url};
 use structopt::clap::{self, arg_enum, AppSettings, Error, ErrorKind, Result};
 use structopt::StructOpt;
 
-use crate::{buffer::Buffer, request_items::RequestItem};
+use crate::{
+    buffer::Buffer, paginate::Paginate, regex, request_items::RequestItem, template::Template,
+    utils::valid_json,
+};
 
 // Some doc comments were copy-pasted from HTTPie
 
@@ -26,7 +32,7 @@ use crate::{buffer::Buffer, request_items::RequestItem};
 /// xh is a friendly and fast tool for sending HTTP requests.
 ///
 /// It reimplements as much as possible of HTTPie's excellent design.
-#[derive(StructOpt, Debug)]
+#[derive(StructOpt, Debug, Clone)]
 #[structopt(name = "xh", settings = &[AppSettings::DeriveDisplayOrder, AppSettings::UnifiedHelpMessage])]
 pub struct Cli {
     /// (default) Serialize data items from the command line as a JSON object.
@@ -41,14 +47,135 @@ pub struct Cli {
     #[structopt(short = "m", long, overrides_with_all = &["json", "form"])]
     pub multipart: bool,
 
+    /// Append `[]` to the names of file fields that use wildcards.
+    ///
+    /// A field like `photos@shots/*.jpg` uploads every matching file in a field
+    /// of its own. With this flag they're all named `photos[]`, which many web
+    /// frameworks expect for lists.
+    /// {n}{n}{n}
+    #[structopt(long)]
+    pub glob_brackets: bool,
+
+    /// Add a header to one part of a multipart request, e.g. `--part-header 'doc:Content-ID:<doc1>'`.
+    ///
+    /// FIELD is the name of the request item the part comes from. Repeat this
+    /// option to add more headers.
+    ///
+    /// With this option the whole request body is built in memory, rather than
+    /// streaming files from disk.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "FIELD:NAME:VALUE", number_of_values = 1)]
+    pub part_header: Vec<PartHeader>,
+
+    /// Add data fields from a CSV file, or a TSV file if it ends in `.tsv`.
+    ///
+    /// The first row holds the field names and the second row their values. Fields
+    /// given on the command line come after these, so they take precedence.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub fields_from: Option<PathBuf>,
+
+    /// Set a variable for `{{NAME}}` placeholders in the URL and request items.
+    ///
+    /// For example, `--var host=staging.example.com` turns `https://{{host}}/users`
+    /// into `https://staging.example.com/users`. Placeholders are filled in in the
+    /// values of request items, so one command can be pointed at different
+    /// environments. Using a variable that isn't set is an error. Repeat this
+    /// option to set more variables.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "NAME=VALUE", number_of_values = 1)]
+    pub var: Vec<Var>,
+
+    /// Read variables for `{{NAME}}` placeholders from a JSON object.
+    ///
+    /// Variables given with --var take precedence over the ones in this file.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub var_file: Option<PathBuf>,
+
     #[structopt(skip)]
     pub request_type: RequestType,
 
+    /// Which characters to percent-encode in URL parameters and form fields.
+    ///
+    /// "strict" encodes everything but letters, digits and `*-._`. "minimal" only
+    /// encodes characters that would change the meaning of the request, like `&`
+    /// and `=`, and leaves e.g. `:` and `,` alone. "none" sends values as they are,
+    /// except that URLs can't contain spaces or non-ASCII characters.
+    /// {n}{n}{n}
+    #[structopt(long, possible_values = &EncodeSet::variants(), case_insensitive = true,
+                default_value = "strict", value_name = "SET")]
+    pub encode_set: EncodeSet,
+
+    /// Fail if the URL path has `.` or `..` segments, instead of resolving them.
+    ///
+    /// Unlike curl's --path-as-is, xh can't send a path exactly as it's typed:
+    /// the URL parser it relies on always resolves `.` and `..` segments. This
+    /// at least makes sure that a request for `/static/../admin` doesn't quietly
+    /// go to `/admin`. Duplicate slashes and escapes like `%2F` are always sent
+    /// unchanged, and other characters are only percent-encoded if they can't
+    /// appear in a request line; neither can be changed.
+    /// {n}{n}{n}
+    #[structopt(long)]
+    pub reject_dot_segments: bool,
+
+    /// Send JSON as the request body, e.g. `--json-body '{}'`.
+    ///
+    /// This is mainly useful for sending an empty object or array, which request
+    /// items can't express. It can't be combined with data fields.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "JSON")]
+    pub json_body: Option<String>,
+
     /// Controls output processing.
+    ///
+    /// `format` indents JSON, lays CSV out as a table and spreads minified
+    /// JavaScript and CSS over indented lines. Errors in JSON, like
+    /// application/problem+json, get a summary above them. `colors` highlights
+    /// the syntax of the body and headers, and `all` does both.
+    /// {n}{n}{n}
     #[structopt(long, possible_values = &Pretty::variants(), case_insensitive = true, value_name = "STYLE")]
     pub pretty: Option<Pretty>,
 
+    /// When to color the output.
+    ///
+    /// With `always` colors are kept even when the output is piped or redirected,
+    /// and with `never` they're left out on a terminal. This only affects colors,
+    /// not formatting.
+    /// {n}{n}{n}
+    #[structopt(long, possible_values = &ColorMode::variants(), case_insensitive = true, value_name = "WHEN")]
+    pub color: Option<ColorMode>,
+
+    /// Show the output in a pager.
+    ///
+    /// Defaults to `less -FRX`, which leaves by itself if the output fits on one
+    /// screen. Has no effect if the output isn't a terminal.
+    ///
+    /// Example: `--pager='less -R'`
+    /// {n}{n}{n}
+    #[structopt(long, require_equals = true, value_name = "COMMAND")]
+    pub pager: Option<Option<String>>,
+
+    /// Fine-tune the output, e.g. `--format-options headers.sort:true`.
+    ///
+    /// Options are separated by commas. Headers are sorted by name when the
+    /// output is formatted. Pass headers.sort:false to show them in the order
+    /// they were received, like when debugging a proxy that reorders them.
+    /// {n}{n}{n}
+    #[structopt(
+        long,
+        value_name = "OPTIONS",
+        number_of_values = 1,
+        use_delimiter = true
+    )]
+    pub format_options: Vec<FormatOption>,
+
     /// Output coloring style.
+    ///
+    /// `light` suits terminals with a light background, `contrast` uses bright
+    /// colors and bold keys, and `colorblind` avoids telling red and green apart.
+    /// A default can be set with "style" in the config file.
+    /// {n}{n}{n}
     #[structopt(short = "s", long, value_name = "THEME", possible_values = &Theme::variants(), case_insensitive = true)]
     pub style: Option<Theme>,
 
@@ -71,22 +198,165 @@ pub struct Cli {
     pub body: bool,
 
     /// Print the whole request as well as the response.
+    ///
+    /// This also shows the address the connection went to, and takes each
+    /// Set-Cookie header apart: its expiry in local time, its flags, and
+    /// attributes that browsers would reject.
+    ///
+    /// With --meta the addresses the host name resolved to are listed as well, and
+    /// if none of them accepts a connection, the error for each one is printed.
+    /// TTLs and CNAME records aren't shown, because the system resolver doesn't
+    /// report them.
+    ///
+    /// For HTTPS it shows the protocol the server picked with ALPN and its
+    /// certificate. The TLS version, cipher suite and key exchange group aren't
+    /// shown, because the HTTP library doesn't report them.
+    ///
+    /// Interim responses such as 103 Early Hints are not shown, because the
+    /// HTTP library skips over them before xh gets the final response.
+    /// {n}{n}{n}
     #[structopt(short = "v", long)]
     pub verbose: bool,
 
-    /// Do not print to stdout or stderr.
-    #[structopt(short = "q", long)]
-    pub quiet: bool,
+    /// Do not print to stdout. Repeat (-qq) to not print to stderr either.
+    ///
+    /// With -q, warnings still go to stderr, like the status of a failed request
+    /// with --check-status. With -qq only errors do. Both hide the progress bar
+    /// of --download.
+    /// {n}{n}{n}
+    #[structopt(short = "q", long, parse(from_occurrences))]
+    pub quiet: u8,
+
+    /// Print the summary of a --download or --meta, and errors, to stderr as JSON.
+    ///
+    /// Errors are printed as `{"type": "error", "kind": KIND, "message": ...}`.
+    /// KIND is `dns`, `connect`, `tls`, `timeout`, `io`, `too-many-redirects`,
+    /// `body-too-large`, `http-status` for the statuses of --check-status (along
+    /// with `status`), or `other`. These names won't change, unlike messages.
+    ///
+    /// The summary is printed even with -q.
+    /// {n}{n}{n}
+    #[structopt(long)]
+    pub json_output: bool,
+
+    /// Print how long the parts of the exchange took, after the response.
+    ///
+    /// That's the DNS lookup, the time to the first byte of the response, the
+    /// body download and the total. The HTTP library doesn't say when it has
+    /// connected, so connecting and the TLS handshake are part of the time to
+    /// the first byte, along with the time the server took.
+    ///
+    /// To time the DNS lookup xh does it before the HTTP library connects. That
+    /// also lets --verbose list the addresses, and try each of them again for at
+    /// most 5 seconds in total if none accepted a connection.
+    /// {n}{n}{n}
+    #[structopt(long)]
+    pub meta: bool,
 
     /// Always stream the response body.
     #[structopt(short = "S", long)]
     pub stream: bool,
 
+    /// Format the response body as newline-delimited JSON, whatever its content type.
+    ///
+    /// This is the default for types like application/x-ndjson. Each line is
+    /// formatted as a JSON document of its own.
+    /// {n}{n}{n}
+    #[structopt(long)]
+    pub json_lines: bool,
+
+    /// Render markdown bodies with headings, lists and highlighted code blocks.
+    #[structopt(long)]
+    pub render_markdown: bool,
+
+    /// Show the response body as a table, whatever its content type.
+    ///
+    /// This is the default for CSV. The first row is taken to be the header.
+    /// {n}{n}{n}
+    #[structopt(long)]
+    pub table: bool,
+
+    /// Cut table cells down to at most NUM columns.
+    #[structopt(long, value_name = "NUM")]
+    pub column_width: Option<usize>,
+
+    /// Print the response body even if it looks like binary data.
+    ///
+    /// This also formats bodies that don't have a textual content type.
+    /// {n}{n}{n}
+    #[structopt(long, overrides_with = "force-binary")]
+    pub force_text: bool,
+
+    /// Treat the response body as binary data, even if it looks like text.
+    ///
+    /// It's then never shown in the terminal, and written out without formatting.
+    /// {n}{n}{n}
+    #[structopt(long, overrides_with = "force-text")]
+    pub force_binary: bool,
+
+    /// Don't format request or response bodies that are larger than SIZE.
+    ///
+    /// Larger bodies are printed as they are, without waiting for the whole body.
+    /// SIZE can have a K, M or G suffix. The default is 10M, 0 means no limit.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "SIZE")]
+    pub format_max_size: Option<ByteSize>,
+
+    /// Only highlight the first SIZE of a body and print the rest without colors.
+    ///
+    /// SIZE can have a K, M or G suffix. The default is 1M, 0 means no limit.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "SIZE")]
+    pub highlight_max_size: Option<ByteSize>,
+
+    /// Decode the response body as ENCODING for display, e.g. `latin1` or `shift_jis`.
+    ///
+    /// By default the charset from the Content-Type header is used. Failing
+    /// that, HTML and XML bodies are checked for a declared encoding, and
+    /// anything else is assumed to be UTF-8.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "ENCODING")]
+    pub response_charset: Option<Charset>,
+
+    /// Print at most NUM lines of each body.
+    #[structopt(long, value_name = "NUM")]
+    pub max_lines: Option<usize>,
+
+    /// Wrap long lines in bodies.
+    ///
+    /// `on` wraps at the width of the terminal, a number wraps at that many
+    /// columns, and `off` leaves lines as they are, which is the default.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "on|off|WIDTH")]
+    pub wrap: Option<Wrap>,
+
+    /// Number the lines of bodies.
+    #[structopt(long)]
+    pub line_numbers: bool,
+
     /// Save output to FILE instead of stdout.
     #[structopt(short = "o", long, value_name = "FILE", parse(from_os_str))]
     pub output: Option<PathBuf>,
 
+    /// Save the response's status line and headers to FILE.
+    ///
+    /// FILE can contain `%s` for the status code, `%h` for the host, `%t` for the
+    /// current Unix time and `%o` for the --output file name, e.g.
+    /// `--output-headers %o.headers`. Use `%%` for a literal `%`.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub output_headers: Option<PathBuf>,
+
     /// Download the body to a file instead of printing it.
+    ///
+    /// If the server advertises a SHA-256 or SHA-512 checksum of the file in a
+    /// `Repr-Digest` or `Digest` header then the download is checked against it.
+    ///
+    /// A download that ends before the Content-Length it was announced with is an
+    /// error, and the file is removed. It isn't retried, apart from trying the
+    /// next --mirror if there is one. With --continue the file is kept instead,
+    /// and running the same command again resumes it.
+    /// {n}{n}{n}
     #[structopt(short = "d", long)]
     pub download: bool,
 
@@ -94,19 +364,135 @@ pub struct Cli {
     #[structopt(short = "c", long = "continue", name = "continue")]
     pub resume: bool,
 
-    // Currently deprecated in favor of --bearer, un-hide if new auth types are introduced
+    /// Fail instead of overwriting a file that already exists.
+    #[structopt(long, overrides_with = "overwrite")]
+    pub no_clobber: bool,
+
+    /// Overwrite a file that already exists instead of numbering the download.
+    ///
+    /// Without --output the file name is taken from the response, and if that's
+    /// taken then a number is added. --output always overwrites.
+    /// {n}{n}{n}
+    #[structopt(long, overrides_with = "no-clobber")]
+    pub overwrite: bool,
+
+    /// How to number a download whose file name is taken. The default is `%f-%n`.
+    ///
+    /// `%f` is the file name, `%b` the file name without its extension, `%e` the
+    /// extension with its dot and `%n` the number, e.g. `%b (%n)%e`. Use `%%`
+    /// for a literal `%`.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "TEMPLATE")]
+    pub number_format: Option<NumberFormat>,
+
+    /// Only request part of the body, e.g. `0-1023`, `1024-` or `-500`.
+    ///
+    /// A range starting with a dash requests that many bytes from the end of the body.
+    ///
+    /// The response is checked to make sure the server sent the requested range.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "RANGE", allow_hyphen_values = true)]
+    pub range: Option<ByteRange>,
+
+    /// Decode a download that the server compressed, instead of saving it as is.
+    ///
+    /// This also allows the server to compress it, unless --range or --split
+    /// is used. gzip, deflate, br and zstd are supported.
+    /// {n}{n}{n}
+    #[structopt(long)]
+    pub decompress: bool,
+
+    /// List the files in a gzip, tar or zip download instead of saving it.
+    ///
+    /// The archive is read as it arrives and nothing is written to disk. Of a
+    /// gzip file that isn't a tarball only the header is read, which holds its
+    /// original name. Zip files are listed from the headers in front of each
+    /// file, because their index is at the end.
+    /// {n}{n}{n}
+    #[structopt(long)]
+    pub peek: bool,
+
+    /// Download over N connections at once, each fetching part of the file.
+    ///
+    /// This needs a server that supports range requests. If it doesn't, or
+    /// if the download goes to stdout, a single connection is used as usual.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "N")]
+    pub split: Option<usize>,
+
+    /// Don't set the downloaded file's modification time from the Last-Modified header.
+    #[structopt(long)]
+    pub ignore_last_modified: bool,
+
+    /// Fall back on URL if the download fails, resuming where it stopped.
+    ///
+    /// Can be repeated to try several mirrors in order. Mirrors that the server
+    /// advertises with `Link: <URL>; rel=duplicate` headers are tried after these.
+    /// Requires --output.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "URL", number_of_values = 1)]
+    pub mirror: Vec<Url>,
+
+    /// How often to redraw the --download progress bar, in seconds. The default is 0.1.
+    ///
+    /// Drawing the bar takes a surprising amount of CPU time, so on very fast
+    /// transfers a larger interval can make the download itself faster.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "SEC")]
+    pub progress_interval: Option<Interval>,
+
     /// Specify the auth mechanism.
+    ///
+    /// Without this --auth is sent as Basic credentials. `digest` waits for
+    /// the server's 401 response to answer its challenge. See --auth-challenge.
+    ///
+    /// `negotiate` uses SPNEGO (e.g. Kerberos) with the credentials in your
+    /// ticket cache, so it doesn't need --auth. It's only supported on Unix
+    /// other than macOS, through the system's GSSAPI library. `bearer` is the
+    /// same as --bearer.
+    /// {n}{n}{n}
     #[structopt(short = "A", long, possible_values = &AuthType::variants(),
-                default_value = "basic", case_insensitive = true, hidden = true)]
-    pub auth_type: AuthType,
+                case_insensitive = true)]
+    pub auth_type: Option<AuthType>,
 
     /// Authenticate as USER with PASS. PASS will be prompted if missing.
     ///
     /// Use a trailing colon (i.e. `USER:`) to authenticate with just a username.
+    /// Leave out USER (i.e. `--auth=`) to be prompted for it as well.
     /// {n}{n}{n}
     #[structopt(short = "a", long, value_name = "USER[:PASS]")]
     pub auth: Option<String>,
 
+    /// Wait for the server to ask for --auth instead of sending it right away.
+    ///
+    /// The request is first sent without credentials, and --auth is used for
+    /// whichever of Basic, Digest or Negotiate the server asks for in its 401
+    /// response. This costs a round trip. It has no effect with --auth-type.
+    /// {n}{n}{n}
+    #[structopt(long)]
+    pub auth_challenge: bool,
+
+    /// Read the password for --auth from stdin instead of prompting.
+    ///
+    /// All of stdin is read, minus a trailing newline, so it can't also be
+    /// the request body.
+    /// {n}{n}{n}
+    #[structopt(long)]
+    pub password_stdin: bool,
+
+    /// Show credentials in the output of --verbose and --curl.
+    ///
+    /// By default the values of the Authorization, Proxy-Authorization, Cookie
+    /// and Set-Cookie headers and of those given to --redact are masked, and
+    /// so are passwords and tokens in --curl, so the output can be shared safely.
+    /// {n}{n}{n}
+    #[structopt(long)]
+    pub show_secrets: bool,
+
+    /// Mask the value of HEADER in output, like credentials. Can be repeated.
+    #[structopt(long, value_name = "HEADER", number_of_values = 1)]
+    pub redact: Vec<HeaderName>,
+
     /// Authenticate with a bearer token.
     #[structopt(long, value_name = "TOKEN")]
     pub bearer: Option<String>,
@@ -129,6 +515,104 @@ pub struct Cli {
     #[structopt(long)]
     pub check_status: bool,
 
+    /// Exit with other codes for some kinds of failure, like `4xx=10,timeout=28`.
+    ///
+    /// The kinds are `3xx`, `4xx` and `5xx` for the statuses of --check-status,
+    /// `dns` for hosts that can't be looked up, `connect` for connections that
+    /// fail otherwise, `tls` for failed TLS handshakes, `timeout` for --timeout
+    /// and --deadline, `too-many-redirects` for --max-redirects,
+    /// `body-too-large` for --max-body-size and `io` for failing to read or
+    /// write a file or the connection. Other failures exit with 1, like `dns`,
+    /// `connect` and `io` do unless they're mapped.
+    ///
+    /// Defaults can be set with "exit_codes" in the config file, which this
+    /// takes precedence over kind by kind.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "MAP")]
+    pub exit_code_map: Option<ExitCodeMap>,
+
+    /// Print a line about each response, like `--template '{status} {time_total}\n'`.
+    ///
+    /// It's printed to stdout after the response, even with --quiet. The
+    /// variables are `status`, `reason`, `http_version`, `method`, `url` (after
+    /// redirects), `remote_address`, `time_namelookup` (the seconds the DNS
+    /// lookup took), `time_starttransfer` (the seconds until the response
+    /// headers arrived), `time_total`, `size_download` (the bytes
+    /// of the body that were printed or downloaded), and `header.NAME` for a
+    /// response header. Write `{{` and `}}` for literal braces. `\n` and `\t`
+    /// stand for a newline and a tab.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "FORMAT")]
+    pub template: Option<Template>,
+
+    /// Log everything that's sent and received to FILE, or to stderr.
+    ///
+    /// Like curl's --trace, each header block and chunk of body gets a line with
+    /// a timestamp (UTC), its direction and its size. Binary data is shown in hex.
+    /// The bytes are the ones xh exchanges with its HTTP library, so TLS, HTTP/2
+    /// framing and compression aren't visible, and neither are the requests
+    /// of --follow. Credentials are masked unless --show-secrets is used. Request
+    /// bodies that are streamed, like files, are left out.
+    ///
+    /// Pass `--trace=-` for stdout. The `=` is required.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "FILE", min_values = 0, require_equals = true)]
+    pub trace: Option<Option<String>>,
+
+    /// Append a record of each request and its response to FILE.
+    ///
+    /// Every exchange gets a line of JSON with the time it was sent, the method,
+    /// URL, headers and body of the request, and the status, headers and body
+    /// size of the response. Credentials are masked unless --show-secrets is used.
+    /// The file is only ever appended to. A default can be set with "audit_log"
+    /// in the config file.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub audit_log: Option<PathBuf>,
+
+    /// Start a distributed trace, by sending a W3C `traceparent` header.
+    ///
+    /// The trace ID is printed to stderr, to find the request in a tracing
+    /// backend. MODE is `sampled`, the default, or `not-sampled`, which asks
+    /// services not to record the trace. The `=` is required.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "MODE", min_values = 0, require_equals = true)]
+    pub traceparent: Option<Option<Sampled>>,
+
+    /// Send this `tracestate` header along with --traceparent, like `vendor=value`.
+    #[structopt(long, value_name = "LIST")]
+    pub tracestate: Option<TraceState>,
+
+    /// Send a span for the request to an OpenTelemetry collector.
+    ///
+    /// URL is the base URL of its OTLP/HTTP receiver, like `http://localhost:4318`.
+    /// The span has the timings of --meta as events. Its IDs are sent in a
+    /// `traceparent` header, as with --traceparent, so the services that handle
+    /// the request add their spans to the same trace. This needs xh to be built
+    /// with the `otel` feature.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "URL")]
+    pub otel_endpoint: Option<Url>,
+
+    /// Follow the response to the pages after it, and print each of them.
+    ///
+    /// The next page is found in a `Link: <url>; rel="next"` header, like GitHub
+    /// sends. APIs that put it in the JSON body take the path to it instead:
+    /// `--paginate=links.next` for a URL, or `--paginate=meta.cursor:cursor`
+    /// for a cursor to send as the `cursor` query parameter. Numbers in the path
+    /// index arrays.
+    ///
+    /// Pages are requested until there's no next one, a request fails, or
+    /// --max-pages is reached. Credentials are only sent along to the same
+    /// origin. The `=` is required.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "PATH", min_values = 0, require_equals = true)]
+    pub paginate: Option<Option<Paginate>>,
+
+    /// Stop --paginate after this many pages, counting the first.
+    #[structopt(long, value_name = "NUM")]
+    pub max_pages: Option<usize>,
+
     /// Do follow redirects.
     #[structopt(short = "F", long)]
     pub follow: bool,
@@ -144,6 +628,59 @@ pub struct Cli {
     #[structopt(long, value_name = "SEC")]
     pub timeout: Option<Timeout>,
 
+    /// Give up once this much time has passed, e.g. `--deadline 30s` or `--deadline 2m`.
+    ///
+    /// Unlike --timeout this covers the whole run: redirects, retries, failing over
+    /// to mirrors and downloading the body. Each request's timeout is cut short to
+    /// what's left of it. xh then exits with status 2, and removes a download that
+    /// isn't finished unless it can be resumed.
+    ///
+    /// A plain number is a number of seconds.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "TIME")]
+    pub deadline: Option<Deadline>,
+
+    /// Send the request again when the server asks for it with Retry-After.
+    ///
+    /// A 429 (Too Many Requests) or 503 (Service Unavailable) response with a
+    /// Retry-After header is retried after the time it gives, up to three
+    /// times. The wait is capped by --retry-max-wait.
+    /// {n}{n}{n}
+    #[structopt(long)]
+    pub respect_retry_after: bool,
+
+    /// The longest --respect-retry-after waits, e.g. `10s` or `5m`. The default is `1m`.
+    #[structopt(long, value_name = "TIME")]
+    pub retry_max_wait: Option<Deadline>,
+
+    /// Keep responses in a local HTTP cache, and use them while they're fresh.
+    ///
+    /// Responses to GET requests are stored in DIR, or in the user's cache
+    /// directory (like ~/.cache/xh), when their headers allow it, like a browser's
+    /// private cache would (RFC 9111). A stored response is used without sending
+    /// the request until it goes stale, and revalidated with If-None-Match or
+    /// If-Modified-Since after that. Other methods remove the stored response for
+    /// their URL. Requests with an Authorization or Cookie header only store and
+    /// use responses marked `Cache-Control: public`.
+    ///
+    /// A line on stderr says when a response came from the cache. Send
+    /// `Cache-Control:no-cache` to revalidate anyway. The `=` is required.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "DIR", min_values = 0, require_equals = true)]
+    pub cache: Option<Option<String>>,
+
+    /// Abort the transfer once the response body is larger than SIZE.
+    ///
+    /// This guards scripts against endpoints that send far more than expected.
+    /// The transfer stops as soon as the limit is passed, or before it starts if
+    /// the Content-Length is already too large, and xh exits with status 7.
+    /// An unfinished download is removed unless it can be resumed.
+    ///
+    /// SIZE can have a K, M or G suffix, like `100M`.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "SIZE")]
+    pub max_body_size: Option<ByteSize>,
+
     /// Use a proxy for a protocol. For example: `--proxy https:http://proxy.host:8080`.
     ///
     /// PROTOCOL can be `http`, `https` or `all`.
@@ -153,12 +690,37 @@ pub struct Cli {
     ///
     /// You can specify proxies for multiple protocols by repeating this option.
     ///
-    /// The environment variables `http_proxy` and `https_proxy` can also be used, but
-    /// are completely ignored if --proxy is passed.
+    /// The environment variables `http_proxy`, `https_proxy` and `all_proxy` can
+    /// also be used, but are completely ignored if --proxy is passed. Hosts in
+    /// `no_proxy` are reached directly: a domain also matches its subdomains,
+    /// and IP addresses, CIDR ranges like `10.0.0.0/8` and `*` work too.
+    /// --verbose shows which proxy was used and why.
     /// {n}{n}{n}
     #[structopt(long, value_name = "PROTOCOL:URL", number_of_values = 1)]
     pub proxy: Vec<Proxy>,
 
+    /// Authenticate to the proxy as USER with PASS. PASS will be prompted if missing.
+    ///
+    /// This applies to every proxy given with --proxy, and takes precedence
+    /// over credentials in the proxy URL. They're sent as Basic auth, both on
+    /// plain HTTP requests and when tunneling HTTPS through CONNECT. If the
+    /// proxy answers a plain HTTP request with a Digest challenge the request
+    /// is sent again with Digest auth.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "USER[:PASS]")]
+    pub proxy_auth: Option<String>,
+
+    /// Use the proxy settings of macOS or Windows.
+    ///
+    /// They're only used if --proxy isn't passed and no proxy is set in the
+    /// environment. Only the HTTP and HTTPS proxies are used: the exceptions in
+    /// the settings aren't applied, though no_proxy is, and neither SOCKS nor
+    /// proxy auto-configuration (PAC) is supported. --verbose can't tell which
+    /// proxy was picked.
+    /// {n}{n}{n}
+    #[structopt(long)]
+    pub system_proxy: bool,
+
     /// If "no", skip SSL verification. If a file path, use it as a CA bundle.
     ///
     /// Specifying a CA bundle will disable the system's built-in root certificates.
@@ -169,6 +731,13 @@ pub struct Cli {
     pub verify: Option<Verify>,
 
     /// Use a client side certificate for SSL.
+    ///
+    /// This can be a PEM file, a PKCS#12 bundle (.p12 or .pfx), or a PKCS#11
+    /// URI for a key on a smartcard or other token, e.g.
+    /// "pkcs11:token=YubiKey;id=%01". The module is loaded from the URI's
+    /// module-path attribute, or else p11-kit-proxy, except on Windows where
+    /// module-path is required. Its PIN is read like --cert-key-pass.
+    /// {n}{n}{n}
     #[structopt(long, value_name = "FILE", parse(from_os_str))]
     pub cert: Option<PathBuf>,
 
@@ -179,6 +748,18 @@ pub struct Cli {
     #[structopt(long, value_name = "FILE", parse(from_os_str))]
     pub cert_key: Option<PathBuf>,
 
+    /// The passphrase for an encrypted --cert-key or PKCS#12 --cert.
+    ///
+    /// Keys have to be in PKCS#8 format ("ENCRYPTED PRIVATE KEY"), and bundles
+    /// have to use AES rather than the legacy RC2 or 3DES encryption. Other
+    /// files are rejected with the openssl command that converts them.
+    ///
+    /// If this isn't given, the XH_CERT_KEY_PASS environment variable is used,
+    /// and if that isn't set either you'll be prompted for the passphrase.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "PASS")]
+    pub cert_key_pass: Option<String>,
+
     /// The default scheme to use if not specified in the URL.
     #[structopt(long, value_name = "SCHEME", hidden = true)]
     pub default_scheme: Option<String>,
@@ -187,10 +768,123 @@ pub struct Cli {
     #[structopt(long)]
     pub https: bool,
 
+    /// Check the method and URL before sending, and fail on likely mistakes.
+    ///
+    /// This catches a method that looks like a typo (`GTE`), a scheme other
+    /// than http and https, a scheme with its colon or slashes missing, a
+    /// request item in the place of the URL, and a URL without a host.
+    /// {n}{n}{n}
+    #[structopt(long)]
+    pub strict_url: bool,
+
     /// Do not attempt to read stdin.
+    ///
+    /// Stdin is only sent as the body if there are no request items, or if one
+    /// of them is `@-`.
+    /// {n}{n}{n}
     #[structopt(short = "I", long)]
     pub ignore_stdin: bool,
 
+    /// Stop waiting for stdin if nothing arrives within this many seconds.
+    ///
+    /// The request is then sent without a body, or fails if the body was
+    /// supposed to come from `@-`. By default xh waits for as long as it takes.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "SEC")]
+    pub stdin_timeout: Option<Timeout>,
+
+    /// Read requests from a file instead, one JSON object per line ("-" for stdin).
+    ///
+    /// Each line looks like `{"method": "post", "url": "example.org", "items": ["a=b"]}`,
+    /// where only "url" is required and "items" are request items as written on the
+    /// command line. Options like --form, --auth and --timeout apply to every request.
+    ///
+    /// Response bodies are discarded. Instead a line of JSON is printed for each
+    /// request as it finishes, with its status, size and time taken, or the
+    /// "error" and its "error_kind" (like the "kind" of --json-output).
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub input: Option<PathBuf>,
+
+    /// How many requests from --input to send at the same time.
+    #[structopt(long, value_name = "NUM")]
+    pub parallel: Option<usize>,
+
+    /// Send the requests from --input NUM times, and print latency statistics.
+    ///
+    /// After the last request the percentiles of the time taken (p50, p90 and
+    /// p99), a histogram of the times and the numbers of each status code and
+    /// kind of error are printed to stderr.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "NUM")]
+    pub repeat: Option<usize>,
+
+    /// Write the statistics of --repeat to FILE in Prometheus' text format.
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub metrics_file: Option<PathBuf>,
+
+    /// Send the same request to every URL in a file, one per line ("-" for stdin).
+    ///
+    /// A line can start with a method to use for that URL, e.g. `DELETE example.org/a`.
+    /// Empty lines and lines starting with `#` are skipped. Options and request items
+    /// from the command line apply to every URL, which is then given without one:
+    /// `grep -o 'https://[^ ]*' log | xh --urls-from - x-trace:1`.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub urls_from: Option<PathBuf>,
+
+    /// Send the request described in a YAML or JSON file.
+    ///
+    /// The file can have a "method", "url", "headers", "query", "body", "auth",
+    /// "auth_type" and "expect" with the expected "status", which makes any other
+    /// status a failure. It's JSON if its name ends in .json. The body is sent as
+    /// JSON, or as a form with --form if it's an object of strings.
+    ///
+    /// The command line overrides the file: a method, a URL with its scheme, or
+    /// --auth replace the file's, and request items replace the headers, query
+    /// parameters and body fields with the same name. Placeholders are filled in
+    /// from --var and --var-file.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub request_file: Option<PathBuf>,
+
+    /// Send the request called NAME in requests.toml. Can be repeated.
+    ///
+    /// requests.toml is read from the current directory. Each [NAME] table in it
+    /// has a "url" and optionally a "method", a list of request "items" and the
+    /// expected "status", which makes any other status a failure. Placeholders are
+    /// filled in from --var and --var-file. Options from the command line apply to
+    /// every request.
+    /// {n}{n}{n}
+    #[structopt(long, value_name = "NAME", number_of_values = 1, min_values = 1)]
+    pub run: Option<Vec<String>>,
+
+    /// Send every request in requests.toml, like --run with all of their names.
+    #[structopt(long)]
+    pub run_all: bool,
+
+    /// Run a server that prints the requests it receives, instead of sending one.
+    ///
+    /// The server listens on localhost and handles one connection at a time. It
+    /// prints every request like --verbose would, and replies to each one with
+    /// --reply-status and --reply-body. --headers and --body choose what gets
+    /// printed.
+    /// {n}{n}{n}
+    #[structopt(long)]
+    pub serve: bool,
+
+    /// The port for --serve to listen on, 8080 by default.
+    #[structopt(long, value_name = "PORT")]
+    pub port: Option<u16>,
+
+    /// The status --serve replies with, 200 by default.
+    #[structopt(long, value_name = "CODE")]
+    pub reply_status: Option<StatusCode>,
+
+    /// The body --serve replies with, sent as JSON if it's valid JSON.
+    #[structopt(long, value_name = "TEXT")]
+    pub reply_body: Option<String>,
+
     /// Print a translation to a `curl` command.
     ///
     /// For translating the other way, try https://curl2httpie.online/.
@@ -208,21 +902,26 @@ pub struct Cli {
     /// If omitted, either a GET or a POST will be done depending on whether the
     /// request sends data.
     /// {n}{n}{n}
-    #[structopt(value_name = "[METHOD] URL")]
-    raw_method_or_url: String,
+    #[structopt(value_name = "[METHOD] URL", required_unless_one = &["input", "urls-from", "request-file", "run", "run-all", "serve"])]
+    raw_method_or_url: Option<String>,
 
     /// Optional key-value pairs to be included in the request
     ///
     ///   - key==value to add a parameter to the URL
     ///   - key=value to add a JSON field (--json) or form field (--form)
     ///   - key:=value to add a complex JSON value (e.g. `numbers:=[1,2,3]`)
-    ///   - key@filename to upload a file from filename (with --form)
+    ///   - key@filename to upload a file from filename (with --form),
+    ///     or key@dir/*.jpg to upload every matching file
     ///   - @filename to use a file as the request body
     ///   - header:value to add a header
     ///   - header: to unset a header
     ///   - header; to add a header with an empty value
+    ///   - scheme://url to send the same request to another URL
     ///
     /// A backslash can be used to escape special characters (e.g. weird\:key=value).
+    ///
+    /// With more than one URL the requests are sent one after the other, and the
+    /// exit status is the highest of theirs. Standard input isn't read then.
     #[structopt(value_name = "REQUEST_ITEM", verbatim_doc_comment)]
     raw_rest_args: Vec<String>,
 
@@ -234,6 +933,14 @@ pub struct Cli {
     #[structopt(skip)]
     pub url: String,
 
+    /// More URLs to send the same request to.
+    #[structopt(skip)]
+    pub more_urls: Vec<String>,
+
+    /// The status the response should have, from a request in requests.toml.
+    #[structopt(skip)]
+    pub expect_status: Option<u16>,
+
     /// Optional key-value pairs to be included in the request.
     #[structopt(skip)]
     pub request_items: Vec<RequestItem>,
@@ -249,38 +956,109 @@ pub struct Cli {
 /// (https://crates.io/crates/cargo-expand, https://crates.io/crates/ripgrep)
 /// But this is fragile, please apply human judgment.
 const NEGATION_FLAGS: &[&str] = &[
+    "--no-audit-log",
     "--no-auth",
+    "--no-auth-challenge",
     "--no-auth-type",
     "--no-bearer",
     "--no-body",
+    "--no-cache",
     "--no-cert",
     "--no-cert-key",
+    "--no-cert-key-pass",
     "--no-check-status",
+    "--no-color",
+    "--no-column-width",
     "--no-continue",
     "--no-curl",
     "--no-curl-long",
+    "--no-deadline",
+    "--no-decompress",
     "--no-default-scheme",
     "--no-download",
+    "--no-encode-set",
+    "--no-exit-code-map",
+    "--no-fields-from",
     "--no-follow",
+    "--no-force-binary",
+    "--no-force-text",
     "--no-form",
+    "--no-glob-brackets",
+    "--no-format-max-size",
+    "--no-format-options",
     "--no-headers",
+    "--no-highlight-max-size",
     "--no-https",
+    "--no-ignore-last-modified",
     "--no-ignore-netrc",
     "--no-ignore-stdin",
+    "--no-input",
     "--no-json",
+    "--no-json-output",
+    "--no-json-body",
+    "--no-json-lines",
+    "--no-line-numbers",
+    "--no-max-body-size",
+    "--no-max-lines",
+    "--no-max-pages",
     "--no-max-redirects",
+    "--no-meta",
+    "--no-metrics-file",
+    "--no-mirror",
     "--no-multipart",
+    "--no-no-clobber",
+    "--no-number-format",
     "--no-offline",
+    "--no-otel-endpoint",
     "--no-output",
+    "--no-output-headers",
+    "--no-overwrite",
+    "--no-pager",
+    "--no-paginate",
+    "--no-parallel",
+    "--no-part-header",
+    "--no-password-stdin",
+    "--no-peek",
+    "--no-port",
     "--no-pretty",
     "--no-print",
+    "--no-progress-interval",
     "--no-proxy",
+    "--no-proxy-auth",
     "--no-quiet",
+    "--no-range",
+    "--no-redact",
+    "--no-reject-dot-segments",
+    "--no-render-markdown",
+    "--no-repeat",
+    "--no-reply-body",
+    "--no-reply-status",
+    "--no-request-file",
+    "--no-respect-retry-after",
+    "--no-response-charset",
+    "--no-retry-max-wait",
+    "--no-run",
+    "--no-run-all",
+    "--no-serve",
+    "--no-show-secrets",
+    "--no-split",
+    "--no-stdin-timeout",
     "--no-stream",
+    "--no-strict-url",
     "--no-style",
+    "--no-system-proxy",
+    "--no-table",
+    "--no-template",
     "--no-timeout",
+    "--no-trace",
+    "--no-traceparent",
+    "--no-tracestate",
+    "--no-urls-from",
+    "--no-var",
+    "--no-var-file",
     "--no-verbose",
     "--no-verify",
+    "--no-wrap",
 ];
 
 impl Cli {
@@ -295,7 +1073,22 @@ impl Cli {
     {
         match Self::from_iter_safe(iter) {
             Ok(cli) => cli,
-            Err(err) if err.kind == ErrorKind::HelpDisplayed => {
+            Err(err) if help_tag(&err) == Some(PRINT_COMPLETIONS) => {
+                print!("{}", err.message);
+                safe_exit();
+            }
+            Err(err) if help_tag(&err) == Some(GENERATE_COMPLETIONS) => {
+                let info = err.info.unwrap_or_default();
+                for &shell in &clap::Shell::variants() {
+                    // Elvish complains about multiple deprecations and these don't seem to work
+                    // If you must use them, generate them manually with xh print_completions elvish
+                    if shell != "elvish" {
+                        Cli::clap().gen_completions(&info[1], shell.parse().unwrap(), &info[2]);
+                    }
+                }
+                safe_exit();
+            }
+            Err(err) if err.kind == ErrorKind::HelpDisplayed => {
                 // The logic here is a little tricky.
                 //
                 // Normally with structopt/clap, -h prints short help while --help
@@ -349,7 +1142,8 @@ impl Cli {
         let matches = app.get_matches_from_safe_borrow(iter)?;
         let mut cli = Self::from_clap(&matches);
 
-        match cli.raw_method_or_url.as_str() {
+        let raw_method_or_url = cli.raw_method_or_url.take().unwrap_or_default();
+        match raw_method_or_url.as_str() {
             "help" => {
                 return Err(Error {
                     message: "XH_PRINT_LONG_HELP".to_string(),
@@ -361,21 +1155,80 @@ impl Cli {
             "generate_completions" => return Err(generate_completions(app, cli.raw_rest_args)),
             _ => {}
         }
-        let mut rest_args = mem::take(&mut cli.raw_rest_args).into_iter();
-        match parse_method(&cli.raw_method_or_url) {
-            Some(method) => {
-                cli.method = Some(method);
-                cli.url = rest_args.next().ok_or_else(|| {
-                    Error::with_description("Missing URL", ErrorKind::MissingArgumentOrSubcommand)
-                })?;
+        let mut rest_args = mem::take(&mut cli.raw_rest_args).into_iter().peekable();
+        if cli.serve || cli.run.is_some() || cli.run_all {
+            if !raw_method_or_url.is_empty() {
+                let flag = if cli.serve { "--serve" } else { "--run" };
+                return Err(Error::with_description(
+                    &format!("{} can't be combined with a URL or request items", flag),
+                    ErrorKind::ArgumentConflict,
+                ));
+            }
+        } else if cli.input.is_some() {
+            if !raw_method_or_url.is_empty() {
+                return Err(Error::with_description(
+                    "--input can't be combined with a URL",
+                    ErrorKind::ArgumentConflict,
+                ));
+            }
+        } else if cli.urls_from.is_some() {
+            // The URLs are in the file, so this is a method or a request item
+            match parse_method(&raw_method_or_url) {
+                Some(method) => cli.method = Some(method),
+                None if raw_method_or_url.is_empty() => {}
+                None => {
+                    let request_item = raw_method_or_url;
+                    if regex!("^[a-zA-Z][a-zA-Z0-9+.-]*://").is_match(&request_item) {
+                        cli.url = request_item;
+                    } else {
+                        cli.request_items
+                            .push(parse_request_item(&request_item, "")?);
+                    }
+                }
+            }
+        } else if cli.request_file.is_some() {
+            // The file has a method and URL, so the command line may have
+            // either, both or neither
+            let is_url = |arg: &str| regex!("^[a-zA-Z][a-zA-Z0-9+.-]*://").is_match(arg);
+            match parse_method(&raw_method_or_url) {
+                Some(method) => {
+                    cli.method = Some(method);
+                    if rest_args.peek().map_or(false, |arg| is_url(arg)) {
+                        cli.url = rest_args.next().unwrap();
+                    }
+                }
+                None if raw_method_or_url.is_empty() => {}
+                None if is_url(&raw_method_or_url) => cli.url = raw_method_or_url,
+                None => {
+                    cli.request_items
+                        .push(parse_request_item(&raw_method_or_url, "")?);
+                }
             }
-            None => {
-                cli.method = None;
-                cli.url = mem::take(&mut cli.raw_method_or_url);
+        } else {
+            match parse_method(&raw_method_or_url) {
+                Some(method) => {
+                    cli.method = Some(method);
+                    cli.url = rest_args.next().ok_or_else(|| {
+                        Error::with_description(
+                            "Missing URL",
+                            ErrorKind::MissingArgumentOrSubcommand,
+                        )
+                    })?;
+                }
+                None => {
+                    cli.method = None;
+                    cli.url = raw_method_or_url;
+                }
             }
         }
         for request_item in rest_args {
-            cli.request_items.push(request_item.parse()?);
+            if cli.input.is_none() && regex!("^[a-zA-Z][a-zA-Z0-9+.-]*://").is_match(&request_item)
+            {
+                cli.more_urls.push(request_item);
+            } else {
+                cli.request_items
+                    .push(parse_request_item(&request_item, &cli.url)?);
+            }
         }
 
         if matches!(
@@ -386,11 +1239,171 @@ impl Cli {
         }
 
         cli.process_relations()?;
+        if cli.strict_url && !cli.url.is_empty() {
+            cli.check_url()?;
+        }
         Ok(cli)
     }
 
+    /// Look for likely mistakes in the method and URL, for --strict-url.
+    fn check_url(&self) -> clap::Result<()> {
+        if let Some(method) = &self.method {
+            if let Some(suggestion) = similar_method(method.as_str()) {
+                return Err(Error::with_description(
+                    &format!("Unknown method {}, did you mean {}?", method, suggestion),
+                    ErrorKind::InvalidValue,
+                ));
+            }
+        }
+
+        let url = &self.url;
+        let invalid =
+            |message: String| Err(Error::with_description(&message, ErrorKind::InvalidValue));
+        if let Some(captures) = regex!("^([a-zA-Z][a-zA-Z0-9+.-]*)://").captures(url) {
+            let scheme = captures[1].to_ascii_lowercase();
+            let rest = &url[captures[0].len()..];
+            if rest.is_empty() || rest.starts_with('/') {
+                return invalid(format!("The URL {} has no host", url));
+            }
+            if scheme != "http" && scheme != "https" {
+                return match ["http", "https"]
+                    .iter()
+                    .find(|known| strsim::osa_distance(&scheme, known) == 1)
+                {
+                    Some(known) => invalid(format!(
+                        "Unsupported scheme in {}, did you mean {}://{}?",
+                        url, known, rest
+                    )),
+                    None => invalid(format!(
+                        "Unsupported scheme in {}, only http and https work",
+                        url
+                    )),
+                };
+            }
+        } else if let Some(captures) = regex!("^(?i)(https?)(?::/?|//?)([^/:0-9].*)$").captures(url)
+        {
+            return invalid(format!(
+                "The scheme of {} is missing its colon or slashes, did you mean {}://{}?",
+                url, &captures[1], &captures[2]
+            ));
+        } else if looks_like_request_item(url) {
+            return invalid(format!(
+                "{:?} looks like a request item, but it's where the URL goes. \
+                 The URL comes before the request items.",
+                url
+            ));
+        }
+
+        match crate::url::construct_url(
+            url,
+            self.default_scheme.as_deref(),
+            &[],
+            &[],
+            EncodeSet::strict,
+        ) {
+            Ok(parsed) if parsed.host_str().map_or(true, str::is_empty) => {
+                invalid(format!("The URL {} has no host", url))
+            }
+            Ok(_) => Ok(()),
+            Err(err) => invalid(format!("Invalid URL {}: {}", url, err)),
+        }
+    }
+
     /// Set flags that are implied by other flags and report conflicting flags.
     fn process_relations(&mut self) -> clap::Result<()> {
+        for (present, flag) in &[
+            (self.port.is_some(), "--port"),
+            (self.reply_status.is_some(), "--reply-status"),
+            (self.reply_body.is_some(), "--reply-body"),
+        ] {
+            if *present && !self.serve {
+                return Err(Error::with_description(
+                    &format!("{} only works with --serve", flag),
+                    ErrorKind::MissingArgumentOrSubcommand,
+                ));
+            }
+        }
+        if self.run_all {
+            if self.run.is_some() {
+                return Err(Error::with_description(
+                    "--run-all can't be combined with --run",
+                    ErrorKind::ArgumentConflict,
+                ));
+            }
+            self.run = Some(Vec::new());
+        }
+        if self.serve && self.run.is_some() {
+            return Err(Error::with_description(
+                "--serve can't be combined with --run",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if self.run.is_some() && (self.input.is_some() || self.urls_from.is_some()) {
+            return Err(Error::with_description(
+                "--run can't be combined with --input or --urls-from",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if self.template.is_some() && self.input.is_some() {
+            return Err(Error::with_description(
+                "--template can't be combined with --input, which prints JSON instead",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if self.paginate.is_some() && (self.download || self.input.is_some()) {
+            return Err(Error::with_description(
+                "--paginate can't be combined with --download or --input",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if self.tracestate.is_some() && self.traceparent.is_none() {
+            return Err(Error::with_description(
+                "--tracestate only works with --traceparent",
+                ErrorKind::MissingArgumentOrSubcommand,
+            ));
+        }
+        if self.retry_max_wait.is_some() && !self.respect_retry_after {
+            return Err(Error::with_description(
+                "--retry-max-wait only works with --respect-retry-after",
+                ErrorKind::MissingArgumentOrSubcommand,
+            ));
+        }
+        if self.max_pages.is_some() && self.paginate.is_none() {
+            return Err(Error::with_description(
+                "--max-pages only works with --paginate",
+                ErrorKind::MissingArgumentOrSubcommand,
+            ));
+        }
+        if self.request_file.is_some() && (self.input.is_some() || self.run.is_some()) {
+            return Err(Error::with_description(
+                "--request-file can't be combined with --input or --run",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if self.cache.is_some() && (self.download || self.input.is_some()) {
+            return Err(Error::with_description(
+                "--cache can't be combined with --download or --input",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if self.urls_from.is_some() && self.input.is_some() {
+            return Err(Error::with_description(
+                "--urls-from can't be combined with --input",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if self.urls_from.is_some() && self.output.is_some() {
+            return Err(Error::with_description(
+                "--output can't be combined with --urls-from",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if !self.more_urls.is_empty() && self.output.is_some() {
+            return Err(Error::with_description(
+                "--output can't be combined with more than one URL",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
         if self.resume && !self.download {
             return Err(Error::with_description(
                 "--continue only works with --download",
@@ -403,18 +1416,228 @@ impl Cli {
                 ErrorKind::MissingArgumentOrSubcommand,
             ));
         }
+        if self.resume && self.range.is_some() {
+            return Err(Error::with_description(
+                "--continue can't be combined with --range",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if !self.mirror.is_empty() {
+            if !self.download {
+                return Err(Error::with_description(
+                    "--mirror only works with --download",
+                    ErrorKind::MissingArgumentOrSubcommand,
+                ));
+            }
+            if self.output.is_none() {
+                return Err(Error::with_description(
+                    "--mirror requires --output",
+                    ErrorKind::MissingArgumentOrSubcommand,
+                ));
+            }
+            let conflict = if self.range.is_some() {
+                Some("--range")
+            } else if self.split.is_some() {
+                Some("--split")
+            } else if self.decompress {
+                Some("--decompress")
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(Error::with_description(
+                    &format!("--mirror can't be combined with {}", conflict),
+                    ErrorKind::ArgumentConflict,
+                ));
+            }
+        }
+        if self.password_stdin {
+            match &self.auth {
+                None => {
+                    return Err(Error::with_description(
+                        "--password-stdin requires --auth",
+                        ErrorKind::MissingArgumentOrSubcommand,
+                    ));
+                }
+                Some(auth) if auth.contains(':') => {
+                    return Err(Error::with_description(
+                        "--password-stdin can't be combined with a password in --auth",
+                        ErrorKind::ArgumentConflict,
+                    ));
+                }
+                Some(_) => {}
+            }
+            if self.input.as_deref() == Some(Path::new("-")) {
+                return Err(Error::with_description(
+                    "--password-stdin can't be combined with --input=-",
+                    ErrorKind::ArgumentConflict,
+                ));
+            }
+            if self.urls_from.as_deref() == Some(Path::new("-")) {
+                return Err(Error::with_description(
+                    "--password-stdin can't be combined with --urls-from=-",
+                    ErrorKind::ArgumentConflict,
+                ));
+            }
+        }
+        if self.no_clobber && self.resume {
+            return Err(Error::with_description(
+                "--no-clobber can't be combined with --continue",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        for (present, flag) in &[
+            (self.no_clobber, "--no-clobber"),
+            (self.overwrite, "--overwrite"),
+            (self.number_format.is_some(), "--number-format"),
+            (self.peek, "--peek"),
+        ] {
+            if *present && !self.download {
+                return Err(Error::with_description(
+                    &format!("{} only works with --download", flag),
+                    ErrorKind::MissingArgumentOrSubcommand,
+                ));
+            }
+        }
+        if self.peek && (self.output.is_some() || self.resume || self.split.is_some()) {
+            return Err(Error::with_description(
+                "--peek can't be combined with --output, --continue or --split",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if self.decompress && !self.download {
+            return Err(Error::with_description(
+                "--decompress only works with --download",
+                ErrorKind::MissingArgumentOrSubcommand,
+            ));
+        }
+        if self.decompress && self.resume {
+            return Err(Error::with_description(
+                "--decompress can't be combined with --continue",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if self.ignore_last_modified && !self.download {
+            return Err(Error::with_description(
+                "--ignore-last-modified only works with --download",
+                ErrorKind::MissingArgumentOrSubcommand,
+            ));
+        }
+        if self.split.is_some() && !self.download {
+            return Err(Error::with_description(
+                "--split only works with --download",
+                ErrorKind::MissingArgumentOrSubcommand,
+            ));
+        }
+        if self.split.is_some() && self.resume {
+            return Err(Error::with_description(
+                "--split can't be combined with --continue",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if self.split == Some(0) {
+            return Err(Error::with_description(
+                "--split must be at least 1",
+                ErrorKind::InvalidValue,
+            ));
+        }
+        if self.parallel.is_some() && self.input.is_none() {
+            return Err(Error::with_description(
+                "--parallel only works with --input",
+                ErrorKind::MissingArgumentOrSubcommand,
+            ));
+        }
+        if self.proxy_auth.is_some() && self.proxy.is_empty() {
+            return Err(Error::with_description(
+                "--proxy-auth requires --proxy",
+                ErrorKind::MissingArgumentOrSubcommand,
+            ));
+        }
+        if self.auth_type == Some(AuthType::digest) && self.input.is_some() {
+            return Err(Error::with_description(
+                "--auth-type=digest can't be used with --input",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if self.parallel == Some(0) {
+            return Err(Error::with_description(
+                "--parallel must be at least 1",
+                ErrorKind::InvalidValue,
+            ));
+        }
+        if self.repeat.is_some() && self.input.is_none() {
+            return Err(Error::with_description(
+                "--repeat only works with --input",
+                ErrorKind::MissingArgumentOrSubcommand,
+            ));
+        }
+        if self.repeat == Some(0) {
+            return Err(Error::with_description(
+                "--repeat must be at least 1",
+                ErrorKind::InvalidValue,
+            ));
+        }
+        if self.metrics_file.is_some() && self.repeat.is_none() {
+            return Err(Error::with_description(
+                "--metrics-file requires --repeat",
+                ErrorKind::MissingArgumentOrSubcommand,
+            ));
+        }
+        if self.input.is_some()
+            && (self.download
+                || self.output_headers.is_some()
+                || self.offline
+                || self.curl
+                || self.curl_long)
+        {
+            return Err(Error::with_description(
+                "--input can't be combined with --download, --output-headers, --offline or --curl",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if let Some(json_body) = &self.json_body {
+            if self.form || self.multipart {
+                return Err(Error::with_description(
+                    "--json-body can't be combined with --form or --multipart",
+                    ErrorKind::ArgumentConflict,
+                ));
+            }
+            if !valid_json(json_body) {
+                return Err(Error::with_description(
+                    "--json-body must be valid JSON",
+                    ErrorKind::InvalidValue,
+                ));
+            }
+        }
         if self.download {
             self.follow = true;
         }
+        if self.otel_endpoint.is_some() {
+            if !cfg!(feature = "otel") {
+                return Err(Error::with_description(
+                    "--otel-endpoint needs xh to be built with the otel feature",
+                    ErrorKind::InvalidValue,
+                ));
+            }
+            // The span's IDs go out in the traceparent header
+            if self.traceparent.is_none() {
+                self.traceparent = Some(None);
+            }
+        }
         if self.curl_long {
             self.curl = true;
         }
         if self.https {
             self.default_scheme = Some("https".to_string());
         }
-        if self.auth_type == AuthType::bearer && self.auth.is_some() {
+        if self.auth_type == Some(AuthType::bearer) && self.auth.is_some() {
             self.bearer = self.auth.take();
         }
+        if self.auth_type == Some(AuthType::negotiate) {
+            // Credentials come from the ticket cache. curl and HTTPie's
+            // negotiate plugin want a dummy `--auth=:`, so accept that too.
+            self.auth = None;
+        }
         // `overrides_with_all` ensures that only one of these is true
         if self.json {
             // Also the default, so this shouldn't do anything
@@ -450,7 +1673,67 @@ impl Cli {
     }
 }
 
-fn parse_method(method: &str) -> Option<Method> {
+/// The methods that are likely to be mistyped.
+const COMMON_METHODS: &[&str] = &["GET", "POST", "PUT", "PATCH", "DELETE", "HEAD", "OPTIONS"];
+
+/// A common method that `method` is one typo away from.
+fn similar_method(method: &str) -> Option<&'static str> {
+    let method = method.to_ascii_uppercase();
+    if COMMON_METHODS.contains(&method.as_str()) {
+        return None;
+    }
+    COMMON_METHODS
+        .iter()
+        .copied()
+        .find(|known| strsim::osa_distance(&method, known) == 1)
+}
+
+fn looks_like_request_item(arg: &str) -> bool {
+    regex!("^[^/?#:]*(==|:=|=)").is_match(arg)
+}
+
+/// Parse a request item, and say so if the error looks like the method and
+/// URL were given in the wrong order.
+fn parse_request_item(item: &str, url: &str) -> clap::Result<RequestItem> {
+    item.parse().map_err(|err: Error| {
+        let hint = if COMMON_METHODS.contains(&item.to_ascii_uppercase().as_str()) {
+            if url.is_empty() {
+                "Did you mean to pass the method first?".to_string()
+            } else {
+                format!(
+                    "Did you mean to pass the method first, like `xh {} {}`?",
+                    item.to_ascii_uppercase(),
+                    url
+                )
+            }
+        } else {
+            // Say `xh GET: example.com`, with a method that isn't one because
+            // of a stray character, which makes the URL the method
+            let method = url.trim_matches(|ch: char| !ch.is_ascii_alphabetic());
+            if method != url && COMMON_METHODS.contains(&method.to_ascii_uppercase().as_str()) {
+                format!(
+                    "{:?} was taken as the URL, did you mean `xh {} {}`?",
+                    url,
+                    method.to_ascii_uppercase(),
+                    item
+                )
+            } else if looks_like_request_item(url) {
+                format!(
+                    "{:?} was taken as the URL, did you mean `xh {} {}`?",
+                    url, item, url
+                )
+            } else {
+                return err;
+            }
+        };
+        Error::with_description(
+            &format!("{:?} is not a valid request item. {}", item, hint),
+            ErrorKind::InvalidValue,
+        )
+    })
+}
+
+pub fn parse_method(method: &str) -> Option<Method> {
     // This unfortunately matches "localhost"
     if !method.is_empty() && method.chars().all(|c| c.is_ascii_alphabetic()) {
         // Method parsing seems to fail if the length is 0 or if there's a null byte
@@ -461,8 +1744,20 @@ fn parse_method(method: &str) -> Option<Method> {
     }
 }
 
-// This signature is a little weird: we either return an error or don't
-// return at all
+/// The tag of a HelpDisplayed error that asks from_iter() to do something
+/// other than print help.
+fn help_tag(err: &Error) -> Option<&str> {
+    match &err.info {
+        Some(info) if err.kind == ErrorKind::HelpDisplayed => info.first().map(String::as_str),
+        _ => None,
+    }
+}
+
+const PRINT_COMPLETIONS: &str = "XH_PRINT_COMPLETIONS";
+const GENERATE_COMPLETIONS: &str = "XH_GENERATE_COMPLETIONS";
+
+// Completions are passed back as a HelpDisplayed error, like help is, and
+// from_iter() prints or writes them. That way from_iter_safe() never exits.
 fn print_completions(mut app: clap::App, rest_args: Vec<String>) -> Error {
     let bin_name = match app.get_bin_name() {
         // This name is borrowed from `app`, and `gen_completions_to()` mutably
@@ -489,11 +1784,14 @@ fn print_completions(mut app: clap::App, rest_args: Vec<String>) -> Error {
         // See https://github.com/clap-rs/clap/pull/2359, currently unreleased
         completions = completions.replace(r#" -n "__fish_use_subcommand""#, "");
     }
-    print!("{}", completions);
-    safe_exit();
+    Error {
+        message: completions,
+        kind: ErrorKind::HelpDisplayed,
+        info: Some(vec![PRINT_COMPLETIONS.to_string()]),
+    }
 }
 
-fn generate_completions(mut app: clap::App, rest_args: Vec<String>) -> Error {
+fn generate_completions(app: clap::App, mut rest_args: Vec<String>) -> Error {
     let bin_name = match app.get_bin_name() {
         Some(name) => name.to_owned(),
         None => return Error::with_description("Missing binary name", ErrorKind::EmptyValue),
@@ -504,21 +1802,25 @@ fn generate_completions(mut app: clap::App, rest_args: Vec<String>) -> Error {
             ErrorKind::WrongNumberOfValues,
         );
     }
-    for &shell in &clap::Shell::variants() {
-        // Elvish complains about multiple deprecations and these don't seem to work
-        // If you must use them, generate them manually with xh print_completions elvish
-        if shell != "elvish" {
-            app.gen_completions(&bin_name, shell.parse().unwrap(), &rest_args[0]);
-        }
+    Error {
+        message: String::new(),
+        kind: ErrorKind::HelpDisplayed,
+        info: Some(vec![
+            GENERATE_COMPLETIONS.to_string(),
+            bin_name,
+            rest_args.remove(0),
+        ]),
     }
-    safe_exit();
 }
 
+// There's no OAuth2 type: xh has neither an OAuth2 helper nor sessions to
+// keep tokens in between runs, so device-code and authorization-code flows
+// have nowhere to live yet. Tokens obtained elsewhere work with `bearer`.
 arg_enum! {
     #[allow(non_camel_case_types)]
-    #[derive(Debug, PartialEq)]
+    #[derive(Debug, PartialEq, Clone, Copy)]
     pub enum AuthType {
-        basic, bearer
+        basic, bearer, digest, negotiate
     }
 }
 
@@ -536,16 +1838,43 @@ impl Pretty {
         matches!(self, Pretty::colors | Pretty::all)
     }
 
+    /// Turn colors on or off, keeping the formatting as it was.
+    pub fn with_color(self, color: ColorMode) -> Pretty {
+        match (color, self.format()) {
+            (ColorMode::auto, _) => self,
+            (ColorMode::always, true) => Pretty::all,
+            (ColorMode::always, false) => Pretty::colors,
+            (ColorMode::never, true) => Pretty::format,
+            (ColorMode::never, false) => Pretty::none,
+        }
+    }
+
     pub fn format(self) -> bool {
         matches!(self, Pretty::format | Pretty::all)
     }
 }
 
+arg_enum! {
+    #[allow(non_camel_case_types)]
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    pub enum ColorMode {
+        always, auto, never
+    }
+}
+
+arg_enum! {
+    #[allow(non_camel_case_types)]
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    pub enum EncodeSet {
+        strict, minimal, none
+    }
+}
+
 arg_enum! {
     #[allow(non_camel_case_types)]
     #[derive(Debug, PartialEq, Clone, Copy)]
     pub enum Theme {
-        auto, solarized
+        auto, solarized, light, contrast, colorblind
     }
 }
 
@@ -554,11 +1883,14 @@ impl Theme {
         match self {
             Theme::auto => "ansi",
             Theme::solarized => "solarized",
+            Theme::light => "light",
+            Theme::contrast => "contrast",
+            Theme::colorblind => "colorblind",
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Print {
     pub request_headers: bool,
     pub request_body: bool,
@@ -621,110 +1953,669 @@ impl Print {
     }
 }
 
-impl FromStr for Print {
+impl FromStr for Print {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Print> {
+        let mut request_headers = false;
+        let mut request_body = false;
+        let mut response_headers = false;
+        let mut response_body = false;
+
+        for char in s.chars() {
+            match char {
+                'H' => request_headers = true,
+                'B' => request_body = true,
+                'h' => response_headers = true,
+                'b' => response_body = true,
+                char => {
+                    return Err(Error::with_description(
+                        &format!("{:?} is not a valid value", char),
+                        ErrorKind::InvalidValue,
+                    ))
+                }
+            }
+        }
+
+        let p = Print {
+            request_headers,
+            request_body,
+            response_headers,
+            response_body,
+        };
+        Ok(p)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Timeout(Duration);
+
+impl Timeout {
+    pub fn as_duration(&self) -> Option<Duration> {
+        Some(self.0).filter(|t| t != &Duration::from_nanos(0))
+    }
+}
+
+impl FromStr for Timeout {
+    type Err = Error;
+
+    fn from_str(sec: &str) -> Result<Timeout> {
+        let pos_sec: f64 = match sec.parse::<f64>() {
+            Ok(sec) if sec.is_sign_positive() => sec,
+            _ => {
+                return Err(Error::with_description(
+                    "Invalid seconds as connection timeout",
+                    ErrorKind::InvalidValue,
+                ))
+            }
+        };
+
+        let dur = Duration::from_secs_f64(pos_sec);
+        Ok(Timeout(dur))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Deadline(pub Duration);
+
+impl FromStr for Deadline {
+    type Err = Error;
+
+    fn from_str(time: &str) -> Result<Deadline> {
+        let duration = match time.parse::<f64>() {
+            Ok(sec) if sec.is_finite() && sec.is_sign_positive() => {
+                Some(Duration::from_secs_f64(sec))
+            }
+            Ok(_) => None,
+            Err(_) => humantime::parse_duration(time).ok(),
+        };
+        match duration {
+            Some(duration) if duration > Duration::from_secs(0) => Ok(Deadline(duration)),
+            _ => Err(Error::with_description(
+                "Invalid time, expected e.g. `30s`, `2m` or `1h 30m`",
+                ErrorKind::InvalidValue,
+            )),
+        }
+    }
+}
+
+/// A kind of failure that can be given its own exit code.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Outcome {
+    Redirect,
+    ClientError,
+    ServerError,
+    Dns,
+    Connect,
+    Timeout,
+    Tls,
+    TooManyRedirects,
+    BodyTooLarge,
+    Io,
+}
+
+impl Outcome {
+    const NAMES: &'static [(&'static str, Outcome)] = &[
+        ("3xx", Outcome::Redirect),
+        ("4xx", Outcome::ClientError),
+        ("5xx", Outcome::ServerError),
+        ("dns", Outcome::Dns),
+        ("connect", Outcome::Connect),
+        ("timeout", Outcome::Timeout),
+        ("tls", Outcome::Tls),
+        ("too-many-redirects", Outcome::TooManyRedirects),
+        ("body-too-large", Outcome::BodyTooLarge),
+        ("io", Outcome::Io),
+    ];
+
+    /// The name --json-output gives this kind of failure. The statuses are all
+    /// `http-status`, since the status itself is given too.
+    pub fn kind(self) -> &'static str {
+        match self {
+            Outcome::Redirect | Outcome::ClientError | Outcome::ServerError => "http-status",
+            _ => {
+                let (name, _) = Outcome::NAMES
+                    .iter()
+                    .find(|&&(_, outcome)| outcome == self)
+                    .unwrap();
+                name
+            }
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, _) = Outcome::NAMES
+            .iter()
+            .find(|(_, outcome)| outcome == self)
+            .unwrap();
+        write!(f, "{}", name)
+    }
+}
+
+/// Exit codes for kinds of failure, from --exit-code-map or the config file.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct ExitCodeMap(pub HashMap<Outcome, i32>);
+
+impl ExitCodeMap {
+    /// The exit code for `outcome`, or `default` if it isn't mapped.
+    pub fn code(&self, outcome: Outcome, default: i32) -> i32 {
+        self.0.get(&outcome).copied().unwrap_or(default)
+    }
+
+    /// Map a kind of failure, given by name, to an exit code.
+    pub fn insert(&mut self, name: &str, code: i64) -> std::result::Result<(), String> {
+        let outcome = Outcome::NAMES
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(name.trim()))
+            .map(|&(_, outcome)| outcome)
+            .ok_or_else(|| {
+                let names: Vec<_> = Outcome::NAMES.iter().map(|(known, _)| *known).collect();
+                format!(
+                    "Unknown kind of failure {:?}, expected one of {}",
+                    name.trim(),
+                    names.join(", ")
+                )
+            })?;
+        if !(1..=255).contains(&code) {
+            return Err(format!(
+                "Invalid exit code {} for {}, expected 1 to 255",
+                code, outcome
+            ));
+        }
+        self.0.insert(outcome, code as i32);
+        Ok(())
+    }
+}
+
+impl FromStr for ExitCodeMap {
+    type Err = Error;
+
+    fn from_str(map: &str) -> Result<ExitCodeMap> {
+        let mut exit_codes = ExitCodeMap::default();
+        for entry in map.split(',').filter(|entry| !entry.trim().is_empty()) {
+            let code = entry
+                .find('=')
+                .and_then(|index| Some((index, entry[index + 1..].trim().parse().ok()?)));
+            let result = match code {
+                Some((index, code)) => exit_codes.insert(&entry[..index], code),
+                None => Err(format!("Invalid entry {:?}, expected KIND=CODE", entry)),
+            };
+            result.map_err(|message| Error::with_description(&message, ErrorKind::InvalidValue))?;
+        }
+        Ok(exit_codes)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Interval(pub Duration);
+
+impl FromStr for Interval {
+    type Err = Error;
+
+    fn from_str(sec: &str) -> Result<Interval> {
+        match sec.parse::<f64>() {
+            Ok(sec) if sec.is_finite() && sec.is_sign_positive() => {
+                Ok(Interval(Duration::from_secs_f64(sec)))
+            }
+            _ => Err(Error::with_description(
+                "Invalid number of seconds",
+                ErrorKind::InvalidValue,
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Proxy {
+    Http(Url),
+    Https(Url),
+    All(Url),
+}
+
+impl Proxy {
+    /// Find the proxy that a request to `url` goes through, if any.
+    ///
+    /// Later proxies take precedence, like they do when building the client.
+    pub fn for_url<'a>(proxies: &'a [Proxy], url: &Url) -> Option<&'a Url> {
+        proxies
+            .iter()
+            .rev()
+            .find_map(|proxy| match (proxy, url.scheme()) {
+                (Proxy::Http(proxy), "http") | (Proxy::Https(proxy), "https") => Some(proxy),
+                (Proxy::All(proxy), _) => Some(proxy),
+                _ => None,
+            })
+    }
+}
+
+impl FromStr for Proxy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let split_arg: Vec<&str> = s.splitn(2, ':').collect();
+        match split_arg[..] {
+            [protocol, url] => {
+                let url = reqwest::Url::try_from(url).map_err(|e| {
+                    Error::with_description(
+                        &format!(
+                            "Invalid proxy URL '{}' for protocol '{}': {}",
+                            url, protocol, e
+                        ),
+                        ErrorKind::InvalidValue,
+                    )
+                })?;
+
+                match protocol.to_lowercase().as_str() {
+                    "http" => Ok(Proxy::Http(url)),
+                    "https" => Ok(Proxy::Https(url)),
+                    "all" => Ok(Proxy::All(url)),
+                    _ => Err(Error::with_description(
+                        &format!("Unknown protocol to set a proxy for: {}", protocol),
+                        ErrorKind::InvalidValue,
+                    )),
+                }
+            }
+            _ => Err(Error::with_description(
+                "The value passed to --proxy should be formatted as <PROTOCOL>:<PROXY_URL>",
+                ErrorKind::InvalidValue,
+            )),
+        }
+    }
+}
+
+/// A header for a single part of a multipart body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartHeader {
+    pub field: String,
+    pub name: HeaderName,
+    pub value: HeaderValue,
+}
+
+impl FromStr for PartHeader {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<PartHeader> {
+        let parts: Vec<&str> = s.splitn(3, ':').collect();
+        match parts[..] {
+            [field, name, value] => {
+                let invalid = |err: &dyn fmt::Display| {
+                    Error::with_description(
+                        &format!("Invalid part header {:?}: {}", s, err),
+                        ErrorKind::InvalidValue,
+                    )
+                };
+                Ok(PartHeader {
+                    field: field.to_owned(),
+                    name: HeaderName::from_bytes(name.trim().as_bytes())
+                        .map_err(|err| invalid(&err))?,
+                    value: HeaderValue::from_str(value.trim()).map_err(|err| invalid(&err))?,
+                })
+            }
+            _ => Err(Error::with_description(
+                "The value passed to --part-header should be formatted as <FIELD>:<NAME>:<VALUE>",
+                ErrorKind::InvalidValue,
+            )),
+        }
+    }
+}
+
+/// A variable set with `--var`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Var {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for Var {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Var> {
+        let index = s.find('=').ok_or_else(|| {
+            Error::with_description(
+                "The value passed to --var should be formatted as <NAME>=<VALUE>",
+                ErrorKind::InvalidValue,
+            )
+        })?;
+        let name = &s[..index];
+        if !regex!(r"^[A-Za-z_][A-Za-z0-9_.-]*$").is_match(name) {
+            return Err(Error::with_description(
+                &format!("Invalid variable name {:?}", name),
+                ErrorKind::InvalidValue,
+            ));
+        }
+        Ok(Var {
+            name: name.to_owned(),
+            value: s[index + 1..].to_owned(),
+        })
+    }
+}
+
+/// A single `--format-options` setting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FormatOption {
+    HeadersSort(bool),
+}
+
+impl FormatOption {
+    /// Whether to sort headers by name, going by the last option that says so.
+    pub fn headers_sort(options: &[FormatOption]) -> Option<bool> {
+        options
+            .iter()
+            .rev()
+            .map(|option| match *option {
+                FormatOption::HeadersSort(sort) => sort,
+            })
+            .next()
+    }
+}
+
+impl FromStr for FormatOption {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<FormatOption> {
+        let parts: Vec<&str> = s.splitn(2, ':').collect();
+        match parts[..] {
+            ["headers.sort", value] => match value {
+                "true" => Ok(FormatOption::HeadersSort(true)),
+                "false" => Ok(FormatOption::HeadersSort(false)),
+                _ => Err(Error::with_description(
+                    &format!(
+                        "Invalid value for headers.sort: {:?}, expected true or false",
+                        value
+                    ),
+                    ErrorKind::InvalidValue,
+                )),
+            },
+            [name, _] => Err(Error::with_description(
+                &format!("Unknown format option {:?}, expected headers.sort", name),
+                ErrorKind::InvalidValue,
+            )),
+            _ => Err(Error::with_description(
+                "Format options should be formatted as <NAME>:<VALUE>",
+                ErrorKind::InvalidValue,
+            )),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Wrap {
+    On,
+    Off,
+    Width(usize),
+}
+
+impl FromStr for Wrap {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Wrap> {
+        match s.to_lowercase().as_str() {
+            "on" => Ok(Wrap::On),
+            "off" => Ok(Wrap::Off),
+            width => match width.parse() {
+                Ok(0) | Err(_) => Err(Error::with_description(
+                    "Wrap should be on, off or a positive number of columns",
+                    ErrorKind::InvalidValue,
+                )),
+                Ok(width) => Ok(Wrap::Width(width)),
+            },
+        }
+    }
+}
+
+/// Whether --traceparent marks the trace as sampled.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Sampled(pub bool);
+
+impl FromStr for Sampled {
     type Err = Error;
-    fn from_str(s: &str) -> Result<Print> {
-        let mut request_headers = false;
-        let mut request_body = false;
-        let mut response_headers = false;
-        let mut response_body = false;
 
-        for char in s.chars() {
-            match char {
-                'H' => request_headers = true,
-                'B' => request_body = true,
-                'h' => response_headers = true,
-                'b' => response_body = true,
-                char => {
-                    return Err(Error::with_description(
-                        &format!("{:?} is not a valid value", char),
-                        ErrorKind::InvalidValue,
-                    ))
-                }
-            }
+    fn from_str(s: &str) -> Result<Sampled> {
+        match s {
+            "sampled" => Ok(Sampled(true)),
+            "not-sampled" => Ok(Sampled(false)),
+            _ => Err(Error::with_description(
+                "--traceparent should be sampled or not-sampled",
+                ErrorKind::InvalidValue,
+            )),
         }
+    }
+}
 
-        let p = Print {
-            request_headers,
-            request_body,
-            response_headers,
-            response_body,
+/// A W3C Trace Context `tracestate` list, like `congo=t61rcWkgMzE,rojo=00f067aa`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TraceState(pub String);
+
+impl FromStr for TraceState {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<TraceState> {
+        let valid_member = |member: &str| {
+            let member = member.trim();
+            let (key, value) = match member.find('=') {
+                Some(index) => (&member[..index], &member[index + 1..]),
+                None => return false,
+            };
+            // Keys may be `tenant@system` for multi-tenant vendors
+            let valid_key = key.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+                && key.len() <= 256
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "_-*/@".contains(c));
+            let valid_value = !value.is_empty()
+                && value.len() <= 256
+                && !value.ends_with(' ')
+                && value
+                    .chars()
+                    .all(|c| (' '..='~').contains(&c) && c != ',' && c != '=');
+            valid_key && valid_value
         };
-        Ok(p)
+        let members: Vec<&str> = s.split(',').filter(|m| !m.trim().is_empty()).collect();
+        if members.is_empty() || members.len() > 32 || !members.iter().all(|m| valid_member(m)) {
+            return Err(Error::with_description(
+                &format!(
+                    "Invalid --tracestate {:?}, expected up to 32 members like `vendor=value`",
+                    s
+                ),
+                ErrorKind::InvalidValue,
+            ));
+        }
+        Ok(TraceState(
+            members
+                .iter()
+                .map(|m| m.trim())
+                .collect::<Vec<_>>()
+                .join(","),
+        ))
     }
 }
 
-#[derive(Debug)]
-pub struct Timeout(Duration);
+/// A number of bytes, e.g. `1024`, `64K` or `1.5G`.
+///
+/// The suffixes use powers of 1024, and can also be written like `MB` or `MiB`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ByteSize(pub u64);
 
-impl Timeout {
-    pub fn as_duration(&self) -> Option<Duration> {
-        Some(self.0).filter(|t| t != &Duration::from_nanos(0))
+impl FromStr for ByteSize {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ByteSize> {
+        let lowercase = s.to_ascii_lowercase();
+        let number = lowercase
+            .trim_end_matches("ib")
+            .trim_end_matches('b')
+            .trim_end();
+        let (number, multiplier) = match number.chars().last() {
+            Some('k') => (&number[..number.len() - 1], 1 << 10),
+            Some('m') => (&number[..number.len() - 1], 1 << 20),
+            Some('g') => (&number[..number.len() - 1], 1 << 30),
+            _ => (number, 1),
+        };
+        match number.trim().parse::<f64>() {
+            Ok(number) if number.is_finite() && number >= 0.0 => {
+                Ok(ByteSize((number * multiplier as f64) as u64))
+            }
+            _ => Err(Error::with_description(
+                &format!(
+                    "Invalid size {:?}, expected a number with an optional K, M or G suffix",
+                    s
+                ),
+                ErrorKind::InvalidValue,
+            )),
+        }
     }
 }
 
-impl FromStr for Timeout {
+/// A character encoding, by any of the names the Encoding Standard knows it by.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Charset(pub &'static Encoding);
+
+impl FromStr for Charset {
     type Err = Error;
 
-    fn from_str(sec: &str) -> Result<Timeout> {
-        let pos_sec: f64 = match sec.parse::<f64>() {
-            Ok(sec) if sec.is_sign_positive() => sec,
-            _ => {
-                return Err(Error::with_description(
-                    "Invalid seconds as connection timeout",
-                    ErrorKind::InvalidValue,
-                ))
-            }
+    fn from_str(s: &str) -> Result<Charset> {
+        match Encoding::for_label(s.trim().as_bytes()) {
+            Some(encoding) => Ok(Charset(encoding)),
+            None => Err(Error::with_description(
+                &format!("Unknown encoding {:?}", s),
+                ErrorKind::InvalidValue,
+            )),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ByteRange {
+    /// `FIRST-LAST` or `FIRST-`
+    FromTo(u64, Option<u64>),
+    /// `-LENGTH`, i.e. the last LENGTH bytes
+    Suffix(u64),
+}
+
+impl ByteRange {
+    pub fn header_value(&self) -> String {
+        format!("bytes={}", self)
+    }
+}
+
+impl FromStr for ByteRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ByteRange> {
+        let invalid = || {
+            Error::with_description(
+                &format!(
+                    "Invalid range {:?}, expected FIRST-LAST, FIRST- or -LENGTH",
+                    s
+                ),
+                ErrorKind::InvalidValue,
+            )
         };
+        let parts: Vec<&str> = s.splitn(2, '-').collect();
+        match parts[..] {
+            ["", length] => match length.parse() {
+                Ok(0) | Err(_) => Err(invalid()),
+                Ok(length) => Ok(ByteRange::Suffix(length)),
+            },
+            [first, ""] => Ok(ByteRange::FromTo(
+                first.parse().map_err(|_| invalid())?,
+                None,
+            )),
+            [first, last] => {
+                let first: u64 = first.parse().map_err(|_| invalid())?;
+                let last: u64 = last.parse().map_err(|_| invalid())?;
+                if first > last {
+                    return Err(invalid());
+                }
+                Ok(ByteRange::FromTo(first, Some(last)))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
 
-        let dur = Duration::from_secs_f64(pos_sec);
-        Ok(Timeout(dur))
+impl fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ByteRange::FromTo(first, Some(last)) => write!(f, "{}-{}", first, last),
+            ByteRange::FromTo(first, None) => write!(f, "{}-", first),
+            ByteRange::Suffix(length) => write!(f, "-{}", length),
+        }
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Proxy {
-    Http(Url),
-    Https(Url),
-    All(Url),
+/// A template for numbering a download whose file name is taken.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumberFormat(String);
+
+impl NumberFormat {
+    pub fn expand(&self, file_name: &str, number: u32) -> String {
+        let (base, extension) = match file_name.rfind('.') {
+            Some(index) if index > 0 => file_name.split_at(index),
+            _ => (file_name, ""),
+        };
+        let mut expanded = String::with_capacity(self.0.len() + file_name.len());
+        let mut chars = self.0.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '%' {
+                expanded.push(ch);
+                continue;
+            }
+            // Validated by from_str
+            match chars.next() {
+                Some('f') => expanded.push_str(file_name),
+                Some('b') => expanded.push_str(base),
+                Some('e') => expanded.push_str(extension),
+                Some('n') => expanded.push_str(&number.to_string()),
+                _ => expanded.push('%'),
+            }
+        }
+        expanded
+    }
 }
 
-impl FromStr for Proxy {
+impl Default for NumberFormat {
+    fn default() -> NumberFormat {
+        NumberFormat("%f-%n".to_string())
+    }
+}
+
+impl FromStr for NumberFormat {
     type Err = Error;
 
-    fn from_str(s: &str) -> Result<Self> {
-        let split_arg: Vec<&str> = s.splitn(2, ':').collect();
-        match split_arg[..] {
-            [protocol, url] => {
-                let url = reqwest::Url::try_from(url).map_err(|e| {
-                    Error::with_description(
+    fn from_str(s: &str) -> Result<NumberFormat> {
+        let mut has_number = false;
+        let mut chars = s.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '%' {
+                continue;
+            }
+            match chars.next() {
+                Some('n') => has_number = true,
+                Some('f') | Some('b') | Some('e') | Some('%') => {}
+                other => {
+                    return Err(Error::with_description(
                         &format!(
-                            "Invalid proxy URL '{}' for protocol '{}': {}",
-                            url, protocol, e
+                            "Unknown placeholder in --number-format: %{}",
+                            other.map_or(String::new(), String::from)
                         ),
                         ErrorKind::InvalidValue,
-                    )
-                })?;
-
-                match protocol.to_lowercase().as_str() {
-                    "http" => Ok(Proxy::Http(url)),
-                    "https" => Ok(Proxy::Https(url)),
-                    "all" => Ok(Proxy::All(url)),
-                    _ => Err(Error::with_description(
-                        &format!("Unknown protocol to set a proxy for: {}", protocol),
-                        ErrorKind::InvalidValue,
-                    )),
+                    ))
                 }
             }
-            _ => Err(Error::with_description(
-                "The value passed to --proxy should be formatted as <PROTOCOL>:<PROXY_URL>",
+        }
+        if !has_number {
+            return Err(Error::with_description(
+                "--number-format must contain %n",
                 ErrorKind::InvalidValue,
-            )),
+            ));
         }
+        Ok(NumberFormat(s.to_string()))
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Verify {
     Yes,
     No,
@@ -851,6 +2742,86 @@ mod tests {
         );
     }
 
+    #[test]
+    fn more_urls() {
+        let cli = parse(&[
+            "get",
+            "example.org/a",
+            "https://example.org/b",
+            "x-trace:http://example.org",
+            "http://example.org/c",
+        ])
+        .unwrap();
+        assert_eq!(cli.url, "example.org/a");
+        assert_eq!(
+            cli.more_urls,
+            vec!["https://example.org/b", "http://example.org/c"]
+        );
+        assert_eq!(
+            cli.request_items,
+            vec![RequestItem::HttpHeader(
+                "x-trace".to_string(),
+                "http://example.org".to_string()
+            )]
+        );
+
+        parse(&["-o=out", "example.org/a", "http://example.org/b"]).unwrap_err();
+    }
+
+    #[test]
+    fn serve() {
+        let cli = parse(&["--serve", "--port=9000", "--reply-status=201"]).unwrap();
+        assert!(cli.serve);
+        assert_eq!(cli.port, Some(9000));
+        assert_eq!(cli.reply_status, Some(StatusCode::CREATED));
+
+        parse(&["--serve", "9000"]).unwrap_err();
+        parse(&["--port=9000", "example.org"]).unwrap_err();
+        parse(&["--serve", "--reply-status=20"]).unwrap_err();
+
+        // Without the flag it's a host name like any other
+        let cli = parse(&["get", "serve"]).unwrap();
+        assert!(!cli.serve);
+        assert_eq!(cli.url, "serve");
+    }
+
+    #[test]
+    fn run_collection() {
+        let cli = parse(&["--run=login", "--run=list-users", "-v"]).unwrap();
+        assert_eq!(
+            cli.run,
+            Some(vec!["login".to_string(), "list-users".to_string()])
+        );
+        assert!(cli.verbose);
+        assert_eq!(cli.url, "");
+
+        let cli = parse(&["--run-all"]).unwrap();
+        assert_eq!(cli.run, Some(vec![]));
+
+        parse(&["--run"]).unwrap_err();
+        parse(&["--run=login", "example.org"]).unwrap_err();
+        parse(&["--run-all", "--run=login"]).unwrap_err();
+
+        let cli = parse(&["get", "run"]).unwrap();
+        assert_eq!(cli.run, None);
+        assert_eq!(cli.url, "run");
+    }
+
+    #[test]
+    fn urls_from() {
+        let cli = parse(&["--urls-from=-", "x-trace:1", "a=b"]).unwrap();
+        assert_eq!(cli.url, "");
+        assert_eq!(cli.method, None);
+        assert_eq!(cli.request_items.len(), 2);
+
+        let cli = parse(&["--urls-from=urls.txt", "post"]).unwrap();
+        assert_eq!(cli.method, Some(Method::POST));
+        assert!(cli.request_items.is_empty());
+
+        parse(&["--urls-from=-", "--password-stdin", "--auth=user"]).unwrap_err();
+        parse(&["--urls-from=-", "--input=requests.jsonl"]).unwrap_err();
+    }
+
     #[test]
     fn auth() {
         let cli = parse(&["--auth=user:pass", ":"]).unwrap();
@@ -963,6 +2934,189 @@ mod tests {
         );
     }
 
+    #[test]
+    fn proxy_for_url() {
+        let proxies = parse(&[
+            "--proxy=https:http://127.0.0.1:8000",
+            "--proxy=all:http://127.0.0.1:8001",
+            "--proxy=http:http://127.0.0.1:8002",
+            "example.org",
+        ])
+        .unwrap()
+        .proxy;
+        let proxy_for = |url: &str| {
+            Proxy::for_url(&proxies, &Url::parse(url).unwrap()).map(|proxy| proxy.port().unwrap())
+        };
+
+        assert_eq!(proxy_for("http://example.org"), Some(8002));
+        assert_eq!(proxy_for("https://example.org"), Some(8001));
+        assert_eq!(
+            Proxy::for_url(&[], &Url::parse("http://example.org").unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn range_parsing() {
+        let cli = parse(&["--range=0-1023", ":"]).unwrap();
+        assert_eq!(cli.range, Some(ByteRange::FromTo(0, Some(1023))));
+
+        let cli = parse(&["--range=1024-", ":"]).unwrap();
+        assert_eq!(cli.range, Some(ByteRange::FromTo(1024, None)));
+
+        let cli = parse(&["--range", "-500", ":"]).unwrap();
+        assert_eq!(cli.range, Some(ByteRange::Suffix(500)));
+        assert_eq!(cli.range.unwrap().header_value(), "bytes=-500");
+
+        for invalid in &[
+            "--range=5-2",
+            "--range=-0",
+            "--range=-",
+            "--range=a-b",
+            "--range=5",
+        ] {
+            parse(&[invalid, ":"]).unwrap_err();
+        }
+
+        parse(&["-do=fname", "--continue", "--range=0-10", ":"]).unwrap_err();
+    }
+
+    #[test]
+    fn byte_size_parsing() {
+        let expected = vec![
+            ("0", Some(0)),
+            ("1024", Some(1024)),
+            ("10b", Some(10)),
+            ("64K", Some(64 * 1024)),
+            ("64kb", Some(64 * 1024)),
+            ("1.5M", Some(1536 * 1024)),
+            ("2 MiB", Some(2 * 1024 * 1024)),
+            ("1g", Some(1024 * 1024 * 1024)),
+            ("", None),
+            ("-1", None),
+            ("M", None),
+            ("ten", None),
+            ("10T", None),
+        ];
+        for (input, output) in expected {
+            assert_eq!(
+                input.parse::<ByteSize>().ok(),
+                output.map(ByteSize),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn completions_dont_exit() {
+        let err = Cli::from_iter_safe(&["xh", "print_completions", "bash"]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::HelpDisplayed);
+        assert!(err.message.contains("complete"));
+
+        let err = Cli::from_iter_safe(&["xh", "print_completions", "nope"]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidValue);
+    }
+
+    #[test]
+    fn deadline_parsing() {
+        assert_eq!(
+            "1.5".parse::<Deadline>().unwrap(),
+            Deadline(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            "1m 30s".parse::<Deadline>().unwrap(),
+            Deadline(Duration::from_secs(90))
+        );
+        for invalid in &["0", "-1", "0s", "soon", "inf"] {
+            assert!(invalid.parse::<Deadline>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn exit_code_map_parsing() {
+        let map = "4xx=10, TIMEOUT=28,".parse::<ExitCodeMap>().unwrap();
+        assert_eq!(map.code(Outcome::ClientError, 4), 10);
+        assert_eq!(map.code(Outcome::Timeout, 1), 28);
+        assert_eq!(map.code(Outcome::ServerError, 5), 5);
+        let map = "dns=6,connect=7".parse::<ExitCodeMap>().unwrap();
+        assert_eq!(map.code(Outcome::Dns, 1), 6);
+        assert_eq!(Outcome::Dns.kind(), "dns");
+        assert_eq!(Outcome::ClientError.kind(), "http-status");
+        for invalid in &["4xx", "4xx=", "4xx=0", "4xx=256", "6xx=10", "4xx=ten"] {
+            assert!(invalid.parse::<ExitCodeMap>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn interval_parsing() {
+        assert_eq!(
+            "0.25".parse::<Interval>().ok(),
+            Some(Interval(Duration::from_millis(250)))
+        );
+        assert_eq!(
+            "0".parse::<Interval>().ok(),
+            Some(Interval(Duration::from_secs(0)))
+        );
+        assert!("-1".parse::<Interval>().is_err());
+        assert!("inf".parse::<Interval>().is_err());
+        assert!("soon".parse::<Interval>().is_err());
+    }
+
+    #[test]
+    fn number_format_parsing() {
+        let format: NumberFormat = "%b (%n)%e".parse().unwrap();
+        assert_eq!(format.expand("report.pdf", 2), "report (2).pdf");
+        assert_eq!(format.expand(".bashrc", 1), ".bashrc (1)");
+        assert_eq!(NumberFormat::default().expand("a.tar.gz", 3), "a.tar.gz-3");
+        let format: NumberFormat = "%%%n_%f".parse().unwrap();
+        assert_eq!(format.expand("file", 1), "%1_file");
+
+        for invalid in &["%f", "%f-%x%n", "%n%"] {
+            invalid.parse::<NumberFormat>().unwrap_err();
+        }
+    }
+
+    #[test]
+    fn var_parsing() {
+        let cli = parse(&["--var", "host=a=b", "--var=token=", ":"]).unwrap();
+        assert_eq!(cli.var[0].name, "host");
+        assert_eq!(cli.var[0].value, "a=b");
+        assert_eq!(cli.var[1].value, "");
+        assert!(parse(&["--var", "host", ":"]).is_err());
+        assert!(parse(&["--var", "{{host}}=x", ":"]).is_err());
+    }
+
+    #[test]
+    fn part_header_parsing() {
+        assert_eq!(
+            "doc:Content-ID:<doc1@example.org>"
+                .parse::<PartHeader>()
+                .unwrap(),
+            PartHeader {
+                field: "doc".to_string(),
+                name: HeaderName::from_static("content-id"),
+                value: HeaderValue::from_static("<doc1@example.org>"),
+            }
+        );
+        assert!("doc:Content-ID".parse::<PartHeader>().is_err());
+        assert!("doc:bad header:x".parse::<PartHeader>().is_err());
+    }
+
+    #[test]
+    fn pager_and_color() {
+        let cli = parse(&["--pager", "example.org"]).unwrap();
+        assert_eq!(cli.pager, Some(None));
+        assert_eq!(cli.url, "example.org");
+        let cli = parse(&["--pager=less -R", ":"]).unwrap();
+        assert_eq!(cli.pager, Some(Some("less -R".to_string())));
+
+        assert_eq!(Pretty::none.with_color(ColorMode::always), Pretty::colors);
+        assert_eq!(Pretty::format.with_color(ColorMode::always), Pretty::all);
+        assert_eq!(Pretty::all.with_color(ColorMode::never), Pretty::format);
+        assert_eq!(Pretty::colors.with_color(ColorMode::auto), Pretty::colors);
+    }
+
     #[test]
     fn executable_name() {
         let args = Cli::from_iter_safe(&["xhs", "example.org"]).unwrap();
@@ -975,6 +3129,69 @@ mod tests {
         assert_eq!(args.https, true);
     }
 
+    #[test]
+    fn misplaced_method() {
+        let error = |args: &[&str]| parse(args).unwrap_err().message;
+        assert_eq!(
+            error(&["example.org", "post", "a=b"]),
+            "error: \"post\" is not a valid request item. \
+             Did you mean to pass the method first, like `xh POST example.org`?"
+        );
+        assert_eq!(
+            error(&["GET:", "example.org"]),
+            "error: \"example.org\" is not a valid request item. \
+             \"GET:\" was taken as the URL, did you mean `xh GET example.org`?"
+        );
+        assert_eq!(
+            error(&["a=b", "example.org"]),
+            "error: \"example.org\" is not a valid request item. \
+             \"a=b\" was taken as the URL, did you mean `xh example.org a=b`?"
+        );
+        assert_eq!(
+            error(&["example.org", "nonsense"]),
+            "error: \"nonsense\" is not a valid request item"
+        );
+    }
+
+    #[test]
+    fn strict_url() {
+        let error = |args: &[&str]| {
+            let args: Vec<&str> = Some("--strict-url").iter().chain(args).copied().collect();
+            parse(&args).err().map(|err| err.message)
+        };
+        assert_eq!(error(&["get", "example.org"]), None);
+        assert_eq!(error(&["propfind", ":3000/dav"]), None);
+        assert_eq!(error(&["https://example.org/a=b"]), None);
+        assert_eq!(
+            error(&["gte", "example.org"]).as_deref(),
+            Some("error: Unknown method GTE, did you mean GET?")
+        );
+        assert_eq!(
+            error(&["htps://example.org"]).as_deref(),
+            Some("error: Unsupported scheme in htps://example.org, did you mean https://example.org?")
+        );
+        assert_eq!(
+            error(&["ftp://example.org"]).as_deref(),
+            Some("error: Unsupported scheme in ftp://example.org, only http and https work")
+        );
+        assert_eq!(
+            error(&["http//example.org"]).as_deref(),
+            Some(
+                "error: The scheme of http//example.org is missing its colon or slashes, \
+                 did you mean http://example.org?"
+            )
+        );
+        assert!(error(&["x:=1"])
+            .unwrap_or_default()
+            .contains("looks like a request item"));
+        assert_eq!(
+            error(&["http:///path"]).as_deref(),
+            Some("error: The URL http:///path has no host")
+        );
+        // Without --strict-url these go through
+        assert!(parse(&["gte", "http//example.org"]).is_ok());
+    }
+
     #[test]
     fn negated_flags() {
         let cli = parse(&["--no-offline", ":"]).unwrap();
@@ -1074,6 +3291,6 @@ mod tests {
         ])
         .unwrap();
         assert_eq!(cli.bearer, None);
-        assert_eq!(cli.auth_type, AuthType::basic);
+        assert_eq!(cli.auth_type, None);
     }
 }
@@ -0,0 +1,418 @@
+// Named requests from `requests.toml`, sent with `--run NAME`
+//
+// Each table is a request:
+//
+//     [login]
+//     method = "POST"
+//     url = "{{base}}/login"
+//     items = ["user=alice", "password:={{password}}"]
+//     status = 200
+//
+// Only the part of TOML this needs is supported: tables, and keys with
+// strings, integers, booleans or arrays as values.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::cli::Cli;
+use crate::config::Config;
+use crate::request_items::RequestItem;
+use crate::runtime::Runtime;
+use crate::vars::Vars;
+use crate::{run_each, share_args};
+
+pub const COLLECTION_FILE: &str = "requests.toml";
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Request {
+    method: Option<String>,
+    url: String,
+    #[serde(default)]
+    items: Vec<String>,
+    /// The status the response should have. Anything else is a failure.
+    status: Option<u16>,
+}
+
+/// Send the requests named in `args.run`, or all of them with --run-all.
+pub fn run(mut args: Cli, config: &Config, runtime: &Runtime) -> Result<i32> {
+    let path = Path::new(COLLECTION_FILE);
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let collection =
+        parse_collection(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+    let names = args.run.take().unwrap_or_default();
+    for name in &names {
+        if !collection
+            .iter()
+            .any(|(request_name, _)| request_name == name)
+        {
+            return Err(anyhow!("No request named {:?} in {}", name, path.display()));
+        }
+    }
+
+    // Placeholders without a variable should be reported, not sent
+    let vars = Vars::from_args(&args)?.unwrap_or_default();
+    share_args(&mut args)?;
+    let mut requests = Vec::new();
+    for (name, request) in collection {
+        if !args.run_all && !names.contains(&name) {
+            continue;
+        }
+        let request_args =
+            to_args(&args, &vars, request).with_context(|| format!("In [{}]", name))?;
+        requests.push((name, request_args));
+    }
+    run_each(requests.into_iter(), config, runtime)
+}
+
+fn to_args(args: &Cli, vars: &Vars, request: Request) -> Result<Cli> {
+    let mut args = args.clone();
+    if let Some(method) = request.method {
+        args.method = Some(
+            Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                .with_context(|| format!("Invalid method: {}", method))?,
+        );
+    }
+    args.url = request.url;
+    args.request_items = request
+        .items
+        .iter()
+        .map(|item| item.parse::<RequestItem>())
+        .collect::<Result<Vec<_>, _>>()?;
+    args.expect_status = request.status;
+    vars.apply(&mut args)?;
+    Ok(args)
+}
+
+/// Parse the requests in a collection, in the order they're written.
+fn parse_collection(text: &str) -> Result<Vec<(String, Request)>> {
+    parse_toml(text)?
+        .into_iter()
+        .map(|(name, table)| {
+            let request =
+                serde_json::from_value(table).with_context(|| format!("In [{}]", name))?;
+            Ok((name, request))
+        })
+        .collect()
+}
+
+/// Parse a TOML document made of tables into an object of objects.
+fn parse_toml(text: &str) -> Result<Map<String, Value>> {
+    let mut parser = Parser { text, pos: 0 };
+    parser
+        .document()
+        .map_err(|err| anyhow!("Line {}: {}", parser.line(), err))
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn document(&mut self) -> Result<Map<String, Value>> {
+        let mut tables = Map::new();
+        let mut current: Option<String> = None;
+        loop {
+            self.skip_blank();
+            match self.peek() {
+                None => return Ok(tables),
+                Some('[') => {
+                    self.bump();
+                    self.skip_space();
+                    if self.peek() == Some('[') {
+                        return Err(anyhow!("Arrays of tables aren't supported"));
+                    }
+                    let name = self.key()?;
+                    self.skip_space();
+                    self.expect(']')?;
+                    self.end_of_line()?;
+                    if tables.contains_key(&name) {
+                        return Err(anyhow!("[{}] is defined twice", name));
+                    }
+                    tables.insert(name.clone(), Value::Object(Map::new()));
+                    current = Some(name);
+                }
+                Some(_) => {
+                    let key = self.key()?;
+                    self.skip_space();
+                    self.expect('=')?;
+                    self.skip_space();
+                    let value = self.value()?;
+                    self.end_of_line()?;
+                    let table = match &current {
+                        Some(name) => tables.get_mut(name).unwrap(),
+                        None => return Err(anyhow!("Expected a [table] before {:?}", key)),
+                    };
+                    let table = table.as_object_mut().unwrap();
+                    if table.contains_key(&key) {
+                        return Err(anyhow!("{:?} is defined twice", key));
+                    }
+                    table.insert(key, value);
+                }
+            }
+        }
+    }
+
+    fn key(&mut self) -> Result<String> {
+        let key = match self.peek() {
+            Some('"') => self.basic_string()?,
+            Some('\'') => self.literal_string()?,
+            _ => {
+                let start = self.pos;
+                while let Some(ch) = self.peek() {
+                    if !(ch.is_ascii_alphanumeric() || ch == '_' || ch == '-') {
+                        break;
+                    }
+                    self.bump();
+                }
+                if start == self.pos {
+                    return Err(anyhow!("Expected a key"));
+                }
+                self.text[start..self.pos].to_string()
+            }
+        };
+        self.skip_space();
+        if self.peek() == Some('.') {
+            return Err(anyhow!("Dotted keys aren't supported"));
+        }
+        Ok(key)
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        match self.peek() {
+            Some('"') => Ok(Value::String(self.basic_string()?)),
+            Some('\'') => Ok(Value::String(self.literal_string()?)),
+            Some('[') => {
+                self.bump();
+                let mut values = Vec::new();
+                loop {
+                    self.skip_blank();
+                    if self.peek() == Some(']') {
+                        self.bump();
+                        return Ok(Value::Array(values));
+                    }
+                    values.push(self.value()?);
+                    self.skip_blank();
+                    match self.bump() {
+                        Some(',') => {}
+                        Some(']') => return Ok(Value::Array(values)),
+                        _ => return Err(anyhow!("Expected , or ] in array")),
+                    }
+                }
+            }
+            Some(ch) if ch.is_ascii_alphanumeric() || ch == '+' || ch == '-' => {
+                let start = self.pos;
+                while let Some(ch) = self.peek() {
+                    // Also take in floats and dates, to reject them
+                    if !(ch.is_ascii_alphanumeric() || "+-_.:".contains(ch)) {
+                        break;
+                    }
+                    self.bump();
+                }
+                let word = &self.text[start..self.pos];
+                match word {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    _ => word
+                        .replace('_', "")
+                        .parse::<i64>()
+                        .map(Value::from)
+                        .map_err(|_| anyhow!("Unsupported value {:?}", word)),
+                }
+            }
+            _ => Err(anyhow!("Expected a value")),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String> {
+        if self.text[self.pos..].starts_with("\"\"\"") {
+            return Err(anyhow!("Multi-line strings aren't supported"));
+        }
+        self.bump();
+        let mut out = String::new();
+        loop {
+            match self.bump() {
+                None | Some('\n') => return Err(anyhow!("Unterminated string")),
+                Some('"') => return Ok(out),
+                Some('\\') => {
+                    let escaped = match self.bump() {
+                        Some('b') => '\u{8}',
+                        Some('t') => '\t',
+                        Some('n') => '\n',
+                        Some('f') => '\u{c}',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('u') => self.unicode_escape(4)?,
+                        Some('U') => self.unicode_escape(8)?,
+                        _ => return Err(anyhow!("Invalid escape in string")),
+                    };
+                    out.push(escaped);
+                }
+                Some(ch) => out.push(ch),
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self, len: usize) -> Result<char> {
+        let digits = self.text.get(self.pos..self.pos + len).unwrap_or("");
+        let ch = u32::from_str_radix(digits, 16)
+            .ok()
+            .and_then(std::char::from_u32)
+            .ok_or_else(|| anyhow!("Invalid unicode escape in string"))?;
+        self.pos += len;
+        Ok(ch)
+    }
+
+    fn literal_string(&mut self) -> Result<String> {
+        if self.text[self.pos..].starts_with("'''") {
+            return Err(anyhow!("Multi-line strings aren't supported"));
+        }
+        self.bump();
+        let start = self.pos;
+        loop {
+            match self.bump() {
+                None | Some('\n') => return Err(anyhow!("Unterminated string")),
+                Some('\'') => return Ok(self.text[start..self.pos - 1].to_string()),
+                Some(_) => {}
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        if self.bump() == Some(expected) {
+            Ok(())
+        } else {
+            Err(anyhow!("Expected {}", expected))
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<()> {
+        self.skip_space();
+        self.skip_comment();
+        match self.peek() {
+            None => Ok(()),
+            Some('\n') | Some('\r') => Ok(()),
+            _ => Err(anyhow!("Expected the end of the line")),
+        }
+    }
+
+    /// Skip whitespace, newlines and comments.
+    fn skip_blank(&mut self) {
+        loop {
+            self.skip_space();
+            self.skip_comment();
+            match self.peek() {
+                Some('\n') | Some('\r') => {
+                    self.bump();
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn skip_space(&mut self) {
+        while let Some(' ') | Some('\t') = self.peek() {
+            self.bump();
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while let Some(ch) = self.peek() {
+                if ch == '\n' {
+                    break;
+                }
+                self.bump();
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.pos += ch.len_utf8();
+        Some(ch)
+    }
+
+    fn line(&self) -> usize {
+        self.text[..self.pos].matches('\n').count() + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collection_parsing() {
+        let text = r#"
+# Shared by the whole team
+[login]
+method = "post"
+url = "{{base}}/login"  # the API gateway
+items = [
+    "user=alice",
+    'password={{password}}',
+]
+status = 2_00
+
+["list users"]
+url = "{{base}}/users?q=\"x\"é"
+"#;
+        assert_eq!(
+            parse_collection(text).unwrap(),
+            vec![
+                (
+                    "login".to_string(),
+                    Request {
+                        method: Some("post".to_string()),
+                        url: "{{base}}/login".to_string(),
+                        items: vec![
+                            "user=alice".to_string(),
+                            "password={{password}}".to_string()
+                        ],
+                        status: Some(200),
+                    }
+                ),
+                (
+                    "list users".to_string(),
+                    Request {
+                        method: None,
+                        url: "{{base}}/users?q=\"x\"\u{e9}".to_string(),
+                        items: vec![],
+                        status: None,
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn collection_errors() {
+        let errors = &[
+            ("url = \"a\"", "Line 1: Expected a [table]"),
+            ("[a]\nurl = \"a\"\n[a]", "Line 3: [a] is defined twice"),
+            ("[a]\nurl = \"a", "Line 2: Unterminated string"),
+            ("[a.b]", "Line 1: Dotted keys"),
+            ("[a]\nurl = 1.5", "Line 2: Unsupported value"),
+            ("[a]\nurl = \"a\" b", "Line 2: Expected the end of the line"),
+        ];
+        for (text, error) in errors {
+            let message = parse_toml(text).unwrap_err().to_string();
+            assert!(message.starts_with(error), "{:?}: {}", text, message);
+        }
+        let message = format!("{:#}", parse_collection("[a]\nuri = \"a\"").unwrap_err());
+        assert!(message.contains("unknown field `uri`"), "{}", message);
+    }
+}
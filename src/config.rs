@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use anyhow::{anyhow, Context, Result};
+use mime::Mime;
+use reqwest::Url;
+use serde::{Deserialize, Deserializer};
+use syntect::parsing::SyntaxSet;
+
+use crate::cli::{ExitCodeMap, Theme};
+use crate::formatting::{find_syntax, load_syntaxes};
+use crate::utils::parse_command;
+
+/// Settings read from `config.json` in the config directory.
+///
+/// The directory is `$XH_CONFIG_DIR` if that's set, and otherwise `xh` in the
+/// platform's config directory, e.g. `~/.config/xh`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Commands to pipe response bodies through for display, by content type.
+    ///
+    /// Keys are media types like `application/wasm`, or `image/*` to match a
+    /// whole family.
+    pub formatters: HashMap<String, String>,
+
+    /// Syntaxes to highlight response bodies with, by content type.
+    ///
+    /// Values are names or file extensions of syntaxes, either built in or
+    /// from the `.sublime-syntax` files in the `syntaxes` directory next to
+    /// the config file.
+    pub syntaxes: HashMap<String, String>,
+
+    /// The output coloring style to use when --style isn't given.
+    #[serde(deserialize_with = "deserialize_theme")]
+    pub style: Option<Theme>,
+
+    /// Credentials to use when none are given on the command line, by host.
+    ///
+    /// Keys are host names, optionally with a port (`example.com:8080`).
+    pub credentials: HashMap<String, Credential>,
+
+    /// Exit codes for kinds of failure, like `{"4xx": 10, "timeout": 28}`.
+    ///
+    /// --exit-code-map overrides these one kind at a time.
+    #[serde(deserialize_with = "deserialize_exit_codes")]
+    pub exit_codes: ExitCodeMap,
+
+    /// The file to append a record of each exchange to when --audit-log
+    /// isn't given.
+    pub audit_log: Option<PathBuf>,
+
+    /// A directory to keep a HAR archive per day in, with every exchange.
+    pub har_dir: Option<PathBuf>,
+}
+
+/// An entry in `credentials`, like `{"type": "bearer", "token": {"env": "TOKEN"}}`.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Credential {
+    Basic {
+        username: String,
+        password: Option<Secret>,
+    },
+    Bearer {
+        token: Secret,
+    },
+    Negotiate,
+}
+
+/// A secret in the config file: the value itself, `{"env": "NAME"}` to read
+/// an environment variable, or `{"command": "..."}` to use the output of a
+/// command, e.g. a password manager.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Secret {
+    Value(String),
+    Env { env: String },
+    Command { command: String },
+}
+
+impl Secret {
+    pub fn resolve(&self) -> Result<String> {
+        match self {
+            Secret::Value(value) => Ok(value.clone()),
+            Secret::Env { env } => env::var(env)
+                .with_context(|| format!("Failed to read the environment variable {}", env)),
+            Secret::Command { command } => {
+                let output = parse_command(command)
+                    .and_then(|mut cmd| cmd.stderr(Stdio::inherit()).output())
+                    .with_context(|| format!("Failed to run {:?}", command))?;
+                if !output.status.success() {
+                    return Err(anyhow!("{:?} failed: {}", command, output.status));
+                }
+                let secret = String::from_utf8(output.stdout)
+                    .with_context(|| format!("{:?} printed invalid UTF-8", command))?;
+                Ok(secret.trim_end_matches(&['\r', '\n'][..]).to_string())
+            }
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Result<Config> {
+        let path = match config_dir() {
+            Some(dir) => dir.join("config.json"),
+            None => return Ok(Config::default()),
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", path.display()))
+            }
+        };
+        serde_json::from_str(&text)
+            .with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Load the user's own syntaxes from the `syntaxes` directory, and check
+    /// that the ones named in `syntaxes` exist.
+    pub fn load_syntaxes(&self) -> Result<Option<SyntaxSet>> {
+        let custom = match config_dir() {
+            Some(dir) => load_syntaxes(&dir.join("syntaxes"))?,
+            None => None,
+        };
+        for (content_type, syntax) in &self.syntaxes {
+            if find_syntax(syntax, custom.as_ref()).is_none() {
+                return Err(anyhow!(
+                    "Unknown syntax {:?} for {} in config file",
+                    syntax,
+                    content_type
+                ));
+            }
+        }
+        Ok(custom)
+    }
+}
+
+fn deserialize_theme<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Theme>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(style) => style.parse().map(Some).map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+fn deserialize_exit_codes<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<ExitCodeMap, D::Error> {
+    let mut exit_codes = ExitCodeMap::default();
+    for (outcome, code) in HashMap::<String, i64>::deserialize(deserializer)? {
+        exit_codes
+            .insert(&outcome, code)
+            .map_err(serde::de::Error::custom)?;
+    }
+    Ok(exit_codes)
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    match env::var_os("XH_CONFIG_DIR") {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => dirs::config_dir().map(|dir| dir.join("xh")),
+    }
+}
+
+/// Find the entry for a content type in a map like `formatters`, preferring
+/// an exact match over a wildcard like `image/*`.
+pub fn find_by_content_type<'a>(
+    map: &'a HashMap<String, String>,
+    content_type: &str,
+) -> Option<&'a str> {
+    let mime = content_type.parse::<Mime>().ok()?;
+    map.get(mime.essence_str())
+        .or_else(|| map.get(&format!("{}/*", mime.type_())))
+        .map(String::as_str)
+}
+
+/// Find the entry for a URL in `credentials`, preferring one for its port,
+/// then one that names its scheme.
+///
+/// Keys without a scheme only apply to HTTPS, so the secrets aren't sent in
+/// the clear. Plain HTTP needs a key like `http://localhost:8080`. Entries are
+/// only matched by URL, not by the realm of a server's challenge.
+pub fn find_credential<'a>(
+    credentials: &'a HashMap<String, Credential>,
+    url: &Url,
+) -> Option<&'a Credential> {
+    let host = url.host_str()?;
+    let prefixes: &[&str] = match url.scheme() {
+        "https" => &["https://", ""],
+        "http" => &["http://"],
+        _ => &[],
+    };
+    prefixes.iter().find_map(|prefix| {
+        url.port_or_known_default()
+            .and_then(|port| credentials.get(&format!("{}{}:{}", prefix, host, port)))
+            .or_else(|| credentials.get(&format!("{}{}", prefix, host)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Outcome;
+
+    #[test]
+    fn formatter_lookup() {
+        let config: Config = serde_json::from_str(
+            r#"{"formatters": {"application/wasm": "wasm-objdump -x -", "image/*": "exiftool -"}}"#,
+        )
+        .unwrap();
+        let find = |content_type| find_by_content_type(&config.formatters, content_type);
+        assert_eq!(find("application/wasm"), Some("wasm-objdump -x -"));
+        assert_eq!(
+            find("Application/WASM; charset=binary"),
+            Some("wasm-objdump -x -")
+        );
+        assert_eq!(find("image/png"), Some("exiftool -"));
+        assert_eq!(find("text/plain"), None);
+        assert_eq!(find("nonsense"), None);
+
+        assert!(serde_json::from_str::<Config>(r#"{"formatter": {}}"#).is_err());
+        assert!(serde_json::from_str::<Config>(r#"{"style": "neon"}"#).is_err());
+        assert_eq!(
+            serde_json::from_str::<Config>(r#"{"style": "Light"}"#)
+                .unwrap()
+                .style,
+            Some(Theme::light)
+        );
+        assert!(serde_json::from_str::<Config>("{}")
+            .unwrap()
+            .formatters
+            .is_empty());
+        assert_eq!(
+            serde_json::from_str::<Config>(r#"{"exit_codes": {"tls": 35}}"#)
+                .unwrap()
+                .exit_codes
+                .code(Outcome::Tls, 1),
+            35
+        );
+        assert!(serde_json::from_str::<Config>(r#"{"exit_codes": {"tls": 0}}"#).is_err());
+    }
+
+    #[test]
+    fn credentials() {
+        let config: Config = serde_json::from_str(
+            r#"{"credentials": {
+                "example.com": {"type": "basic", "username": "user", "password": "pass"},
+                "http://example.com:8080": {"type": "bearer", "token": {"env": "TOKEN"}},
+                "intranet": {"type": "negotiate"}
+            }}"#,
+        )
+        .unwrap();
+        let find = |url: &str| find_credential(&config.credentials, &url.parse().unwrap());
+        assert_eq!(
+            find("https://example.com/path"),
+            Some(&Credential::Basic {
+                username: "user".to_string(),
+                password: Some(Secret::Value("pass".to_string())),
+            })
+        );
+        assert_eq!(
+            find("http://example.com:8080"),
+            Some(&Credential::Bearer {
+                token: Secret::Env {
+                    env: "TOKEN".to_string()
+                }
+            })
+        );
+        assert_eq!(find("https://intranet"), Some(&Credential::Negotiate));
+        assert_eq!(find("https://example.org"), None);
+        // Only keys that say so apply to plain HTTP
+        assert_eq!(find("http://intranet"), None);
+        assert_eq!(find("http://example.com/path"), None);
+        assert!(matches!(
+            find("https://example.com:8080"),
+            Some(Credential::Basic { .. })
+        ));
+
+        assert!(serde_json::from_str::<Config>(
+            r#"{"credentials": {"example.com": {"type": "digest", "username": "user"}}}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<Config>(
+            r#"{"credentials": {"example.com": {"type": "bearer", "token": "x", "extra": 1}}}"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,341 @@
+// A breakdown of Set-Cookie headers for --verbose, pointing out the attribute
+// mistakes that make browsers reject a cookie or handle it differently
+
+use std::fmt::Display;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use reqwest::Url;
+
+use crate::redact::MASK;
+
+/// A Set-Cookie header taken apart. Attribute names are matched without
+/// regard to case, and a repeated attribute replaces the earlier one.
+#[derive(Debug, Default, PartialEq)]
+pub struct SetCookie {
+    pub name: String,
+    pub value: String,
+    pub expires: Option<String>,
+    pub max_age: Option<String>,
+    pub domain: Option<String>,
+    pub path: Option<String>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<String>,
+    pub partitioned: bool,
+    pub priority: Option<String>,
+    pub unknown: Vec<String>,
+}
+
+impl SetCookie {
+    pub fn parse(header: &str) -> SetCookie {
+        let mut parts = header.split(';');
+        let pair = parts.next().unwrap_or("");
+        // A pair without a = is a value with an empty name (RFC 6265bis)
+        let (name, value) = match pair.find('=') {
+            Some(index) => (pair[..index].trim(), pair[index + 1..].trim()),
+            None => ("", pair.trim()),
+        };
+        let mut cookie = SetCookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            ..SetCookie::default()
+        };
+        for attribute in parts {
+            let (key, value) = match attribute.find('=') {
+                Some(index) => (attribute[..index].trim(), attribute[index + 1..].trim()),
+                None => (attribute.trim(), ""),
+            };
+            let value = Some(value.to_string());
+            match key.to_ascii_lowercase().as_str() {
+                "" => {}
+                "expires" => cookie.expires = value,
+                "max-age" => cookie.max_age = value,
+                "domain" => cookie.domain = value,
+                "path" => cookie.path = value,
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                "samesite" => cookie.same_site = value,
+                "partitioned" => cookie.partitioned = true,
+                "priority" => cookie.priority = value,
+                _ => cookie.unknown.push(key.to_string()),
+            }
+        }
+        cookie
+    }
+
+    /// Describe the cookie in `name: value` lines, with its expiry shown in
+    /// `tz` and any problems with it as warnings. `url` is the URL that set
+    /// the cookie.
+    pub fn describe<Tz>(&self, url: &Url, now: DateTime<Utc>, tz: &Tz, redact: bool) -> String
+    where
+        Tz: TimeZone,
+        Tz::Offset: Display,
+    {
+        let mut lines = vec![format!(
+            "cookie: {}={}",
+            self.name,
+            if redact { MASK } else { &self.value }
+        )];
+        let mut warnings = Vec::new();
+
+        let date = |date: DateTime<Utc>| {
+            let when = if date > now {
+                format!("in {}", format_seconds((date - now).num_seconds()))
+            } else {
+                format!(
+                    "{} ago, which deletes the cookie",
+                    format_seconds((now - date).num_seconds())
+                )
+            };
+            format!(
+                "{} ({})",
+                date.with_timezone(tz).format("%Y-%m-%d %H:%M:%S %:z"),
+                when
+            )
+        };
+        let max_age = self.max_age.as_ref().and_then(|max_age| {
+            let seconds = max_age.parse::<i64>().ok();
+            if seconds.is_none() {
+                warnings.push(format!(
+                    "Max-Age {:?} isn't a number of seconds, so it's ignored",
+                    max_age
+                ));
+            }
+            seconds
+        });
+        let expires = self.expires.as_ref().and_then(|expires| {
+            let date = parse_cookie_date(expires);
+            if date.is_none() {
+                warnings.push(format!(
+                    "can't read the Expires date {:?}, so it's ignored",
+                    expires
+                ));
+            }
+            date
+        });
+        match (max_age, expires) {
+            (Some(seconds), _) if seconds <= 0 => {
+                lines.push("expires: now (Max-Age), which deletes the cookie".to_string())
+            }
+            (Some(seconds), expires) => {
+                // Browsers cap a lifetime at 400 days, which also keeps this from overflowing
+                let expiry = now + chrono::Duration::seconds(seconds.min(400 * 24 * 3600));
+                lines.push(format!("expires: {} from Max-Age", date(expiry)));
+                if expires.is_some() {
+                    lines.push("  Max-Age takes precedence over Expires".to_string());
+                }
+            }
+            (None, Some(expires)) => lines.push(format!("expires: {}", date(expires))),
+            (None, None) => lines.push("expires: at the end of the session".to_string()),
+        }
+
+        if let Some(domain) = &self.domain {
+            lines.push(format!("domain: {}", domain));
+            let domain = domain.trim_start_matches('.').to_ascii_lowercase();
+            if let Some(host) = url.host_str() {
+                if host != domain && !host.ends_with(&format!(".{}", domain)) {
+                    warnings.push(format!(
+                        "Domain {} doesn't match {}, so browsers reject the cookie",
+                        domain, host
+                    ));
+                }
+            }
+        }
+        if let Some(path) = &self.path {
+            lines.push(format!("path: {}", path));
+            if !path.starts_with('/') {
+                warnings.push(
+                    "Path doesn't start with /, so browsers use the default path".to_string(),
+                );
+            }
+        }
+
+        let mut flags = Vec::new();
+        if self.secure {
+            flags.push("Secure".to_string());
+        }
+        if self.http_only {
+            flags.push("HttpOnly".to_string());
+        }
+        if let Some(same_site) = &self.same_site {
+            flags.push(format!("SameSite={}", same_site));
+        }
+        if self.partitioned {
+            flags.push("Partitioned".to_string());
+        }
+        if let Some(priority) = &self.priority {
+            flags.push(format!("Priority={}", priority));
+        }
+        if !flags.is_empty() {
+            lines.push(format!("flags: {}", flags.join(", ")));
+        }
+
+        match self
+            .same_site
+            .as_ref()
+            .map(|value| value.to_ascii_lowercase())
+        {
+            Some(ref value) if value == "none" && !self.secure => {
+                warnings.push("SameSite=None without Secure is rejected by browsers".to_string())
+            }
+            Some(ref value) if !["strict", "lax", "none"].contains(&value.as_str()) => warnings
+                .push(format!(
+                    "SameSite={} isn't Strict, Lax or None, so it's ignored",
+                    self.same_site.as_deref().unwrap_or("")
+                )),
+            _ => {}
+        }
+        if self.partitioned && !self.secure {
+            warnings.push("Partitioned without Secure is rejected by browsers".to_string());
+        }
+        let local = matches!(url.host_str(), Some("localhost") | Some("127.0.0.1"));
+        if self.secure && url.scheme() == "http" && !local {
+            warnings.push("browsers don't accept Secure cookies over plain HTTP".to_string());
+        }
+        if self.name.starts_with("__Secure-") && !self.secure {
+            warnings.push("the __Secure- prefix requires Secure".to_string());
+        }
+        if self.name.starts_with("__Host-")
+            && (!self.secure || self.domain.is_some() || self.path.as_deref() != Some("/"))
+        {
+            warnings
+                .push("the __Host- prefix requires Secure and Path=/, and no Domain".to_string());
+        }
+        for attribute in &self.unknown {
+            warnings.push(format!(
+                "unknown attribute {:?} is ignored by browsers",
+                attribute
+            ));
+        }
+
+        lines.extend(
+            warnings
+                .into_iter()
+                .map(|warning| format!("warning: {}", warning)),
+        );
+        lines.join("\n")
+    }
+}
+
+/// Parse a date like `Wed, 21 Oct 2015 07:28:00 GMT`, or the older
+/// `Wed, 21-Oct-2015 07:28:00 GMT` that servers still send.
+fn parse_cookie_date(date: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(date)
+        .or_else(|_| DateTime::parse_from_rfc2822(&date.replace('-', " ")))
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+fn format_seconds(seconds: i64) -> String {
+    // Past an hour the seconds are noise
+    let seconds = if seconds >= 3600 {
+        seconds - seconds % 60
+    } else {
+        seconds
+    };
+    humantime::format_duration(Duration::from_secs(seconds.max(0) as u64)).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn describe(url: &str, header: &str) -> String {
+        let now: DateTime<Utc> = "2021-06-01T12:00:00Z".parse().unwrap();
+        SetCookie::parse(header).describe(&Url::parse(url).unwrap(), now, &Utc, false)
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            SetCookie::parse("id=a3fWa; Max-Age=2592000; path=/; secure; SameSite=Lax; Foo"),
+            SetCookie {
+                name: "id".to_string(),
+                value: "a3fWa".to_string(),
+                max_age: Some("2592000".to_string()),
+                path: Some("/".to_string()),
+                secure: true,
+                same_site: Some("Lax".to_string()),
+                unknown: vec!["Foo".to_string()],
+                ..SetCookie::default()
+            }
+        );
+        let cookie = SetCookie::parse("token; HttpOnly");
+        assert_eq!((cookie.name.as_str(), cookie.value.as_str()), ("", "token"));
+        assert!(cookie.http_only);
+    }
+
+    #[test]
+    fn expiry() {
+        assert_eq!(
+            describe(
+                "https://example.com",
+                "a=1; Expires=Wed, 02 Jun 2021 13:00:00 GMT"
+            ),
+            "cookie: a=1\nexpires: 2021-06-02 13:00:00 +00:00 (in 1day 1h)"
+        );
+        assert_eq!(
+            describe(
+                "https://example.com",
+                "a=1; expires=Tue, 01-Jun-2021 11:59:30 GMT"
+            ),
+            "cookie: a=1\nexpires: 2021-06-01 11:59:30 +00:00 (30s ago, which deletes the cookie)"
+        );
+        assert_eq!(
+            describe(
+                "https://example.com",
+                "a=1; Max-Age=90; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+            ),
+            indoc::indoc! {"
+                cookie: a=1
+                expires: 2021-06-01 12:01:30 +00:00 (in 1m 30s) from Max-Age
+                  Max-Age takes precedence over Expires"}
+        );
+        assert_eq!(
+            describe("https://example.com", "a=; Max-Age=0"),
+            "cookie: a=\nexpires: now (Max-Age), which deletes the cookie"
+        );
+        assert_eq!(
+            describe("https://example.com", "a=1; Expires=soon"),
+            indoc::indoc! {r#"
+                cookie: a=1
+                expires: at the end of the session
+                warning: can't read the Expires date "soon", so it's ignored"#}
+        );
+    }
+
+    #[test]
+    fn warnings() {
+        assert_eq!(
+            describe(
+                "http://example.com/app",
+                "__Host-id=1; Domain=.other.com; Path=/app; SameSite=None; HttpOnly; Partitioned"
+            ),
+            indoc::indoc! {"
+                cookie: __Host-id=1
+                expires: at the end of the session
+                domain: .other.com
+                path: /app
+                flags: HttpOnly, SameSite=None, Partitioned
+                warning: Domain other.com doesn't match example.com, so browsers reject the cookie
+                warning: SameSite=None without Secure is rejected by browsers
+                warning: Partitioned without Secure is rejected by browsers
+                warning: the __Host- prefix requires Secure and Path=/, and no Domain"}
+        );
+        assert_eq!(
+            describe(
+                "http://api.example.com",
+                "__Secure-id=1; Domain=Example.com; Secure; SameSite=Loose"
+            ),
+            indoc::indoc! {"
+                cookie: __Secure-id=1
+                expires: at the end of the session
+                domain: Example.com
+                flags: Secure, SameSite=Loose
+                warning: SameSite=Loose isn't Strict, Lax or None, so it's ignored
+                warning: browsers don't accept Secure cookies over plain HTTP"}
+        );
+        assert!(!describe("http://localhost:8000", "a=1; Secure").contains("warning"));
+    }
+}
@@ -1,20 +1,31 @@
+use std::convert::TryFrom;
 use std::fs::{self, File, OpenOptions};
-use std::io::{self, ErrorKind};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
-use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, Context, Result};
 use atty::Stream;
+use chrono::DateTime;
+use filetime::FileTime;
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
 use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
 use mime2ext::mime2ext;
 use reqwest::{
-    blocking::Response,
-    header::{HeaderMap, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE},
+    blocking::{Client, Request, Response},
+    header::{
+        HeaderMap, HeaderValue, ACCEPT_RANGES, AUTHORIZATION, CONTENT_DISPOSITION,
+        CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, COOKIE, LAST_MODIFIED,
+        RANGE,
+    },
     StatusCode,
 };
 
+use crate::cli::{ByteRange, Interval, NumberFormat};
 use crate::regex;
-use crate::utils::{copy_largebuf, test_pretend_term};
+use crate::utils::{copy_largebuf, test_pretend_term, BodyTooLarge, BUFFER_SIZE};
 
 fn get_content_length(headers: &HeaderMap) -> Option<u64> {
     headers
@@ -71,8 +82,28 @@ pub fn get_file_size(path: Option<&Path>) -> Option<u64> {
     Some(fs::metadata(path?).ok()?.len())
 }
 
+/// What to do if the file to download to already exists.
+pub enum Collision {
+    /// Number a file name that came from the response. --output is still
+    /// overwritten, like a redirect would.
+    Number(NumberFormat),
+    Overwrite,
+    Fail,
+}
+
+/// Create a file that mustn't exist yet.
+fn create_new(path: &Path) -> Result<File> {
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(file) => Ok(file),
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+            Err(anyhow!("{:?} already exists", path))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
 /// Find a file name that doesn't exist yet.
-fn open_new_file(file_name: PathBuf) -> io::Result<(PathBuf, File)> {
+fn open_new_file(file_name: String, format: &NumberFormat) -> io::Result<(PathBuf, File)> {
     fn try_open_new(file_name: &Path) -> io::Result<Option<File>> {
         match OpenOptions::new()
             .write(true)
@@ -84,15 +115,11 @@ fn open_new_file(file_name: PathBuf) -> io::Result<(PathBuf, File)> {
             Err(err) => Err(err),
         }
     }
-    if let Some(file) = try_open_new(&file_name)? {
-        return Ok((file_name, file));
+    if let Some(file) = try_open_new(Path::new(&file_name))? {
+        return Ok((file_name.into(), file));
     }
-    for suffix in 1..u32::MAX {
-        let candidate = {
-            let mut candidate = file_name.clone().into_os_string();
-            candidate.push(format!("-{}", suffix));
-            PathBuf::from(candidate)
-        };
+    for number in 1..u32::MAX {
+        let candidate = PathBuf::from(format.expand(&file_name, number));
         if let Some(file) = try_open_new(&candidate)? {
             return Ok((candidate, file));
         }
@@ -100,9 +127,10 @@ fn open_new_file(file_name: PathBuf) -> io::Result<(PathBuf, File)> {
     panic!("Could not create file after unreasonable number of attempts");
 }
 
+/// Parse a Content-Range header into its first byte, last byte, and complete length.
 // https://github.com/httpie/httpie/blob/84c7327057/httpie/downloads.py#L44
 // https://tools.ietf.org/html/rfc7233#section-4.2
-fn total_for_content_range(header: &str, expected_start: u64) -> Result<u64> {
+fn parse_content_range(header: &str) -> Result<(u64, u64, Option<u64>)> {
     regex!(RE_RANGE =
         r"^bytes (?P<first_byte_pos>\d+)-(?P<last_byte_pos>\d+)"
         r"/(?:\*|(?P<complete_length>\d+))$"
@@ -110,7 +138,7 @@ fn total_for_content_range(header: &str, expected_start: u64) -> Result<u64> {
     let caps = RE_RANGE
         .captures(header)
         // Could happen if header uses unit other than bytes
-        .ok_or_else(|| anyhow!("Can't parse Content-Range header, can't resume download"))?;
+        .ok_or_else(|| anyhow!("Can't parse Content-Range header: {:?}", header))?;
     let first_byte_pos: u64 = caps
         .name("first_byte_pos")
         .unwrap()
@@ -140,6 +168,14 @@ fn total_for_content_range(header: &str, expected_start: u64) -> Result<u64> {
         if last_byte_pos >= complete_length {
             return Err(anyhow!("Invalid Content-Range: {:?}", header));
         }
+    }
+    Ok((first_byte_pos, last_byte_pos, complete_length))
+}
+
+fn total_for_content_range(header: &str, expected_start: u64) -> Result<u64> {
+    let (first_byte_pos, last_byte_pos, complete_length) =
+        parse_content_range(header).context("Can't resume download")?;
+    if let Some(complete_length) = complete_length {
         if complete_length != last_byte_pos + 1 {
             return Err(anyhow!("Content-Range has wrong end: {:?}", header));
         }
@@ -150,6 +186,50 @@ fn total_for_content_range(header: &str, expected_start: u64) -> Result<u64> {
     Ok(last_byte_pos + 1)
 }
 
+/// Check whether a Content-Range header describes the range we asked for.
+///
+/// Servers are allowed to send less than was asked for if the body is too
+/// short, but the range has to start (or end) at the right place.
+fn content_range_matches(header: &str, range: ByteRange) -> Result<bool> {
+    let (first, last, complete_length) = parse_content_range(header)?;
+    let ends_at_end = complete_length.map_or(true, |length| last + 1 == length);
+    Ok(match range {
+        ByteRange::FromTo(start, None) => first == start && ends_at_end,
+        ByteRange::FromTo(start, Some(end)) => {
+            first == start && (last == end || (last < end && ends_at_end))
+        }
+        ByteRange::Suffix(suffix_length) => {
+            let expected_length = complete_length
+                .map_or(suffix_length, |length| std::cmp::min(suffix_length, length));
+            ends_at_end && last - first + 1 == expected_length
+        }
+    })
+}
+
+/// Make sure the server honored a `--range` request.
+pub fn check_range_response(response: &Response, range: ByteRange) -> Result<()> {
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(anyhow!(
+            "Server did not honor the range request (HTTP {})",
+            response.status()
+        ));
+    }
+    let header = response
+        .headers()
+        .get(CONTENT_RANGE)
+        .ok_or_else(|| anyhow!("Missing Content-Range header"))?
+        .to_str()
+        .map_err(|_| anyhow!("Bad Content-Range header"))?;
+    if !content_range_matches(header, range)? {
+        return Err(anyhow!(
+            "Content-Range {:?} does not match the requested range {}",
+            header,
+            range
+        ));
+    }
+    Ok(())
+}
+
 const BAR_TEMPLATE: &str =
     "{spinner:.green} {percent}% [{wide_bar:.cyan/blue}] {bytes} {bytes_per_sec} ETA {eta}";
 const UNCOLORED_BAR_TEMPLATE: &str =
@@ -157,42 +237,564 @@ const UNCOLORED_BAR_TEMPLATE: &str =
 const SPINNER_TEMPLATE: &str = "{spinner:.green} {bytes} {bytes_per_sec} {wide_msg}";
 const UNCOLORED_SPINNER_TEMPLATE: &str = "{spinner} {bytes} {bytes_per_sec} {wide_msg}";
 
+/// How often the progress bar is redrawn unless --progress-interval says otherwise.
+const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The peak speed in the download summary is the fastest over this long.
+const PEAK_WINDOW: Duration = Duration::from_secs(1);
+
+/// A reader that only reports its progress once per interval.
+///
+/// indicatif renders the whole bar on every update before deciding whether
+/// to draw it, which adds up when a fast transfer consists of many small reads.
+/// Progress is added rather than set so that --split can share the bar.
+struct ThrottledProgress<'a, R: Read> {
+    inner: R,
+    pb: &'a ProgressBar,
+    interval: Duration,
+    last_update: Instant,
+    pending: u64,
+}
+
+impl<'a, R: Read> ThrottledProgress<'a, R> {
+    fn new(inner: R, pb: &'a ProgressBar, interval: Duration) -> Self {
+        ThrottledProgress {
+            inner,
+            pb,
+            interval,
+            last_update: Instant::now(),
+            pending: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for ThrottledProgress<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.pending += n as u64;
+        if n == 0 || self.last_update.elapsed() >= self.interval {
+            self.pb.inc(self.pending);
+            self.pending = 0;
+            self.last_update = Instant::now();
+        }
+        Ok(n)
+    }
+}
+
+impl<R: Read> Drop for ThrottledProgress<'_, R> {
+    // Decoders may stop reading before they see the end of the body
+    fn drop(&mut self) {
+        self.pb.inc(self.pending);
+    }
+}
+
+/// A reader that counts the bytes read through it.
+struct CountingReader<R: Read> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+/// Keeps track of the bytes received, for the average and peak speed and
+/// for --max-body-size. It's shared by the threads of a --split download.
+#[derive(Clone)]
+struct Meter(Arc<Mutex<MeterState>>);
+
+struct MeterState {
+    received: u64,
+    limit: Option<u64>,
+    window_start: Instant,
+    window_received: u64,
+    peak: Option<f64>,
+}
+
+impl Meter {
+    fn new(limit: Option<u64>) -> Meter {
+        Meter(Arc::new(Mutex::new(MeterState {
+            received: 0,
+            limit,
+            window_start: Instant::now(),
+            window_received: 0,
+            peak: None,
+        })))
+    }
+
+    fn record(&self, bytes: u64) -> io::Result<()> {
+        let mut state = self.0.lock().unwrap();
+        state.received += bytes;
+        if let Some(limit) = state.limit.filter(|&limit| state.received > limit) {
+            return Err(BodyTooLarge(limit).into_io_error());
+        }
+        state.window_received += bytes;
+        let elapsed = state.window_start.elapsed();
+        if elapsed >= PEAK_WINDOW {
+            let speed = state.window_received as f64 / elapsed.as_secs_f64();
+            state.peak = Some(state.peak.map_or(speed, |peak| peak.max(speed)));
+            state.window_start = Instant::now();
+            state.window_received = 0;
+        }
+        Ok(())
+    }
+
+    fn received(&self) -> u64 {
+        self.0.lock().unwrap().received
+    }
+
+    /// The fastest speed over a whole window, if the download lasted that long.
+    fn peak(&self) -> Option<f64> {
+        self.0.lock().unwrap().peak
+    }
+}
+
+struct MeteredReader<'a, R: Read> {
+    inner: R,
+    meter: &'a Meter,
+}
+
+impl<R: Read> Read for MeteredReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.meter.record(n as u64)?;
+        Ok(n)
+    }
+}
+
+/// A Content-Encoding that --decompress can undo.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ContentCoding {
+    Gzip,
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+/// Find the codings that were applied to a body, in order.
+fn content_codings(headers: &HeaderMap) -> Result<Vec<ContentCoding>> {
+    let mut codings = Vec::new();
+    for value in headers.get_all(CONTENT_ENCODING) {
+        let value = value
+            .to_str()
+            .map_err(|_| anyhow!("Bad Content-Encoding header"))?;
+        for coding in value.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+            codings.push(match coding.to_ascii_lowercase().as_str() {
+                "gzip" | "x-gzip" => ContentCoding::Gzip,
+                "deflate" => ContentCoding::Deflate,
+                "br" => ContentCoding::Brotli,
+                "zstd" => ContentCoding::Zstd,
+                "identity" => continue,
+                _ => {
+                    return Err(anyhow!(
+                        "Can't decompress {:?}, leave out --decompress to save it as is",
+                        coding
+                    ))
+                }
+            });
+        }
+    }
+    Ok(codings)
+}
+
+/// Undo `codings`, last one first.
+fn decode<'a>(
+    mut reader: Box<dyn Read + 'a>,
+    codings: &[ContentCoding],
+) -> io::Result<Box<dyn Read + 'a>> {
+    for coding in codings.iter().rev() {
+        reader = match coding {
+            ContentCoding::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            ContentCoding::Deflate => Box::new(ZlibDecoder::new(reader)),
+            ContentCoding::Brotli => {
+                Box::new(brotli_decompressor::Decompressor::new(reader, BUFFER_SIZE))
+            }
+            ContentCoding::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        };
+    }
+    Ok(reader)
+}
+
+/// What --split needs to fetch the rest of a file over more connections.
+pub struct Split {
+    pub parts: usize,
+    pub client: Client,
+    /// A copy of the request that got the response being downloaded
+    pub request: Request,
+}
+
+impl Split {
+    /// Split the download if the server supports ranges and we know where it
+    /// ends. The body has to arrive unencoded, since ranges apply to the
+    /// encoded body and reqwest would decode each part separately.
+    fn segments(&self, response: &Response, total_length: Option<u64>) -> Option<Vec<(u64, u64)>> {
+        let accepts_ranges = response
+            .headers()
+            .get(ACCEPT_RANGES)
+            .map_or(false, |value| value == "bytes");
+        if response.status() != StatusCode::OK
+            || !accepts_ranges
+            || response.headers().contains_key(CONTENT_ENCODING)
+        {
+            return None;
+        }
+        let total_length = total_length.filter(|&length| length > 1)?;
+        let parts = std::cmp::min(self.parts as u64, total_length);
+        let segment_length = (total_length + parts - 1) / parts;
+        Some(
+            (0..total_length)
+                .step_by(segment_length as usize)
+                .map(|start| {
+                    (
+                        start,
+                        std::cmp::min(start + segment_length, total_length) - 1,
+                    )
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Fetch bytes `first` to `last` (inclusive) into their place in `path`.
+fn download_segment(
+    client: &Client,
+    mut request: Request,
+    path: &Path,
+    (first, last): (u64, u64),
+    pb: Option<&ProgressBar>,
+    interval: Duration,
+    meter: &Meter,
+) -> Result<()> {
+    let range = format!("bytes={}-{}", first, last);
+    request
+        .headers_mut()
+        .insert(RANGE, HeaderValue::from_str(&range)?);
+    let response = client.execute(request)?;
+    let header = response
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok());
+    let matches = match header {
+        Some(header) if response.status() == StatusCode::PARTIAL_CONTENT => {
+            let (start, end, _) = parse_content_range(header)?;
+            start == first && end == last
+        }
+        _ => false,
+    };
+    if !matches {
+        return Err(anyhow!(
+            "Server did not honor the range request for bytes {}-{} (HTTP {})",
+            first,
+            last,
+            response.status()
+        ));
+    }
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.seek(SeekFrom::Start(first))?;
+    write_segment(response, &mut file, last - first + 1, pb, interval, meter)
+}
+
+/// Download the first segment from `response` and the others over new
+/// connections, each straight into its place in the file.
+fn download_segments(
+    response: Response,
+    path: &Path,
+    split: Split,
+    segments: &[(u64, u64)],
+    pb: Option<&ProgressBar>,
+    interval: Duration,
+    meter: &Meter,
+) -> Result<()> {
+    let (first, last) = segments[0];
+    let total_length = segments[segments.len() - 1].1 + 1;
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(total_length)?;
+
+    let mut handles = Vec::new();
+    for &segment in &segments[1..] {
+        let client = split.client.clone();
+        let request = split
+            .request
+            .try_clone()
+            .ok_or_else(|| anyhow!("Can't split a request with a streamed body"))?;
+        let path = path.to_owned();
+        let pb = pb.cloned();
+        let meter = meter.clone();
+        handles.push(thread::spawn(move || {
+            download_segment(
+                &client,
+                request,
+                &path,
+                segment,
+                pb.as_ref(),
+                interval,
+                &meter,
+            )
+        }));
+    }
+    let result = write_segment(response, &mut file, last - first + 1, pb, interval, meter);
+    for handle in handles {
+        handle
+            .join()
+            .map_err(|_| anyhow!("A download thread panicked"))??;
+    }
+    result
+}
+
+/// Copy exactly `length` bytes from `reader`, which must not end early.
+fn write_segment(
+    reader: impl Read,
+    file: &mut File,
+    length: u64,
+    pb: Option<&ProgressBar>,
+    interval: Duration,
+    meter: &Meter,
+) -> Result<()> {
+    let mut reader = MeteredReader {
+        inner: reader,
+        meter,
+    }
+    .take(length);
+    match pb {
+        Some(pb) => copy_largebuf(&mut ThrottledProgress::new(&mut reader, pb, interval), file)?,
+        None => copy_largebuf(&mut reader, file)?,
+    }
+    if reader.limit() != 0 {
+        return Err(anyhow!(
+            "Connection closed before the download was complete"
+        ));
+    }
+    Ok(())
+}
+
+/// Copy the body to `writer`, decoding it if there are `codings`. Returns the
+/// number of bytes written if it was decoded.
+fn copy_body(
+    response: Response,
+    writer: &mut impl io::Write,
+    codings: &[ContentCoding],
+    pb: Option<&ProgressBar>,
+    interval: Duration,
+    meter: &Meter,
+) -> Result<Option<u64>> {
+    let response = MeteredReader {
+        inner: response,
+        meter,
+    };
+    let mut reader: Box<dyn Read + '_> = match pb {
+        Some(pb) => Box::new(ThrottledProgress::new(response, pb, interval)),
+        None => Box::new(response),
+    };
+    if codings.is_empty() {
+        copy_largebuf(&mut reader, writer)?;
+        return Ok(None);
+    }
+    let mut reader = CountingReader {
+        inner: decode(reader, codings)?,
+        count: 0,
+    };
+    copy_largebuf(&mut reader, writer).context("Failed to decompress the download")?;
+    Ok(Some(reader.count))
+}
+
+/// Download the file from a mirror, continuing what's already in `path`.
+///
+/// `request` is sent with the mirror's URL and `timeout`. Like with redirects,
+/// credentials are only kept if the mirror is on the same host.
+pub fn request_mirror(
+    client: &Client,
+    request: &Request,
+    mirror: &reqwest::Url,
+    path: &Path,
+    timeout: Option<Duration>,
+) -> Result<(Response, Option<u64>)> {
+    let mut request = request
+        .try_clone()
+        .ok_or_else(|| anyhow!("Can't send a streamed body to a mirror"))?;
+    *request.timeout_mut() = timeout;
+    let url = request.url();
+    if (url.host_str(), url.port_or_known_default())
+        != (mirror.host_str(), mirror.port_or_known_default())
+    {
+        request.headers_mut().remove(AUTHORIZATION);
+        request.headers_mut().remove(COOKIE);
+    }
+    *request.url_mut() = mirror.clone();
+    let resume = get_file_size(Some(path)).filter(|&size| size > 0);
+    match resume {
+        Some(size) => request
+            .headers_mut()
+            .insert(RANGE, HeaderValue::from_str(&format!("bytes={}-", size))?),
+        None => request.headers_mut().remove(RANGE),
+    };
+    let response = client.execute(request)?;
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "{} replied with HTTP {}",
+            mirror,
+            response.status()
+        ));
+    }
+    Ok((response, resume))
+}
+
+/// What a download did, for the summary at the end.
+pub struct Transfer {
+    /// None if the download went to stdout
+    pub path: Option<PathBuf>,
+    /// Bytes received, not counting those a resumed download already had
+    pub received: u64,
+    /// The size after --decompress
+    pub decoded: Option<u64>,
+    pub peak_speed: Option<f64>,
+}
+
+impl Transfer {
+    /// Print the summary, `connect` being the wait for the response and
+    /// `transfer` the time spent on the body after that.
+    pub fn print_summary(&self, connect: Duration, transfer: Duration, json: bool) {
+        // Never divide by zero, a download takes some time
+        let seconds = transfer.as_secs_f64().max(0.001);
+        let average_speed = self.received as f64 / seconds;
+        let peak_speed = self.peak_speed.unwrap_or(average_speed);
+        if json {
+            eprintln!(
+                "{}",
+                serde_json::json!({
+                    "type": "download",
+                    "path": self.path,
+                    "bytes": self.received,
+                    "decoded_bytes": self.decoded,
+                    "connect_seconds": connect.as_secs_f64(),
+                    "transfer_seconds": transfer.as_secs_f64(),
+                    "average_speed": average_speed,
+                    "peak_speed": peak_speed,
+                })
+            );
+            return;
+        }
+        let size = match self.decoded {
+            Some(decoded) => format!(
+                "{} ({} compressed)",
+                HumanBytes(decoded),
+                HumanBytes(self.received)
+            ),
+            None => HumanBytes(self.received).to_string(),
+        };
+        let time = match transfer.as_secs() {
+            0 => "less than a second".to_string(),
+            secs => humantime::format_duration(Duration::from_secs(secs)).to_string(),
+        };
+        let connect = Duration::from_millis(connect.as_millis() as u64);
+        eprintln!(
+            "Done. {} in {} ({}/s, peak {}/s) after {} to connect",
+            size,
+            time,
+            HumanBytes(average_speed as u64),
+            HumanBytes(peak_speed as u64),
+            humantime::format_duration(connect)
+        );
+        if let Some(path) = &self.path {
+            eprintln!("Saved to {:?}", path);
+        }
+    }
+}
+
+/// How to save a download.
+pub struct DownloadOptions<'a> {
+    /// The file to save to, instead of one named after the URL
+    pub file_name: Option<PathBuf>,
+    pub collision: &'a Collision,
+    pub color: bool,
+    pub quiet: bool,
+    pub progress_interval: Option<Interval>,
+    /// Undo the Content-Encoding on the way to disk
+    pub decompress: bool,
+    /// Give the file the response's Last-Modified time
+    pub preserve_mtime: bool,
+    /// Keep a truncated file so it can be resumed, e.g. with --continue
+    pub keep_partial: bool,
+    pub max_size: Option<u64>,
+}
+
 pub fn download_file(
-    mut response: Response,
-    file_name: Option<PathBuf>,
+    response: Response,
     // If we fall back on taking the filename from the URL it has to be the
     // original URL, before redirects. That's less surprising and matches
     // HTTPie. Hence this argument.
     orig_url: &reqwest::Url,
     mut resume: Option<u64>,
-    color: bool,
-    quiet: bool,
-) -> Result<()> {
+    split: Option<Split>,
+    options: &DownloadOptions,
+) -> Result<Transfer> {
+    let DownloadOptions {
+        collision,
+        color,
+        quiet,
+        progress_interval,
+        decompress,
+        preserve_mtime,
+        keep_partial,
+        max_size,
+        ..
+    } = *options;
     if resume.is_some() && response.status() != StatusCode::PARTIAL_CONTENT {
         resume = None;
     }
+    // Checked before creating the file, which would otherwise be left empty
+    let codings = if decompress {
+        content_codings(response.headers())?
+    } else {
+        Vec::new()
+    };
+    let modified = if preserve_mtime {
+        last_modified(response.headers())
+    } else {
+        None
+    };
 
     let mut buffer: Box<dyn io::Write>;
     let dest_name: PathBuf;
+    let mut to_file = true;
 
-    if let Some(file_name) = file_name {
-        let mut open_opts = OpenOptions::new();
-        open_opts.write(true).create(true);
-        if resume.is_some() {
-            open_opts.append(true);
-        } else {
-            open_opts.truncate(true);
-        }
-
+    if let Some(file_name) = options.file_name.clone() {
         dest_name = file_name;
-        buffer = Box::new(open_opts.open(&dest_name)?);
+        buffer = match collision {
+            Collision::Fail if resume.is_none() => Box::new(create_new(&dest_name)?),
+            _ => {
+                let mut open_opts = OpenOptions::new();
+                open_opts.write(true).create(true);
+                if resume.is_some() {
+                    open_opts.append(true);
+                } else {
+                    open_opts.truncate(true);
+                }
+                Box::new(open_opts.open(&dest_name)?)
+            }
+        };
     } else if test_pretend_term() || atty::is(Stream::Stdout) {
-        let (new_name, handle) = open_new_file(get_file_name(&response, &orig_url).into())?;
+        let file_name = get_file_name(&response, orig_url);
+        let (new_name, handle) = match collision {
+            Collision::Number(format) => open_new_file(file_name, format)?,
+            Collision::Overwrite => {
+                let handle = File::create(&file_name)?;
+                (file_name.into(), handle)
+            }
+            Collision::Fail => {
+                let handle = create_new(Path::new(&file_name))?;
+                (file_name.into(), handle)
+            }
+        };
         dest_name = new_name;
         buffer = Box::new(handle);
     } else {
         dest_name = "<stdout>".into();
         buffer = Box::new(io::stdout());
+        to_file = false;
     }
 
     let starting_length: u64;
@@ -211,8 +813,6 @@ pub fn download_file(
         total_length = get_content_length(&response.headers());
     }
 
-    let starting_time = Instant::now();
-
     let pb = if quiet {
         None
     } else if let Some(total_length) = total_length {
@@ -243,38 +843,250 @@ pub fn download_file(
         pb.reset_eta();
     }
 
-    match pb {
-        Some(ref pb) => {
-            copy_largebuf(&mut pb.wrap_read(response), &mut buffer)?;
-            let downloaded_length = pb.position() - starting_length;
-            pb.finish_and_clear();
-            let time_taken = starting_time.elapsed().as_secs();
-            if let Some(speed) = downloaded_length.checked_div(time_taken) {
-                eprintln!(
-                    "Done. {} in {} ({}/s)",
-                    HumanBytes(downloaded_length),
-                    humantime::format_duration(Duration::from_secs(time_taken)),
-                    HumanBytes(speed)
-                );
-            } else {
-                eprintln!(
-                    "Done. {} in less than a second",
-                    HumanBytes(downloaded_length)
-                );
+    let interval = progress_interval.map_or(DEFAULT_PROGRESS_INTERVAL, |i| i.0);
+    // Progress is measured in bytes received, so this is the size on disk
+    let mut decoded_length = None;
+    let meter = Meter::new(max_size);
+    let segments = match &split {
+        Some(split) if to_file => split.segments(&response, total_length),
+        _ => None,
+    };
+    let expected_length = response.content_length();
+    let result = match (split, segments) {
+        // Each segment checks its own length
+        (Some(split), Some(segments)) => download_segments(
+            response,
+            &dest_name,
+            split,
+            &segments,
+            pb.as_ref(),
+            interval,
+            &meter,
+        ),
+        _ => copy_body(
+            response,
+            &mut buffer,
+            &codings,
+            pb.as_ref(),
+            interval,
+            &meter,
+        )
+        .and_then(|decoded| {
+            decoded_length = decoded;
+            match expected_length {
+                Some(expected) if meter.received() != expected => Err(anyhow!(
+                    "Received {} of {} bytes",
+                    meter.received(),
+                    expected
+                )),
+                _ => Ok(()),
             }
+        }),
+    };
+    drop(buffer);
+
+    if let Err(err) = result {
+        if let Some(pb) = &pb {
+            pb.finish_and_clear();
         }
-        None => {
-            copy_largebuf(&mut response, &mut buffer)?;
+        if !to_file {
+            return Err(err);
+        } else if keep_partial {
+            return Err(err.context(format!(
+                "Download incomplete, kept {:?} to resume",
+                dest_name
+            )));
         }
+        fs::remove_file(&dest_name)?;
+        return Err(err.context(format!("Download incomplete, removed {:?}", dest_name)));
     }
 
-    Ok(())
+    // Only once everything is written, or it would be bumped again
+    if let Some(modified) = modified.filter(|_| to_file) {
+        if let Err(err) = set_modified(&dest_name, modified) {
+            eprintln!(
+                "{}: warning: couldn't set the modification time of {:?}: {}",
+                env!("CARGO_PKG_NAME"),
+                dest_name,
+                err
+            );
+        }
+    }
+
+    if let Some(pb) = pb {
+        pb.finish_and_clear();
+    }
+
+    Ok(Transfer {
+        path: if to_file { Some(dest_name) } else { None },
+        received: meter.received(),
+        decoded: decoded_length,
+        peak_speed: meter.peak(),
+    })
+}
+
+/// Parse the Last-Modified header. Dates before 1970 are ignored.
+fn last_modified(headers: &HeaderMap) -> Option<SystemTime> {
+    let value = headers.get(LAST_MODIFIED)?.to_str().ok()?;
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    let secs = u64::try_from(date.timestamp()).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+fn set_modified(path: &Path, time: SystemTime) -> io::Result<()> {
+    // Leaves the access time alone
+    filetime::set_file_mtime(path, FileTime::from_system_time(time))
+}
+
+/// Fill in the placeholders in an --output-headers file name.
+fn expand_output_template(
+    template: &Path,
+    status: StatusCode,
+    host: &str,
+    output: Option<&Path>,
+) -> Result<PathBuf> {
+    let template = match template.to_str() {
+        Some(template) if template.contains('%') => template,
+        _ => return Ok(template.to_owned()),
+    };
+    let mut path = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            path.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => path.push_str(status.as_str()),
+            Some('h') => path.push_str(host),
+            Some('t') => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+                path.push_str(&now.as_secs().to_string());
+            }
+            Some('o') => match output {
+                Some(output) => path.push_str(&output.to_string_lossy()),
+                None => return Err(anyhow!("%o in --output-headers requires --output")),
+            },
+            Some('%') => path.push('%'),
+            Some(other) => {
+                return Err(anyhow!(
+                    "Unknown placeholder in --output-headers: %{}",
+                    other
+                ))
+            }
+            None => return Err(anyhow!("--output-headers can't end with a single %")),
+        }
+    }
+    Ok(path.into())
+}
+
+/// Save the status line and headers of a response, for --output-headers.
+pub fn save_headers(response: &Response, template: &Path, output: Option<&Path>) -> Result<()> {
+    let path = expand_output_template(
+        template,
+        response.status(),
+        response.url().host_str().unwrap_or(""),
+        output,
+    )?;
+    let mut text = format!("{:?} {}\n", response.version(), response.status()).into_bytes();
+    for (name, value) in response.headers() {
+        text.extend(name.as_str().as_bytes());
+        text.extend(b": ");
+        text.extend(value.as_bytes());
+        text.push(b'\n');
+    }
+    fs::write(&path, text).with_context(|| format!("Failed to write headers to {}", path.display()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn last_modified_header() {
+        let parse = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(LAST_MODIFIED, HeaderValue::from_static(value));
+            last_modified(&headers)
+        };
+        assert_eq!(
+            parse("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(UNIX_EPOCH + Duration::from_secs(1_445_412_480))
+        );
+        assert_eq!(parse("Thu, 01 Jan 1931 00:00:00 GMT"), None);
+        assert_eq!(parse("yesterday"), None);
+    }
+
+    #[test]
+    fn output_template_expansion() {
+        let expand = |template: &str, output: Option<&str>| {
+            expand_output_template(
+                Path::new(template),
+                StatusCode::NOT_FOUND,
+                "example.org",
+                output.map(Path::new),
+            )
+        };
+        assert_eq!(
+            expand("%h-%s.txt", None).unwrap(),
+            PathBuf::from("example.org-404.txt")
+        );
+        assert_eq!(
+            expand("%o.headers", Some("body.bin")).unwrap(),
+            PathBuf::from("body.bin.headers")
+        );
+        assert_eq!(
+            expand("100%%.txt", None).unwrap(),
+            PathBuf::from("100%.txt")
+        );
+        assert!(expand("%o.headers", None).is_err());
+        assert!(expand("%x", None).is_err());
+        assert!(expand("50%", None).is_err());
+    }
+
+    #[test]
+    fn decompression() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        // A brotli stream with a single uncompressed meta-block
+        fn brotli(data: &[u8]) -> Vec<u8> {
+            let header = ((data.len() as u32 - 1) << 4) | 1 << 20;
+            let mut stream = header.to_le_bytes()[..3].to_vec();
+            stream.extend_from_slice(data);
+            stream.push(0x03);
+            stream
+        }
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(b"hello").unwrap();
+        let body = brotli(&gzip.finish().unwrap());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip, br"));
+        let codings = content_codings(&headers).unwrap();
+        assert_eq!(codings, [ContentCoding::Gzip, ContentCoding::Brotli]);
+        let mut decoded = String::new();
+        decode(Box::new(&body[..]), &codings)
+            .unwrap()
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "hello");
+
+        let body = zstd::stream::encode_all(&b"hello"[..], 0).unwrap();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("zstd"));
+        let codings = content_codings(&headers).unwrap();
+        let mut decoded = String::new();
+        decode(Box::new(&body[..]), &codings)
+            .unwrap()
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "hello");
+
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("compress"));
+        assert!(content_codings(&headers).is_err());
+    }
+
     #[test]
     fn content_range_parsing() {
         let expected = vec![
@@ -290,4 +1102,34 @@ mod tests {
             assert_eq!(total_for_content_range(header, start).ok(), result);
         }
     }
+
+    #[test]
+    fn content_range_matching() {
+        let expected = vec![
+            ("0-1023", "bytes 0-1023/2048", true),
+            ("0-1023", "bytes 0-99/100", true),
+            ("0-1023", "bytes 0-99/*", true),
+            ("0-1023", "bytes 0-2047/2048", false),
+            ("0-1023", "bytes 1-1023/2048", false),
+            ("1024-", "bytes 1024-2047/2048", true),
+            ("1024-", "bytes 1024-2047/*", true),
+            ("1024-", "bytes 1024-2046/2048", false),
+            ("-500", "bytes 1548-2047/2048", true),
+            ("-500", "bytes 0-99/100", true),
+            ("-500", "bytes 1500-1999/*", true),
+            ("-500", "bytes 1547-2047/2048", false),
+            ("-500", "bytes 1548-2046/2048", false),
+        ];
+        for (range, header, result) in expected {
+            let range: ByteRange = range.parse().unwrap();
+            assert_eq!(
+                content_range_matches(header, range).unwrap(),
+                result,
+                "{} {}",
+                range,
+                header
+            );
+        }
+        content_range_matches("items 0-10/20", ByteRange::FromTo(0, Some(10))).unwrap_err();
+    }
 }
@@ -1,11 +1,17 @@
 use std::io::{self, Write};
+use std::path::Path;
 
+use anyhow::{Context, Result};
+use atty::Stream;
+use indicatif::HumanBytes;
 use syntect::dumps::from_binary;
 use syntect::easy::HighlightLines;
 use syntect::highlighting::ThemeSet;
-use syntect::parsing::SyntaxSet;
+use syntect::parsing::{SyntaxReference, SyntaxSet, SyntaxSetBuilder};
+use syntect::util::LinesWithEndings;
 use termcolor::WriteColor;
 
+use crate::utils::test_pretend_term;
 use crate::{buffer::Buffer, cli::Theme};
 
 pub fn get_json_formatter() -> jsonxf::Formatter {
@@ -31,43 +37,177 @@ lazy_static::lazy_static! {
     )));
 }
 
+/// Load the `.sublime-syntax` files in `dir`, if it exists.
+pub fn load_syntaxes(dir: &Path) -> Result<Option<SyntaxSet>> {
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    let mut builder = SyntaxSetBuilder::new();
+    builder
+        .add_from_folder(dir, true)
+        .with_context(|| format!("Failed to load syntax definitions from {}", dir.display()))?;
+    Ok(Some(builder.build()))
+}
+
+/// Find a syntax by name or file extension, among the built-in syntaxes
+/// and then among the user's own.
+pub fn find_syntax<'a>(
+    token: &str,
+    custom: Option<&'a SyntaxSet>,
+) -> Option<(&'a SyntaxSet, &'a SyntaxReference)> {
+    let builtin: &'static SyntaxSet = match token {
+        "json" | "http" => &PS_BASIC,
+        _ => &PS_LARGE,
+    };
+    if let Some(syntax) = builtin.find_syntax_by_extension(token) {
+        return Some((builtin, syntax));
+    }
+    let custom = custom?;
+    custom
+        .find_syntax_by_token(token)
+        .map(|syntax| (custom, syntax))
+}
+
 pub struct Highlighter<'a> {
-    highlighter: HighlightLines<'static>,
-    syntax_set: &'static SyntaxSet,
+    highlighter: HighlightLines<'a>,
+    syntax_set: &'a SyntaxSet,
     out: &'a mut Buffer,
+    limit: Option<u64>,
+    highlighted: u64,
+    truncated: bool,
 }
 
 /// A wrapper around a [`Buffer`] to add syntax highlighting when printing.
 impl<'a> Highlighter<'a> {
-    pub fn new(syntax: &'static str, theme: Theme, out: &'a mut Buffer) -> Self {
-        let syntax_set: &SyntaxSet = match syntax {
-            "json" | "http" => &PS_BASIC,
-            _ => &PS_LARGE,
-        };
-        let syntax = syntax_set
-            .find_syntax_by_extension(syntax)
-            .expect("syntax not found");
+    /// Only the first `limit` bytes are highlighted, if given. Highlighting is
+    /// by far the slowest part of printing, so this keeps huge bodies from
+    /// stalling the output.
+    pub fn new(
+        syntax: &str,
+        custom_syntaxes: Option<&'a SyntaxSet>,
+        theme: Theme,
+        limit: Option<u64>,
+        out: &'a mut Buffer,
+    ) -> Self {
+        let (syntax_set, syntax) = find_syntax(syntax, custom_syntaxes).expect("syntax not found");
         Self {
             highlighter: HighlightLines::new(syntax, &TS.themes[theme.as_str()]),
             syntax_set,
             out,
+            limit,
+            highlighted: 0,
+            truncated: false,
         }
     }
 
     /// Write a single piece of highlighted text.
+    ///
+    /// This should be at most one line. Longer text works, but syntect then
+    /// has to collect the regions of all of it before anything is written.
     pub fn highlight(&mut self, line: &str) -> io::Result<()> {
-        for (style, component) in self.highlighter.highlight(line, self.syntax_set) {
-            self.out.set_color(&convert_style(style))?;
-            write!(self.out, "{}", component)?;
+        let (line, rest) = match self.limit {
+            Some(limit) if self.highlighted + line.len() as u64 > limit => {
+                let mut end = (limit - self.highlighted) as usize;
+                while !line.is_char_boundary(end) {
+                    end -= 1;
+                }
+                line.split_at(end)
+            }
+            _ => (line, ""),
+        };
+        if !line.is_empty() {
+            for (style, component) in self.highlighter.highlight(line, self.syntax_set) {
+                self.out.set_color(&convert_style(style))?;
+                self.out.write_all(component.as_bytes())?;
+            }
+            self.highlighted += line.len() as u64;
+        }
+        if !rest.is_empty() {
+            if !self.truncated {
+                self.truncated = true;
+                self.out.reset()?;
+                // Scripts see the colors stop, there's no one to tell why
+                if atty::is(Stream::Stderr) || test_pretend_term() {
+                    eprintln!(
+                        "{}: warning: body is larger than {}, only highlighting the start of it",
+                        env!("CARGO_PKG_NAME"),
+                        HumanBytes(self.limit.unwrap_or_default())
+                    );
+                }
+            }
+            self.out.write_all(rest.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Write highlighted text that may span many lines.
+    pub fn highlight_lines(&mut self, text: &str) -> io::Result<()> {
+        for line in LinesWithEndings::from(text) {
+            self.highlight(line)?;
         }
         Ok(())
     }
 
+    /// Write text without highlighting it, e.g. indentation.
+    pub fn write_plain(&mut self, text: &str) -> io::Result<()> {
+        self.out.reset()?;
+        self.out.write_all(text.as_bytes())
+    }
+
     pub fn highlight_bytes(&mut self, line: &[u8]) -> io::Result<()> {
         self.highlight(&String::from_utf8_lossy(line))
     }
 }
 
+/// Highlights text as it's written, one complete line at a time.
+///
+/// This lets a formatter feed it directly, so its output never has to be
+/// collected in full before it's colored. Call [`finish`](Self::finish) to
+/// write out a trailing unterminated line.
+pub struct HighlightWriter<'a> {
+    highlighter: Highlighter<'a>,
+    line: Vec<u8>,
+}
+
+impl<'a> HighlightWriter<'a> {
+    pub fn new(highlighter: Highlighter<'a>) -> Self {
+        Self {
+            highlighter,
+            line: Vec::new(),
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        if !self.line.is_empty() {
+            self.highlighter.highlight_bytes(&self.line)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for HighlightWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while let Some(end) = memchr::memchr(b'\n', rest) {
+            let (line, tail) = rest.split_at(end + 1);
+            if self.line.is_empty() {
+                self.highlighter.highlight_bytes(line)?;
+            } else {
+                self.line.extend_from_slice(line);
+                self.highlighter.highlight_bytes(&self.line)?;
+                self.line.clear();
+            }
+            rest = tail;
+        }
+        self.line.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.highlighter.out.flush()
+    }
+}
+
 impl Drop for Highlighter<'_> {
     fn drop(&mut self) {
         // This is just a best-effort attempt to restore the terminal, failure can be ignored
@@ -79,6 +219,7 @@ fn convert_style(style: syntect::highlighting::Style) -> termcolor::ColorSpec {
     use syntect::highlighting::FontStyle;
     let mut spec = termcolor::ColorSpec::new();
     spec.set_fg(convert_color(style.foreground))
+        .set_bold(style.font_style.contains(FontStyle::BOLD))
         .set_underline(style.font_style.contains(FontStyle::UNDERLINE));
     spec
 }
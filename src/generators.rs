@@ -0,0 +1,206 @@
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use serde_json::Value;
+
+use crate::regex;
+
+/// Expand a value generator such as `now+2h`, `uuid`, `randint(1,100)` or
+/// `base64(blob.bin)`.
+///
+/// Returns `None` if `spec` isn't a generator, in which case it's probably
+/// a file name. All generators in a request should share the same `now`.
+pub fn generate(spec: &str, now: SystemTime) -> Result<Option<Value>> {
+    if let Some(caps) = regex!(r"^(now|unix|http-date)(?:([+-])(\d+)([smhdw]))?$").captures(spec) {
+        let time = match (caps.get(2), caps.get(3), caps.get(4)) {
+            (Some(sign), Some(amount), Some(unit)) => {
+                let unit = match unit.as_str() {
+                    "s" => 1,
+                    "m" => 60,
+                    "h" => 60 * 60,
+                    "d" => 24 * 60 * 60,
+                    "w" => 7 * 24 * 60 * 60,
+                    _ => unreachable!(),
+                };
+                let offset = amount
+                    .as_str()
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|amount| amount.checked_mul(unit))
+                    .map(Duration::from_secs);
+                let time = match offset {
+                    Some(offset) if sign.as_str() == "+" => now.checked_add(offset),
+                    Some(offset) => now.checked_sub(offset),
+                    None => None,
+                };
+                time.ok_or_else(|| anyhow!("Timestamp out of range: {}", spec))?
+            }
+            _ => now,
+        };
+        let datetime = DateTime::<Utc>::from(time);
+        let value = match &caps[1] {
+            "now" => Value::from(datetime.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            "unix" => Value::from(time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())),
+            "http-date" => Value::from(datetime.format("%a, %d %b %Y %H:%M:%S GMT").to_string()),
+            _ => unreachable!(),
+        };
+        return Ok(Some(value));
+    }
+
+    let mut rng = rand::thread_rng();
+    if spec == "uuid" {
+        Ok(Some(Value::from(uuid_v4(rng.gen()))))
+    } else if let Some(caps) = regex!(r"^rand\((\d+)\)$").captures(spec) {
+        let len: usize = caps[1]
+            .parse()
+            .map_err(|_| anyhow!("Invalid length: {}", spec))?;
+        let text: String = (&mut rng)
+            .sample_iter(Alphanumeric)
+            .take(len)
+            .map(char::from)
+            .collect();
+        Ok(Some(Value::from(text)))
+    } else if let Some(caps) = regex!(r"^randint\((-?\d+), *(-?\d+)\)$").captures(spec) {
+        let bounds = (caps[1].parse::<i64>(), caps[2].parse::<i64>());
+        match bounds {
+            (Ok(low), Ok(high)) if low <= high => Ok(Some(Value::from(rng.gen_range(low..=high)))),
+            _ => Err(anyhow!("Invalid range: {}", spec)),
+        }
+    } else if let Some(caps) = regex!(r"^(base64|hex)\((.+)\)$").captures(spec) {
+        // Allow hex(@file) for symmetry with the rest of the syntax
+        let path = caps[2].strip_prefix('@').unwrap_or(&caps[2]);
+        let data = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+        let text = match &caps[1] {
+            "base64" => base64::encode(data),
+            "hex" => data.iter().map(|b| format!("{:02x}", b)).collect(),
+            _ => unreachable!(),
+        };
+        Ok(Some(Value::from(text)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Format random bytes as a version 4 UUID.
+fn uuid_v4(mut bytes: [u8; 16]) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// A W3C Trace Context `traceparent` header that starts a new trace, and its
+/// trace ID. The trace and parent IDs are made from the `random` bytes.
+pub fn traceparent(mut random: [u8; 24], sampled: bool) -> (String, String) {
+    // IDs of all zeroes are invalid
+    random[15] |= 1;
+    random[23] |= 1;
+    let hex: String = random.iter().map(|b| format!("{:02x}", b)).collect();
+    let trace_id = hex[..32].to_string();
+    let header = format!(
+        "00-{}-{}-{}",
+        trace_id,
+        &hex[32..],
+        if sampled { "01" } else { "00" }
+    );
+    (header, trace_id)
+}
+
+/// Turn a generated value into text for a header, query or form field.
+pub fn value_to_string(value: Value) -> String {
+    match value {
+        Value::String(text) => text,
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gen(spec: &str) -> Option<Value> {
+        // 2021-04-01T12:00:00Z
+        let now = UNIX_EPOCH + Duration::from_secs(1617278400);
+        generate(spec, now).unwrap()
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(gen("now"), Some(Value::from("2021-04-01T12:00:00Z")));
+        assert_eq!(gen("now+2h"), Some(Value::from("2021-04-01T14:00:00Z")));
+        assert_eq!(gen("unix"), Some(Value::from(1617278400)));
+        assert_eq!(gen("unix-1m"), Some(Value::from(1617278340)));
+        assert_eq!(
+            gen("http-date+1d"),
+            Some(Value::from("Fri, 02 Apr 2021 12:00:00 GMT"))
+        );
+        assert_eq!(gen("now+2"), None);
+        assert_eq!(gen("data.json"), None);
+        assert!(generate("now+99999999999999999999w", SystemTime::now()).is_err());
+    }
+
+    #[test]
+    fn random_values() {
+        let uuid = gen("uuid").unwrap();
+        assert!(
+            regex!("^[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}$")
+                .is_match(uuid.as_str().unwrap())
+        );
+        assert_ne!(gen("uuid"), Some(uuid));
+
+        let text = gen("rand(16)").unwrap();
+        let text = text.as_str().unwrap();
+        assert_eq!(text.len(), 16);
+        assert!(text.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        for _ in 0..100 {
+            let n = gen("randint(-2, 2)").unwrap().as_i64().unwrap();
+            assert!((-2..=2).contains(&n));
+        }
+        assert_eq!(gen("randint(7,7)"), Some(Value::from(7)));
+        assert!(generate("randint(2,1)", SystemTime::now()).is_err());
+        assert_eq!(gen("rand"), None);
+    }
+
+    #[test]
+    fn file_encodings() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"\xff\x00ab").unwrap();
+        let path = file.path().to_string_lossy();
+        assert_eq!(
+            gen(&format!("base64({})", path)),
+            Some(Value::from("/wBhYg=="))
+        );
+        assert_eq!(
+            gen(&format!("hex(@{})", path)),
+            Some(Value::from("ff006162"))
+        );
+        assert!(generate("hex(/nonexistent/file)", SystemTime::now()).is_err());
+        assert_eq!(gen("hex()"), None);
+    }
+
+    #[test]
+    fn trace_context() {
+        let (header, trace_id) = traceparent([0xab; 24], true);
+        assert_eq!(
+            header,
+            "00-abababababababababababababababab-abababababababab-01"
+        );
+        assert_eq!(trace_id, "abababababababababababababababab");
+        let (header, _) = traceparent([0; 24], false);
+        assert_eq!(
+            header,
+            "00-00000000000000000000000000000001-0000000000000001-00"
+        );
+    }
+}
@@ -0,0 +1,257 @@
+// "har_dir" in the config file: every exchange is added to a HAR archive for
+// the day, so there's a history of what was sent that other tools can replay
+//
+// HAR is one JSON document rather than a line per entry, so adding to it means
+// rewriting it. That's done through a temporary file, so an archive is never
+// left half written.
+
+use std::fs::{self, File};
+use std::io::{BufReader, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use reqwest::header::{HeaderMap, HeaderName, CONTENT_TYPE, LOCATION};
+use serde_json::{json, Value};
+
+use crate::audit::Sent;
+use crate::redact::{redact_headers, MASK};
+use crate::template::Metadata;
+use crate::utils::looks_binary;
+
+pub struct HarArchive {
+    dir: PathBuf,
+    /// Headers to mask, or `None` for --show-secrets
+    redact: Option<Vec<HeaderName>>,
+}
+
+impl HarArchive {
+    pub fn new(dir: &Path, redact: Option<Vec<HeaderName>>) -> HarArchive {
+        HarArchive {
+            dir: dir.to_path_buf(),
+            redact,
+        }
+    }
+
+    /// Add an exchange to the archive of the (local) day it was sent on.
+    pub fn record(&self, sent: &Sent, response: &Metadata) -> Result<()> {
+        let day = DateTime::<Local>::from(sent.time).format("%Y-%m-%d");
+        let path = self
+            .dir
+            .join(format!("{}-{}.har", env!("CARGO_PKG_NAME"), day));
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {:?}", self.dir))?;
+
+        let mut har = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))
+                .with_context(|| format!("{:?} isn't a HAR file", path))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => empty(),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {:?}", path));
+            }
+        };
+        match har["log"]["entries"].as_array_mut() {
+            Some(entries) => entries.push(entry(sent, response, self.redact.as_deref())),
+            None => return Err(anyhow::anyhow!("{:?} has no log entries", path)),
+        }
+
+        let temp = path.with_extension("har.tmp");
+        fs::write(&temp, serde_json::to_string_pretty(&har)?)
+            .with_context(|| format!("Failed to write {:?}", temp))?;
+        fs::rename(&temp, &path).with_context(|| format!("Failed to write {:?}", path))
+    }
+}
+
+fn empty() -> Value {
+    json!({
+        "log": {
+            "version": "1.2",
+            "creator": {
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "entries": [],
+        }
+    })
+}
+
+fn entry(sent: &Sent, response: &Metadata, redact: Option<&[HeaderName]>) -> Value {
+    let headers = |headers: &HeaderMap| {
+        let mut headers = headers.clone();
+        if let Some(redact) = redact {
+            redact_headers(&mut headers, redact);
+        }
+        headers
+            .iter()
+            .map(|(name, value)| {
+                json!({
+                    "name": name.as_str(),
+                    "value": String::from_utf8_lossy(value.as_bytes()),
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut url = sent.url.clone();
+    if redact.is_some() && url.password().is_some() {
+        let _ = url.set_password(Some(MASK));
+    }
+    let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    // The request went over the connection the response came back on
+    let version = format!("{:?}", response.version);
+
+    let mut request = json!({
+        "method": sent.method.as_str(),
+        "url": url.as_str(),
+        "httpVersion": version,
+        "cookies": [],
+        "headers": headers(&sent.headers),
+        "queryString": url
+            .query_pairs()
+            .map(|(name, value)| json!({ "name": name, "value": value }))
+            .collect::<Vec<_>>(),
+        "headersSize": -1,
+        "bodySize": sent.body.as_ref().map_or(0, Vec::len),
+    });
+    if let Some(body) = &sent.body {
+        let mime_type = sent
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+        request["postData"] = match std::str::from_utf8(body) {
+            Ok(text) if !looks_binary(text) => json!({ "mimeType": mime_type, "text": text }),
+            // HAR has no encoding for request bodies, so this is an extension
+            _ => json!({
+                "mimeType": mime_type,
+                "text": base64::encode(body),
+                "_encoding": "base64",
+            }),
+        };
+    }
+
+    let header = |name| {
+        response
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("")
+    };
+    let wait = response.time_starttransfer - response.time_namelookup;
+    json!({
+        "startedDateTime": DateTime::<Utc>::from(sent.time)
+            .to_rfc3339_opts(SecondsFormat::Millis, true),
+        "time": millis(response.time_total),
+        "request": request,
+        "response": {
+            "status": response.status.as_u16(),
+            "statusText": response.status.canonical_reason().unwrap_or(""),
+            "httpVersion": version,
+            "cookies": [],
+            "headers": headers(&response.headers),
+            // The body is streamed elsewhere, so only its size is known
+            "content": {
+                "size": response.size_download,
+                "mimeType": header(CONTENT_TYPE),
+            },
+            "redirectURL": header(LOCATION),
+            "headersSize": -1,
+            "bodySize": response.size_download,
+        },
+        "cache": {},
+        // Connecting and TLS can't be told apart from waiting for the response
+        "timings": {
+            "dns": millis(response.time_namelookup),
+            "connect": -1,
+            "ssl": -1,
+            "send": 0,
+            "wait": millis(wait),
+            "receive": millis(response.time_total - response.time_starttransfer),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use reqwest::header::{HeaderValue, AUTHORIZATION};
+    use reqwest::{Method, StatusCode, Url, Version};
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn exchange(time: SystemTime) -> (Sent, Metadata) {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer hunter2"));
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        let sent = Sent {
+            method: Method::PUT,
+            url: Url::parse("https://example.com/items/1?dry_run=1").unwrap(),
+            headers,
+            body: Some(b"{\"a\":1}".to_vec()),
+            time,
+        };
+        let metadata = Metadata {
+            method: Method::PUT,
+            status: StatusCode::OK,
+            version: Version::HTTP_2,
+            url: sent.url.clone(),
+            remote_addr: None,
+            headers: HeaderMap::new(),
+            time_namelookup: Duration::from_millis(5),
+            time_starttransfer: Duration::from_millis(45),
+            time_total: Duration::from_millis(50),
+            size_download: 12,
+        };
+        (sent, metadata)
+    }
+
+    #[test]
+    fn har_entry() {
+        let (sent, metadata) = exchange(UNIX_EPOCH + Duration::from_secs(1_622_548_800));
+        let entry = entry(&sent, &metadata, Some(&[]));
+        assert_eq!(entry["startedDateTime"], "2021-06-01T12:00:00.000Z");
+        assert_eq!(entry["time"], 50.0);
+        assert_eq!(entry["request"]["httpVersion"], "HTTP/2.0");
+        assert!(entry["request"]["headers"]
+            .as_array()
+            .unwrap()
+            .contains(&json!({ "name": "authorization", "value": "Bearer ********" })));
+        assert_eq!(
+            entry["request"]["queryString"],
+            json!([{ "name": "dry_run", "value": "1" }])
+        );
+        assert_eq!(
+            entry["request"]["postData"],
+            json!({ "mimeType": "application/json", "text": "{\"a\":1}" })
+        );
+        assert_eq!(entry["response"]["statusText"], "OK");
+        assert_eq!(entry["timings"]["wait"], 40.0);
+        assert_eq!(entry["timings"]["receive"], 5.0);
+    }
+
+    #[test]
+    fn appends_to_the_day() {
+        let dir = tempdir().unwrap();
+        let archive = HarArchive::new(&dir.path().join("history"), None);
+        let (sent, metadata) = exchange(SystemTime::now());
+        archive.record(&sent, &metadata).unwrap();
+        archive.record(&sent, &metadata).unwrap();
+
+        let files: Vec<_> = fs::read_dir(dir.path().join("history"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].starts_with("xh-") && files[0].ends_with(".har"));
+        let har: Value =
+            serde_json::from_slice(&fs::read(dir.path().join("history").join(&files[0])).unwrap())
+                .unwrap();
+        assert_eq!(har["log"]["entries"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            har["log"]["entries"][0]["request"]["headers"][0]["value"],
+            "Bearer hunter2"
+        );
+    }
+}
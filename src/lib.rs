@@ -0,0 +1,362 @@
+//! xh's request building, pretty-printing and downloads, for use in other tools.
+//!
+//! The `xh` binary only calls [`main`], and [`run`] does the same within
+//! another program. The parts it's made of are:
+//!
+//! - [`cli::Cli`], the command line options, which the other parts take their
+//!   settings from
+//! - [`request_items`], which turns items like `name=value` and `Header:value`
+//!   into headers, a query and a body
+//! - [`build_client`] and [`build_request`], which set up a request like xh does
+//! - [`printer::Printer`], with [`formatting`] and [`buffer`], which prints
+//!   requests and responses with colors and indentation
+//! - [`download`], which saves a response body to a file with a progress bar
+//!
+//! ```
+//! use xh::cli::Cli;
+//! use xh::request_items::RequestItems;
+//! use xh::url::construct_url;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let args = Cli::from_iter_safe(&["xh", "example.org/items", "name=xh", "X-Api-Key:1234"])?;
+//! let items = RequestItems::new(args.request_items.clone());
+//! let (headers, _) = items.headers()?;
+//! let url = construct_url(&args.url, None, &items.query()?, &[], args.encode_set)?;
+//! let body = items.body(args.request_type, &args.part_header)?;
+//!
+//! let client = xh::build_client(&args, false, None, None)?;
+//! let request = xh::build_request(&client, &args, body.pick_method(), url, body)?
+//!     .headers(headers)
+//!     .build()?;
+//! assert_eq!(request.method(), "POST");
+//! assert_eq!(request.url().as_str(), "http://example.org/items");
+//! # Ok(())
+//! # }
+//! ```
+
+mod audit;
+mod auth;
+mod batch;
+pub mod buffer;
+mod cache;
+mod certificate;
+pub mod cli;
+mod collection;
+mod config;
+mod cookies;
+pub mod download;
+pub mod formatting;
+mod generators;
+mod har;
+mod identity;
+mod markdown;
+mod metalink;
+mod negotiate;
+#[cfg(feature = "otel")]
+mod otel;
+mod output;
+mod paginate;
+mod peek;
+mod pkcs11;
+pub mod printer;
+mod problem;
+mod proxy;
+mod redact;
+mod reindent;
+mod request;
+mod request_file;
+pub mod request_items;
+mod runtime;
+mod send;
+mod serve;
+mod stats;
+mod table;
+mod template;
+pub mod timing;
+mod to_curl;
+mod trace;
+pub mod url;
+mod utils;
+mod vars;
+
+use std::io::{self, stdout, Write};
+use std::mem;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use reqwest::header::HeaderMap;
+use reqwest::{Method, StatusCode};
+
+use crate::auth::parse_auth;
+use crate::cli::{Cli, ExitCodeMap, Outcome};
+use crate::config::Config;
+use crate::request::is_stdin_body;
+use crate::request_file::RequestFile;
+use crate::runtime::Runtime;
+use crate::send::send;
+use crate::timing::{DnsError, Timings};
+use crate::utils::{find_cause, BodyTooLarge, Cutoff, DeadlineExceeded};
+use crate::vars::Vars;
+
+pub use crate::request::{build_client, build_request};
+
+/// What [`run`] did: the exit status `xh` would have, and the response it got.
+#[derive(Debug, Default)]
+pub struct RunResult {
+    pub exit_code: i32,
+    /// The status of the response. `None` if no request was sent, like with
+    /// `--offline` or `--curl`, or if several were, like with `--input` or
+    /// more than one URL. With `--paginate` it's the first page's.
+    pub status: Option<StatusCode>,
+    /// The headers of the response, if there was one
+    pub headers: HeaderMap,
+    /// The file the body went to, with `--output` or `--download`. Otherwise
+    /// it was printed to stdout, so pass `--output` to read it afterwards.
+    pub body_path: Option<PathBuf>,
+    /// How long the request took, if there was a response
+    pub timings: Option<Timings>,
+}
+
+impl RunResult {
+    fn exited(exit_code: i32) -> RunResult {
+        RunResult {
+            exit_code,
+            ..RunResult::default()
+        }
+    }
+}
+
+/// Run xh with the arguments of the process, like the `xh` binary does.
+/// Returns the exit status.
+pub fn main() -> Result<i32> {
+    let args = Cli::from_args();
+    let config = Config::load()?;
+    let runtime = Runtime::new(&args, &config);
+    let json_output = args.json_output;
+    match run_with_config(args, &config, &runtime) {
+        Ok(result) => Ok(result.exit_code),
+        Err(err) if json_output || error_exit_code(&err, &runtime.exit_codes) != 1 => {
+            Ok(print_error(&err, &runtime.exit_codes, json_output))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Do what the `xh` binary does with `args`, within this process. Output is
+/// printed the same way, and errors are returned rather than printed.
+///
+/// ```no_run
+/// use xh::cli::Cli;
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let args = Cli::from_iter_safe(&["xh", "--ignore-stdin", "-o", "item.json", "example.org/item"])?;
+/// let result = xh::run(args)?;
+/// if result.status.map_or(false, |status| status.is_success()) {
+///     let body = std::fs::read(result.body_path.unwrap())?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn run(args: Cli) -> Result<RunResult> {
+    let config = Config::load()?;
+    let runtime = Runtime::new(&args, &config);
+    run_with_config(args, &config, &runtime)
+}
+
+fn run_with_config(mut args: Cli, config: &Config, runtime: &Runtime) -> Result<RunResult> {
+    if let Some(path) = &args.request_file {
+        RequestFile::load(path)?.apply(&mut args)?;
+    }
+    let mut listed_urls = match &args.urls_from {
+        Some(path) => batch::read_urls(path)?,
+        None => Vec::new(),
+    };
+    if let Some(vars) = Vars::from_args(&args)? {
+        vars.apply(&mut args)?;
+        for (_, url) in &mut listed_urls {
+            *url = vars.substitute(url)?;
+        }
+    }
+
+    if args.serve {
+        serve::serve(&args, config).map(RunResult::exited)
+    } else if args.run.is_some() {
+        collection::run(args, config, runtime).map(RunResult::exited)
+    } else if args.more_urls.is_empty() && args.urls_from.is_none() {
+        send(args, config, runtime).map_err(|err| blame_deadline(err, runtime.cutoff))
+    } else {
+        run_urls(args, listed_urls, config, runtime).map(RunResult::exited)
+    }
+}
+
+/// The exit status for an error, 1 unless it's a kind scripts may want to
+/// tell apart.
+fn error_exit_code(err: &anyhow::Error, exit_codes: &ExitCodeMap) -> i32 {
+    match error_outcome(err) {
+        _ if err.downcast_ref::<DeadlineExceeded>().is_some() => {
+            exit_codes.code(Outcome::Timeout, 2)
+        }
+        Some(Outcome::BodyTooLarge) => exit_codes.code(Outcome::BodyTooLarge, 7),
+        Some(outcome) => exit_codes.code(outcome, 1),
+        None => 1,
+    }
+}
+
+/// Print an error, as JSON for --json-output, and return the exit status.
+fn print_error(err: &anyhow::Error, exit_codes: &ExitCodeMap, json: bool) -> i32 {
+    if json {
+        eprintln!(
+            "{}",
+            serde_json::json!({
+                "type": "error",
+                "kind": error_outcome(err).map_or("other", Outcome::kind),
+                "message": format!("{:#}", err),
+            })
+        );
+    } else {
+        eprintln!("Error: {:?}", err);
+    }
+    error_exit_code(err, exit_codes)
+}
+
+/// The kind of failure behind an error, if it's one that's told apart. Its
+/// [`Outcome::kind`] is a stable name for it, unlike the error's message.
+pub fn error_outcome(err: &anyhow::Error) -> Option<Outcome> {
+    let http_error = find_cause::<reqwest::Error>(err);
+    if find_cause::<BodyTooLarge>(err).is_some() {
+        Some(Outcome::BodyTooLarge)
+    } else if http_error.map_or(false, reqwest::Error::is_redirect) {
+        Some(Outcome::TooManyRedirects)
+    } else if err.downcast_ref::<DeadlineExceeded>().is_some()
+        || http_error.map_or(false, reqwest::Error::is_timeout)
+        || find_cause::<io::Error>(err).map_or(false, |err| err.kind() == io::ErrorKind::TimedOut)
+    {
+        Some(Outcome::Timeout)
+    } else if find_cause::<rustls::Error>(err).is_some() {
+        Some(Outcome::Tls)
+    } else if find_cause::<DnsError>(err).is_some() {
+        Some(Outcome::Dns)
+    } else if http_error.map_or(false, reqwest::Error::is_connect) {
+        Some(Outcome::Connect)
+    } else if find_cause::<io::Error>(err).is_some() {
+        Some(Outcome::Io)
+    } else {
+        None
+    }
+}
+
+/// The exit status for a response, if it's a failure.
+fn status_exit_code(args: &Cli, exit_codes: &ExitCodeMap, status: u16) -> i32 {
+    match status {
+        code if args.expect_status == Some(code) => 0,
+        _ if args.expect_status.is_some() => 1,
+        _ if !(args.check_status || args.download) => 0,
+        300..=399 if !args.follow => exit_codes.code(Outcome::Redirect, 3),
+        400..=499 => exit_codes.code(Outcome::ClientError, 4),
+        500..=599 => exit_codes.code(Outcome::ServerError, 5),
+        _ => 0,
+    }
+}
+
+/// Report a timeout as running out of --deadline, if it has. Requests time
+/// out when it does, so that's what happened.
+fn blame_deadline(err: anyhow::Error, cutoff: Option<Cutoff>) -> anyhow::Error {
+    match cutoff {
+        Some(cutoff)
+            if cutoff.passed()
+                && err.downcast_ref::<DeadlineExceeded>().is_none()
+                && error_outcome(&err) == Some(Outcome::Timeout) =>
+        {
+            err.context(DeadlineExceeded(cutoff.limit))
+        }
+        _ => err,
+    }
+}
+
+/// Send the same request to every URL, one after the other. `listed_urls`
+/// come from --urls-from and may have their own method.
+fn run_urls(
+    mut args: Cli,
+    listed_urls: Vec<(Option<Method>, String)>,
+    config: &Config,
+    runtime: &Runtime,
+) -> Result<i32> {
+    share_args(&mut args)?;
+    let mut urls = Vec::new();
+    if !args.url.is_empty() {
+        urls.push((None, mem::take(&mut args.url)));
+    }
+    urls.extend(args.more_urls.drain(..).map(|url| (None, url)));
+    urls.extend(listed_urls);
+
+    let requests = urls.into_iter().map(|(method, url)| {
+        let label = match &method {
+            Some(method) => format!("{} {}", method, url),
+            None => url.clone(),
+        };
+        let mut args = args.clone();
+        if method.is_some() {
+            args.method = method;
+        }
+        args.url = url;
+        (label, args)
+    });
+    run_each(requests, config, runtime)
+}
+
+/// Get options ready to be used for several requests: prompt for passwords
+/// once instead of for every request, and don't read stdin.
+fn share_args(args: &mut Cli) -> Result<()> {
+    if let Some(auth) = args.auth.take() {
+        let (username, password) = parse_auth(auth, "<host>", args.password_stdin)?;
+        args.auth = Some(format!("{}:{}", username, password.unwrap_or_default()));
+        args.password_stdin = false;
+    }
+    if let Some(auth) = args.proxy_auth.take() {
+        let (username, password) = parse_auth(auth, "<proxy>", false)?;
+        args.proxy_auth = Some(format!("{}:{}", username, password.unwrap_or_default()));
+    }
+    // A body from stdin can only be read once
+    if args.request_items.iter().any(is_stdin_body) {
+        return Err(anyhow!(
+            "@- only works for a single request, stdin can only be read once"
+        ));
+    }
+    args.ignore_stdin = true;
+    Ok(())
+}
+
+/// Send requests one after the other, each preceded by a `==> label <==`
+/// line, and return the highest exit status.
+fn run_each(
+    requests: impl Iterator<Item = (String, Cli)>,
+    config: &Config,
+    runtime: &Runtime,
+) -> Result<i32> {
+    let mut exit_code = 0;
+    for (index, (label, args)) in requests.enumerate() {
+        if args.quiet == 0 && !args.download && !args.curl {
+            let mut stdout = stdout();
+            if index > 0 {
+                writeln!(stdout)?;
+            }
+            writeln!(stdout, "==> {} <==", label)?;
+            stdout.flush()?;
+        }
+        let json_output = args.json_output;
+        let code = match send(args, config, runtime) {
+            Ok(result) => result.exit_code,
+            Err(err) => {
+                let err = blame_deadline(err, runtime.cutoff);
+                let code = print_error(&err, &runtime.exit_codes, json_output);
+                // The requests after it would fail the same way
+                if err.downcast_ref::<DeadlineExceeded>().is_some() {
+                    return Ok(exit_code.max(code));
+                }
+                code
+            }
+        };
+        exit_code = exit_code.max(code);
+    }
+    Ok(exit_code)
+}
@@ -0,0 +1,329 @@
+// Renders markdown for the terminal, for --render-markdown
+
+use std::io::{self, Write};
+
+use pulldown_cmark::{CodeBlockKind, Event, LinkType, Options, Parser, Tag};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+use termcolor::{ColorSpec, WriteColor};
+
+use crate::{
+    buffer::Buffer,
+    cli::Theme,
+    formatting::{find_syntax, Highlighter},
+};
+
+/// How to color the output. Without this only the layout is rendered.
+pub struct Colors<'a> {
+    pub theme: Theme,
+    pub custom_syntaxes: Option<&'a SyntaxSet>,
+    pub highlight_max_size: Option<u64>,
+}
+
+pub fn render(text: &str, out: &mut Buffer, colors: Option<Colors<'_>>) -> io::Result<()> {
+    let mut renderer = Renderer {
+        out,
+        colors,
+        prefix: Vec::new(),
+        bullet: None,
+        lists: Vec::new(),
+        line_started: false,
+        blank_line: false,
+        heading: None,
+        strong: 0,
+        emphasis: 0,
+        links: Vec::new(),
+        code: None,
+    };
+    let options = Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    for event in Parser::new_ext(text, options) {
+        renderer.event(event)?;
+    }
+    renderer.end_line()
+}
+
+struct Renderer<'a, 'b> {
+    out: &'a mut Buffer,
+    colors: Option<Colors<'b>>,
+    /// What continuation lines start with, e.g. indentation for list items
+    prefix: Vec<String>,
+    /// A list marker to print instead of the last part of the prefix
+    bullet: Option<String>,
+    /// The next number of each ordered list we're in
+    lists: Vec<Option<u64>>,
+    line_started: bool,
+    blank_line: bool,
+    heading: Option<u32>,
+    strong: usize,
+    emphasis: usize,
+    links: Vec<(LinkType, String)>,
+    /// The language and contents of the code block we're in
+    code: Option<(String, String)>,
+}
+
+impl Renderer<'_, '_> {
+    fn event(&mut self, event: Event<'_>) -> io::Result<()> {
+        match event {
+            Event::Start(Tag::Paragraph) => self.begin_block()?,
+            Event::End(Tag::Paragraph) => self.end_block()?,
+            Event::Start(Tag::Heading(level)) => {
+                self.begin_block()?;
+                self.heading = Some(level);
+                self.text(&format!("{} ", "#".repeat(level as usize)))?;
+            }
+            Event::End(Tag::Heading(_)) => {
+                self.heading = None;
+                self.end_block()?;
+            }
+            Event::Start(Tag::BlockQuote) => {
+                self.begin_block()?;
+                self.prefix.push("│ ".to_string());
+            }
+            Event::End(Tag::BlockQuote) => {
+                self.end_line()?;
+                self.prefix.pop();
+                self.blank_line = true;
+            }
+            Event::Start(Tag::List(start)) => {
+                self.begin_block()?;
+                self.lists.push(start);
+            }
+            Event::End(Tag::List(_)) => {
+                self.end_line()?;
+                self.lists.pop();
+                // Nested lists stay part of their item
+                self.blank_line = self.lists.is_empty();
+            }
+            Event::Start(Tag::Item) => {
+                self.end_line()?;
+                self.blank()?;
+                let bullet = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "• ".to_string(),
+                };
+                self.prefix.push(" ".repeat(bullet.chars().count()));
+                self.bullet = Some(bullet);
+            }
+            Event::End(Tag::Item) => {
+                self.end_line()?;
+                self.prefix.pop();
+                self.bullet = None;
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                self.begin_block()?;
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => {
+                        info.split_whitespace().next().unwrap_or("").to_string()
+                    }
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.code = Some((language, String::new()));
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                if let Some((language, code)) = self.code.take() {
+                    self.code_block(&language, &code)?;
+                }
+                self.end_block()?;
+            }
+            Event::Start(Tag::Emphasis) => self.emphasis += 1,
+            Event::End(Tag::Emphasis) => self.emphasis -= 1,
+            Event::Start(Tag::Strong) => self.strong += 1,
+            Event::End(Tag::Strong) => self.strong -= 1,
+            Event::Start(Tag::Strikethrough) | Event::End(Tag::Strikethrough) => self.text("~~")?,
+            Event::Start(Tag::Link(link_type, url, _)) => {
+                self.links.push((link_type, url.to_string()));
+            }
+            Event::End(Tag::Link(..)) => {
+                if let Some((link_type, url)) = self.links.pop() {
+                    // Autolinks already show their address
+                    if !matches!(link_type, LinkType::Autolink | LinkType::Email) {
+                        self.text(&format!(" <{}>", url))?;
+                    }
+                }
+            }
+            Event::Start(Tag::Image(..)) => self.text("[image: ")?,
+            Event::End(Tag::Image(_, url, _)) => self.text(&format!("] <{}>", url))?,
+            Event::Start(Tag::FootnoteDefinition(name)) => {
+                self.begin_block()?;
+                self.text(&format!("[^{}]: ", name))?;
+            }
+            Event::End(Tag::FootnoteDefinition(_)) => self.end_block()?,
+            Event::Start(_) | Event::End(_) => {}
+            Event::Text(text) => match &mut self.code {
+                Some((_, code)) => code.push_str(&text),
+                None => self.text(&text)?,
+            },
+            Event::Code(code) => self.text(&format!("`{}`", code))?,
+            Event::Html(html) => {
+                for (i, line) in html.trim_end_matches('\n').split('\n').enumerate() {
+                    if i > 0 {
+                        self.end_line()?;
+                    }
+                    self.text(line)?;
+                }
+            }
+            Event::FootnoteReference(name) => self.text(&format!("[^{}]", name))?,
+            Event::SoftBreak | Event::HardBreak => self.end_line()?,
+            Event::Rule => {
+                self.begin_block()?;
+                self.text(&"─".repeat(40))?;
+                self.end_block()?;
+            }
+            Event::TaskListMarker(done) => self.text(if done { "[x] " } else { "[ ] " })?,
+        }
+        Ok(())
+    }
+
+    fn begin_block(&mut self) -> io::Result<()> {
+        self.end_line()?;
+        self.blank()
+    }
+
+    fn end_block(&mut self) -> io::Result<()> {
+        self.end_line()?;
+        self.blank_line = true;
+        Ok(())
+    }
+
+    /// Print the blank line that separates blocks, if one is due.
+    fn blank(&mut self) -> io::Result<()> {
+        if self.blank_line {
+            let prefix = self.prefix.concat();
+            writeln!(self.out, "{}", prefix.trim_end())?;
+            self.blank_line = false;
+        }
+        Ok(())
+    }
+
+    fn begin_line(&mut self) -> io::Result<()> {
+        if !self.line_started {
+            match self.bullet.take() {
+                Some(bullet) => {
+                    let outer = &self.prefix[..self.prefix.len() - 1];
+                    write!(self.out, "{}{}", outer.concat(), bullet)?;
+                }
+                None => write!(self.out, "{}", self.prefix.concat())?,
+            }
+            self.line_started = true;
+        }
+        Ok(())
+    }
+
+    fn end_line(&mut self) -> io::Result<()> {
+        if self.line_started {
+            self.out.write_all(b"\n")?;
+            self.line_started = false;
+        }
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str) -> io::Result<()> {
+        self.begin_line()?;
+        let bold = self.strong > 0 || self.heading.is_some();
+        let italic = self.emphasis > 0;
+        let underline = self.heading == Some(1) || !self.links.is_empty();
+        if self.colors.is_none() || !(bold || italic || underline) {
+            return self.out.write_all(text.as_bytes());
+        }
+        let mut spec = ColorSpec::new();
+        spec.set_bold(bold)
+            .set_italic(italic)
+            .set_underline(underline);
+        self.out.set_color(&spec)?;
+        self.out.write_all(text.as_bytes())?;
+        self.out.reset()
+    }
+
+    /// Print a code block indented, and highlighted if its language is known.
+    fn code_block(&mut self, language: &str, code: &str) -> io::Result<()> {
+        self.begin_line()?;
+        let indent = format!("{}    ", self.prefix.concat());
+        self.out.write_all(b"    ")?;
+        let colors = self
+            .colors
+            .as_ref()
+            .filter(|colors| find_syntax(language, colors.custom_syntaxes).is_some());
+        match colors {
+            Some(colors) => {
+                let mut highlighter = Highlighter::new(
+                    language,
+                    colors.custom_syntaxes,
+                    colors.theme,
+                    colors.highlight_max_size,
+                    self.out,
+                );
+                for (i, line) in LinesWithEndings::from(code).enumerate() {
+                    if i > 0 {
+                        highlighter.write_plain(&indent)?;
+                    }
+                    highlighter.highlight(line)?;
+                }
+            }
+            None => {
+                for (i, line) in LinesWithEndings::from(code).enumerate() {
+                    if i > 0 {
+                        self.out.write_all(indent.as_bytes())?;
+                    }
+                    self.out.write_all(line.as_bytes())?;
+                }
+            }
+        }
+        // The code ends with a line break of its own
+        self.line_started = code.is_empty();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_plain(text: &str) -> String {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut buffer = Buffer::new(false, Some(file.path()), false, None, None, None).unwrap();
+        render(text, &mut buffer, None).unwrap();
+        buffer.flush().unwrap();
+        drop(buffer);
+        std::fs::read_to_string(file.path()).unwrap()
+    }
+
+    #[test]
+    fn layout() {
+        let text = indoc::indoc! {"
+            # Changelog
+
+            Some *text*
+            over [two](https://example.com) lines.
+
+            - one
+            - two
+              1. nested
+
+            > quoted
+
+            ```json
+            {\"a\": 1}
+            ```
+        "};
+        assert_eq!(
+            render_plain(text),
+            indoc::indoc! {"
+                # Changelog
+
+                Some text
+                over two <https://example.com> lines.
+
+                • one
+                • two
+                  1. nested
+
+                │ quoted
+
+                    {\"a\": 1}
+            "}
+        );
+    }
+}
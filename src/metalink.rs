@@ -0,0 +1,260 @@
+// Metalink/HTTP (RFC 6249): mirrors and checksums advertised in headers
+//
+// A server lists mirrors of a download as `Link: <url>; rel=duplicate` and
+// the hash of the whole file in `Repr-Digest` (RFC 9530) or the older
+// `Digest` (RFC 3230). XML Metalink files aren't supported, --mirror covers
+// that use.
+
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use reqwest::header::{HeaderMap, LINK};
+use reqwest::Url;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::regex;
+use crate::utils::{to_hex, BUFFER_SIZE};
+
+/// A target from a Link header. Parameter names are lowercased.
+#[derive(Debug)]
+pub struct Link {
+    pub url: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl Link {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether `rel` is one of the link's relation types.
+    pub fn has_rel(&self, rel: &str) -> bool {
+        self.param("rel").map_or(false, |rels| {
+            rels.split_whitespace()
+                .any(|value| value.eq_ignore_ascii_case(rel))
+        })
+    }
+}
+
+/// Parse all Link headers, skipping anything malformed.
+pub fn parse_links(headers: &HeaderMap) -> Vec<Link> {
+    regex!(TARGET = r#"<([^>]*)>((?:\s*;\s*[^;,=\s]+(?:\s*=\s*(?:"[^"]*"|[^;,\s]*))?)*)"#);
+    regex!(PARAM = r#";\s*([^;,=\s]+)(?:\s*=\s*(?:"([^"]*)"|([^;,\s]*)))?"#);
+
+    let mut links = Vec::new();
+    for value in headers.get_all(LINK) {
+        let value = match value.to_str() {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        for target in TARGET.captures_iter(value) {
+            let params = PARAM
+                .captures_iter(&target[2])
+                .map(|param| {
+                    let value = param.get(2).or_else(|| param.get(3));
+                    (
+                        param[1].to_ascii_lowercase(),
+                        value.map_or("", |value| value.as_str()).to_string(),
+                    )
+                })
+                .collect();
+            links.push(Link {
+                url: target[1].to_string(),
+                params,
+            });
+        }
+    }
+    links
+}
+
+/// The mirrors a server advertised, most preferred first.
+pub fn mirrors(headers: &HeaderMap, base: &Url) -> Vec<Url> {
+    let mut mirrors: Vec<(u32, Url)> = parse_links(headers)
+        .into_iter()
+        .filter(|link| link.has_rel("duplicate"))
+        .filter_map(|link| {
+            let priority = link
+                .param("pri")
+                .and_then(|pri| pri.parse().ok())
+                .unwrap_or(u32::MAX);
+            Some((priority, base.join(&link.url).ok()?))
+        })
+        .collect();
+    mirrors.sort_by_key(|(priority, _)| *priority);
+    mirrors.into_iter().map(|(_, url)| url).collect()
+}
+
+/// A hash of the whole file, as advertised by the server.
+#[derive(Debug)]
+pub struct Checksum {
+    name: &'static str,
+    algorithm: Algorithm,
+    expected: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Algorithm {
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    fn output_len(self) -> usize {
+        match self {
+            Algorithm::Sha256 => 32,
+            Algorithm::Sha512 => 64,
+        }
+    }
+}
+
+/// Hash everything `reader` has left.
+fn hash<D: Digest>(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let mut hasher = D::new();
+    let mut buf = vec![0; BUFFER_SIZE];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => hasher.update(&buf[..len]),
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+impl Checksum {
+    /// Pick the strongest checksum in the response headers.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Checksum> {
+        let mut checksums = Vec::new();
+        for (header, structured) in &[("repr-digest", true), ("digest", false)] {
+            for value in headers.get_all(*header) {
+                let value = match value.to_str() {
+                    Ok(value) => value,
+                    Err(_) => continue,
+                };
+                checksums.extend(value.split(',').filter_map(|item| {
+                    let index = item.find('=')?;
+                    let encoded = item[index + 1..].trim();
+                    // Repr-Digest uses structured fields, with byte sequences in colons
+                    let encoded = if *structured {
+                        encoded.strip_prefix(':')?.strip_suffix(':')?
+                    } else {
+                        encoded
+                    };
+                    Checksum::new(item[..index].trim(), base64::decode(encoded).ok()?)
+                }));
+            }
+        }
+        checksums
+            .into_iter()
+            .max_by_key(|checksum| checksum.algorithm.output_len())
+    }
+
+    fn new(name: &str, expected: Vec<u8>) -> Option<Checksum> {
+        let (name, algorithm) = match name.to_ascii_lowercase().as_str() {
+            "sha-256" => ("SHA-256", Algorithm::Sha256),
+            "sha-512" => ("SHA-512", Algorithm::Sha512),
+            _ => return None,
+        };
+        if expected.len() != algorithm.output_len() {
+            return None;
+        }
+        Some(Checksum {
+            name,
+            algorithm,
+            expected,
+        })
+    }
+
+    pub fn verify(&self, path: &Path) -> Result<()> {
+        let mut file = File::open(path)?;
+        let actual = match self.algorithm {
+            Algorithm::Sha256 => hash::<Sha256>(&mut file)?,
+            Algorithm::Sha512 => hash::<Sha512>(&mut file)?,
+        };
+        if actual != self.expected {
+            return Err(anyhow!(
+                "{} checksum mismatch: the server advertised {} but the download has {}",
+                self.name,
+                to_hex(&self.expected),
+                to_hex(&actual)
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use std::io::Write;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn links() {
+        let headers = headers(&[
+            (
+                "link",
+                r#"<https://a.example/f>; rel="duplicate foo"; pri=2, </f>; rel=describedby"#,
+            ),
+            ("link", "<http://b.example/f>;rel=duplicate;pri=1"),
+        ]);
+        let links = parse_links(&headers);
+        assert_eq!(links.len(), 3);
+        assert_eq!(links[0].url, "https://a.example/f");
+        assert!(links[0].has_rel("duplicate"));
+        assert_eq!(links[0].param("pri"), Some("2"));
+        assert!(!links[1].has_rel("duplicate"));
+
+        let base = Url::parse("http://example.org/dir/f").unwrap();
+        let mirrors: Vec<String> = mirrors(&headers, &base)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(mirrors, ["http://b.example/f", "https://a.example/f"]);
+    }
+
+    #[test]
+    fn checksums() {
+        // SHA-256 and SHA-512 of "hello"
+        let sha256 = "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=";
+        let sha512 = "m3HSJL1i83hdltRq0+o9czGb+8KJDKra4t/3JRlnPKcjI8PZm6XBHXx6zG4UuMXaDEZjR1wuXDre9G9zvN7AQw==";
+
+        let checksum = Checksum::from_headers(&headers(&[(
+            "digest",
+            "MD5=XUFAKrxLKna5cZ2REBfFkg==,SHA-256=LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=",
+        )]))
+        .unwrap();
+        assert_eq!(checksum.name, "SHA-256");
+        assert_eq!(checksum.expected, base64::decode(sha256).unwrap());
+
+        let checksum = Checksum::from_headers(&headers(&[(
+            "repr-digest",
+            "sha-256=:LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=:, sha-512=:m3HSJL1i83hdltRq0+o9czGb+8KJDKra4t/3JRlnPKcjI8PZm6XBHXx6zG4UuMXaDEZjR1wuXDre9G9zvN7AQw==:",
+        )]))
+        .unwrap();
+        assert_eq!(checksum.name, "SHA-512");
+        assert_eq!(checksum.expected, base64::decode(sha512).unwrap());
+
+        assert!(Checksum::from_headers(&headers(&[("digest", "sha-256=short")])).is_none());
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"hello").unwrap();
+        checksum.verify(file.path()).unwrap();
+        file.write_all(b"!").unwrap();
+        assert!(checksum.verify(file.path()).is_err());
+    }
+}
@@ -0,0 +1,425 @@
+// SPNEGO tokens for --auth-type=negotiate, from the system's GSSAPI library
+//
+// The library is loaded at runtime so that xh doesn't need it to start, and
+// so that builds don't need its headers.
+
+use anyhow::{anyhow, Result};
+
+/// Get an `Authorization: Negotiate` header value for `host`.
+///
+/// This uses the credentials in the user's Kerberos ticket cache (see `kinit`).
+/// Only Unix has a GSSAPI library to get them from; Windows would need SSPI.
+/// macOS's GSS framework packs its structs differently from everyone else, so
+/// it's left out rather than called with the wrong layout.
+pub fn authorization(host: &str) -> Result<String> {
+    imp::token(host)
+        .map(|token| header_value(&token))
+        .map_err(|err| anyhow!("Negotiate authentication failed: {}", err))
+}
+
+fn header_value(token: &[u8]) -> String {
+    format!("Negotiate {}", base64::encode(token))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod imp {
+    use std::ffi::c_void;
+    use std::ptr;
+
+    use libloading::Library;
+
+    type Status = u32;
+
+    #[repr(C)]
+    struct Buffer {
+        length: usize,
+        value: *mut c_void,
+    }
+
+    #[repr(C)]
+    struct Oid {
+        length: u32,
+        elements: *mut c_void,
+    }
+
+    type Name = *mut c_void;
+    type Context = *mut c_void;
+
+    // 1.2.840.113554.1.2.1.4
+    const HOSTBASED_SERVICE: &[u8] = b"\x2a\x86\x48\x86\xf7\x12\x01\x02\x01\x04";
+    // 1.3.6.1.5.5.2
+    const SPNEGO: &[u8] = b"\x2b\x06\x01\x05\x05\x02";
+
+    const GSS_CODE: i32 = 1;
+    const MECH_CODE: i32 = 2;
+
+    const LIBRARIES: &[&str] = &["libgssapi_krb5.so.2", "libgssapi.so.3"];
+
+    type ImportName = unsafe extern "C" fn(*mut Status, *mut Buffer, *mut Oid, *mut Name) -> Status;
+    type InitSecContext = unsafe extern "C" fn(
+        *mut Status,
+        *mut c_void,
+        *mut Context,
+        Name,
+        *mut Oid,
+        u32,
+        u32,
+        *mut c_void,
+        *mut Buffer,
+        *mut *mut Oid,
+        *mut Buffer,
+        *mut u32,
+        *mut u32,
+    ) -> Status;
+    type ReleaseName = unsafe extern "C" fn(*mut Status, *mut Name) -> Status;
+    type ReleaseBuffer = unsafe extern "C" fn(*mut Status, *mut Buffer) -> Status;
+    type DeleteSecContext = unsafe extern "C" fn(*mut Status, *mut Context, *mut Buffer) -> Status;
+    type DisplayStatus =
+        unsafe extern "C" fn(*mut Status, u32, i32, *mut Oid, *mut u32, *mut Buffer) -> Status;
+
+    /// The GSSAPI functions a token takes, with the signatures from RFC 2744.
+    struct Functions {
+        import_name: ImportName,
+        init_sec_context: InitSecContext,
+        release_name: ReleaseName,
+        release_buffer: ReleaseBuffer,
+        delete_sec_context: DeleteSecContext,
+        display_status: DisplayStatus,
+    }
+
+    impl Functions {
+        /// Look the functions up in `library`. They're only valid for as
+        /// long as it stays loaded.
+        ///
+        /// # Safety
+        ///
+        /// `library` must be a GSSAPI library.
+        unsafe fn load(library: &Library) -> Result<Functions, String> {
+            unsafe fn symbol<T: Copy>(library: &Library, name: &str) -> Result<T, String> {
+                library
+                    .get::<T>(name.as_bytes())
+                    .map(|symbol| *symbol)
+                    .map_err(|err| format!("the GSSAPI library is unusable: {}", err))
+            }
+            Ok(Functions {
+                import_name: symbol(library, "gss_import_name")?,
+                init_sec_context: symbol(library, "gss_init_sec_context")?,
+                release_name: symbol(library, "gss_release_name")?,
+                release_buffer: symbol(library, "gss_release_buffer")?,
+                delete_sec_context: symbol(library, "gss_delete_sec_context")?,
+                display_status: symbol(library, "gss_display_status")?,
+            })
+        }
+    }
+
+    fn is_error(major: Status) -> bool {
+        major & 0xffff_0000 != 0
+    }
+
+    pub fn token(host: &str) -> Result<Vec<u8>, String> {
+        let library = LIBRARIES
+            .iter()
+            // SAFETY: loading a library runs its initializers. These are the
+            // system's GSSAPI libraries, which are made to be loaded like this.
+            .find_map(|name| unsafe { Library::new(name).ok() })
+            .ok_or_else(|| format!("no GSSAPI library found (tried {})", LIBRARIES.join(", ")))?;
+        // SAFETY: `library` is a GSSAPI library, and stays loaded while its
+        // functions are called
+        unsafe {
+            let functions = Functions::load(&library)?;
+            init_context(&functions, host)
+        }
+    }
+
+    /// Copy out the contents of a buffer the library filled in. It may have
+    /// left it empty, with a null pointer.
+    ///
+    /// # Safety
+    ///
+    /// Unless it's null, `buffer.value` must point to `buffer.length` bytes.
+    unsafe fn buffer_bytes(buffer: &Buffer) -> Vec<u8> {
+        if buffer.value.is_null() || buffer.length == 0 {
+            return Vec::new();
+        }
+        std::slice::from_raw_parts(buffer.value as *const u8, buffer.length).to_vec()
+    }
+
+    /// Start a security context with `HTTP@host` and return its first token.
+    ///
+    /// # Safety
+    ///
+    /// `functions` must behave as RFC 2744 says. All pointers passed to them
+    /// point to locals that outlive the calls, and whatever they allocate is
+    /// released before returning.
+    unsafe fn init_context(functions: &Functions, host: &str) -> Result<Vec<u8>, String> {
+        let describe = |major: Status, minor: Status| {
+            let mut messages = Vec::new();
+            for &(code, kind) in &[(major, GSS_CODE), (minor, MECH_CODE)] {
+                if code == 0 {
+                    continue;
+                }
+                let mut context = 0;
+                loop {
+                    let mut minor = 0;
+                    let mut text = Buffer {
+                        length: 0,
+                        value: ptr::null_mut(),
+                    };
+                    let status = (functions.display_status)(
+                        &mut minor,
+                        code,
+                        kind,
+                        ptr::null_mut(),
+                        &mut context,
+                        &mut text,
+                    );
+                    if is_error(status) {
+                        break;
+                    }
+                    let bytes = buffer_bytes(&text);
+                    messages.push(String::from_utf8_lossy(&bytes).trim().to_string());
+                    (functions.release_buffer)(&mut minor, &mut text);
+                    if context == 0 {
+                        break;
+                    }
+                }
+            }
+            messages.join(": ")
+        };
+
+        let mut minor = 0;
+        let mut service = format!("HTTP@{}", host).into_bytes();
+        let mut service = Buffer {
+            length: service.len(),
+            value: service.as_mut_ptr() as *mut c_void,
+        };
+        let mut name_type = Oid {
+            length: HOSTBASED_SERVICE.len() as u32,
+            elements: HOSTBASED_SERVICE.as_ptr() as *mut c_void,
+        };
+        let mut name: Name = ptr::null_mut();
+        let major = (functions.import_name)(&mut minor, &mut service, &mut name_type, &mut name);
+        if is_error(major) {
+            return Err(describe(major, minor));
+        }
+
+        let mut mech = Oid {
+            length: SPNEGO.len() as u32,
+            elements: SPNEGO.as_ptr() as *mut c_void,
+        };
+        let mut context: Context = ptr::null_mut();
+        let mut output = Buffer {
+            length: 0,
+            value: ptr::null_mut(),
+        };
+        let major = (functions.init_sec_context)(
+            &mut minor,
+            ptr::null_mut(),
+            &mut context,
+            name,
+            &mut mech,
+            0,
+            0,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut output,
+            ptr::null_mut(),
+            ptr::null_mut(),
+        );
+        let result = if is_error(major) {
+            Err(describe(major, minor))
+        } else {
+            match buffer_bytes(&output) {
+                token if token.is_empty() => Err("the GSSAPI library gave no token".to_string()),
+                token => Ok(token),
+            }
+        };
+
+        let mut ignored = 0;
+        (functions.release_buffer)(&mut ignored, &mut output);
+        if !context.is_null() {
+            (functions.delete_sec_context)(&mut ignored, &mut context, ptr::null_mut());
+        }
+        (functions.release_name)(&mut ignored, &mut name);
+        result
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::cell::RefCell;
+        use std::slice;
+
+        // A stand-in for the library, which hands out a token for the name
+        // it was given and records what's released
+
+        thread_local! {
+            static RELEASED: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+            static FAIL: RefCell<bool> = const { RefCell::new(false) };
+        }
+
+        fn release(what: &'static str) {
+            RELEASED.with(|released| released.borrow_mut().push(what));
+        }
+
+        /// Move bytes into a buffer, to be freed by `release_buffer`.
+        fn fill(buffer: *mut Buffer, bytes: Vec<u8>) {
+            let bytes = Box::leak(bytes.into_boxed_slice());
+            unsafe {
+                (*buffer).length = bytes.len();
+                (*buffer).value = bytes.as_mut_ptr() as *mut c_void;
+            }
+        }
+
+        unsafe extern "C" fn import_name(
+            _minor: *mut Status,
+            input: *mut Buffer,
+            name_type: *mut Oid,
+            name: *mut Name,
+        ) -> Status {
+            let oid = slice::from_raw_parts(
+                (*name_type).elements as *const u8,
+                (*name_type).length as usize,
+            );
+            assert_eq!(oid, HOSTBASED_SERVICE);
+            let input = buffer_bytes(&*input);
+            *name = Box::into_raw(Box::new(input)) as Name;
+            0
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        unsafe extern "C" fn init_sec_context(
+            minor: *mut Status,
+            _credentials: *mut c_void,
+            context: *mut Context,
+            name: Name,
+            mech: *mut Oid,
+            _flags: u32,
+            _time: u32,
+            _bindings: *mut c_void,
+            _input: *mut Buffer,
+            _actual_mech: *mut *mut Oid,
+            output: *mut Buffer,
+            _ret_flags: *mut u32,
+            _time_rec: *mut u32,
+        ) -> Status {
+            if FAIL.with(|fail| *fail.borrow()) {
+                *minor = 7;
+                return 0x0007_0000;
+            }
+            let mech =
+                slice::from_raw_parts((*mech).elements as *const u8, (*mech).length as usize);
+            assert_eq!(mech, SPNEGO);
+            *context = Box::into_raw(Box::new(0u8)) as Context;
+            let mut token = b"token for ".to_vec();
+            token.extend_from_slice(&*(name as *const Vec<u8>));
+            fill(output, token);
+            0
+        }
+
+        unsafe extern "C" fn release_name(_minor: *mut Status, name: *mut Name) -> Status {
+            drop(Box::from_raw(*name as *mut Vec<u8>));
+            *name = ptr::null_mut();
+            release("name");
+            0
+        }
+
+        unsafe extern "C" fn release_buffer(_minor: *mut Status, buffer: *mut Buffer) -> Status {
+            if !(*buffer).value.is_null() {
+                let bytes = slice::from_raw_parts_mut((*buffer).value as *mut u8, (*buffer).length);
+                drop(Box::from_raw(bytes as *mut [u8]));
+                (*buffer).value = ptr::null_mut();
+                (*buffer).length = 0;
+                release("buffer");
+            }
+            0
+        }
+
+        unsafe extern "C" fn delete_sec_context(
+            _minor: *mut Status,
+            context: *mut Context,
+            _output: *mut Buffer,
+        ) -> Status {
+            drop(Box::from_raw(*context as *mut u8));
+            *context = ptr::null_mut();
+            release("context");
+            0
+        }
+
+        unsafe extern "C" fn display_status(
+            _minor: *mut Status,
+            code: u32,
+            kind: i32,
+            _mech: *mut Oid,
+            _context: *mut u32,
+            text: *mut Buffer,
+        ) -> Status {
+            let text_for = match kind {
+                GSS_CODE => format!("major {:#x}", code),
+                _ => format!("minor {}", code),
+            };
+            fill(text, text_for.into_bytes());
+            0
+        }
+
+        const STUB: Functions = Functions {
+            import_name,
+            init_sec_context,
+            release_name,
+            release_buffer,
+            delete_sec_context,
+            display_status,
+        };
+
+        #[test]
+        fn token_exchange() {
+            let token = unsafe { init_context(&STUB, "example.com") }.unwrap();
+            assert_eq!(token, b"token for HTTP@example.com");
+            RELEASED.with(|released| {
+                assert_eq!(*released.borrow(), ["buffer", "context", "name"]);
+            });
+        }
+
+        #[test]
+        fn failed_exchange() {
+            FAIL.with(|fail| *fail.borrow_mut() = true);
+            let err = unsafe { init_context(&STUB, "example.com") }.unwrap_err();
+            assert_eq!(err, "major 0x70000: minor 7");
+            // The messages are released too
+            RELEASED.with(|released| {
+                assert_eq!(*released.borrow(), ["buffer", "buffer", "name"]);
+            });
+        }
+
+        #[test]
+        fn empty_buffer() {
+            let buffer = Buffer {
+                length: 5,
+                value: ptr::null_mut(),
+            };
+            // SAFETY: the pointer is null
+            assert!(unsafe { buffer_bytes(&buffer) }.is_empty());
+        }
+    }
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+mod imp {
+    pub fn token(_host: &str) -> Result<Vec<u8>, String> {
+        Err("not supported on this platform".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_encoding() {
+        assert_eq!(
+            header_value(b"\x60\x82\x01\x9e\x06\x06\x2b\x06\x01\x05\x05\x02"),
+            "Negotiate YIIBngYGKwYBBQUC"
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use std::io::Read;
+
+use anyhow::Result;
+use reqwest::blocking::Response;
+use reqwest::Url;
+
+use crate::buffer::Buffer;
+use crate::cli::{Cli, FormatOption, Pretty};
+use crate::config::Config;
+use crate::paginate::Paginate;
+use crate::peek;
+use crate::printer::{BinaryMode, Printer, PrinterOptions};
+use crate::trace::Trace;
+use crate::utils::SizeLimit;
+
+/// Set up a printer for the formatting options in `args` and the config file.
+pub fn build_printer(
+    args: &Cli,
+    config: &Config,
+    pretty: Pretty,
+    buffer: Buffer,
+) -> Result<Printer> {
+    let custom_syntaxes = if pretty.color() {
+        config.load_syntaxes()?
+    } else {
+        None
+    };
+    let binary_mode = if args.force_text {
+        BinaryMode::Text
+    } else if args.force_binary {
+        BinaryMode::Binary
+    } else {
+        BinaryMode::Sniff
+    };
+    let options = PrinterOptions {
+        theme: args.style.or(config.style),
+        sort_headers: FormatOption::headers_sort(&args.format_options),
+        stream: args.stream,
+        json_lines: args.json_lines,
+        render_markdown: args.render_markdown,
+        table: args.table,
+        column_width: args.column_width,
+        binary_mode,
+        charset: args.response_charset,
+        formatters: config.formatters.clone(),
+        syntaxes: config.syntaxes.clone(),
+        custom_syntaxes,
+        format_max_size: args.format_max_size,
+        highlight_max_size: args.highlight_max_size,
+        redact: args.redact.clone(),
+        show_secrets: args.show_secrets,
+    };
+    Ok(Printer::new(pretty, options, buffer))
+}
+
+/// List the files in an archive body for --peek, and return how much of the
+/// body was read.
+pub fn peek_archive(
+    printer: &mut Printer,
+    response: Response,
+    print_body: bool,
+    max_body_size: Option<u64>,
+) -> Result<u64> {
+    let mut body = SizeLimit::new(response, max_body_size);
+    if print_body {
+        peek::list(&mut body, |entry| {
+            printer.print_raw(&format!("{}\n", entry))
+        })?;
+    }
+    Ok(body.bytes_read())
+}
+
+/// Print a response body if it's wanted, and find the page after it for
+/// --paginate. Returns how many bytes of the body were read.
+pub fn print_page(
+    printer: &mut Printer,
+    response: Response,
+    print_body: bool,
+    paginate: Option<&Paginate>,
+    max_body_size: Option<u64>,
+    trace: Option<&Trace>,
+) -> Result<(u64, Option<Url>)> {
+    let url = response.url().clone();
+    match paginate {
+        Some(paginate) if paginate.reads_body() => {
+            let headers = response.headers().clone();
+            let status = response.status();
+            let body: Box<dyn Read> = match trace {
+                Some(trace) => Box::new(trace.reader(response)),
+                None => Box::new(response),
+            };
+            let mut buffer = Vec::new();
+            SizeLimit::new(body, max_body_size).read_to_end(&mut buffer)?;
+            if print_body {
+                printer.print_buffered_body(&headers, status, &buffer)?;
+            }
+            let next = paginate.next_page(&url, &headers, Some(&buffer));
+            Ok((buffer.len() as u64, next))
+        }
+        _ => {
+            let next =
+                paginate.and_then(|paginate| paginate.next_page(&url, response.headers(), None));
+            let size = if print_body {
+                printer.print_response_body(response, max_body_size, trace)?
+            } else {
+                0
+            };
+            Ok((size, next))
+        }
+    }
+}
@@ -0,0 +1,162 @@
+// --paginate: follow a response to the pages after it, from `Link: <url>;
+// rel=next` (RFC 8288) or from a field in the JSON body
+
+use std::str::FromStr;
+
+use reqwest::header::HeaderMap;
+use reqwest::Url;
+use serde_json::Value;
+use structopt::clap::{Error, ErrorKind, Result};
+
+use crate::metalink::parse_links;
+
+/// Where the URL of the next page comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Paginate {
+    /// A Link header with rel=next, like GitHub sends
+    Link,
+    /// A field of the JSON body, found by following `path`. It holds a URL,
+    /// or with `param` a cursor to send as that query parameter.
+    Field {
+        path: Vec<String>,
+        param: Option<String>,
+    },
+}
+
+impl Paginate {
+    /// Whether the body has to be read to find the next page.
+    pub fn reads_body(&self) -> bool {
+        matches!(self, Paginate::Field { .. })
+    }
+
+    /// Find the next page after a response for `url`, if there is one.
+    pub fn next_page(&self, url: &Url, headers: &HeaderMap, body: Option<&[u8]>) -> Option<Url> {
+        match self {
+            Paginate::Link => parse_links(headers)
+                .into_iter()
+                .find(|link| link.has_rel("next"))
+                .and_then(|link| url.join(&link.url).ok()),
+            Paginate::Field { path, param } => {
+                let body: Value = serde_json::from_slice(body?).ok()?;
+                let value = path.iter().try_fold(&body, |value, key| match value {
+                    Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+                    value => value.get(key),
+                })?;
+                let value = match value {
+                    Value::String(value) if !value.is_empty() => value.clone(),
+                    Value::Number(value) => value.to_string(),
+                    // null, false or "" mean that this is the last page
+                    _ => return None,
+                };
+                match param {
+                    Some(param) => Some(with_query_param(url, param, &value)),
+                    None => url.join(&value).ok(),
+                }
+            }
+        }
+    }
+}
+
+/// Set a query parameter, replacing any values it had.
+fn with_query_param(url: &Url, param: &str, value: &str) -> Url {
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| key != param)
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    let mut url = url.clone();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .append_pair(param, value);
+    url
+}
+
+impl FromStr for Paginate {
+    type Err = Error;
+
+    /// Parse `PATH[:PARAM]`, where PATH is like `links.next` or `pages.0.url`.
+    fn from_str(value: &str) -> Result<Paginate> {
+        let (path, param) = match value.rfind(':') {
+            Some(index) => (&value[..index], Some(value[index + 1..].to_string())),
+            None => (value, None),
+        };
+        if path.is_empty() || path.split('.').any(str::is_empty) || param.as_deref() == Some("") {
+            return Err(Error::with_description(
+                &format!(
+                    "Invalid --paginate value {:?}, expected a path like `meta.next` \
+                     or `meta.cursor:cursor`",
+                    value
+                ),
+                ErrorKind::InvalidValue,
+            ));
+        }
+        Ok(Paginate::Field {
+            path: path.split('.').map(str::to_string).collect(),
+            param,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use reqwest::header::{HeaderValue, LINK};
+
+    #[test]
+    fn link_header() {
+        let url = Url::parse("https://api.example.com/items?page=1").unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            LINK,
+            HeaderValue::from_static(r#"</items?page=2>; rel="next", </items?page=5>; rel="last""#),
+        );
+        assert_eq!(
+            Paginate::Link
+                .next_page(&url, &headers, None)
+                .unwrap()
+                .as_str(),
+            "https://api.example.com/items?page=2"
+        );
+        assert_eq!(
+            Paginate::Link.next_page(&url, &HeaderMap::new(), None),
+            None
+        );
+    }
+
+    #[test]
+    fn json_field() {
+        let url = Url::parse("https://api.example.com/items?limit=2&cursor=a").unwrap();
+        let headers = HeaderMap::new();
+        let next = |paginate: &str, body: &str| {
+            paginate
+                .parse::<Paginate>()
+                .unwrap()
+                .next_page(&url, &headers, Some(body.as_bytes()))
+                .map(String::from)
+        };
+        assert_eq!(
+            next("links.next", r#"{"links": {"next": "/items?page=2"}}"#).as_deref(),
+            Some("https://api.example.com/items?page=2")
+        );
+        assert_eq!(
+            next("meta.cursor:cursor", r#"{"meta": {"cursor": "b c"}}"#).as_deref(),
+            Some("https://api.example.com/items?limit=2&cursor=b+c")
+        );
+        assert_eq!(
+            next("pages.1:page", r#"{"pages": [1, 2]}"#).as_deref(),
+            Some("https://api.example.com/items?limit=2&cursor=a&page=2")
+        );
+        assert_eq!(
+            next("meta.cursor:cursor", r#"{"meta": {"cursor": null}}"#),
+            None
+        );
+        assert_eq!(next("links.next", r#"{"links": {}}"#), None);
+        assert_eq!(next("links.next", "not json"), None);
+
+        assert!("".parse::<Paginate>().is_err());
+        assert!("a..b".parse::<Paginate>().is_err());
+        assert!("a:".parse::<Paginate>().is_err());
+    }
+}
@@ -0,0 +1,471 @@
+// --peek: list the files in a gzip, tar or zip download as it streams in,
+// without saving it
+//
+// Zip archives keep their index at the end, so they're read from the local
+// headers in front of each file instead.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{NaiveDate, NaiveDateTime, TimeZone, Utc};
+use flate2::bufread::{DeflateDecoder, MultiGzDecoder};
+
+const BLOCK: usize = 512;
+
+/// A file in an archive.
+#[derive(Debug, Default, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    /// The uncompressed size, if the archive says it up front
+    pub size: Option<u64>,
+    pub modified: Option<NaiveDateTime>,
+    /// Where a symbolic or hard link points
+    pub link: Option<String>,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.size {
+            Some(size) => write!(f, "{:>12}", size)?,
+            None => write!(f, "{:>12}", "?")?,
+        }
+        match self.modified {
+            Some(modified) => write!(f, "  {}", modified.format("%Y-%m-%d %H:%M"))?,
+            None => write!(f, "  {:16}", "")?,
+        }
+        write!(f, "  {}", self.name)?;
+        if let Some(link) = &self.link {
+            write!(f, " -> {}", link)?;
+        }
+        Ok(())
+    }
+}
+
+/// Read an archive from `body` and call `each` with every file in it, in
+/// order. A gzip file that isn't a tarball is a single entry, and nothing
+/// after its header is read.
+pub fn list(body: impl Read, mut each: impl FnMut(&Entry) -> io::Result<()>) -> Result<()> {
+    let (head, body) = read_head(BufReader::new(body))?;
+    if head.starts_with(b"\x1f\x8b") {
+        let mut decoder = MultiGzDecoder::new(Cursor::new(head).chain(body));
+        let (inner, rest) = read_head(BufReader::new(&mut decoder))?;
+        if is_tar_header(&inner) {
+            return list_tar(Cursor::new(inner).chain(rest), each);
+        }
+        drop(rest);
+        let header = decoder.header();
+        let name = header
+            .and_then(|header| header.filename())
+            .map(|name| String::from_utf8_lossy(name).into_owned());
+        let modified = header
+            .map(|header| header.mtime())
+            .filter(|&mtime| mtime != 0)
+            .and_then(|mtime| timestamp(mtime.into()));
+        each(&Entry {
+            name: name.unwrap_or_else(|| "(no name)".to_string()),
+            size: None,
+            modified,
+            link: None,
+        })?;
+        Ok(())
+    } else if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+        list_zip(Cursor::new(head).chain(body), each)
+    } else if is_tar_header(&head) {
+        list_tar(Cursor::new(head).chain(body), each)
+    } else {
+        bail!("The response isn't a gzip, tar or zip archive")
+    }
+}
+
+/// Read up to a block, for telling formats apart, and return it along with
+/// the rest of the reader.
+fn read_head<R: BufRead>(mut reader: R) -> io::Result<(Vec<u8>, R)> {
+    let mut head = Vec::with_capacity(BLOCK);
+    (&mut reader).take(BLOCK as u64).read_to_end(&mut head)?;
+    Ok((head, reader))
+}
+
+fn timestamp(seconds: i64) -> Option<NaiveDateTime> {
+    Utc.timestamp_opt(seconds, 0)
+        .single()
+        .map(|date| date.naive_utc())
+}
+
+fn skip(reader: &mut impl Read, length: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(length), &mut io::sink())?;
+    if skipped < length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// Whether a block is a tar header, which its checksum gives away even for
+/// the old formats without the `ustar` magic.
+fn is_tar_header(block: &[u8]) -> bool {
+    if block.len() < BLOCK {
+        return false;
+    }
+    let expected = match octal(&block[148..156]) {
+        Some(expected) => expected,
+        None => return false,
+    };
+    // The checksum is taken with its own field as spaces
+    let sum: u64 = block
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| match i {
+            148..=155 => u64::from(b' '),
+            _ => u64::from(byte),
+        })
+        .sum();
+    sum == expected
+}
+
+/// A number in a tar header, in octal or GNU's base-256.
+fn octal(field: &[u8]) -> Option<u64> {
+    if field.first().map_or(false, |&byte| byte & 0x80 != 0) {
+        let mut value = u64::from(field[0] & 0x7f);
+        for &byte in &field[1..] {
+            value = value.checked_mul(256)?.checked_add(u64::from(byte))?;
+        }
+        return Some(value);
+    }
+    let text = text(field);
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+/// A NUL-terminated string field.
+fn text(field: &[u8]) -> String {
+    let end = field
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn list_tar(mut reader: impl Read, mut each: impl FnMut(&Entry) -> io::Result<()>) -> Result<()> {
+    let mut block = [0; BLOCK];
+    // Long names and PAX attributes apply to the header after them
+    let mut long_name = None;
+    let mut long_link = None;
+    let mut pax_size = None;
+    loop {
+        reader.read_exact(&mut block)?;
+        if block.iter().all(|&byte| byte == 0) {
+            return Ok(());
+        }
+        if !is_tar_header(&block) {
+            bail!("The tar archive is damaged, its headers don't add up");
+        }
+        let size = pax_size
+            .take()
+            .or_else(|| octal(&block[124..136]))
+            .unwrap_or(0);
+        let padded = (size + BLOCK as u64 - 1) / BLOCK as u64 * BLOCK as u64;
+        match block[156] {
+            b'L' | b'K' | b'x' => {
+                let mut data = Vec::new();
+                (&mut reader).take(size).read_to_end(&mut data)?;
+                skip(&mut reader, padded - size)?;
+                match block[156] {
+                    b'L' => long_name = Some(text(&data)),
+                    b'K' => long_link = Some(text(&data)),
+                    _ => {
+                        for (key, value) in pax_records(&data) {
+                            match key.as_str() {
+                                "path" => long_name = Some(value),
+                                "linkpath" => long_link = Some(value),
+                                "size" => pax_size = value.parse().ok(),
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+                continue;
+            }
+            // Global PAX attributes only hold defaults
+            b'g' => {
+                skip(&mut reader, padded)?;
+                continue;
+            }
+            _ => {}
+        }
+        let name = long_name.take().unwrap_or_else(|| {
+            let prefix = text(&block[345..500]);
+            let name = text(&block[..100]);
+            if block[257..262] == *b"ustar" && !prefix.is_empty() {
+                format!("{}/{}", prefix, name)
+            } else {
+                name
+            }
+        });
+        let link = long_link.take().or_else(|| match block[156] {
+            b'1' | b'2' => Some(text(&block[157..257])),
+            _ => None,
+        });
+        each(&Entry {
+            name,
+            size: Some(size),
+            modified: octal(&block[136..148]).and_then(|mtime| timestamp(mtime as i64)),
+            link,
+        })?;
+        // Links and directories say their size but have no data
+        match block[156] {
+            b'1' | b'2' | b'3' | b'4' | b'5' | b'6' => {}
+            _ => skip(&mut reader, padded)?,
+        }
+    }
+}
+
+/// The `length key=value\n` records of PAX extended headers.
+fn pax_records(mut data: &[u8]) -> Vec<(String, String)> {
+    let mut records = Vec::new();
+    while let Some(space) = data.iter().position(|&byte| byte == b' ') {
+        let length: usize = match text(&data[..space]).parse() {
+            Ok(length) if length > space && length <= data.len() => length,
+            _ => break,
+        };
+        let record = String::from_utf8_lossy(&data[space + 1..length]).into_owned();
+        let record = record.trim_end_matches('\n');
+        if let Some(equals) = record.find('=') {
+            records.push((
+                record[..equals].to_string(),
+                record[equals + 1..].to_string(),
+            ));
+        }
+        data = &data[length..];
+    }
+    records
+}
+
+fn u16_at(bytes: &[u8], index: usize) -> u16 {
+    u16::from(bytes[index]) | u16::from(bytes[index + 1]) << 8
+}
+
+fn u32_at(bytes: &[u8], index: usize) -> u32 {
+    u32::from(u16_at(bytes, index)) | u32::from(u16_at(bytes, index + 2)) << 16
+}
+
+fn u64_at(bytes: &[u8], index: usize) -> u64 {
+    u64::from(u32_at(bytes, index)) | u64::from(u32_at(bytes, index + 4)) << 32
+}
+
+/// An MS-DOS date and time, which zip uses.
+fn dos_time(date: u16, time: u16) -> Option<NaiveDateTime> {
+    NaiveDate::from_ymd_opt(
+        1980 + i32::from(date >> 9),
+        u32::from(date >> 5 & 0xf),
+        u32::from(date & 0x1f),
+    )?
+    .and_hms_opt(
+        u32::from(time >> 11),
+        u32::from(time >> 5 & 0x3f),
+        u32::from(time & 0x1f) * 2,
+    )
+}
+
+fn list_zip(
+    mut reader: impl BufRead,
+    mut each: impl FnMut(&Entry) -> io::Result<()>,
+) -> Result<()> {
+    let mut header = [0; 30];
+    loop {
+        reader.read_exact(&mut header[..4])?;
+        if header[..4] != *b"PK\x03\x04" {
+            // What comes after the files is the central directory
+            return Ok(());
+        }
+        reader.read_exact(&mut header[4..])?;
+        let flags = u16_at(&header, 6);
+        let method = u16_at(&header, 8);
+        let mut compressed = u64::from(u32_at(&header, 18));
+        let mut size = u64::from(u32_at(&header, 22));
+        let mut name = vec![0; usize::from(u16_at(&header, 26))];
+        let mut extra = vec![0; usize::from(u16_at(&header, 28))];
+        reader.read_exact(&mut name)?;
+        reader.read_exact(&mut extra)?;
+        let name = String::from_utf8_lossy(&name).into_owned();
+
+        // Zip64 keeps the sizes that don't fit in an extra field
+        let mut zip64 = false;
+        let mut fields = &extra[..];
+        while fields.len() >= 4 {
+            let id = u16_at(fields, 0);
+            let length = usize::from(u16_at(fields, 2)).min(fields.len() - 4);
+            if id == 0x0001 && length >= 16 {
+                zip64 = true;
+                size = u64_at(fields, 4);
+                compressed = u64_at(fields, 12);
+            }
+            fields = &fields[4 + length..];
+        }
+
+        let mut entry = Entry {
+            name,
+            size: Some(size),
+            modified: dos_time(u16_at(&header, 12), u16_at(&header, 10)),
+            link: None,
+        };
+        if flags & 0x08 == 0 {
+            each(&entry)?;
+            skip(&mut reader, compressed)?;
+            continue;
+        }
+
+        // The sizes come after the data, so the data has to be decompressed
+        // to find its end
+        if method != 8 || flags & 0x01 != 0 {
+            return Err(anyhow!(
+                "Can't list past {}, its size is only in the zip's index at the end",
+                entry.name
+            ));
+        }
+        entry.size = Some(io::copy(
+            &mut DeflateDecoder::new(&mut reader),
+            &mut io::sink(),
+        )?);
+        each(&entry)?;
+        let mut descriptor = [0; 4];
+        reader.read_exact(&mut descriptor)?;
+        // The descriptor's signature is optional, and its CRC and sizes
+        // are left to skip
+        let rest = if zip64 { 20 } else { 12 };
+        if descriptor == *b"PK\x07\x08" {
+            skip(&mut reader, rest)?;
+        } else {
+            skip(&mut reader, rest - 4)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use flate2::write::{DeflateEncoder, GzEncoder};
+    use flate2::{Compression, GzBuilder};
+
+    fn names(body: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        list(body, |entry| {
+            lines.push(entry.to_string());
+            Ok(())
+        })
+        .unwrap();
+        lines
+    }
+
+    fn tar_header(name: &str, kind: u8, size: usize, link: &str) -> Vec<u8> {
+        let mut block = vec![0; BLOCK];
+        block[..name.len()].copy_from_slice(name.as_bytes());
+        block[100..107].copy_from_slice(b"0000644");
+        block[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+        block[136..147].copy_from_slice(b"14055420500");
+        block[156] = kind;
+        block[157..157 + link.len()].copy_from_slice(link.as_bytes());
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+        block[148..156].copy_from_slice(b"        ");
+        let sum: u32 = block.iter().map(|&byte| u32::from(byte)).sum();
+        block[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+        block
+    }
+
+    #[test]
+    fn tar() {
+        let long = format!("dir/{}.txt", "x".repeat(120));
+        let mut tar = Vec::new();
+        tar.extend(tar_header("dir/", b'5', 0, ""));
+        tar.extend(tar_header("dir/hello.txt", b'0', 6, ""));
+        tar.extend(b"hello\n");
+        tar.resize(tar.len() + BLOCK - 6, 0);
+        tar.extend(tar_header("././@LongLink", b'L', long.len() + 1, ""));
+        tar.extend(long.as_bytes());
+        tar.resize(tar.len() + BLOCK - long.len(), 0);
+        tar.extend(tar_header("dir/truncated", b'0', 0, ""));
+        tar.extend(tar_header("link", b'2', 0, "dir/hello.txt"));
+        tar.extend(vec![0; BLOCK * 2]);
+
+        let expected = [
+            "           0  2021-06-01 12:00  dir/".to_string(),
+            "           6  2021-06-01 12:00  dir/hello.txt".to_string(),
+            format!("           0  2021-06-01 12:00  {}", long),
+            "           0  2021-06-01 12:00  link -> dir/hello.txt".to_string(),
+        ];
+        assert_eq!(names(&tar), expected);
+
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&tar).unwrap();
+        assert_eq!(names(&gz.finish().unwrap()), expected);
+
+        tar[BLOCK + 1] = b'x';
+        assert!(list(&tar[..], |_| Ok(())).is_err());
+    }
+
+    #[test]
+    fn gzip() {
+        let mut gz = GzBuilder::new()
+            .filename("notes.txt")
+            .mtime(1622548800)
+            .write(Vec::new(), Compression::default());
+        gz.write_all(b"not a tarball").unwrap();
+        assert_eq!(
+            names(&gz.finish().unwrap()),
+            ["           ?  2021-06-01 12:00  notes.txt"]
+        );
+    }
+
+    fn zip_entry(name: &str, data: &[u8], streamed: bool) -> Vec<u8> {
+        let mut deflate = DeflateEncoder::new(Vec::new(), Compression::default());
+        deflate.write_all(data).unwrap();
+        let compressed = deflate.finish().unwrap();
+        let sizes = if streamed {
+            [0, 0]
+        } else {
+            [compressed.len() as u32, data.len() as u32]
+        };
+        let mut entry = b"PK\x03\x04\x14\x00".to_vec();
+        entry.extend(&(if streamed { 0x08u16 } else { 0 }).to_le_bytes());
+        entry.extend(&8u16.to_le_bytes());
+        // 12:00:00 on 2021-06-01
+        entry.extend(&0x6000u16.to_le_bytes());
+        entry.extend(&0x52c1u16.to_le_bytes());
+        entry.extend(&0u32.to_le_bytes());
+        entry.extend(&sizes[0].to_le_bytes());
+        entry.extend(&sizes[1].to_le_bytes());
+        entry.extend(&(name.len() as u16).to_le_bytes());
+        entry.extend(&0u16.to_le_bytes());
+        entry.extend(name.as_bytes());
+        entry.extend(&compressed);
+        if streamed {
+            entry.extend(b"PK\x07\x08");
+            entry.extend(&0u32.to_le_bytes());
+            entry.extend(&(compressed.len() as u32).to_le_bytes());
+            entry.extend(&(data.len() as u32).to_le_bytes());
+        }
+        entry
+    }
+
+    #[test]
+    fn zip() {
+        let mut zip = zip_entry("a.txt", b"hello", false);
+        zip.extend(zip_entry("b/c.txt", &[b'x'; 1000], true));
+        zip.extend(b"PK\x01\x02 and the rest of the central directory");
+        assert_eq!(
+            names(&zip),
+            [
+                "           5  2021-06-01 12:00  a.txt",
+                "        1000  2021-06-01 12:00  b/c.txt"
+            ]
+        );
+
+        assert!(list(&b"<!doctype html>"[..], |_| Ok(())).is_err());
+    }
+}
@@ -0,0 +1,598 @@
+// Client certificates on PKCS#11 tokens (smartcards, YubiKeys, HSMs), for
+// --cert pkcs11:...
+//
+// The private key never leaves the token, so reqwest can't be given an
+// Identity. Instead we configure rustls ourselves, with a client certificate
+// whose key asks the token to sign the handshake.
+//
+// The module is loaded at runtime by cryptoki, so that xh doesn't need it to
+// start, and so that builds don't need its headers.
+
+use std::convert::TryFrom;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::{anyhow, bail, Context, Result};
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::error::{Error as Pkcs11Error, RvError};
+use cryptoki::mechanism::rsa::{PkcsMgfType, PkcsPssParams};
+use cryptoki::mechanism::{Mechanism, MechanismType};
+use cryptoki::object::{Attribute, AttributeType, KeyType, ObjectClass, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use cryptoki::slot::{Slot, TokenInfo};
+use cryptoki::types::{AuthPin, Ulong};
+use percent_encoding::percent_decode_str;
+use rustls::client::{ResolvesClientCert, ServerCertVerified, ServerCertVerifier};
+use rustls::sign::{CertifiedKey, Signer, SigningKey};
+use rustls::{
+    Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName, SignatureAlgorithm,
+    SignatureScheme,
+};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+/// Build a TLS configuration that authenticates with a key on a PKCS#11 token.
+///
+/// This mirrors what reqwest sets up: `roots` replaces the built-in root
+/// certificates, and `verify` can turn off server certificate verification.
+pub fn tls_config(
+    uri: &str,
+    pin: Option<String>,
+    roots: Option<&[Vec<u8>]>,
+    verify: bool,
+) -> Result<ClientConfig> {
+    let uri = Uri::parse(uri)?;
+    let key = load(&uri, pin).context("Failed to load the client certificate from PKCS#11")?;
+    let resolver = Arc::new(Resolver(Arc::new(key)));
+
+    let builder = ClientConfig::builder().with_safe_defaults();
+    let mut config = if verify {
+        let mut store = RootCertStore::empty();
+        match roots {
+            Some(roots) => {
+                for root in roots {
+                    store
+                        .add(&Certificate(root.clone()))
+                        .context("Failed to load the custom CA bundle")?;
+                }
+            }
+            None => store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|root| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    root.subject,
+                    root.spki,
+                    root.name_constraints,
+                )
+            })),
+        }
+        builder
+            .with_root_certificates(store)
+            .with_client_cert_resolver(resolver)
+    } else {
+        builder
+            .with_custom_certificate_verifier(Arc::new(NoVerifier))
+            .with_client_cert_resolver(resolver)
+    };
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+struct Resolver(Arc<CertifiedKey>);
+
+impl ResolvesClientCert for Resolver {
+    fn resolve(&self, _: &[&[u8]], _: &[SignatureScheme]) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(&self.0))
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+/// --verify=no, like reqwest's `danger_accept_invalid_certs`. The handshake
+/// signatures are still checked by the trait's default methods.
+struct NoVerifier;
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _: &Certificate,
+        _: &[Certificate],
+        _: &ServerName,
+        _: &mut dyn Iterator<Item = &[u8]>,
+        _: &[u8],
+        _: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// An RFC 7512 URI, like `pkcs11:token=YubiKey;id=%01?module-path=/usr/lib/libykcs11.so`.
+struct Uri {
+    path: Vec<(String, Vec<u8>)>,
+    query: Vec<(String, Vec<u8>)>,
+}
+
+impl Uri {
+    fn parse(uri: &str) -> Result<Uri> {
+        let rest = uri
+            .strip_prefix("pkcs11:")
+            .ok_or_else(|| anyhow!("Not a PKCS#11 URI: {}", uri))?;
+        let (path, query) = match rest.find('?') {
+            Some(index) => (&rest[..index], &rest[index + 1..]),
+            None => (rest, ""),
+        };
+        let parse = |text: &str, separator: char| -> Result<Vec<(String, Vec<u8>)>> {
+            text.split(separator)
+                .filter(|attribute| !attribute.is_empty())
+                .map(|attribute| match attribute.find('=') {
+                    Some(index) => Ok((
+                        attribute[..index].to_string(),
+                        percent_decode_str(&attribute[index + 1..]).collect(),
+                    )),
+                    None => Err(anyhow!("Invalid PKCS#11 URI attribute: {:?}", attribute)),
+                })
+                .collect()
+        };
+        Ok(Uri {
+            path: parse(path, ';')?,
+            query: parse(query, '&')?,
+        })
+    }
+
+    fn path(&self, name: &str) -> Option<&[u8]> {
+        self.path
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    fn query(&self, name: &str) -> Option<&[u8]> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_slice())
+    }
+}
+
+// DER encoded OIDs, as found in CKA_EC_PARAMS
+const SECP256R1: &[u8] = b"\x06\x08\x2a\x86\x48\xce\x3d\x03\x01\x07";
+const SECP384R1: &[u8] = b"\x06\x05\x2b\x81\x04\x00\x22";
+
+// The start of a DigestInfo structure, which CKM_RSA_PKCS expects to be
+// prepended to the hash
+const SHA256_PREFIX: &[u8] =
+    b"\x30\x31\x30\x0d\x06\x09\x60\x86\x48\x01\x65\x03\x04\x02\x01\x05\x00\x04\x20";
+const SHA384_PREFIX: &[u8] =
+    b"\x30\x41\x30\x0d\x06\x09\x60\x86\x48\x01\x65\x03\x04\x02\x02\x05\x00\x04\x30";
+const SHA512_PREFIX: &[u8] =
+    b"\x30\x51\x30\x0d\x06\x09\x60\x86\x48\x01\x65\x03\x04\x02\x03\x05\x00\x04\x40";
+
+#[cfg(target_os = "macos")]
+const DEFAULT_MODULE: Option<&str> = Some("p11-kit-proxy.dylib");
+#[cfg(all(unix, not(target_os = "macos")))]
+const DEFAULT_MODULE: Option<&str> = Some("p11-kit-proxy.so");
+#[cfg(not(unix))]
+const DEFAULT_MODULE: Option<&str> = None;
+
+/// Turn the errors that users can do something about into readable ones.
+fn describe(err: Pkcs11Error) -> anyhow::Error {
+    match err {
+        Pkcs11Error::Pkcs11(RvError::PinIncorrect, _) => anyhow!("wrong PIN"),
+        Pkcs11Error::Pkcs11(RvError::PinLocked, _) => anyhow!("the PIN is locked"),
+        err => err.into(),
+    }
+}
+
+/// Find the token that the URI refers to.
+fn find_token(pkcs11: &Pkcs11, uri: &Uri) -> Result<(Slot, TokenInfo)> {
+    let wanted_slot = uri
+        .path("slot-id")
+        .map(|slot| {
+            String::from_utf8_lossy(slot)
+                .parse::<u64>()
+                .map_err(|_| anyhow!("Invalid slot-id in PKCS#11 URI"))
+        })
+        .transpose()?;
+    for slot in pkcs11.get_slots_with_token()? {
+        if wanted_slot.map_or(false, |wanted| wanted != slot.id()) {
+            continue;
+        }
+        let info = pkcs11.get_token_info(slot)?;
+        let fields = [
+            ("token", info.label()),
+            ("manufacturer", info.manufacturer_id()),
+            ("model", info.model()),
+            ("serial", info.serial_number()),
+        ];
+        if fields.iter().all(|(name, field)| {
+            uri.path(name)
+                .map_or(true, |value| value == field.as_bytes())
+        }) {
+            return Ok((slot, info));
+        }
+    }
+    bail!("No matching PKCS#11 token found")
+}
+
+fn load(uri: &Uri, pin: Option<String>) -> Result<CertifiedKey> {
+    let module_path = match uri.query("module-path") {
+        Some(path) => String::from_utf8_lossy(path).into_owned(),
+        None => match DEFAULT_MODULE {
+            Some(path) => path.to_string(),
+            None => bail!("The PKCS#11 URI needs a module-path on this platform"),
+        },
+    };
+    let pkcs11 = Pkcs11::new(&module_path)
+        .with_context(|| format!("Failed to load the PKCS#11 module {}", module_path))?;
+    // Signing happens on reqwest's runtime thread, so the module has to do
+    // its own locking
+    match pkcs11.initialize(CInitializeArgs::OsThreads) {
+        Ok(()) | Err(Pkcs11Error::Pkcs11(RvError::CryptokiAlreadyInitialized, _)) => {}
+        Err(err) => return Err(err.into()),
+    }
+    let (slot, info) = find_token(&pkcs11, uri)?;
+    let session = pkcs11.open_ro_session(slot)?;
+
+    if info.login_required() {
+        let pin = if info.protected_authentication_path() {
+            // The PIN is entered on the reader itself
+            None
+        } else if let Some(pin) = uri.query("pin-value") {
+            Some(String::from_utf8_lossy(pin).into_owned())
+        } else {
+            match pin.or_else(|| env::var("XH_CERT_KEY_PASS").ok()) {
+                Some(pin) => Some(pin),
+                None => {
+                    let prompt = format!("http: PIN for {}: ", info.label());
+                    Some(
+                        rpassword::read_password_from_tty(Some(&prompt))
+                            .context("Failed to read the PIN")?,
+                    )
+                }
+            }
+        };
+        match session.login(UserType::User, pin.map(AuthPin::new).as_ref()) {
+            Ok(()) | Err(Pkcs11Error::Pkcs11(RvError::UserAlreadyLoggedIn, _)) => {}
+            Err(err) => return Err(describe(err)),
+        }
+    }
+
+    let mut template = Vec::new();
+    if let Some(label) = uri.path("object") {
+        template.push(Attribute::Label(label.to_vec()));
+    }
+    if let Some(id) = uri.path("id") {
+        template.push(Attribute::Id(id.to_vec()));
+    }
+
+    let mut certificate_template = template.clone();
+    certificate_template.push(Attribute::Class(ObjectClass::CERTIFICATE));
+    let certificate = *session
+        .find_objects(&certificate_template)?
+        .first()
+        .ok_or_else(|| anyhow!("No matching certificate found on the token"))?;
+    let (mut der, mut id) = (None, Vec::new());
+    for attribute in
+        session.get_attributes(certificate, &[AttributeType::Value, AttributeType::Id])?
+    {
+        match attribute {
+            Attribute::Value(value) => der = Some(value),
+            Attribute::Id(value) => id = value,
+            _ => {}
+        }
+    }
+    let der = der.ok_or_else(|| anyhow!("The certificate on the token has no value"))?;
+
+    // The key is usually only linked to its certificate by their ID
+    let mut key_template = if id.is_empty() {
+        template
+    } else {
+        vec![Attribute::Id(id)]
+    };
+    key_template.push(Attribute::Class(ObjectClass::PRIVATE_KEY));
+    let key = *session
+        .find_objects(&key_template)?
+        .first()
+        .ok_or_else(|| anyhow!("No private key found for the certificate"))?;
+
+    let (mut key_type, mut ec_params) = (None, None);
+    for attribute in
+        session.get_attributes(key, &[AttributeType::KeyType, AttributeType::EcParams])?
+    {
+        match attribute {
+            Attribute::KeyType(value) => key_type = Some(value),
+            Attribute::EcParams(value) => ec_params = Some(value),
+            _ => {}
+        }
+    }
+    let kind = match key_type {
+        Some(KeyType::RSA) => KeyKind::Rsa,
+        Some(KeyType::EC) => match ec_params.as_deref() {
+            Some(SECP256R1) => KeyKind::Ec(SignatureScheme::ECDSA_NISTP256_SHA256),
+            Some(SECP384R1) => KeyKind::Ec(SignatureScheme::ECDSA_NISTP384_SHA384),
+            _ => bail!("Unsupported elliptic curve"),
+        },
+        Some(key_type) => bail!("Unsupported key type {}", key_type),
+        None => bail!("The private key on the token has no type"),
+    };
+
+    let key = Key {
+        // PKCS#11 sessions can only run one operation at a time
+        session: Arc::new(Mutex::new(session)),
+        handle: key,
+        kind,
+    };
+    Ok(CertifiedKey::new(vec![Certificate(der)], Arc::new(key)))
+}
+
+#[derive(Clone, Copy)]
+enum KeyKind {
+    Rsa,
+    Ec(SignatureScheme),
+}
+
+#[derive(Clone)]
+struct Key {
+    session: Arc<Mutex<Session>>,
+    handle: ObjectHandle,
+    kind: KeyKind,
+}
+
+impl SigningKey for Key {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        let supported = match self.kind {
+            KeyKind::Rsa => vec![
+                SignatureScheme::RSA_PSS_SHA256,
+                SignatureScheme::RSA_PSS_SHA384,
+                SignatureScheme::RSA_PSS_SHA512,
+                SignatureScheme::RSA_PKCS1_SHA256,
+                SignatureScheme::RSA_PKCS1_SHA384,
+                SignatureScheme::RSA_PKCS1_SHA512,
+            ],
+            KeyKind::Ec(scheme) => vec![scheme],
+        };
+        let scheme = supported
+            .into_iter()
+            .find(|scheme| offered.contains(scheme))?;
+        Some(Box::new(KeySigner {
+            key: self.clone(),
+            scheme,
+        }))
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        match self.kind {
+            KeyKind::Rsa => SignatureAlgorithm::RSA,
+            KeyKind::Ec(_) => SignatureAlgorithm::ECDSA,
+        }
+    }
+}
+
+struct KeySigner {
+    key: Key,
+    scheme: SignatureScheme,
+}
+
+impl Signer for KeySigner {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, rustls::Error> {
+        use SignatureScheme::*;
+
+        // Tokens are slow and may not support hashing, so only the hash
+        // is sent to them
+        let (hash, hash_mechanism, mgf, prefix) = match self.scheme {
+            RSA_PSS_SHA256 | RSA_PKCS1_SHA256 | ECDSA_NISTP256_SHA256 => (
+                Sha256::digest(message).to_vec(),
+                MechanismType::SHA256,
+                PkcsMgfType::MGF1_SHA256,
+                SHA256_PREFIX,
+            ),
+            RSA_PSS_SHA384 | RSA_PKCS1_SHA384 | ECDSA_NISTP384_SHA384 => (
+                Sha384::digest(message).to_vec(),
+                MechanismType::SHA384,
+                PkcsMgfType::MGF1_SHA384,
+                SHA384_PREFIX,
+            ),
+            _ => (
+                Sha512::digest(message).to_vec(),
+                MechanismType::SHA512,
+                PkcsMgfType::MGF1_SHA512,
+                SHA512_PREFIX,
+            ),
+        };
+        let hash = hash.as_slice();
+
+        let (mechanism, data) = match self.scheme {
+            RSA_PSS_SHA256 | RSA_PSS_SHA384 | RSA_PSS_SHA512 => {
+                let params = PkcsPssParams {
+                    hash_alg: hash_mechanism,
+                    mgf,
+                    s_len: Ulong::try_from(hash.len())
+                        .map_err(|err| rustls::Error::General(err.to_string()))?,
+                };
+                (Mechanism::RsaPkcsPss(params), hash.to_vec())
+            }
+            RSA_PKCS1_SHA256 | RSA_PKCS1_SHA384 | RSA_PKCS1_SHA512 => {
+                (Mechanism::RsaPkcs, [prefix, hash].concat())
+            }
+            _ => (Mechanism::Ecdsa, hash.to_vec()),
+        };
+
+        let signature = self
+            .key
+            .session
+            .lock()
+            .unwrap()
+            .sign(&mechanism, self.key.handle, &data)
+            .map_err(|err| rustls::Error::General(format!("PKCS#11 signing failed: {}", err)))?;
+        match self.key.kind {
+            KeyKind::Rsa => Ok(signature),
+            KeyKind::Ec(_) => Ok(ecdsa_der(&signature)),
+        }
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+}
+
+/// Convert an ECDSA signature from PKCS#11's r || s to the DER encoding
+/// TLS uses.
+fn ecdsa_der(signature: &[u8]) -> Vec<u8> {
+    let (r, s) = signature.split_at(signature.len() / 2);
+    let mut contents = Vec::new();
+    for integer in &[r, s] {
+        let start = integer
+            .iter()
+            .position(|&byte| byte != 0)
+            .unwrap_or(integer.len() - 1);
+        let integer = &integer[start..];
+        let pad = integer[0] & 0x80 != 0;
+        contents.push(0x02);
+        contents.push(integer.len() as u8 + pad as u8);
+        if pad {
+            contents.push(0);
+        }
+        contents.extend_from_slice(integer);
+    }
+    let mut der = vec![0x30, contents.len() as u8];
+    der.extend(contents);
+    der
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uris() {
+        let uri =
+            Uri::parse("pkcs11:token=My%20Token;id=%01%02?pin-value=1234&module-path=/lib/p11.so")
+                .unwrap();
+        assert_eq!(uri.path("token"), Some(&b"My Token"[..]));
+        assert_eq!(uri.path("id"), Some(&b"\x01\x02"[..]));
+        assert_eq!(uri.path("object"), None);
+        assert_eq!(uri.query("pin-value"), Some(&b"1234"[..]));
+        assert_eq!(uri.query("module-path"), Some(&b"/lib/p11.so"[..]));
+
+        assert!(Uri::parse("pkcs11:").is_ok());
+        assert!(Uri::parse("pkcs11:token").is_err());
+    }
+
+    #[test]
+    fn ecdsa_signatures() {
+        let mut raw = vec![0; 64];
+        raw[0] = 0x80;
+        raw[31] = 1;
+        raw[63] = 2;
+        let der = ecdsa_der(&raw);
+        assert_eq!(&der[..5], b"\x30\x26\x02\x21\x00");
+        assert_eq!(der[5], 0x80);
+        assert_eq!(&der[37..], b"\x02\x01\x02");
+    }
+
+    /// Sign with a key on a fresh SoftHSM token. Only runs if SOFTHSM2_MODULE
+    /// points to the module, e.g. /usr/lib/softhsm/libsofthsm2.so.
+    #[test]
+    fn softhsm() {
+        use cryptoki::mechanism::Mechanism;
+        use cryptoki::object::CertificateType;
+        use p256::ecdsa::signature::Verifier;
+        use p256::ecdsa::{DerSignature, VerifyingKey};
+
+        let module = match env::var("SOFTHSM2_MODULE") {
+            Ok(module) => module,
+            Err(_) => return,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("softhsm2.conf");
+        std::fs::write(
+            &config,
+            format!("directories.tokendir = {}\n", dir.path().display()),
+        )
+        .unwrap();
+        env::set_var("SOFTHSM2_CONF", &config);
+
+        let cert = pem::parse(include_str!(
+            "../tests/fixtures/certs/client.badssl.com.crt"
+        ))
+        .unwrap()
+        .contents;
+        let point = {
+            let pkcs11 = Pkcs11::new(&module).unwrap();
+            pkcs11.initialize(CInitializeArgs::OsThreads).unwrap();
+            let slot = pkcs11.get_slots_with_token().unwrap()[0];
+            let so_pin = AuthPin::new("1234".to_string());
+            pkcs11.init_token(slot, &so_pin, "xh").unwrap();
+            let session = pkcs11.open_rw_session(slot).unwrap();
+            session.login(UserType::So, Some(&so_pin)).unwrap();
+            session.init_pin(&AuthPin::new("5678".to_string())).unwrap();
+            session.logout().unwrap();
+            session
+                .login(UserType::User, Some(&AuthPin::new("5678".to_string())))
+                .unwrap();
+
+            let (public, _) = session
+                .generate_key_pair(
+                    &Mechanism::EccKeyPairGen,
+                    &[
+                        Attribute::Token(true),
+                        Attribute::Verify(true),
+                        Attribute::EcParams(SECP256R1.to_vec()),
+                        Attribute::Id(vec![1]),
+                    ],
+                    &[
+                        Attribute::Token(true),
+                        Attribute::Private(true),
+                        Attribute::Sign(true),
+                        Attribute::Id(vec![1]),
+                    ],
+                )
+                .unwrap();
+            // The certificate doesn't match the key, which doesn't matter
+            // for signing
+            session
+                .create_object(&[
+                    Attribute::Class(ObjectClass::CERTIFICATE),
+                    Attribute::CertificateType(CertificateType::X_509),
+                    Attribute::Token(true),
+                    Attribute::Id(vec![1]),
+                    Attribute::Subject(b"\x30\x00".to_vec()),
+                    Attribute::Value(cert.clone()),
+                ])
+                .unwrap();
+            match &session
+                .get_attributes(public, &[AttributeType::EcPoint])
+                .unwrap()[..]
+            {
+                // An uncompressed point wrapped in an OCTET STRING
+                [Attribute::EcPoint(point)] => point[2..].to_vec(),
+                _ => panic!("no EC point"),
+            }
+        };
+
+        let uri = format!(
+            "pkcs11:token=xh;id=%01?module-path={}&pin-value=5678",
+            module
+        );
+        {
+            let key = load(&Uri::parse(&uri).unwrap(), None).unwrap();
+            assert_eq!(key.cert, vec![Certificate(cert)]);
+            let signer = key
+                .key
+                .choose_scheme(&[SignatureScheme::ECDSA_NISTP256_SHA256])
+                .unwrap();
+            let signature = signer.sign(b"hello").unwrap();
+            VerifyingKey::from_sec1_bytes(&point)
+                .unwrap()
+                .verify(b"hello", &DerSignature::try_from(&signature[..]).unwrap())
+                .unwrap();
+        }
+
+        // The module is finalized once the key is dropped, which logs out
+        let uri = format!("pkcs11:token=xh?module-path={}&pin-value=0000", module);
+        match load(&Uri::parse(&uri).unwrap(), None) {
+            Err(error) => assert_eq!(error.to_string(), "wrong PIN"),
+            Ok(_) => panic!("a wrong PIN was accepted"),
+        }
+    }
+}
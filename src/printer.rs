@@ -1,19 +1,39 @@
+use std::collections::HashMap;
 use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::process::Stdio;
+use std::thread;
 
-use encoding_rs::{Encoding, UTF_8};
+use chrono::{Local, Utc};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
 use encoding_rs_io::DecodeReaderBytesBuilder;
+use indicatif::HumanBytes;
 use mime::Mime;
 use reqwest::blocking::{Request, Response};
 use reqwest::header::{
-    HeaderMap, HeaderName, HeaderValue, ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, HOST,
+    HeaderMap, HeaderName, HeaderValue, ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, HOST, SET_COOKIE,
 };
+use reqwest::tls::TlsInfo;
+use reqwest::{StatusCode, Url, Version};
+use syntect::parsing::SyntaxSet;
 use termcolor::WriteColor;
 
 use crate::{
     buffer::Buffer,
-    cli::{Pretty, Theme},
-    formatting::{get_json_formatter, Highlighter},
-    utils::{copy_largebuf, get_content_type, test_mode, valid_json, ContentType, BUFFER_SIZE},
+    certificate::Certificate,
+    cli::{ByteSize, Charset, Pretty, Theme},
+    config::find_by_content_type,
+    cookies::SetCookie,
+    formatting::{get_json_formatter, HighlightWriter, Highlighter},
+    markdown, problem,
+    proxy::Route,
+    redact::redact_headers,
+    reindent, table,
+    timing::{Lookup, Timings},
+    trace::Trace,
+    utils::{
+        copy_largebuf, get_content_type, looks_binary, parse_command, test_mode, valid_json,
+        ContentType, SizeLimit, BUFFER_SIZE,
+    },
 };
 
 const BINARY_SUPPRESSOR: &str = concat!(
@@ -23,6 +43,15 @@ const BINARY_SUPPRESSOR: &str = concat!(
     "\n"
 );
 
+/// Multipart request bodies are printed with each part cut off after this many bytes.
+const PART_PREVIEW_SIZE: usize = 1024;
+
+/// Bodies larger than this are not formatted unless --format-max-size says otherwise.
+const DEFAULT_FORMAT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Only this much of a body is highlighted unless --highlight-max-size says otherwise.
+const DEFAULT_HIGHLIGHT_MAX_SIZE: u64 = 1024 * 1024;
+
 /// A wrapper around a reader that reads line by line, (optionally) returning
 /// an error if the line appears to be binary.
 ///
@@ -63,42 +92,149 @@ impl<'a, T: Read> BinaryGuard<'a, T> {
     }
 }
 
+/// Whether response bodies are checked for binary data before they're printed.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BinaryMode {
+    /// Look at the body itself, not just its content type
+    #[default]
+    Sniff,
+    /// Always print the body as text (--force-text)
+    Text,
+    /// Never print the body as text (--force-binary)
+    Binary,
+}
+
+/// How a Printer formats what it prints, apart from --pretty. The defaults
+/// are what xh does without any options or config file.
+#[derive(Default)]
+pub struct PrinterOptions {
+    pub theme: Option<Theme>,
+    /// Whether to sort headers, if not the default for --pretty
+    pub sort_headers: Option<bool>,
+    pub stream: bool,
+    pub json_lines: bool,
+    pub render_markdown: bool,
+    pub table: bool,
+    pub column_width: Option<usize>,
+    pub binary_mode: BinaryMode,
+    pub charset: Option<Charset>,
+    /// Commands to format bodies with, by content type
+    pub formatters: HashMap<String, String>,
+    /// Syntaxes to highlight bodies with, by content type
+    pub syntaxes: HashMap<String, String>,
+    pub custom_syntaxes: Option<SyntaxSet>,
+    pub format_max_size: Option<ByteSize>,
+    pub highlight_max_size: Option<ByteSize>,
+    /// Headers to mask on top of the usual ones
+    pub redact: Vec<HeaderName>,
+    /// Don't mask anything, for --show-secrets
+    pub show_secrets: bool,
+}
+
 pub struct Printer {
     indent_json: bool,
     color: bool,
     theme: Theme,
     sort_headers: bool,
     stream: bool,
+    json_lines: bool,
+    render_markdown: bool,
+    table: bool,
+    column_width: Option<usize>,
+    binary_mode: BinaryMode,
+    charset: Option<&'static Encoding>,
+    formatters: HashMap<String, String>,
+    syntaxes: HashMap<String, String>,
+    custom_syntaxes: Option<SyntaxSet>,
+    format_max_size: Option<u64>,
+    highlight_max_size: Option<u64>,
+    /// Headers to mask on top of the usual ones, or `None` for --show-secrets
+    redact: Option<Vec<HeaderName>>,
     buffer: Buffer,
 }
 
 impl Printer {
-    pub fn new(pretty: Pretty, theme: Option<Theme>, stream: bool, buffer: Buffer) -> Self {
-        let theme = theme.unwrap_or(Theme::auto);
+    pub fn new(pretty: Pretty, options: PrinterOptions, buffer: Buffer) -> Self {
+        let theme = options.theme.unwrap_or(Theme::auto);
+        let format_max_size = match options.format_max_size {
+            Some(ByteSize(0)) => None,
+            Some(ByteSize(size)) => Some(size),
+            None => Some(DEFAULT_FORMAT_MAX_SIZE),
+        };
+        let highlight_max_size = match options.highlight_max_size {
+            Some(ByteSize(0)) => None,
+            Some(ByteSize(size)) => Some(size),
+            None => Some(DEFAULT_HIGHLIGHT_MAX_SIZE),
+        };
 
         Printer {
             indent_json: pretty.format(),
-            sort_headers: pretty.format(),
+            sort_headers: options.sort_headers.unwrap_or_else(|| pretty.format()),
             color: pretty.color() && (cfg!(test) || buffer.supports_color()),
-            stream,
+            stream: options.stream,
+            json_lines: options.json_lines,
+            render_markdown: options.render_markdown,
+            table: options.table,
+            column_width: options.column_width,
+            binary_mode: options.binary_mode,
+            charset: options.charset.map(|Charset(encoding)| encoding),
+            formatters: options.formatters,
+            syntaxes: options.syntaxes,
+            custom_syntaxes: options.custom_syntaxes,
+            format_max_size,
+            highlight_max_size,
+            redact: if options.show_secrets {
+                None
+            } else {
+                Some(options.redact)
+            },
             theme,
             buffer,
         }
     }
 
-    fn get_highlighter(&mut self, syntax: &'static str) -> Highlighter<'_> {
-        Highlighter::new(syntax, self.theme, &mut self.buffer)
+    fn get_highlighter(&mut self, syntax: &str) -> Highlighter<'_> {
+        // Headers are never large enough to need a limit
+        let limit = match syntax {
+            "http" => None,
+            _ => self.highlight_max_size,
+        };
+        Highlighter::new(
+            syntax,
+            self.custom_syntaxes.as_ref(),
+            self.theme,
+            limit,
+            &mut self.buffer,
+        )
+    }
+
+    /// Find a syntax for a content type that isn't handled by the built-in
+    /// rules: one from the config file, or one of the user's own syntaxes
+    /// that's named after the media type.
+    fn find_syntax(&self, content_type: &str) -> Option<String> {
+        if let Some(syntax) = find_by_content_type(&self.syntaxes, content_type) {
+            return Some(syntax.to_string());
+        }
+        let custom = self.custom_syntaxes.as_ref()?;
+        let mime = content_type.parse::<Mime>().ok()?;
+        // e.g. application/x-hcl or application/vnd.example+edn
+        let name = match mime.suffix() {
+            Some(suffix) => suffix.as_str(),
+            None => mime.subtype().as_str(),
+        };
+        let name = name.trim_start_matches("x-");
+        custom
+            .find_syntax_by_extension(name)
+            .map(|_| name.to_string())
     }
 
-    fn print_colorized_text(&mut self, text: &str, syntax: &'static str) -> io::Result<()> {
-        // This could perhaps be optimized
-        // syntect processes the whole buffer at once, doing it line by line might
-        // let us start printing earlier (but can decrease quality since regexes
-        // can't look ahead)
+    fn print_colorized_text(&mut self, text: &str, syntax: &str) -> io::Result<()> {
+        // Going line by line keeps syntect from building a list of every
+        // region in the text up front, which dominates on large bodies
         // A buffered writer could improve performance, but we'd have to use a
         // BufferedStandardStream instead of a StandardStream, which is slightly tricky
         // (wrapping a BufWriter around a Buffer wouldn't preserve syntax coloring)
-        self.get_highlighter(syntax).highlight(text)
+        self.get_highlighter(syntax).highlight_lines(text)
     }
 
     fn print_syntax_text(&mut self, text: &str, syntax: &'static str) -> io::Result<()> {
@@ -109,6 +245,19 @@ impl Printer {
         }
     }
 
+    fn print_markdown(&mut self, text: &str) -> io::Result<()> {
+        let colors = if self.color {
+            Some(markdown::Colors {
+                theme: self.theme,
+                custom_syntaxes: self.custom_syntaxes.as_ref(),
+                highlight_max_size: self.highlight_max_size,
+            })
+        } else {
+            None
+        };
+        markdown::render(text, &mut self.buffer, colors)
+    }
+
     fn print_json_text(&mut self, text: &str, check_valid: bool) -> io::Result<()> {
         if !self.indent_json {
             // We don't have to do anything specialized, so fall back to the generic version
@@ -123,12 +272,11 @@ impl Printer {
         }
 
         if self.color {
-            let mut buf = Vec::new();
-            get_json_formatter().format_buf(text.as_bytes(), &mut buf)?;
-            // in principle, buf should already be valid UTF-8,
-            // because JSONXF doesn't mangle it
-            let text = String::from_utf8_lossy(&buf);
-            self.print_colorized_text(&text, "json")
+            // Color the formatter's output as it comes out instead of
+            // collecting all of it first
+            let mut out = HighlightWriter::new(self.get_highlighter("json"));
+            get_json_formatter().format_buf(text.as_bytes(), &mut out)?;
+            out.finish()
         } else {
             let mut out = BufWriter::new(&mut self.buffer);
             get_json_formatter().format_buf(text.as_bytes(), &mut out)?;
@@ -136,28 +284,59 @@ impl Printer {
         }
     }
 
+    /// Print newline-delimited JSON, formatting each line as a document of its own.
+    fn print_json_lines_text(&mut self, text: &str) -> io::Result<()> {
+        // The formatter takes a series of documents in its stride, it's only
+        // the validity check that has to go line by line
+        if text
+            .lines()
+            .all(|line| line.trim().is_empty() || valid_json(line))
+        {
+            self.print_json_text(text, false)
+        } else {
+            self.print_syntax_text(text, "json")
+        }
+    }
+
     fn print_body_text(&mut self, content_type: ContentType, body: &str) -> io::Result<()> {
         match content_type {
             ContentType::Json => self.print_json_text(body, true),
+            ContentType::JsonLines => self.print_json_lines_text(body),
             ContentType::Xml => self.print_syntax_text(body, "xml"),
             ContentType::Html => self.print_syntax_text(body, "html"),
+            ContentType::Css if self.indent_json && reindent::looks_minified(body) => {
+                self.print_syntax_text(&reindent::css(body), "css")
+            }
             ContentType::Css => self.print_syntax_text(body, "css"),
+            ContentType::Markdown if self.render_markdown => self.print_markdown(body),
+            ContentType::Csv if self.indent_json => match table::parse(body) {
+                Some(rows) => table::print(&rows, &mut self.buffer, self.color, self.column_width),
+                None => self.buffer.print(body),
+            },
             // In HTTPie part of this behavior is gated behind the --json flag
             // But it does JSON formatting even without that flag, so doing
             // this check unconditionally is fine
             ContentType::Text | ContentType::JavaScript if valid_json(body) => {
                 self.print_json_text(body, false)
             }
+            ContentType::JavaScript if self.indent_json && reindent::looks_minified(body) => {
+                self.print_syntax_text(&reindent::javascript(body), "js")
+            }
             ContentType::JavaScript => self.print_syntax_text(body, "js"),
             _ => self.buffer.print(body),
         }
     }
 
+    /// Whether streamed output should be checked for binary data.
+    fn check_binary(&self) -> bool {
+        self.buffer.is_terminal() && self.binary_mode != BinaryMode::Text
+    }
+
     fn print_stream(&mut self, reader: &mut impl Read) -> io::Result<()> {
         if !self.buffer.is_terminal() {
             return copy_largebuf(reader, &mut self.buffer);
         }
-        let mut guard = BinaryGuard::new(reader, true);
+        let mut guard = BinaryGuard::new(reader, self.check_binary());
         while let Some(line) = guard.read_line()? {
             self.buffer.print(line)?;
         }
@@ -169,7 +348,7 @@ impl Printer {
         stream: &mut impl Read,
         syntax: &'static str,
     ) -> io::Result<()> {
-        let mut guard = BinaryGuard::new(stream, self.buffer.is_terminal());
+        let mut guard = BinaryGuard::new(stream, self.check_binary());
         let mut highlighter = self.get_highlighter(syntax);
         while let Some(line) = guard.read_line()? {
             highlighter.highlight_bytes(line)?;
@@ -194,22 +373,19 @@ impl Printer {
             // We don't have to do anything specialized, so fall back to the generic version
             self.print_syntax_stream(stream, "json")
         } else if self.color {
-            let mut guard = BinaryGuard::new(stream, self.buffer.is_terminal());
+            let mut guard = BinaryGuard::new(stream, self.check_binary());
             let mut formatter = get_json_formatter();
-            let mut highlighter = self.get_highlighter("json");
-            let mut buf = Vec::new();
+            let mut out = HighlightWriter::new(self.get_highlighter("json"));
             while let Some(line) = guard.read_line()? {
-                formatter.format_buf(line, &mut buf)?;
-                highlighter.highlight_bytes(&buf)?;
-                buf.clear();
+                formatter.format_buf(line, &mut out)?;
             }
-            Ok(())
+            out.finish()
         } else {
             let mut formatter = get_json_formatter();
             if !self.buffer.is_terminal() {
                 return formatter.format_stream_unbuffered(stream, &mut self.buffer);
             }
-            let mut guard = BinaryGuard::new(stream, true);
+            let mut guard = BinaryGuard::new(stream, self.check_binary());
             while let Some(line) = guard.read_line()? {
                 formatter.format_buf(line, &mut self.buffer)?;
             }
@@ -223,7 +399,7 @@ impl Printer {
         body: &mut impl Read,
     ) -> io::Result<()> {
         match content_type {
-            ContentType::Json => self.print_json_stream(body),
+            ContentType::Json | ContentType::JsonLines => self.print_json_stream(body),
             ContentType::Xml => self.print_syntax_stream(body, "xml"),
             ContentType::Html => self.print_syntax_stream(body, "html"),
             ContentType::Css => self.print_syntax_stream(body, "css"),
@@ -247,7 +423,11 @@ impl Printer {
             headers.sort_by_key(|(name, _)| name.as_str());
         }
 
-        let mut header_string = String::new();
+        let size = headers
+            .iter()
+            .map(|(key, value)| key.as_str().len() + value.len() + 3)
+            .sum();
+        let mut header_string = String::with_capacity(size);
         for (key, value) in headers {
             header_string.push_str(key.as_str());
             header_string.push_str(": ");
@@ -266,36 +446,11 @@ impl Printer {
         let method = request.method();
         let url = request.url();
         let query_string = url.query().map_or(String::from(""), |q| ["?", q].concat());
-        let version = reqwest::Version::HTTP_11;
-        let mut headers = request.headers().clone();
+        let version = Version::HTTP_11;
+        let mut headers = request_headers(request);
 
-        headers
-            .entry(ACCEPT)
-            .or_insert_with(|| HeaderValue::from_static("*/*"));
-
-        // See https://github.com/seanmonstar/reqwest/issues/1030
-        // reqwest and hyper add certain headers, but only in the process of
-        // sending the request, which we haven't done yet
-        if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
-            // Added at https://github.com/seanmonstar/reqwest/blob/e56bd160ba/src/blocking/request.rs#L132
-            headers
-                .entry(CONTENT_LENGTH)
-                .or_insert_with(|| body.len().into());
-        }
-        if let Some(host) = request.url().host_str() {
-            // This is incorrect in case of HTTP/2, but we're already assuming
-            // HTTP/1.1 anyway
-            headers.entry(HOST).or_insert_with(|| {
-                // Added at https://github.com/hyperium/hyper/blob/dfa1bb291d/src/client/client.rs#L237
-                if test_mode() {
-                    HeaderValue::from_str("http.mock")
-                } else if let Some(port) = request.url().port() {
-                    HeaderValue::from_str(&format!("{}:{}", host, port))
-                } else {
-                    HeaderValue::from_str(host)
-                }
-                .expect("hostname should already be validated/parsed")
-            });
+        if let Some(redact) = &self.redact {
+            redact_headers(&mut headers, redact);
         }
 
         let request_line = format!("{} {}{} {:?}\n", method, url.path(), query_string, version);
@@ -309,36 +464,428 @@ impl Printer {
     pub fn print_response_headers(&mut self, response: &Response) -> io::Result<()> {
         let version = response.version();
         let status = response.status();
-        let headers = response.headers();
+        let mut headers = response.headers().clone();
+        if let Some(redact) = &self.redact {
+            redact_headers(&mut headers, redact);
+        }
 
         let status_line = format!("{:?} {}\n", version, status);
-        let headers = self.headers_to_string(headers, self.sort_headers);
+        let headers = self.headers_to_string(&headers, self.sort_headers);
 
         self.print_headers(&(status_line + &headers))?;
         self.buffer.print("\n\n")?;
         Ok(())
     }
 
+    /// Print each Set-Cookie header of the response taken apart, with its
+    /// expiry in local time and warnings about attributes browsers reject.
+    pub fn print_cookies(&mut self, response: &Response) -> io::Result<()> {
+        let now = Utc::now();
+        let cookies: Vec<String> = response
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(|value| {
+                SetCookie::parse(value).describe(response.url(), now, &Local, self.redact.is_some())
+            })
+            .collect();
+        if !cookies.is_empty() {
+            self.print_headers(&cookies.join("\n\n"))?;
+            self.buffer.print("\n\n")?;
+        }
+        Ok(())
+    }
+
+    /// Print the address we connected to, the proxy we went through, and a
+    /// summary of the server's certificate if the connection used TLS.
+    /// Internationalized domain names are shown in both forms.
+    pub fn print_connection(
+        &mut self,
+        response: &Response,
+        route: &Route,
+        lookup: Option<&Lookup>,
+    ) -> io::Result<()> {
+        let mut lines = Vec::new();
+        if let Some(host) = response.url().domain() {
+            let (unicode, result) = idna::domain_to_unicode(host);
+            if result.is_ok() && unicode != host {
+                lines.push(format!("host: {} ({})", host, unicode));
+            }
+            // A redirect may have gone to a host that reqwest looked up itself
+            if let Some(lookup) = lookup.filter(|lookup| lookup.host == host) {
+                lines.push(lookup.to_string());
+            }
+        }
+        if let Some(addr) = response.remote_addr() {
+            lines.push(format!("remote-address: {}", addr));
+        }
+        let without_password = |proxy: &Url| {
+            let mut proxy = proxy.clone();
+            let _ = proxy.set_password(None);
+            proxy
+        };
+        match route {
+            Route::Flag(proxy) => lines.push(format!("proxy: {}", without_password(proxy))),
+            Route::Env { proxy, var } => {
+                lines.push(format!("proxy: {} (from {})", without_password(proxy), var))
+            }
+            Route::Bypass {
+                var,
+                no_proxy_var,
+                rule,
+            } => lines.push(format!(
+                "proxy: none ({} is set, but {} matches {:?})",
+                var, no_proxy_var, rule
+            )),
+            Route::System => lines.push("proxy: as the system settings say".to_string()),
+            Route::Direct => (),
+        }
+        let tls_info = response.extensions().get::<TlsInfo>();
+        if tls_info.is_some() {
+            // Both h2 and http/1.1 are offered, so the version tells which
+            // one the server picked. reqwest doesn't report the TLS version,
+            // cipher suite or key exchange.
+            lines.push(match response.version() {
+                Version::HTTP_2 => "alpn: h2".to_string(),
+                _ => "alpn: http/1.1, or none".to_string(),
+            });
+        }
+        let der = tls_info.and_then(TlsInfo::peer_certificate);
+        if let Some(certificate) = der.and_then(Certificate::from_der) {
+            lines.push(certificate.to_string());
+        }
+        if !lines.is_empty() {
+            self.print_headers(&lines.join("\n"))?;
+            self.buffer.print("\n\n")?;
+        }
+        Ok(())
+    }
+
     pub fn print_request_body(&mut self, request: &mut Request) -> anyhow::Result<()> {
-        let content_type = get_content_type(&request.headers());
+        let headers = request.headers().clone();
         if let Some(body) = request.body_mut() {
-            let body = body.buffer()?;
-            if body.contains(&b'\0') {
-                self.buffer.print(BINARY_SUPPRESSOR)?;
+            self.print_raw_request_body(&headers, body.buffer()?)?;
+        }
+        Ok(())
+    }
+
+    /// Print the head of a request that was received rather than sent.
+    pub fn print_raw_request_headers(
+        &mut self,
+        request_line: &str,
+        headers: &HeaderMap,
+    ) -> io::Result<()> {
+        let mut headers = headers.clone();
+        if let Some(redact) = &self.redact {
+            redact_headers(&mut headers, redact);
+        }
+        let headers = self.headers_to_string(&headers, self.sort_headers);
+        self.print_headers(&format!("{}\n{}", request_line, headers))?;
+        self.buffer.print("\n\n")?;
+        Ok(())
+    }
+
+    /// Print a request body that's already in memory, e.g. one that was received.
+    pub fn print_raw_request_body(&mut self, headers: &HeaderMap, body: &[u8]) -> io::Result<()> {
+        let content_type = get_content_type(headers);
+        let boundary = multipart_boundary(headers);
+        self.buffer.set_body(true)?;
+        if let Some(boundary) = boundary {
+            self.print_multipart_body(body, &boundary)?;
+            self.buffer.print("\n")?;
+        } else if body.contains(&b'\0') {
+            self.buffer.print(BINARY_SUPPRESSOR)?;
+        } else if self
+            .format_max_size
+            .map_or(false, |max| body.len() as u64 > max)
+        {
+            // Formatting would make several more copies of what may be
+            // a multi-gigabyte upload
+            if self.color || self.indent_json {
+                eprintln!(
+                    "{}: warning: request body is larger than {}, printing it without formatting",
+                    env!("CARGO_PKG_NAME"),
+                    HumanBytes(self.format_max_size.unwrap_or_default())
+                );
+            }
+            if self.buffer.is_terminal() {
+                self.print_stream(&mut decode_stream(body, UTF_8))?;
             } else {
-                self.print_body_text(content_type, &String::from_utf8_lossy(body))?;
-                self.buffer.print("\n")?;
+                self.buffer.print(body)?;
             }
-            // Breathing room between request and response
             self.buffer.print("\n")?;
+        } else {
+            self.print_body_text(content_type, &String::from_utf8_lossy(body))?;
+            self.buffer.print("\n")?;
+        }
+        self.buffer.set_body(false)?;
+        // Breathing room between request and response
+        self.buffer.print("\n")?;
+        Ok(())
+    }
+
+    /// Print a body after piping it through an external formatter.
+    ///
+    /// If the formatter doesn't work out the body is printed as-is, unless it
+    /// looks binary.
+    fn print_with_formatter(
+        &mut self,
+        command: &str,
+        reader: &mut impl Read,
+    ) -> anyhow::Result<()> {
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body)?;
+        match run_formatter(command, &body) {
+            Ok(output) => self.buffer.print(output)?,
+            Err(err) => {
+                eprintln!(
+                    "{}: warning: formatter {:?} failed: {}",
+                    env!("CARGO_PKG_NAME"),
+                    command,
+                    err
+                );
+                if self.buffer.is_terminal() && looks_binary(&String::from_utf8_lossy(&body)) {
+                    self.buffer.print(BINARY_SUPPRESSOR)?;
+                    return Ok(());
+                }
+                self.buffer.print(&body)?;
+            }
         }
+        self.buffer.print("\n")?;
         Ok(())
     }
 
-    pub fn print_response_body(&mut self, mut response: Response) -> anyhow::Result<()> {
-        let content_type = get_content_type(&response.headers());
+    /// Print a multipart body part by part, leaving out contents that are
+    /// binary or too long to be worth showing.
+    fn print_multipart_body(&mut self, body: &[u8], boundary: &str) -> io::Result<()> {
+        let delimiter = format!("--{}", boundary);
+        let delimiter = delimiter.as_bytes();
+        let mut rest = body;
+        while let Some(pos) = find_bytes(rest, delimiter) {
+            // Everything before a delimiter belongs to the previous part
+            self.print_part(&rest[..pos])?;
+            self.buffer.print(delimiter)?;
+            rest = &rest[pos + delimiter.len()..];
+        }
+        self.buffer.print(rest)
+    }
+
+    fn print_part(&mut self, part: &[u8]) -> io::Result<()> {
+        let pos = match find_bytes(part, b"\r\n\r\n") {
+            Some(pos) => pos,
+            None => return self.buffer.print(part),
+        };
+        let (headers, content) = part.split_at(pos + 4);
+        self.buffer.print(headers)?;
+        // The line break before the next delimiter is part of the delimiter
+        let (content, line_break) = if content.ends_with(b"\r\n") {
+            content.split_at(content.len() - 2)
+        } else {
+            (content, &b""[..])
+        };
+        if looks_binary(&String::from_utf8_lossy(content)) || content.contains(&b'\0') {
+            self.buffer
+                .print(format!("[binary data, {} bytes not shown]", content.len()))?;
+        } else if content.len() > PART_PREVIEW_SIZE {
+            self.buffer.print(&content[..PART_PREVIEW_SIZE])?;
+            self.buffer.print(format!(
+                "\n[{} more bytes not shown]",
+                content.len() - PART_PREVIEW_SIZE
+            ))?;
+        } else {
+            self.buffer.print(content)?;
+        }
+        self.buffer.print(line_break)
+    }
+
+    /// Print a multipart response part by part, with each part's body
+    /// formatted according to its own content type.
+    fn print_multipart_response(&mut self, body: &[u8], boundary: &str) -> io::Result<()> {
+        let delimiter = format!("--{}", boundary);
+        let delimiter = delimiter.as_bytes();
+        let mut rest = match find_bytes(body, delimiter) {
+            // The preamble is meant to be ignored
+            Some(pos) => &body[pos + delimiter.len()..],
+            None => return self.buffer.print(body),
+        };
+        while !rest.starts_with(b"--") {
+            let end = find_bytes(rest, delimiter).unwrap_or(rest.len());
+            self.buffer.print(delimiter)?;
+            self.buffer.print("\n")?;
+            self.print_formatted_part(&rest[..end])?;
+            if end == rest.len() {
+                // The closing delimiter is missing, the body was cut short
+                return Ok(());
+            }
+            rest = &rest[end + delimiter.len()..];
+        }
+        self.buffer.print(delimiter)?;
+        self.buffer.print("--\n")
+    }
+
+    fn print_formatted_part(&mut self, part: &[u8]) -> io::Result<()> {
+        // The line breaks around a part belong to the delimiters
+        let part = if part.starts_with(b"\r\n") {
+            &part[2..]
+        } else {
+            part
+        };
+        let part = match part.len().checked_sub(2) {
+            Some(end) if part.ends_with(b"\r\n") => &part[..end],
+            _ => part,
+        };
+        let (head, content) = if part.starts_with(b"\r\n") {
+            // A part without headers starts with an empty line
+            (&b""[..], &part[2..])
+        } else {
+            match find_bytes(part, b"\r\n\r\n") {
+                Some(pos) => (&part[..pos], &part[pos + 4..]),
+                None => (&b""[..], part),
+            }
+        };
+        let mut headers = HeaderMap::new();
+        for line in head.split(|&b| b == b'\n') {
+            let colon = match line.iter().position(|&b| b == b':') {
+                Some(colon) => colon,
+                None => continue,
+            };
+            let name = HeaderName::from_bytes(&line[..colon]);
+            let value = HeaderValue::from_bytes(trim_bytes(&line[colon + 1..]));
+            if let (Ok(name), Ok(value)) = (name, value) {
+                headers.append(name, value);
+            }
+        }
+        if !headers.is_empty() {
+            let headers = self.headers_to_string(&headers, self.sort_headers);
+            self.print_headers(headers.trim_end())?;
+            self.buffer.print("\n")?;
+        }
+        self.buffer.print("\n")?;
+        let text = String::from_utf8_lossy(content);
+        if self.check_binary() && (looks_binary(&text) || content.contains(&b'\0')) {
+            self.buffer
+                .print(format!("[binary data, {} bytes not shown]", content.len()))?;
+        } else {
+            self.print_body_text(get_content_type(&headers), &text)?;
+        }
+        self.buffer.print("\n")
+    }
+
+    // Trailers (e.g. grpc-status) are not printed after the body because
+    // reqwest discards them: every one of its body types answers
+    // poll_trailers() with None. Sending trailers is impossible for the
+    // same reason. Supporting them means talking to hyper directly.
+    //
+    // Reading more than `max_size` bytes of the body fails with BodyTooLarge.
+    /// Print the body, and return how many bytes of it were read.
+    pub fn print_response_body(
+        &mut self,
+        response: Response,
+        max_size: Option<u64>,
+        trace: Option<&Trace>,
+    ) -> anyhow::Result<u64> {
+        let headers = response.headers().clone();
+        let status = response.status();
+        let body: Box<dyn Read> = match trace {
+            Some(trace) => Box::new(trace.reader(response)),
+            None => Box::new(response),
+        };
+        let mut body = SizeLimit::new(body, max_size);
+        self.buffer.set_body(true)?;
+        let result = self.print_response_body_inner(&headers, status, &mut body);
+        self.buffer.set_body(false)?;
+        result.map(|()| body.bytes_read())
+    }
+
+    /// Print a body that was already read, like the pages of --paginate.
+    pub fn print_buffered_body(
+        &mut self,
+        headers: &HeaderMap,
+        status: StatusCode,
+        body: &[u8],
+    ) -> anyhow::Result<()> {
+        self.buffer.set_body(true)?;
+        let result = self.print_response_body_inner(headers, status, &mut &body[..]);
+        self.buffer.set_body(false)?;
+        result
+    }
+
+    /// Print text that's already formatted, like the output of --template.
+    /// Print the timings of --meta, set apart from the response.
+    pub fn print_meta(&mut self, timings: &Timings) -> io::Result<()> {
+        self.buffer.print("\n")?;
+        self.print_headers(&timings.to_string())?;
+        self.buffer.print("\n")?;
+        self.buffer.flush()
+    }
+
+    pub fn print_raw(&mut self, text: &str) -> io::Result<()> {
+        self.buffer.print(text)?;
+        self.buffer.flush()
+    }
+
+    fn print_response_body_inner(
+        &mut self,
+        headers: &HeaderMap,
+        status: StatusCode,
+        body: &mut impl Read,
+    ) -> anyhow::Result<()> {
+        if self.indent_json {
+            let formatter = headers
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| find_by_content_type(&self.formatters, value))
+                .map(str::to_owned);
+            if let Some(command) = formatter {
+                return self.print_with_formatter(&command, body);
+            }
+        }
+        if self.color && !self.stream && self.binary_mode != BinaryMode::Binary {
+            let syntax = headers
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| self.find_syntax(value));
+            if let Some(syntax) = syntax {
+                match self.read_text_limited(headers, body)? {
+                    Ok(text) if self.check_binary() && looks_binary(&text) => {
+                        self.buffer.print(BINARY_SUPPRESSOR)?;
+                    }
+                    Ok(text) => {
+                        self.print_colorized_text(&text, &syntax)?;
+                        self.buffer.print("\n")?;
+                    }
+                    Err(head) => self.print_oversized_body(head, headers, body)?,
+                }
+                return Ok(());
+            }
+        }
+        let content_type = match get_content_type(headers) {
+            _ if self.json_lines => ContentType::JsonLines,
+            _ if self.table => ContentType::Csv,
+            content_type if !content_type.is_text() && self.binary_mode == BinaryMode::Text => {
+                ContentType::Text
+            }
+            content_type => content_type,
+        };
+        if matches!(content_type, ContentType::Multipart)
+            && !self.stream
+            && self.binary_mode != BinaryMode::Binary
+            && (self.buffer.is_terminal() || self.color || self.indent_json)
+        {
+            if let Some(boundary) = multipart_boundary(headers) {
+                match self.read_limited(body)? {
+                    Ok(body) => self.print_multipart_response(&body, &boundary)?,
+                    Err(head) => self.print_oversized_body(head, headers, body)?,
+                }
+                return Ok(());
+            }
+        }
         if !self.buffer.is_terminal() {
-            if (self.color || self.indent_json) && content_type.is_text() {
+            if (self.color || self.indent_json)
+                && content_type.is_text()
+                && self.binary_mode != BinaryMode::Binary
+            {
                 // The user explicitly asked for formatting even though this is
                 // going into a file, and the response is at least supposed to be
                 // text, so decode it
@@ -352,19 +899,29 @@ impl Printer {
                 // Unconditionally decoding is not an option because the body
                 // might not be text at all
                 if self.stream {
-                    self.print_body_stream(content_type, &mut decode_stream(&mut response))?;
+                    let encoding = self.guess_encoding(headers);
+                    self.print_body_stream(content_type, &mut decode_stream(&mut *body, encoding))?;
                 } else {
-                    let text = response.text()?;
-                    self.print_body_text(content_type, &text)?;
+                    match self.read_text_limited(headers, body)? {
+                        Ok(text) => {
+                            self.print_problem(headers, status, &content_type, &text)?;
+                            self.print_body_text(content_type, &text)?;
+                        }
+                        Err(head) => self.print_oversized_body(head, headers, body)?,
+                    }
                 }
-            } else if self.stream {
-                copy_largebuf(&mut response, &mut self.buffer)?;
             } else {
-                let body = response.bytes()?;
-                self.buffer.print(&body)?;
+                // Nothing to process, so pass the body through in chunks
+                // rather than collecting it first. This keeps memory use
+                // constant no matter how big the body is, and --stream
+                // makes no difference.
+                copy_largebuf(body, &mut self.buffer)?;
             }
+        } else if self.binary_mode == BinaryMode::Binary {
+            self.buffer.print(BINARY_SUPPRESSOR)?;
         } else if self.stream {
-            match self.print_body_stream(content_type, &mut decode_stream(&mut response)) {
+            let encoding = self.guess_encoding(headers);
+            match self.print_body_stream(content_type, &mut decode_stream(&mut *body, encoding)) {
                 Ok(_) => {
                     self.buffer.print("\n")?;
                 }
@@ -374,49 +931,267 @@ impl Printer {
                 Err(err) => return Err(err.into()),
             }
         } else {
-            // Note that .text() behaves like String::from_utf8_lossy()
-            let text = response.text()?;
-            if text.contains('\0') {
-                self.buffer.print(BINARY_SUPPRESSOR)?;
-                return Ok(());
+            match self.read_text_limited(headers, body)? {
+                Ok(text) => {
+                    if self.binary_mode == BinaryMode::Sniff
+                        && (looks_binary(&text) || text.contains('\0'))
+                    {
+                        self.buffer.print(BINARY_SUPPRESSOR)?;
+                        return Ok(());
+                    }
+                    self.print_problem(headers, status, &content_type, &text)?;
+                    self.print_body_text(content_type, &text)?;
+                    self.buffer.print("\n")?;
+                }
+                Err(head) => self.print_oversized_body(head, headers, body)?,
             }
-            self.print_body_text(content_type, &text)?;
-            self.buffer.print("\n")?;
         }
         Ok(())
     }
+
+    /// Print a summary above a JSON body that describes an error, like
+    /// application/problem+json does.
+    fn print_problem(
+        &mut self,
+        headers: &HeaderMap,
+        status: StatusCode,
+        content_type: &ContentType,
+        text: &str,
+    ) -> io::Result<()> {
+        if !self.indent_json || !matches!(content_type, ContentType::Json) {
+            return Ok(());
+        }
+        let content_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        match problem::find(text, content_type, status) {
+            Some(found) => problem::print(&found, status, &mut self.buffer, self.color),
+            None => Ok(()),
+        }
+    }
+
+    /// Read a response body as text, unless it's larger than `format_max_size`.
+    ///
+    /// If it's too large the bytes that were read so far are returned instead,
+    /// and the rest is left in the response.
+    ///
+    /// This decodes like `.text()`, which behaves like String::from_utf8_lossy(),
+    /// but an encoding declared in the body itself is also taken into account.
+    fn read_text_limited(
+        &self,
+        headers: &HeaderMap,
+        body: &mut impl Read,
+    ) -> io::Result<Result<String, Vec<u8>>> {
+        let encoding = self.charset.or_else(|| header_encoding(headers));
+        Ok(self.read_limited(body)?.map(|body| {
+            let encoding = encoding.or_else(|| sniff_encoding(&body)).unwrap_or(UTF_8);
+            encoding.decode(&body).0.into_owned()
+        }))
+    }
+
+    /// Pick the encoding to decode a streaming response with.
+    fn guess_encoding(&self, headers: &HeaderMap) -> &'static Encoding {
+        self.charset
+            .or_else(|| header_encoding(headers))
+            .unwrap_or(UTF_8)
+    }
+
+    /// Read a response body, unless it's larger than `format_max_size`.
+    fn read_limited(&self, reader: &mut impl Read) -> io::Result<Result<Vec<u8>, Vec<u8>>> {
+        let mut body = Vec::new();
+        if let Some(limit) = self.format_max_size {
+            reader.take(limit + 1).read_to_end(&mut body)?;
+            if body.len() as u64 > limit {
+                return Ok(Err(body));
+            }
+        } else {
+            reader.read_to_end(&mut body)?;
+        }
+        Ok(Ok(body))
+    }
+
+    /// Print a body that turned out too large to format, as-is and without
+    /// collecting the rest of it.
+    fn print_oversized_body(
+        &mut self,
+        head: Vec<u8>,
+        headers: &HeaderMap,
+        rest: &mut impl Read,
+    ) -> io::Result<()> {
+        if self.color || self.indent_json {
+            eprintln!(
+                "{}: warning: response body is larger than {}, printing it without formatting",
+                env!("CARGO_PKG_NAME"),
+                HumanBytes(self.format_max_size.unwrap_or_default())
+            );
+        }
+        let encoding = self
+            .charset
+            .or_else(|| header_encoding(headers))
+            .or_else(|| sniff_encoding(&head))
+            .unwrap_or(UTF_8);
+        let mut body = io::Cursor::new(head).chain(rest);
+        if !self.buffer.is_terminal() {
+            return copy_largebuf(&mut body, &mut self.buffer);
+        }
+        let result = self.print_stream(&mut decode_stream(&mut body, encoding));
+        match result {
+            Ok(_) => self.buffer.print("\n"),
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                self.buffer.print(BINARY_SUPPRESSOR)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Run `command` with `input` on its stdin and collect its stdout.
+fn run_formatter(command: &str, input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut child = parse_command(command)?
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_vec();
+    // Feed it from another thread so a formatter that writes as it reads
+    // can't fill up its stdout pipe and deadlock us
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    // Formatters don't have to read all of their input
+    let _ = writer.join();
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("exited with {}", output.status),
+        ))
+    }
+}
+
+/// Find the first occurrence of `needle` in `haystack`.
+/// Get the boundary of a multipart body.
+fn multipart_boundary(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<Mime>().ok())
+        .filter(|mime| mime.type_() == mime::MULTIPART)
+        .and_then(|mime| mime.get_param(mime::BOUNDARY).map(|b| b.to_string()))
+}
+
+fn trim_bytes(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| !b.is_ascii_whitespace());
+    let end = bytes.iter().rposition(|b| !b.is_ascii_whitespace());
+    match (start, end) {
+        (Some(start), Some(end)) => &bytes[start..=end],
+        _ => &[],
+    }
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
 }
 
 /// Decode a streaming response in a way that matches `.text()`.
 ///
+/// The encoding should come from `Printer::guess_encoding()`.
+///
 /// Note that in practice this seems to behave like String::from_utf8_lossy(),
 /// but it makes no guarantees about outputting valid UTF-8 if the input is
 /// invalid UTF-8 (claiming to be UTF-8). So only pass data through here
 /// that's going to the terminal, and don't trust its output.
-fn decode_stream(response: &mut Response) -> impl Read + '_ {
-    let encoding = guess_encoding(response);
-
+fn decode_stream<'a>(stream: impl Read + 'a, encoding: &'static Encoding) -> impl Read + 'a {
     DecodeReaderBytesBuilder::new()
         .encoding(Some(encoding))
-        .build(response)
+        .build(stream)
 }
 
-/// Guess the response's encoding, with UTF-8 as the default.
+/// Get the response's encoding from its Content-Type header.
 ///
 /// reqwest doesn't provide an API for this, so the logic is copied here.
 ///
 /// See https://github.com/seanmonstar/reqwest/blob/2940740493/src/async_impl/response.rs#L172
-fn guess_encoding(response: &Response) -> &'static Encoding {
-    let content_type = response
-        .headers()
+fn header_encoding(headers: &HeaderMap) -> Option<&'static Encoding> {
+    let content_type = headers
         .get(CONTENT_TYPE)
         .and_then(|value| value.to_str().ok())
-        .and_then(|value| value.parse::<Mime>().ok());
-    let encoding_name = content_type
-        .as_ref()
-        .and_then(|mime| mime.get_param("charset").map(|charset| charset.as_str()))
-        .unwrap_or("utf-8");
-    Encoding::for_label(encoding_name.as_bytes()).unwrap_or(UTF_8)
+        .and_then(|value| value.parse::<Mime>().ok())?;
+    let charset = content_type.get_param("charset")?;
+    Encoding::for_label(charset.as_str().as_bytes())
+}
+
+/// Look for an encoding declared in the start of an HTML or XML document,
+/// like `<meta charset="shift_jis">` or `<?xml version="1.0" encoding="latin1"?>`.
+///
+/// A byte order mark doesn't need to be checked here, decoding already
+/// gives it precedence.
+fn sniff_encoding(body: &[u8]) -> Option<&'static Encoding> {
+    let head = &body[..body.len().min(1024)];
+    let head = String::from_utf8_lossy(head).to_ascii_lowercase();
+    let label = if head.trim_start().starts_with("<?xml") {
+        let end = head.find("?>").unwrap_or(head.len());
+        attribute_value(&head[..end], "encoding=")
+    } else {
+        head.match_indices("<meta").find_map(|(pos, _)| {
+            let tag = &head[pos..];
+            let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+            attribute_value(tag, "charset=")
+        })
+    }?;
+    match Encoding::for_label(label.as_bytes())? {
+        // A document that's been decoded to find this can't be UTF-16
+        encoding if encoding == UTF_16LE || encoding == UTF_16BE => Some(UTF_8),
+        encoding => Some(encoding),
+    }
+}
+
+fn attribute_value<'a>(tag: &'a str, key: &str) -> Option<&'a str> {
+    let value = &tag[tag.find(key)? + key.len()..];
+    let value = value.trim_start_matches(['"', '\'']);
+    let end = value
+        .find(|c: char| c == '"' || c == '\'' || c == ';' || c == '>' || c.is_whitespace())
+        .unwrap_or(value.len());
+    Some(&value[..end]).filter(|value| !value.is_empty())
+}
+
+/// The headers of a request as they'll be sent, including the ones that
+/// reqwest and hyper only add while sending it.
+pub fn request_headers(request: &Request) -> HeaderMap {
+    let mut headers = request.headers().clone();
+
+    headers
+        .entry(ACCEPT)
+        .or_insert_with(|| HeaderValue::from_static("*/*"));
+
+    // See https://github.com/seanmonstar/reqwest/issues/1030
+    // reqwest and hyper add certain headers, but only in the process of
+    // sending the request, which we haven't done yet
+    if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+        // Added at https://github.com/seanmonstar/reqwest/blob/e56bd160ba/src/blocking/request.rs#L132
+        headers
+            .entry(CONTENT_LENGTH)
+            .or_insert_with(|| body.len().into());
+    }
+    if let Some(host) = request.url().host_str() {
+        // This is incorrect in case of HTTP/2, but we're already assuming
+        // HTTP/1.1 anyway
+        headers.entry(HOST).or_insert_with(|| {
+            // Added at https://github.com/hyperium/hyper/blob/dfa1bb291d/src/client/client.rs#L237
+            if test_mode() {
+                HeaderValue::from_str("http.mock")
+            } else if let Some(port) = request.url().port() {
+                HeaderValue::from_str(&format!("{}:{}", host, port))
+            } else {
+                HeaderValue::from_str(host)
+            }
+            .expect("hostname should already be validated/parsed")
+        });
+    }
+    headers
 }
 
 #[cfg(test)]
@@ -427,10 +1202,21 @@ mod tests {
 
     fn run_cmd(args: impl IntoIterator<Item = String>, is_stdout_tty: bool) -> Printer {
         let args = Cli::from_iter_safe(args).unwrap();
-        let buffer =
-            Buffer::new(args.download, args.output.as_deref(), is_stdout_tty, None).unwrap();
+        let buffer = Buffer::new(
+            args.download,
+            args.output.as_deref(),
+            is_stdout_tty,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
         let pretty = args.pretty.unwrap_or_else(|| buffer.guess_pretty());
-        Printer::new(pretty, args.style, false, buffer)
+        let options = PrinterOptions {
+            theme: args.style,
+            ..PrinterOptions::default()
+        };
+        Printer::new(pretty, options, buffer)
     }
 
     fn temp_path(filename: &str) -> String {
@@ -507,4 +1293,27 @@ mod tests {
         assert_eq!(p.color, true);
         assert_matches!(p.buffer, Buffer::Stderr(..));
     }
+
+    #[test]
+    fn encoding_sniffing() {
+        use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+
+        assert_eq!(
+            sniff_encoding(b"<!doctype html><meta charset=\"Shift_JIS\">"),
+            Some(SHIFT_JIS)
+        );
+        assert_eq!(
+            sniff_encoding(
+                b"<meta http-equiv='Content-Type' content='text/html; charset=iso-8859-1'>"
+            ),
+            Some(WINDOWS_1252)
+        );
+        assert_eq!(
+            sniff_encoding(b"<?xml version=\"1.0\" encoding='latin1'?><a/>"),
+            Some(WINDOWS_1252)
+        );
+        assert_eq!(sniff_encoding(b"<meta charset=utf-16>"), Some(UTF_8));
+        assert_eq!(sniff_encoding(b"<meta name=charset>"), None);
+        assert_eq!(sniff_encoding(b"charset=latin1"), None);
+    }
 }
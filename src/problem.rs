@@ -0,0 +1,237 @@
+// A summary of error bodies, printed above the JSON itself: RFC 7807's
+// application/problem+json and the envelopes that popular APIs use
+
+use std::io::{self, Write};
+
+use reqwest::StatusCode;
+use serde_json::{Map, Value};
+use termcolor::{Color, ColorSpec, WriteColor};
+
+use crate::buffer::Buffer;
+
+/// The parts of an error that say what went wrong.
+#[derive(Debug, Default, PartialEq)]
+pub struct Problem {
+    pub title: Option<String>,
+    /// A URL that identifies the kind of problem, or documents it
+    pub kind: Option<String>,
+    pub details: Vec<String>,
+    pub instance: Option<String>,
+}
+
+/// Find a problem in a JSON body.
+///
+/// Bodies of `application/problem+json` are always read as problems. Other
+/// JSON is only looked at when the status is an error, because an object
+/// with a `message` is just as likely to be a successful response.
+pub fn find(body: &str, content_type: &str, status: StatusCode) -> Option<Problem> {
+    let object = match serde_json::from_str(body).ok()? {
+        Value::Object(object) => object,
+        _ => return None,
+    };
+    let problem = if content_type.contains("problem+json") {
+        rfc7807(&object)
+    } else if !(status.is_client_error() || status.is_server_error()) {
+        return None;
+    } else if object.contains_key("title")
+        && (object.contains_key("type") || object.contains_key("detail"))
+    {
+        // Many APIs send problem details as plain application/json
+        rfc7807(&object)
+    } else {
+        envelope(&object)
+    };
+    if problem == Problem::default() {
+        None
+    } else {
+        Some(problem)
+    }
+}
+
+fn rfc7807(object: &Map<String, Value>) -> Problem {
+    Problem {
+        title: text(object.get("title")),
+        // about:blank means there's nothing more than the status code
+        kind: text(object.get("type")).filter(|kind| kind != "about:blank"),
+        details: text(object.get("detail")).into_iter().collect(),
+        instance: text(object.get("instance")),
+    }
+}
+
+/// Errors in the shapes that aren't standardized, but common.
+fn envelope(object: &Map<String, Value>) -> Problem {
+    let mut problem = Problem::default();
+    match object.get("error") {
+        // Google, OpenAI, Stripe: {"error": {"code": 404, "message": "..."}}
+        Some(Value::Object(error)) => {
+            problem.title = text(error.get("status"))
+                .or_else(|| text(error.get("type")))
+                .or_else(|| text(error.get("code")));
+            problem.details.extend(text(error.get("message")));
+        }
+        // OAuth 2.0: {"error": "invalid_grant", "error_description": "..."}
+        Some(error @ Value::String(_)) => {
+            problem.title = text(Some(error));
+            problem.details.extend(
+                text(object.get("error_description")).or_else(|| text(object.get("message"))),
+            );
+        }
+        _ => {}
+    }
+    // GraphQL and JSON:API: {"errors": [{"message": "..."}, ...]}
+    if let Some(Value::Array(errors)) = object.get("errors") {
+        problem.details.extend(errors.iter().filter_map(|error| {
+            match error {
+                Value::Object(error) => text(error.get("message"))
+                    .or_else(|| text(error.get("detail")))
+                    .or_else(|| text(error.get("title"))),
+                error => text(Some(error)),
+            }
+        }));
+    }
+    if problem.details.is_empty() {
+        // GitHub: {"message": "...", "documentation_url": "..."}
+        // Django REST framework: {"detail": "..."}
+        problem
+            .details
+            .extend(text(object.get("message")).or_else(|| text(object.get("detail"))));
+        problem.kind = text(object.get("documentation_url"));
+    }
+    problem
+}
+
+/// A string, number or boolean as text.
+fn text(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::String(text) if !text.trim().is_empty() => Some(text.trim().to_string()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Print the problem with its fields lined up, followed by a blank line.
+///
+/// Without a title the status stands in.
+pub fn print(
+    problem: &Problem,
+    status: StatusCode,
+    out: &mut Buffer,
+    color: bool,
+) -> io::Result<()> {
+    let title = match &problem.title {
+        Some(title) => title.clone(),
+        None => status.to_string(),
+    };
+    if color {
+        out.set_color(ColorSpec::new().set_fg(Some(Color::Red)).set_bold(true))?;
+        out.write_all(title.as_bytes())?;
+        out.reset()?;
+    } else {
+        out.write_all(title.as_bytes())?;
+    }
+    out.write_all(b"\n")?;
+
+    let mut fields = Vec::new();
+    fields.extend(problem.kind.iter().map(|kind| ("type", kind)));
+    for (i, detail) in problem.details.iter().enumerate() {
+        fields.push((if i == 0 { "detail" } else { "" }, detail));
+    }
+    fields.extend(
+        problem
+            .instance
+            .iter()
+            .map(|instance| ("instance", instance)),
+    );
+    for (label, value) in fields {
+        // Keep every line of a long detail under the value column
+        let value = value.replace('\n', "\n            ");
+        if color && !label.is_empty() {
+            out.write_all(b"  ")?;
+            out.set_color(ColorSpec::new().set_bold(true))?;
+            write!(out, "{:<8}", label)?;
+            out.reset()?;
+            writeln!(out, "  {}", value)?;
+        } else {
+            writeln!(out, "  {:<8}  {}", label, value)?;
+        }
+    }
+    out.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_error(body: &str) -> Option<Problem> {
+        find(body, "application/json", StatusCode::BAD_REQUEST)
+    }
+
+    #[test]
+    fn problem_details() {
+        let body = r#"{
+            "type": "https://example.com/probs/out-of-credit",
+            "title": "You do not have enough credit.",
+            "detail": "Your current balance is 30, but that costs 50.",
+            "instance": "/account/12345/msgs/abc",
+            "balance": 30
+        }"#;
+        let expected = Problem {
+            title: Some("You do not have enough credit.".to_string()),
+            kind: Some("https://example.com/probs/out-of-credit".to_string()),
+            details: vec!["Your current balance is 30, but that costs 50.".to_string()],
+            instance: Some("/account/12345/msgs/abc".to_string()),
+        };
+        assert_eq!(
+            find(body, "application/problem+json", StatusCode::FORBIDDEN),
+            Some(expected)
+        );
+        assert_eq!(
+            find(
+                r#"{"type": "about:blank", "status": 404}"#,
+                "application/problem+json",
+                StatusCode::NOT_FOUND
+            ),
+            None
+        );
+        // Only problem+json is read on success
+        assert!(find(body, "application/json", StatusCode::OK).is_none());
+        assert!(find(body, "application/problem+json", StatusCode::OK).is_some());
+        assert!(find_error(body).is_some());
+    }
+
+    #[test]
+    fn envelopes() {
+        let problem = find_error(r#"{"error": {"code": 404, "message": "Not here"}}"#).unwrap();
+        assert_eq!(problem.title.as_deref(), Some("404"));
+        assert_eq!(problem.details, ["Not here"]);
+
+        let problem = find_error(
+            r#"{"error": "invalid_grant", "error_description": "The code has expired"}"#,
+        )
+        .unwrap();
+        assert_eq!(problem.title.as_deref(), Some("invalid_grant"));
+        assert_eq!(problem.details, ["The code has expired"]);
+
+        let problem = find_error(
+            r#"{"data": null, "errors": [{"message": "Unknown field"}, {"detail": "Bad id"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(problem.title, None);
+        assert_eq!(problem.details, ["Unknown field", "Bad id"]);
+
+        let problem = find_error(
+            r#"{"message": "Bad credentials", "documentation_url": "https://docs.github.com/rest"}"#,
+        )
+        .unwrap();
+        assert_eq!(problem.details, ["Bad credentials"]);
+        assert_eq!(
+            problem.kind.as_deref(),
+            Some("https://docs.github.com/rest")
+        );
+
+        assert!(find_error(r#"{"id": 1}"#).is_none());
+        assert!(find_error(r#"["error"]"#).is_none());
+        assert!(find_error("not json").is_none());
+    }
+}
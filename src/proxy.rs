@@ -0,0 +1,342 @@
+// Which proxy a request goes through. Proxies from --proxy are used as given,
+// otherwise they come from the environment, the way curl reads it:
+//
+// - `http_proxy`, `https_proxy` and `all_proxy` by scheme, also in uppercase
+//   (except `HTTP_PROXY` in a CGI script, where a client can set it)
+// - `no_proxy`, a list of hosts that are reached directly. A domain also
+//   matches its subdomains, and may have a port. IP addresses, CIDR ranges
+//   like `10.0.0.0/8` and `*` for everything work too.
+//
+// With --system-proxy the settings of macOS or Windows are used if nothing is
+// set in the environment. reqwest reads those itself, so it also picks the
+// proxy for each request then, and doesn't apply the exceptions in them.
+
+use std::env;
+use std::net::IpAddr;
+
+use reqwest::Url;
+
+use crate::cli::Proxy;
+
+/// How a request is routed, and why.
+#[derive(Debug, PartialEq)]
+pub enum Route {
+    /// Through a proxy from --proxy.
+    Flag(Url),
+    /// Through the proxy in an environment variable.
+    Env {
+        proxy: Url,
+        var: &'static str,
+    },
+    /// Past the proxy in an environment variable, because of a rule in
+    /// `no_proxy`.
+    Bypass {
+        var: &'static str,
+        no_proxy_var: &'static str,
+        rule: String,
+    },
+    /// However the system settings say, which reqwest reads and applies.
+    System,
+    Direct,
+}
+
+/// The variables a proxy can be set in, as curl and reqwest read them.
+const PROXY_VARS: &[&str] = &[
+    "http_proxy",
+    "HTTP_PROXY",
+    "https_proxy",
+    "HTTPS_PROXY",
+    "all_proxy",
+    "ALL_PROXY",
+];
+
+impl Route {
+    pub fn resolve(proxies: &[Proxy], system: bool, url: &Url) -> Route {
+        Route::resolve_with(proxies, system, url, |var| env::var(var).ok())
+    }
+
+    /// Whether reqwest should pick proxies from the system settings, rather
+    /// than xh from the environment: with --system-proxy, if the environment
+    /// has none.
+    pub fn uses_system_settings(system: bool) -> bool {
+        system && !env_has_proxy(|var| env::var(var).ok())
+    }
+
+    fn resolve_with(
+        proxies: &[Proxy],
+        system: bool,
+        url: &Url,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Route {
+        if !proxies.is_empty() {
+            return match Proxy::for_url(proxies, url) {
+                Some(proxy) => Route::Flag(proxy.clone()),
+                None => Route::Direct,
+            };
+        }
+        // reqwest only reads the system settings if the environment has no proxy
+        let system = system && !env_has_proxy(&env);
+        let env = |var: &'static str| {
+            env(var)
+                .filter(|value| !value.trim().is_empty())
+                .map(|value| (var, value))
+        };
+
+        let in_cgi = env("REQUEST_METHOD").is_some();
+        let proxy = match url.scheme() {
+            "http" => env("http_proxy").or_else(|| if in_cgi { None } else { env("HTTP_PROXY") }),
+            "https" => env("https_proxy").or_else(|| env("HTTPS_PROXY")),
+            _ => None,
+        }
+        .or_else(|| env("all_proxy"))
+        .or_else(|| env("ALL_PROXY"));
+        let (var, proxy) = match proxy {
+            Some(found) => found,
+            None if system => return Route::System,
+            None => return Route::Direct,
+        };
+
+        if let Some((no_proxy_var, no_proxy)) = env("no_proxy").or_else(|| env("NO_PROXY")) {
+            if let Some(rule) = matching_rule(&no_proxy, url) {
+                return Route::Bypass {
+                    var,
+                    no_proxy_var,
+                    rule: rule.to_string(),
+                };
+            }
+        }
+        // Like curl, assume HTTP if the scheme is missing
+        let proxy = if proxy.contains("://") {
+            Url::parse(&proxy)
+        } else {
+            Url::parse(&format!("http://{}", proxy))
+        };
+        match proxy {
+            Ok(proxy) => Route::Env { proxy, var },
+            // reqwest would have skipped it too
+            Err(_) => Route::Direct,
+        }
+    }
+
+    pub fn proxy(&self) -> Option<&Url> {
+        match self {
+            Route::Flag(proxy) | Route::Env { proxy, .. } => Some(proxy),
+            Route::Bypass { .. } | Route::System | Route::Direct => None,
+        }
+    }
+}
+
+/// Whether a proxy is set in any of the variables, even for another scheme.
+fn env_has_proxy(env: impl Fn(&str) -> Option<String>) -> bool {
+    let in_cgi = env("REQUEST_METHOD").is_some();
+    PROXY_VARS
+        .iter()
+        .filter(|&&var| !(in_cgi && var == "HTTP_PROXY"))
+        .any(|var| env(var).map_or(false, |value| !value.trim().is_empty()))
+}
+
+/// Find the rule in a `no_proxy` list that `url` matches.
+fn matching_rule<'a>(no_proxy: &'a str, url: &Url) -> Option<&'a str> {
+    let host = url.host_str()?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let ip = host.parse::<IpAddr>().ok();
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    no_proxy
+        .split(|ch: char| ch == ',' || ch.is_whitespace())
+        .filter(|rule| !rule.is_empty())
+        .find(|rule| {
+            if *rule == "*" {
+                return true;
+            }
+            if let Some(ip) = ip {
+                return ip_matches(rule, ip);
+            }
+            let (domain, port) = split_port(rule);
+            if port.is_some() && port != url.port_or_known_default() {
+                return false;
+            }
+            let domain = domain
+                .trim_start_matches('*')
+                .trim_start_matches('.')
+                .trim_end_matches('.')
+                .to_ascii_lowercase();
+            !domain.is_empty()
+                && (host == domain
+                    || (host.ends_with(&domain)
+                        && host[..host.len() - domain.len()].ends_with('.')))
+        })
+}
+
+/// Whether `ip` is the address in `rule`, or in its CIDR range.
+fn ip_matches(rule: &str, ip: IpAddr) -> bool {
+    let mut parts = rule.splitn(2, '/');
+    let address = parts.next().unwrap_or("");
+    let address = address.trim_start_matches('[').trim_end_matches(']');
+    let address = match address.parse::<IpAddr>() {
+        Ok(address) => address,
+        Err(_) => return false,
+    };
+    let prefix = match parts.next() {
+        Some(prefix) => match prefix.parse::<u32>() {
+            Ok(prefix) => prefix,
+            Err(_) => return false,
+        },
+        None => return address == ip,
+    };
+    match (address, ip) {
+        (IpAddr::V4(address), IpAddr::V4(ip)) if prefix <= 32 => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(address) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(address), IpAddr::V6(ip)) if prefix <= 128 => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(address) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Split a port off a `no_proxy` rule like `example.com:8080`.
+fn split_port(rule: &str) -> (&str, Option<u16>) {
+    if let Some(index) = rule.rfind(':') {
+        if let Ok(port) = rule[index + 1..].parse() {
+            return (&rule[..index], Some(port));
+        }
+    }
+    (rule, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(vars: &[(&str, &str)], url: &str) -> Route {
+        let env = |var: &str| {
+            vars.iter()
+                .find(|(name, _)| *name == var)
+                .map(|(_, value)| value.to_string())
+        };
+        Route::resolve_with(&[], false, &Url::parse(url).unwrap(), env)
+    }
+
+    fn bypassed(no_proxy: &str, url: &str) -> Option<String> {
+        match resolve(&[("all_proxy", "proxy:3128"), ("no_proxy", no_proxy)], url) {
+            Route::Bypass { rule, .. } => Some(rule),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn environment_proxies() {
+        let proxy = Url::parse("http://proxy:3128").unwrap();
+        assert_eq!(
+            resolve(&[("HTTPS_PROXY", "proxy:3128")], "https://example.com"),
+            Route::Env {
+                proxy: proxy.clone(),
+                var: "HTTPS_PROXY"
+            }
+        );
+        assert_eq!(
+            resolve(&[("https_proxy", "proxy:3128")], "http://example.com"),
+            Route::Direct
+        );
+        assert_eq!(
+            resolve(
+                &[
+                    ("ALL_PROXY", "socks5://other"),
+                    ("http_proxy", "proxy:3128")
+                ],
+                "http://example.com"
+            ),
+            Route::Env {
+                proxy,
+                var: "http_proxy"
+            }
+        );
+        assert_eq!(
+            resolve(
+                &[("HTTP_PROXY", "proxy:3128"), ("REQUEST_METHOD", "GET")],
+                "http://example.com"
+            ),
+            Route::Direct
+        );
+        assert_eq!(
+            resolve(
+                &[("NO_PROXY", "example.com"), ("all_proxy", "proxy")],
+                "http://example.com"
+            ),
+            Route::Bypass {
+                var: "all_proxy",
+                no_proxy_var: "NO_PROXY",
+                rule: "example.com".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn no_proxy_rules() {
+        let rules = "localhost, .internal example.com:8080,10.0.0.0/8,[::1],fd00::/8";
+        let cases = &[
+            ("http://localhost", Some("localhost")),
+            ("http://LOCALHOST.:8000", Some("localhost")),
+            ("http://notlocalhost", None),
+            ("http://internal", Some(".internal")),
+            ("https://db.internal", Some(".internal")),
+            ("http://example.com:8080", Some("example.com:8080")),
+            ("http://api.example.com:8080/x", Some("example.com:8080")),
+            ("http://example.com", None),
+            ("http://10.1.2.3", Some("10.0.0.0/8")),
+            ("http://11.1.2.3", None),
+            ("http://[::1]:80", Some("[::1]")),
+            ("http://[fd12::1]", Some("fd00::/8")),
+            ("http://[fe80::1]", None),
+        ];
+        for (url, rule) in cases {
+            assert_eq!(bypassed(rules, url).as_deref(), *rule, "{}", url);
+        }
+        assert_eq!(bypassed("*", "https://anything").as_deref(), Some("*"));
+        assert_eq!(
+            bypassed("0.0.0.0/0", "http://1.2.3.4").as_deref(),
+            Some("0.0.0.0/0")
+        );
+        assert_eq!(bypassed("", "http://example.com"), None);
+    }
+
+    #[test]
+    fn system_proxies() {
+        let resolve = |vars: &'static [(&str, &str)], url: &str| {
+            let env = |var: &str| {
+                vars.iter()
+                    .find(|(name, _)| *name == var)
+                    .map(|(_, value)| value.to_string())
+            };
+            Route::resolve_with(&[], true, &Url::parse(url).unwrap(), env)
+        };
+        assert_eq!(resolve(&[], "http://example.com"), Route::System);
+        assert_eq!(
+            resolve(&[("https_proxy", " ")], "http://example.com"),
+            Route::System
+        );
+
+        // The environment takes precedence, even with a proxy for another scheme
+        assert_eq!(
+            resolve(&[("http_proxy", "other:8080")], "http://example.com"),
+            Route::Env {
+                proxy: Url::parse("http://other:8080").unwrap(),
+                var: "http_proxy"
+            }
+        );
+        assert_eq!(
+            resolve(&[("https_proxy", "other:8080")], "http://example.com"),
+            Route::Direct
+        );
+        // Except HTTP_PROXY in a CGI script, which reqwest ignores as well
+        assert_eq!(
+            resolve(
+                &[("HTTP_PROXY", "other:8080"), ("REQUEST_METHOD", "GET")],
+                "http://example.com"
+            ),
+            Route::System
+        );
+    }
+}
@@ -0,0 +1,113 @@
+// Masks credentials in what --verbose and --curl print, and in what --trace,
+// --audit-log and the HAR archives write, unless --show-secrets
+//
+// xh doesn't keep sessions, so those are the only places headers end up.
+// Anything that starts saving them should go through here.
+
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, SET_COOKIE,
+};
+
+pub const MASK: &str = "********";
+
+/// Whether a header is always masked or was passed to --redact.
+pub fn is_secret(name: &HeaderName, redact: &[HeaderName]) -> bool {
+    [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE, SET_COOKIE].contains(name) || redact.contains(name)
+}
+
+/// Mask the secret headers in `headers`.
+pub fn redact_headers(headers: &mut HeaderMap, redact: &[HeaderName]) {
+    let names: Vec<HeaderName> = headers
+        .keys()
+        .filter(|name| is_secret(name, redact))
+        .cloned()
+        .collect();
+    for name in names {
+        let values: Vec<HeaderValue> = headers
+            .get_all(&name)
+            .iter()
+            .map(|value| redact_value(&name, value))
+            .collect();
+        headers.remove(&name);
+        for value in values {
+            headers.append(&name, value);
+        }
+    }
+}
+
+/// Mask a secret header's value, keeping the parts that help to debug it:
+/// the scheme of credentials and the names and attributes of cookies.
+pub fn redact_value(name: &HeaderName, value: &HeaderValue) -> HeaderValue {
+    let value = match value.to_str() {
+        Ok(value) => value,
+        Err(_) => return HeaderValue::from_static(MASK),
+    };
+    let redacted = if name == AUTHORIZATION || name == PROXY_AUTHORIZATION {
+        match value.find(' ') {
+            Some(index) if index > 0 => format!("{} {}", &value[..index], MASK),
+            _ => MASK.to_string(),
+        }
+    } else if name == COOKIE {
+        value
+            .split(';')
+            .map(|cookie| redact_cookie(cookie.trim()))
+            .collect::<Vec<_>>()
+            .join("; ")
+    } else if name == SET_COOKIE {
+        match value.find(';') {
+            Some(index) => format!("{}{}", redact_cookie(&value[..index]), &value[index..]),
+            None => redact_cookie(value),
+        }
+    } else {
+        MASK.to_string()
+    };
+    HeaderValue::from_str(&redacted).expect("header value should stay valid")
+}
+
+fn redact_cookie(cookie: &str) -> String {
+    match cookie.find('=') {
+        Some(index) => format!("{}={}", &cookie[..index], MASK),
+        None => MASK.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values() {
+        let api_key = HeaderName::from_static("x-api-key");
+        let expected = [
+            (AUTHORIZATION, "Basic dXNlcjpwYXNz", "Basic ********"),
+            (AUTHORIZATION, "secret", "********"),
+            (PROXY_AUTHORIZATION, "Digest a=\"b\"", "Digest ********"),
+            (COOKIE, "a=1; b=2", "a=********; b=********"),
+            (
+                SET_COOKIE,
+                "id=abc; Path=/; HttpOnly",
+                "id=********; Path=/; HttpOnly",
+            ),
+            (api_key, "secret", "********"),
+        ];
+        for (name, value, redacted) in &expected {
+            let value = HeaderValue::from_static(value);
+            assert_eq!(redact_value(name, &value), redacted);
+        }
+    }
+
+    #[test]
+    fn headers() {
+        let api_key = HeaderName::from_static("x-api-key");
+        let mut headers = HeaderMap::new();
+        headers.append(SET_COOKIE, HeaderValue::from_static("a=1"));
+        headers.append(SET_COOKIE, HeaderValue::from_static("b=2"));
+        headers.append(&api_key, HeaderValue::from_static("secret"));
+        headers.append("x-other", HeaderValue::from_static("visible"));
+        redact_headers(&mut headers, std::slice::from_ref(&api_key));
+        let set_cookie: Vec<_> = headers.get_all(SET_COOKIE).iter().collect();
+        assert_eq!(set_cookie, ["a=********", "b=********"]);
+        assert_eq!(headers[&api_key], MASK);
+        assert_eq!(headers["x-other"], "visible");
+    }
+}
@@ -0,0 +1,466 @@
+// Lays minified JavaScript and CSS out over indented lines, for --pretty=format
+//
+// This is no parser. It keeps track of strings, comments and brackets, and
+// breaks lines around blocks and statements, but the code in between is left
+// as it was. Line breaks that are already there are kept, because in
+// JavaScript they can end a statement.
+
+const INDENT: &str = "    ";
+
+/// Lines at least this long are taken to be minified.
+const LONG_LINE: usize = 200;
+
+/// Whether most of `text` is on very long lines, the way minifiers leave it.
+///
+/// Code that's been laid out by hand is better left alone.
+pub fn looks_minified(text: &str) -> bool {
+    let long: usize = text
+        .lines()
+        .map(str::len)
+        .filter(|&len| len >= LONG_LINE)
+        .sum();
+    long * 2 > text.len()
+}
+
+struct Output {
+    lines: Vec<String>,
+    indent: usize,
+    line: String,
+}
+
+impl Output {
+    fn new() -> Self {
+        Output {
+            lines: Vec::new(),
+            indent: 0,
+            line: String::new(),
+        }
+    }
+
+    fn push(&mut self, text: &str) {
+        self.line.push_str(text);
+    }
+
+    /// Add a space, unless the line is empty or already ends with one.
+    fn space(&mut self) {
+        if !self.line.is_empty() && !self.line.ends_with(' ') {
+            self.line.push(' ');
+        }
+    }
+
+    fn break_line(&mut self) {
+        let line = self.line.trim();
+        if !line.is_empty() {
+            self.lines
+                .push(format!("{}{}", INDENT.repeat(self.indent), line));
+        }
+        self.line.clear();
+    }
+
+    /// The lines, ending with a newline like formatted JSON does.
+    fn finish(mut self) -> String {
+        self.break_line();
+        self.lines.push(String::new());
+        self.lines.join("\n")
+    }
+}
+
+/// Put each rule and declaration of a stylesheet on a line of its own.
+pub fn css(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = Output::new();
+    let mut parens = 0usize;
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        match ch {
+            '"' | '\'' => {
+                let end = skip_string(&chars, i + 1, ch);
+                out.push(&collect(&chars[i..end]));
+                i = end;
+                continue;
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                let end = skip_block_comment(&chars, i + 2);
+                let standalone = out.line.trim().is_empty();
+                out.push(&collect(&chars[i..end]));
+                if standalone {
+                    out.break_line();
+                }
+                i = end;
+                continue;
+            }
+            '(' => {
+                parens += 1;
+                out.push("(");
+            }
+            ')' => {
+                parens = parens.saturating_sub(1);
+                out.push(")");
+            }
+            '{' if parens == 0 => {
+                out.space();
+                out.push("{");
+                out.break_line();
+                out.indent += 1;
+            }
+            ';' if parens == 0 => {
+                out.line = declaration(&out.line);
+                out.push(";");
+                out.break_line();
+            }
+            '}' if parens == 0 => {
+                out.line = declaration(&out.line);
+                out.break_line();
+                out.indent = out.indent.saturating_sub(1);
+                out.push("}");
+                out.break_line();
+            }
+            ch if ch.is_whitespace() => out.space(),
+            ch => out.line.push(ch),
+        }
+        i += 1;
+    }
+    out.finish()
+}
+
+/// Add a space after the colon of a declaration like `color:red`.
+fn declaration(text: &str) -> String {
+    let text = text.trim();
+    if text.starts_with('@') {
+        return text.to_string();
+    }
+    match text.find(':') {
+        Some(index) => format!(
+            "{}: {}",
+            text[..index].trim_end(),
+            text[index + 1..].trim_start()
+        ),
+        None => text.to_string(),
+    }
+}
+
+/// Words after which a `/` starts a regular expression rather than a division.
+const KEYWORDS_BEFORE_EXPRESSION: &[&str] = &[
+    "return",
+    "typeof",
+    "instanceof",
+    "in",
+    "of",
+    "new",
+    "delete",
+    "void",
+    "throw",
+    "case",
+    "do",
+    "else",
+    "yield",
+    "await",
+];
+
+/// Words that continue a statement after a closing brace.
+const KEYWORDS_AFTER_BLOCK: &[&str] = &["else", "catch", "finally", "while"];
+
+/// Put each statement of a script on a line of its own, and indent blocks.
+pub fn javascript(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = Output::new();
+    // The depth of parentheses and square brackets, for each level of braces.
+    // Semicolons and commas inside them don't end a line.
+    let mut parens = vec![0usize];
+    // The last character that wasn't whitespace or part of a comment
+    let mut last = None;
+    // The identifier or keyword that `last` ends, if any
+    let mut word = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        let next = chars.get(i + 1).copied();
+        let (end, comment) = match ch {
+            '"' | '\'' => (skip_string(&chars, i + 1, ch), false),
+            '`' => (skip_template(&chars, i + 1), false),
+            '/' if next == Some('/') => (skip_line_comment(&chars, i + 2), true),
+            '/' if next == Some('*') => (skip_block_comment(&chars, i + 2), true),
+            '/' if starts_expression(last, &word) => (skip_regex(&chars, i + 1), false),
+            _ => (i, false),
+        };
+        if end > i {
+            let standalone = out.line.trim().is_empty();
+            out.push(&collect(&chars[i..end]));
+            if !comment {
+                last = Some(ch);
+                word.clear();
+            } else if standalone && next == Some('*') {
+                out.break_line();
+            }
+            i = end;
+            continue;
+        }
+
+        match ch {
+            '\n' => out.break_line(),
+            ch if ch.is_whitespace() => out.space(),
+            '(' | '[' => {
+                *parens.last_mut().unwrap() += 1;
+                out.line.push(ch);
+            }
+            ')' | ']' => {
+                let depth = parens.last_mut().unwrap();
+                *depth = depth.saturating_sub(1);
+                out.line.push(ch);
+            }
+            '{' => {
+                if last == Some(')') || last.map_or(false, is_identifier) {
+                    out.space();
+                }
+                if next_token(&chars, i + 1) == "}" {
+                    // Leave empty blocks and objects as they are
+                    out.push("{}");
+                    i = skip_whitespace(&chars, i + 1);
+                    last = Some('}');
+                    word.clear();
+                    i += 1;
+                    continue;
+                }
+                out.push("{");
+                out.break_line();
+                out.indent += 1;
+                parens.push(0);
+            }
+            '}' => {
+                out.break_line();
+                out.indent = out.indent.saturating_sub(1);
+                if parens.len() > 1 {
+                    parens.pop();
+                }
+                out.push("}");
+                let following = next_token(&chars, i + 1);
+                if KEYWORDS_AFTER_BLOCK.contains(&following.as_str()) {
+                    out.push(" ");
+                    i = skip_whitespace(&chars, i + 1);
+                    last = Some('}');
+                    word.clear();
+                    continue;
+                }
+                if !following.starts_with(|ch| ")],;.?:(=+-*/%&|<>".contains(ch)) {
+                    out.break_line();
+                }
+            }
+            ';' | ',' if parens.last() == Some(&0) => {
+                out.line.push(ch);
+                out.break_line();
+            }
+            ch => out.line.push(ch),
+        }
+
+        if ch.is_whitespace() {
+            // The next identifier is a new word
+            last = last.map(|last| if is_identifier(last) { ' ' } else { last });
+        } else {
+            if !is_identifier(ch) {
+                word.clear();
+            } else if !last.map_or(false, is_identifier) {
+                word.clear();
+                word.push(ch);
+            } else {
+                word.push(ch);
+            }
+            last = Some(ch);
+        }
+        i += 1;
+    }
+    out.finish()
+}
+
+fn is_identifier(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_' || ch == '$'
+}
+
+/// Whether a `/` after this token starts a regular expression.
+fn starts_expression(last: Option<char>, word: &str) -> bool {
+    match last {
+        None => true,
+        Some(' ') => KEYWORDS_BEFORE_EXPRESSION.contains(&word),
+        Some(ch) if is_identifier(ch) => KEYWORDS_BEFORE_EXPRESSION.contains(&word),
+        Some(ch) => !matches!(ch, ')' | ']' | '}' | '"' | '\'' | '`' | '/'),
+    }
+}
+
+/// The next word or character after whitespace.
+fn next_token(chars: &[char], start: usize) -> String {
+    let start = skip_whitespace(chars, start);
+    let len = chars[start..]
+        .iter()
+        .take_while(|&&ch| is_identifier(ch))
+        .count();
+    collect(&chars[start..chars.len().min(start + len.max(1))])
+}
+
+fn skip_whitespace(chars: &[char], mut i: usize) -> usize {
+    while i < chars.len() && chars[i].is_whitespace() {
+        i += 1;
+    }
+    i
+}
+
+fn collect(chars: &[char]) -> String {
+    chars.iter().collect()
+}
+
+/// Find the end of a string that starts before `i`, closed by `quote`.
+fn skip_string(chars: &[char], mut i: usize, quote: char) -> usize {
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            ch if ch == quote => return i + 1,
+            '\n' => return i,
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+/// Find the end of a template literal, which may have `${...}` inside it.
+fn skip_template(chars: &[char], mut i: usize) -> usize {
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            '`' => return i + 1,
+            '$' if chars.get(i + 1) == Some(&'{') => i = skip_braces(chars, i + 2),
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+/// Find the brace that closes the one before `i`.
+fn skip_braces(chars: &[char], mut i: usize) -> usize {
+    let mut depth = 1;
+    while i < chars.len() {
+        match chars[i] {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            quote @ '"' | quote @ '\'' => {
+                i = skip_string(chars, i + 1, quote);
+                continue;
+            }
+            '`' => {
+                i = skip_template(chars, i + 1);
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+fn skip_line_comment(chars: &[char], i: usize) -> usize {
+    chars[i..]
+        .iter()
+        .position(|&ch| ch == '\n')
+        .map_or(chars.len(), |len| i + len)
+}
+
+fn skip_block_comment(chars: &[char], mut i: usize) -> usize {
+    while i + 1 < chars.len() {
+        if chars[i] == '*' && chars[i + 1] == '/' {
+            return i + 2;
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+/// Find the end of a regular expression, including its flags.
+fn skip_regex(chars: &[char], mut i: usize) -> usize {
+    let mut in_class = false;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            '[' => {
+                in_class = true;
+                i += 1;
+            }
+            ']' => {
+                in_class = false;
+                i += 1;
+            }
+            '/' if !in_class => {
+                i += 1;
+                while i < chars.len() && chars[i].is_alphanumeric() {
+                    i += 1;
+                }
+                return i;
+            }
+            '\n' => return i,
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minified_css() {
+        assert_eq!(
+            css(
+                "/* v1 */a:hover,b{color:red;background:url(\"a;b{c}.png\")}\
+                 @media (min-width:1px){.x{margin:0 auto}}"
+            ),
+            "/* v1 */\n\
+             a:hover,b {\n    \
+                 color: red;\n    \
+                 background: url(\"a;b{c}.png\")\n\
+             }\n\
+             @media (min-width:1px) {\n    \
+                 .x {\n        \
+                     margin: 0 auto\n    \
+                 }\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn minified_javascript() {
+        assert_eq!(
+            javascript(
+                "!function(){var a=1,b=\"}{;\";if(a){f(a,b)}else{g({})}\
+                 for(var i=0;i<2;i++)h(/[/;]/g,`${{x:1}.x};`,a/2)}();"
+            ),
+            "!function() {\n    \
+                 var a=1,\n    \
+                 b=\"}{;\";\n    \
+                 if(a) {\n        \
+                     f(a,b)\n    \
+                 } else {\n        \
+                     g({})\n    \
+                 }\n    \
+                 for(var i=0;i<2;i++)h(/[/;]/g,`${{x:1}.x};`,a/2)\n\
+             }();\n"
+        );
+        // Existing line breaks may end statements, so they stay
+        assert_eq!(javascript("a=1\n  b=2"), "a=1\nb=2\n");
+        assert_eq!(
+            javascript("function f(){return/x/.test(y)}"),
+            "function f() {\n    return/x/.test(y)\n}\n"
+        );
+    }
+
+    #[test]
+    fn detect_minified() {
+        assert!(looks_minified(&format!(
+            "/* v1 */\n{}",
+            "a{b:c}".repeat(100)
+        )));
+        assert!(!looks_minified("a {\n    b: c;\n}\n"));
+    }
+}
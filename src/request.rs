@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, stdin, BufRead, Read};
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::header::{
+    HeaderValue, ACCEPT, ACCEPT_ENCODING, AUTHORIZATION, CONNECTION, CONTENT_TYPE, USER_AGENT,
+};
+use reqwest::redirect::Policy;
+use reqwest::{Method, Url};
+
+use crate::auth::{auth_from_netrc, read_netrc};
+use crate::cli::{AuthType, Cli, Proxy, RequestType, Sampled, Timeout, TraceState, Verify};
+use crate::config::{find_credential, Credential};
+use crate::generators::traceparent;
+use crate::proxy::Route;
+use crate::request_items::{
+    encode_form, Body, RequestItem, FORM_CONTENT_TYPE, JSON_ACCEPT, JSON_CONTENT_TYPE,
+};
+use crate::timing::Lookup;
+use crate::utils::test_mode;
+use crate::{identity, negotiate, pkcs11};
+
+pub fn get_user_agent() -> &'static str {
+    if test_mode() {
+        // Hard-coded user agent for the benefit of tests
+        "xh/0.0.0 (test mode)"
+    } else {
+        concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"))
+    }
+}
+
+/// Whether an item is `@-`, which sends stdin as the body.
+pub fn is_stdin_body(item: &RequestItem) -> bool {
+    matches!(item, RequestItem::FormFile { key, file_name, .. } if key.is_empty() && file_name == "-")
+}
+
+/// Reads all of stdin on another thread, so other work can go on meanwhile.
+pub struct StdinReader {
+    started: mpsc::Receiver<()>,
+    thread: thread::JoinHandle<io::Result<Vec<u8>>>,
+}
+
+impl StdinReader {
+    pub fn spawn() -> StdinReader {
+        let (sender, started) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let stdin = stdin();
+            let mut stdin = stdin.lock();
+            // Wait for the first data, or the end of the input
+            stdin.fill_buf()?;
+            let _ = sender.send(());
+            let mut buffer = Vec::new();
+            stdin.read_to_end(&mut buffer)?;
+            Ok(buffer)
+        });
+        StdinReader { started, thread }
+    }
+
+    /// Collect the input. Returns `None` if none of it arrived within
+    /// `timeout`; the input may never come, so it's then left unread.
+    pub fn finish(self, timeout: Option<Duration>) -> Result<Option<Vec<u8>>> {
+        if let Some(timeout) = timeout {
+            if let Err(RecvTimeoutError::Timeout) = self.started.recv_timeout(timeout) {
+                return Ok(None);
+            }
+        }
+        let buffer = self
+            .thread
+            .join()
+            .map_err(|_| anyhow!("Failed to read stdin"))??;
+        Ok(Some(buffer))
+    }
+}
+
+/// Set up a client for the options in `args`: timeouts, redirects, proxies,
+/// certificates and so on. `https` says whether TLS options apply, and
+/// `lookup` has the addresses to use for the host, if it was looked up
+/// already.
+pub fn build_client(
+    args: &Cli,
+    https: bool,
+    proxy_auth: Option<&(String, Option<String>)>,
+    lookup: Option<&Lookup>,
+) -> Result<Client> {
+    let timeout = args.timeout.as_ref().and_then(|t| t.as_duration());
+    let redirect = match args.follow {
+        true => Policy::limited(args.max_redirects.unwrap_or(10)),
+        false => Policy::none(),
+    };
+
+    // We deliberately stick to reqwest's blocking client. It already drives
+    // a tokio runtime on a background thread, and a Client is cheap to clone
+    // and can be shared between threads, so concurrent features can spawn
+    // threads that reuse one client (and one connection pool). The async
+    // client would make streaming request bodies (files, multipart) opaque,
+    // and then -v could no longer print them.
+    let mut client = Client::builder()
+        .http2_initial_stream_window_size(4_194_304)
+        .http2_initial_connection_window_size(4_194_304)
+        .timeout(timeout)
+        .redirect(redirect)
+        .tls_info(args.verbose);
+    if args.download {
+        // Downloads are saved as sent, even if the server encoded them anyway.
+        // --decompress decodes them itself so it can show progress.
+        client = client.gzip(false).brotli(false);
+    }
+
+    if https {
+        let ca_bundle = match &args.verify {
+            Some(Verify::CustomCaBundle(path)) => {
+                let mut buffer = Vec::new();
+                let mut file = File::open(path).with_context(|| {
+                    format!("Failed to open the custom CA bundle: {}", path.display())
+                })?;
+                file.read_to_end(&mut buffer).with_context(|| {
+                    format!("Failed to read the custom CA bundle: {}", path.display())
+                })?;
+                Some((path, pem::parse_many(buffer)))
+            }
+            _ => None,
+        };
+        let pkcs11_uri = args
+            .cert
+            .as_ref()
+            .and_then(|cert| cert.to_str())
+            .filter(|cert| cert.starts_with("pkcs11:"));
+
+        if let Some(uri) = pkcs11_uri {
+            if args.cert_key.is_some() {
+                return Err(anyhow!("--cert-key can't be used with a PKCS#11 URI"));
+            }
+            let roots = ca_bundle
+                .map(|(_, pems)| pems.into_iter().map(|pem| pem.contents).collect::<Vec<_>>());
+            let config = pkcs11::tls_config(
+                uri,
+                args.cert_key_pass.clone(),
+                roots.as_deref(),
+                args.verify != Some(Verify::No),
+            )?;
+            client = client.use_preconfigured_tls(config);
+        } else {
+            client = match ca_bundle {
+                Some((path, pems)) => {
+                    client = client.tls_built_in_root_certs(false);
+                    for pem in pems {
+                        let certificate =
+                            reqwest::Certificate::from_pem(pem::encode(&pem).as_bytes())
+                                .with_context(|| {
+                                    format!(
+                                        "Failed to load the custom CA bundle: {}",
+                                        path.display()
+                                    )
+                                })?;
+                        client = client.add_root_certificate(certificate);
+                    }
+                    client
+                }
+                None if args.verify == Some(Verify::No) => client.danger_accept_invalid_certs(true),
+                None => client,
+            };
+
+            if let Some(cert) = &args.cert {
+                let identity =
+                    identity::load(cert, args.cert_key.as_deref(), args.cert_key_pass.clone())?;
+                client = client.identity(identity);
+            };
+        }
+    }
+
+    if let Some(lookup) = lookup {
+        client = client.resolve_to_addrs(&lookup.host, &lookup.addrs);
+    }
+
+    for proxy in args.proxy.iter().rev() {
+        let mut proxy = match proxy {
+            Proxy::Http(url) => reqwest::Proxy::http(url.clone()),
+            Proxy::Https(url) => reqwest::Proxy::https(url.clone()),
+            Proxy::All(url) => reqwest::Proxy::all(url.clone()),
+        }?;
+        if let Some((username, password)) = proxy_auth {
+            proxy = proxy.basic_auth(username, password.as_deref().unwrap_or(""));
+        }
+        client = client.proxy(proxy);
+    }
+    if args.proxy.is_empty() && !Route::uses_system_settings(args.system_proxy) {
+        // reqwest's own handling of the environment doesn't know all of
+        // no_proxy, and -v couldn't tell which proxy it picked. Setting a
+        // proxy also keeps reqwest from reading the system settings.
+        client = client.proxy(reqwest::Proxy::custom(move |url| {
+            Route::resolve(&[], false, url).proxy().cloned()
+        }));
+    }
+
+    Ok(client.build()?)
+}
+
+/// Use --json-body as the body, as long as there's no other data.
+pub fn replace_with_json_body(body: Body, json_body: &str) -> Result<Body> {
+    if !body.is_empty() {
+        return Err(anyhow!(
+            "--json-body and request data (key=value) cannot be mixed"
+        ));
+    }
+    Ok(Body::Raw(json_body.as_bytes().to_vec()))
+}
+
+/// Start a request with our default headers and the given body.
+pub fn build_request(
+    client: &Client,
+    args: &Cli,
+    method: Method,
+    url: Url,
+    body: Body,
+) -> Result<RequestBuilder> {
+    // Only advertise encodings reqwest decodes for us. zstd is deliberately
+    // missing: reqwest 0.11 has no decoder for it, so a server that honored
+    // it would hand us a compressed body to print or save.
+    let mut request_builder = client
+        .request(method, url)
+        .header(ACCEPT_ENCODING, HeaderValue::from_static("gzip, br"))
+        .header(CONNECTION, HeaderValue::from_static("keep-alive"))
+        .header(USER_AGENT, get_user_agent());
+
+    if let Some(sampled) = args.traceparent {
+        let (header, trace_id) = traceparent(
+            rand::random(),
+            sampled.map_or(true, |Sampled(sampled)| sampled),
+        );
+        request_builder = request_builder.header("traceparent", header);
+        if let Some(TraceState(state)) = &args.tracestate {
+            request_builder = request_builder.header("tracestate", state.as_str());
+        }
+        if args.quiet < 2 {
+            eprintln!("{}: trace id {}", env!("CARGO_PKG_NAME"), trace_id);
+        }
+    }
+
+    Ok(match body {
+        Body::Form(body) => request_builder
+            .header(CONTENT_TYPE, HeaderValue::from_static(FORM_CONTENT_TYPE))
+            .body(encode_form(&body, args.encode_set)),
+        Body::Multipart(body) => request_builder.multipart(body),
+        Body::EncodedMultipart { boundary, data } => request_builder
+            .header(
+                CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", boundary),
+            )
+            .body(data),
+        Body::Json(body) => {
+            // An empty JSON body would produce "{}" instead of "", so
+            // this is the one kind of body that needs an is_empty() check
+            if !body.is_empty() {
+                request_builder
+                    .header(ACCEPT, HeaderValue::from_static(JSON_ACCEPT))
+                    .json(&body)
+            } else if args.json {
+                request_builder
+                    .header(ACCEPT, HeaderValue::from_static(JSON_ACCEPT))
+                    .header(CONTENT_TYPE, HeaderValue::from_static(JSON_CONTENT_TYPE))
+            } else {
+                // We're here because this is the default request type
+                // There's nothing to do
+                request_builder
+            }
+        }
+        Body::Raw(body) => match args.request_type {
+            RequestType::Json => request_builder
+                .header(ACCEPT, HeaderValue::from_static(JSON_ACCEPT))
+                .header(CONTENT_TYPE, HeaderValue::from_static(JSON_CONTENT_TYPE)),
+            RequestType::Form => {
+                request_builder.header(CONTENT_TYPE, HeaderValue::from_static(FORM_CONTENT_TYPE))
+            }
+            RequestType::Multipart => unreachable!(),
+        }
+        .body(body),
+        // The file is streamed from disk with a known length, so it's never
+        // held in memory unless -v has to print it. Memory-mapping it wouldn't
+        // gain anything: reqwest's blocking client copies every body through
+        // its own fixed-size chunks regardless of the reader we hand it.
+        Body::File {
+            file_name,
+            file_type,
+        } if file_name == Path::new("-") => {
+            let timeout = args.stdin_timeout.as_ref().and_then(Timeout::as_duration);
+            let buffer = StdinReader::spawn().finish(timeout)?.ok_or_else(|| {
+                anyhow!(
+                    "Nothing on stdin after {}, which @- was to send",
+                    humantime::format_duration(timeout.unwrap_or_default())
+                )
+            })?;
+            request_builder.body(buffer).header(
+                CONTENT_TYPE,
+                file_type.unwrap_or_else(|| HeaderValue::from_static(JSON_CONTENT_TYPE)),
+            )
+        }
+        Body::File {
+            file_name,
+            file_type,
+        } => request_builder.body(File::open(file_name)?).header(
+            CONTENT_TYPE,
+            file_type.unwrap_or_else(|| HeaderValue::from_static(JSON_CONTENT_TYPE)),
+        ),
+    })
+}
+
+/// Add credentials, falling back to .netrc if none were passed.
+///
+/// `auth` is taken pre-parsed so that callers decide when to prompt for a password.
+/// Unless `preemptive` is set it's left for the caller to answer a challenge with.
+pub fn authenticate(
+    mut request_builder: RequestBuilder,
+    args: &Cli,
+    auth: Option<(String, Option<String>)>,
+    preemptive: bool,
+    credentials: &HashMap<String, Credential>,
+    url: &Url,
+) -> Result<RequestBuilder> {
+    let negotiate = |request_builder: RequestBuilder| -> Result<RequestBuilder> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow!("Negotiate authentication needs a host name"))?;
+        Ok(request_builder.header(AUTHORIZATION, negotiate::authorization(host)?))
+    };
+    // Credentials from the config file only stand in for ones given as options
+    let credential = match (&auth, &args.bearer, args.auth_type) {
+        (None, None, None) | (None, None, Some(AuthType::basic)) => {
+            find_credential(credentials, url)
+        }
+        _ => None,
+    };
+
+    if args.auth_type == Some(AuthType::negotiate) {
+        request_builder = negotiate(request_builder)?;
+    } else if let Some((username, password)) = auth {
+        // If not, they're sent once a 401 response says how
+        if preemptive {
+            request_builder = request_builder.basic_auth(username, password);
+        }
+    } else if let Some(credential) = credential {
+        let context = || {
+            format!(
+                "Failed to get the credentials for {} from the config file",
+                url
+            )
+        };
+        request_builder = match credential {
+            Credential::Basic { username, password } => {
+                let password = match password {
+                    Some(password) => Some(password.resolve().with_context(context)?),
+                    None => None,
+                };
+                request_builder.basic_auth(username, password)
+            }
+            Credential::Bearer { token } => {
+                request_builder.bearer_auth(token.resolve().with_context(context)?)
+            }
+            Credential::Negotiate => negotiate(request_builder)?,
+        };
+    } else if !args.ignore_netrc {
+        if let Some(host) = url.host_str() {
+            if let Some(netrc) = read_netrc() {
+                if let Some((username, password)) = auth_from_netrc(host, &netrc) {
+                    request_builder = request_builder.basic_auth(username, password);
+                }
+            }
+        }
+    }
+    // The token is sent as given. Refreshing an expired one would need a
+    // refresh token and token endpoint kept from an earlier run, and there
+    // are no sessions to keep them in.
+    if let Some(token) = &args.bearer {
+        request_builder = request_builder.bearer_auth(token);
+    }
+    Ok(request_builder)
+}
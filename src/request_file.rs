@@ -0,0 +1,240 @@
+// --request-file: a whole request described in a YAML or JSON file, which can
+// be reviewed and kept with the code instead of living in shell history
+//
+//     method: POST
+//     url: https://example.org/items
+//     headers:
+//       X-Api-Key: "1234"
+//     query:
+//       page: 2
+//     body:
+//       name: xh
+//       tags: [cli, http]
+//     auth: alice:hunter2
+//     expect:
+//       status: 201
+//
+// The command line has the last word. Its method, URL and auth replace the
+// file's, and its request items replace the headers, query parameters and body
+// fields of the same name.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use yaml_rust::YamlLoader;
+
+use crate::cli::{AuthType, Cli};
+use crate::request_items::{yaml_to_json, RequestItem};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequestFile {
+    method: Option<String>,
+    url: Option<String>,
+    #[serde(default)]
+    headers: Map<String, Value>,
+    /// A list as the value sends the parameter once for each item
+    #[serde(default)]
+    query: Map<String, Value>,
+    /// Sent as JSON, or as form fields with --form if it's an object
+    body: Option<Value>,
+    auth: Option<String>,
+    auth_type: Option<String>,
+    expect: Option<Expect>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Expect {
+    /// The status the response should have. Anything else is a failure.
+    status: Option<u16>,
+}
+
+impl RequestFile {
+    /// Read a request file, which is JSON if its name ends in `.json` and
+    /// YAML otherwise.
+    pub fn load(path: &Path) -> Result<RequestFile> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read the request file {}", path.display()))?;
+        let is_json = path.extension().map_or(false, |ext| ext == "json");
+        let value = if is_json {
+            serde_json::from_str(&text).map_err(Into::into)
+        } else {
+            parse_yaml(&text)
+        };
+        value
+            .and_then(|value| Ok(serde_json::from_value(value)?))
+            .with_context(|| format!("Invalid request file {}", path.display()))
+    }
+
+    /// Fill in what the command line doesn't say.
+    pub fn apply(self, args: &mut Cli) -> Result<()> {
+        if let (None, Some(method)) = (&args.method, self.method) {
+            args.method = Some(
+                Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                    .with_context(|| format!("Invalid method: {}", method))?,
+            );
+        }
+        if args.url.is_empty() {
+            match self.url {
+                Some(url) => args.url = url,
+                None if args.urls_from.is_some() => {}
+                None => return Err(anyhow!("The request file has no url, and none was given")),
+            }
+        }
+        if args.auth.is_none() {
+            args.auth = self.auth;
+        }
+        if let (None, Some(auth_type)) = (args.auth_type, self.auth_type) {
+            args.auth_type = Some(AuthType::from_str(&auth_type).map_err(|err| anyhow!(err))?);
+        }
+        if let Some(Expect { status }) = self.expect {
+            args.expect_status = status;
+        }
+
+        // Names are compared like the command line would: headers without
+        // regard to case, the rest exactly
+        let mut headers = HashSet::new();
+        let mut params = HashSet::new();
+        let mut fields = HashSet::new();
+        for item in &args.request_items {
+            match item {
+                RequestItem::HttpHeader(name, _) | RequestItem::HttpHeaderToUnset(name) => {
+                    headers.insert(name.to_ascii_lowercase());
+                }
+                RequestItem::UrlParam(name, _)
+                | RequestItem::UrlParamToUnset(name)
+                | RequestItem::UrlParamFromFile(name, _) => {
+                    params.insert(name.clone());
+                }
+                RequestItem::DataField(name, _)
+                | RequestItem::DataFieldFromFile(name, _)
+                | RequestItem::JsonField(name, _)
+                | RequestItem::JsonFieldFromFile(name, _)
+                | RequestItem::FormFile { key: name, .. } => {
+                    fields.insert(name.clone());
+                }
+                RequestItem::Cookie(..) => {}
+            }
+        }
+
+        let mut items = Vec::new();
+        for (name, value) in self.headers {
+            if !headers.contains(&name.to_ascii_lowercase()) {
+                let value = scalar(value).with_context(|| format!("In header {:?}", name))?;
+                items.push(RequestItem::HttpHeader(name, value));
+            }
+        }
+        for (name, value) in self.query {
+            if params.contains(&name) {
+                continue;
+            }
+            let values = match value {
+                Value::Array(values) => values,
+                value => vec![value],
+            };
+            for value in values {
+                let value = scalar(value).with_context(|| format!("In query {:?}", name))?;
+                items.push(RequestItem::UrlParam(name.clone(), value));
+            }
+        }
+        match self.body {
+            Some(Value::Object(body)) => {
+                for (name, value) in body {
+                    if fields.contains(&name) {
+                        continue;
+                    }
+                    // Strings work for --form too
+                    items.push(match value {
+                        Value::String(value) => RequestItem::DataField(name, value),
+                        value => RequestItem::JsonField(name, value),
+                    });
+                }
+            }
+            Some(body) if args.json_body.is_none() => args.json_body = Some(body.to_string()),
+            _ => {}
+        }
+        items.append(&mut args.request_items);
+        args.request_items = items;
+        Ok(())
+    }
+}
+
+fn parse_yaml(text: &str) -> Result<Value> {
+    let mut docs = YamlLoader::load_from_str(text)?;
+    if docs.len() != 1 {
+        return Err(anyhow!("Expected a single YAML document"));
+    }
+    yaml_to_json(docs.pop().unwrap())
+        .ok_or_else(|| anyhow!("YAML aliases and complex keys aren't supported"))
+}
+
+/// The text of a header or query value.
+fn scalar(value: Value) -> Result<String> {
+    match value {
+        Value::String(text) => Ok(text),
+        Value::Number(number) => Ok(number.to_string()),
+        Value::Bool(value) => Ok(value.to_string()),
+        _ => Err(anyhow!("Expected a string, found {}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merged_with_the_command_line() {
+        let file: RequestFile = serde_json::from_value(
+            parse_yaml(
+                r#"
+method: put
+url: https://example.org/items/1
+headers:
+  X-Api-Key: "1234"
+  Accept: text/plain
+query:
+  tag: [a, b]
+body:
+  name: xh
+  stars: 5
+auth_type: bearer
+expect:
+  status: 204
+"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut args = Cli::from_iter_safe(&[
+            "xh",
+            "--request-file=request.yaml",
+            "accept:application/json",
+            "stars:=6",
+        ])
+        .unwrap();
+        file.apply(&mut args).unwrap();
+        assert!(Cli::from_iter_safe(&["xh", "--request-file=request.yaml"]).is_ok());
+        assert_eq!(args.method, Some(Method::PUT));
+        assert_eq!(args.url, "https://example.org/items/1");
+        assert_eq!(args.auth_type, Some(AuthType::bearer));
+        assert_eq!(args.expect_status, Some(204));
+        assert_eq!(
+            args.request_items,
+            vec![
+                RequestItem::HttpHeader("X-Api-Key".to_string(), "1234".to_string()),
+                RequestItem::UrlParam("tag".to_string(), "a".to_string()),
+                RequestItem::UrlParam("tag".to_string(), "b".to_string()),
+                RequestItem::DataField("name".to_string(), "xh".to_string()),
+                RequestItem::HttpHeader("accept".to_string(), "application/json".to_string()),
+                RequestItem::JsonField("stars".to_string(), serde_json::json!(6)),
+            ]
+        );
+    }
+}
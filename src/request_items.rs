@@ -3,14 +3,18 @@ use std::{
     io,
     path::{Path, PathBuf},
     str::FromStr,
+    time::SystemTime,
 };
 
-use anyhow::{anyhow, Result};
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use anyhow::{anyhow, Context, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, COOKIE};
 use reqwest::{blocking::multipart, Method};
 use structopt::clap;
+use yaml_rust::{Yaml, YamlLoader};
 
-use crate::cli::RequestType;
+use crate::cli::{Cli, EncodeSet, PartHeader, RequestType};
+use crate::generators::{generate, value_to_string};
+use crate::url::encode_component;
 
 pub const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
 pub const JSON_CONTENT_TYPE: &str = "application/json";
@@ -20,7 +24,11 @@ pub const JSON_ACCEPT: &str = "application/json, */*;q=0.5";
 pub enum RequestItem {
     HttpHeader(String, String),
     HttpHeaderToUnset(String),
+    Cookie(String, String),
     UrlParam(String, String),
+    UrlParamToUnset(String),
+    /// A file of parameters if the key is empty, otherwise a single value.
+    UrlParamFromFile(String, String),
     DataField(String, String),
     DataFieldFromFile(String, String),
     JsonField(String, serde_json::Value),
@@ -35,8 +43,8 @@ pub enum RequestItem {
 impl FromStr for RequestItem {
     type Err = clap::Error;
     fn from_str(request_item: &str) -> clap::Result<RequestItem> {
-        const SPECIAL_CHARS: &str = "=@:;\\";
-        const SEPS: &[&str] = &["=@", ":=@", "==", ":=", "=", "@", ":"];
+        const SPECIAL_CHARS: &str = "=@:;^\\";
+        const SEPS: &[&str] = &["==@", "=@", ":=@", "==", ":=", "^=", "=", "@", ":"];
 
         fn unescape(text: &str) -> String {
             let mut out = String::new();
@@ -88,37 +96,30 @@ impl FromStr for RequestItem {
 
         if let Some((key, sep, value)) = split(request_item) {
             match sep {
+                // Like an empty header value, but `key==` is too useful to give up
+                "==" if value == "-" => Ok(RequestItem::UrlParamToUnset(key)),
+                "==" if value == r"\-" => Ok(RequestItem::UrlParam(key, value[1..].to_owned())),
                 "==" => Ok(RequestItem::UrlParam(key, value)),
+                "==@" => Ok(RequestItem::UrlParamFromFile(key, value)),
+                "^=" => Ok(RequestItem::Cookie(key, value)),
                 "=" => Ok(RequestItem::DataField(key, value)),
-                ":=" => Ok(RequestItem::JsonField(
-                    key,
-                    serde_json::from_str(&value).map_err(|err| {
-                        clap::Error::with_description(
-                            &format!("{:?}: {}", request_item, err),
+                ":=" => match serde_json::from_str(&value) {
+                    Ok(value) => Ok(RequestItem::JsonField(key, value)),
+                    Err(err) => match parse_yaml(&value) {
+                        Some(value) => Ok(RequestItem::JsonField(key, value)),
+                        None => Err(clap::Error::with_description(
+                            &format!("{:?}: {}{}", request_item, err, json_hint(&value)),
                             clap::ErrorKind::InvalidValue,
-                        )
-                    })?,
-                )),
+                        )),
+                    },
+                },
                 "@" => {
-                    // Technically there are concerns about escaping but people
-                    // probably don't put ;type= in their filenames often
-                    let with_type: Vec<&str> = value.rsplitn(2, ";type=").collect();
-                    // rsplitn iterates from the right, so it's either
-                    if let Some(&typed_filename) = with_type.get(1) {
-                        // [mimetype, filename]
-                        Ok(RequestItem::FormFile {
-                            key,
-                            file_name: typed_filename.to_owned(),
-                            file_type: Some(with_type[0].to_owned()),
-                        })
-                    } else {
-                        // [filename]
-                        Ok(RequestItem::FormFile {
-                            key,
-                            file_name: value,
-                            file_type: None,
-                        })
-                    }
+                    let (file_name, file_type) = split_type(&value);
+                    Ok(RequestItem::FormFile {
+                        key,
+                        file_name: file_name.to_owned(),
+                        file_type: file_type.map(str::to_owned),
+                    })
                 }
                 ":" if value.is_empty() => Ok(RequestItem::HttpHeaderToUnset(key)),
                 ":" => Ok(RequestItem::HttpHeader(key, value)),
@@ -130,9 +131,8 @@ impl FromStr for RequestItem {
             // Technically this is too permissive because the ; might be escaped
             Ok(RequestItem::HttpHeader(header.to_owned(), "".to_owned()))
         } else {
-            // TODO: We can also end up here if the method couldn't be parsed
-            // and was interpreted as a URL, making the actual URL a request
-            // item
+            // If the method and URL were mixed up, cli::parse_request_item
+            // explains that
             Err(clap::Error::with_description(
                 &format!("{:?} is not a valid request item", request_item),
                 clap::ErrorKind::InvalidValue,
@@ -141,12 +141,325 @@ impl FromStr for RequestItem {
     }
 }
 
+/// Parse a `:=` value that isn't JSON as YAML, which is easier to type in a
+/// shell, e.g. `{a: 1, b: two}`.
+///
+/// Unquoted strings are rejected: `key:=value` is more likely a mistake than
+/// a roundabout way of writing `key=value`.
+fn parse_yaml(value: &str) -> Option<serde_json::Value> {
+    let mut docs = YamlLoader::load_from_str(value).ok()?;
+    if docs.len() != 1 {
+        return None;
+    }
+    match docs.pop()? {
+        Yaml::String(..) if !value.trim_start().starts_with(['\'', '"']) => None,
+        yaml => yaml_to_json(yaml),
+    }
+}
+
+pub(crate) fn yaml_to_json(yaml: Yaml) -> Option<serde_json::Value> {
+    use serde_json::Value;
+    Some(match yaml {
+        Yaml::Null => Value::Null,
+        Yaml::Boolean(value) => Value::Bool(value),
+        Yaml::Integer(value) => Value::from(value),
+        Yaml::Real(value) => Value::Number(serde_json::Number::from_f64(value.parse().ok()?)?),
+        Yaml::String(value) => Value::String(value),
+        Yaml::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .map(yaml_to_json)
+                .collect::<Option<_>>()?,
+        ),
+        Yaml::Hash(hash) => {
+            let mut map = serde_json::Map::new();
+            for (key, value) in hash {
+                let key = match key {
+                    Yaml::String(key) | Yaml::Real(key) => key,
+                    Yaml::Integer(key) => key.to_string(),
+                    Yaml::Boolean(key) => key.to_string(),
+                    _ => return None,
+                };
+                map.insert(key, yaml_to_json(value)?);
+            }
+            Value::Object(map)
+        }
+        Yaml::Alias(..) | Yaml::BadValue => return None,
+    })
+}
+
+/// Suggest a fix for a `:=` value that isn't valid JSON.
+fn json_hint(value: &str) -> &'static str {
+    let value = value.trim();
+    if value.is_empty() {
+        // Some shells swallow unquoted braces
+        " (for an empty object or array use key:='{}' or key:='[]')"
+    } else if !value.starts_with(|c: char| "{[\"-0123456789tfn".contains(c)) {
+        " (use key=value to send a string)"
+    } else {
+        ""
+    }
+}
+
+/// Split a `;type=` suffix off a value, e.g. `photo.jpg;type=image/jpeg`.
+///
+/// Technically there are concerns about escaping but people probably don't
+/// put ;type= in their filenames or values often.
+fn split_type(value: &str) -> (&str, Option<&str>) {
+    let with_type: Vec<&str> = value.rsplitn(2, ";type=").collect();
+    // rsplitn iterates from the right, so it's either
+    match with_type[..] {
+        // [mimetype, value]
+        [mime_type, value] => (value, Some(mime_type)),
+        // [value]
+        _ => (value, None),
+    }
+}
+
+/// Parse a file of query parameters, either a flat JSON object or
+/// `KEY=VALUE` lines like a `.env` file.
+fn parse_params(text: &str) -> Result<Vec<(String, String)>> {
+    let mut params = Vec::new();
+    if text.trim_start().starts_with('{') {
+        let map: serde_json::Map<String, serde_json::Value> = serde_json::from_str(text)?;
+        for (key, value) in map {
+            // Arrays turn into repeated parameters, e.g. tag=a&tag=b
+            let values = match value {
+                serde_json::Value::Array(values) => values,
+                value => vec![value],
+            };
+            for value in values {
+                let value = match value {
+                    serde_json::Value::String(value) => value,
+                    serde_json::Value::Number(..) | serde_json::Value::Bool(..) => {
+                        value.to_string()
+                    }
+                    _ => return Err(anyhow!("{:?} is not a string, number or boolean", key)),
+                };
+                params.push((key.clone(), value));
+            }
+        }
+    } else {
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let eq = line
+                .find('=')
+                .ok_or_else(|| anyhow!("Expected KEY=VALUE, found {:?}", line))?;
+            let key = line[..eq].trim();
+            let value = line[eq + 1..].trim();
+            let value = match value.as_bytes() {
+                [b'"', .., b'"'] | [b'\'', .., b'\''] => &value[1..value.len() - 1],
+                _ => value,
+            };
+            params.push((key.to_owned(), value.to_owned()));
+        }
+    }
+    Ok(params)
+}
+
+/// Read data fields from a CSV or TSV file with a header row and a row of values.
+fn read_fields(path: &Path) -> Result<Vec<RequestItem>> {
+    let delimiter = match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("tsv") => '\t',
+        _ => ',',
+    };
+    let text = fs::read_to_string(path)?;
+    let rows = parse_delimited(text.trim_start_matches('\u{FEFF}'), delimiter)?;
+    match &rows[..] {
+        [names, values] => {
+            if names.len() != values.len() {
+                return Err(anyhow!(
+                    "There are {} field names but {} values",
+                    names.len(),
+                    values.len()
+                ));
+            }
+            Ok(names
+                .iter()
+                .zip(values)
+                .map(|(name, value)| RequestItem::DataField(name.clone(), value.clone()))
+                .collect())
+        }
+        [_] | [] => Err(anyhow!("Expected a row of field names and a row of values")),
+        _ => Err(anyhow!(
+            "Expected a single row of values, found {}",
+            rows.len() - 1
+        )),
+    }
+}
+
+/// Split CSV-like text into rows of fields, skipping blank lines.
+///
+/// Fields can be quoted with `"` to include the delimiter, newlines or (doubled)
+/// quotes.
+fn parse_delimited(text: &str, delimiter: char) -> Result<Vec<Vec<String>>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' if field.is_empty() => loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    Some('"') => break,
+                    Some(ch) => field.push(ch),
+                    None => return Err(anyhow!("Unterminated quoted field")),
+                }
+            },
+            ch if ch == delimiter => row.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                if row != [""] {
+                    rows.push(std::mem::take(&mut row));
+                }
+                row.clear();
+            }
+            ch => field.push(ch),
+        }
+    }
+    row.push(field);
+    if row != [""] {
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Merge `overlay` into `base`, recursing into objects that both of them have.
+fn merge_json(base: &mut serde_json::Value, overlay: serde_json::Value) {
+    match (base, overlay) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                merge_json(base.entry(key).or_insert(serde_json::Value::Null), value);
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Escape a name for a quoted Content-Disposition parameter.
+fn quote_param(name: &str) -> String {
+    name.replace('\\', "\\\\")
+        .replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn is_glob(path: &str) -> bool {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map_or(false, |name| name.contains(['*', '?']))
+}
+
+/// List the files matching a pattern, in order.
+///
+/// Only the last component of the path may contain wildcards. If a file
+/// with the literal name exists it's used instead.
+fn expand_glob(pattern: &str) -> Result<Vec<String>> {
+    let path = Path::new(pattern);
+    if path.exists() {
+        return Ok(vec![pattern.to_owned()]);
+    }
+    let name_pattern = path.file_name().and_then(|name| name.to_str()).unwrap();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut matches = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => continue,
+        };
+        // Like shells, don't let wildcards match hidden files
+        if name.starts_with('.') && !name_pattern.starts_with('.') {
+            continue;
+        }
+        if entry.file_type()?.is_file() && wildcard_match(name_pattern, name) {
+            matches.push(path.with_file_name(name).to_string_lossy().into_owned());
+        }
+    }
+    if matches.is_empty() {
+        return Err(anyhow!("No files match {:?}", pattern));
+    }
+    matches.sort();
+    Ok(matches)
+}
+
+/// Match `text` against a pattern where `*` matches any run of characters
+/// and `?` matches a single character.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where to resume if the current attempt fails: the position after the
+    // last `*`, and the text position that `*` has consumed up to
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            p += 1;
+            backtrack = Some((p, t));
+        } else if let Some((star_p, star_t)) = backtrack {
+            p = star_p;
+            t = star_t + 1;
+            backtrack = Some((star_p, t));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Build a multipart field that isn't a file upload. Unlike a form field
+/// it can have its own Content-Type.
+fn text_part(value: Vec<u8>, field_type: Option<&str>) -> Result<multipart::Part> {
+    let mut part = multipart::Part::bytes(value);
+    if let Some(field_type) = field_type {
+        part = part.mime_str(field_type)?;
+    }
+    Ok(part)
+}
+
+/// Encode form fields as `application/x-www-form-urlencoded`.
+pub fn encode_form(fields: &[(String, Vec<u8>)], encode_set: EncodeSet) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for (key, value) in fields {
+        if !encoded.is_empty() {
+            encoded.push(b'&');
+        }
+        encoded.extend(encode_component(key.as_bytes(), encode_set));
+        encoded.push(b'=');
+        encoded.extend(encode_component(value, encode_set));
+    }
+    encoded
+}
+
 pub struct RequestItems(pub Vec<RequestItem>);
 
 pub enum Body {
     Json(serde_json::Map<String, serde_json::Value>),
-    Form(Vec<(String, String)>),
+    /// Values are bytes because fields read from files may not be text.
+    Form(Vec<(String, Vec<u8>)>),
     Multipart(multipart::Form),
+    /// A multipart body we had to encode ourselves, for --part-header.
+    EncodedMultipart {
+        boundary: String,
+        data: Vec<u8>,
+    },
     Raw(Vec<u8>),
     File {
         file_name: PathBuf,
@@ -166,7 +479,7 @@ impl Body {
             // This is a slight divergence from HTTPie, which will simply
             // discard stdin if it receives --multipart without request items,
             // but that behavior is useless so there's no need to match it
-            Body::Multipart(..) => false,
+            Body::Multipart(..) | Body::EncodedMultipart { .. } => false,
             Body::File { .. } => false,
         }
     }
@@ -180,7 +493,7 @@ impl Body {
     }
 
     pub fn is_multipart(&self) -> bool {
-        matches!(self, Body::Multipart(..))
+        matches!(self, Body::Multipart(..) | Body::EncodedMultipart { .. })
     }
 }
 
@@ -189,6 +502,77 @@ impl RequestItems {
         RequestItems(request_items)
     }
 
+    /// Apply the options that add or rewrite request items, before the
+    /// request is built.
+    pub fn expand(&mut self, args: &Cli) -> Result<()> {
+        if let Some(path) = &args.fields_from {
+            let fields = read_fields(path)
+                .with_context(|| format!("Failed to read fields from {}", path.display()))?;
+            self.0.splice(0..0, fields);
+        }
+        self.expand_globs(args.glob_brackets)?;
+        self.expand_generators()?;
+        Ok(())
+    }
+
+    /// Replace file fields whose file name contains `*` or `?` with a field
+    /// for each matching file, e.g. `photos@shots/*.jpg`.
+    ///
+    /// If `brackets` is set the field names get a `[]` suffix.
+    fn expand_globs(&mut self, brackets: bool) -> Result<()> {
+        let mut expanded = Vec::with_capacity(self.0.len());
+        for item in self.0.drain(..) {
+            match item {
+                RequestItem::FormFile {
+                    key,
+                    file_name,
+                    file_type,
+                } if !key.is_empty() && is_glob(&file_name) => {
+                    let key = if brackets { format!("{}[]", key) } else { key };
+                    for file_name in expand_glob(&file_name)? {
+                        expanded.push(RequestItem::FormFile {
+                            key: key.clone(),
+                            file_name,
+                            file_type: file_type.clone(),
+                        });
+                    }
+                }
+                item => expanded.push(item),
+            }
+        }
+        self.0 = expanded;
+        Ok(())
+    }
+
+    /// Replace values like `@now` or `=@uuid` with what they generate.
+    ///
+    /// These look like file fields (or a header value starting with `@`),
+    /// so a file can still be used by writing e.g. `./now`. Random values are
+    /// generated anew each time this is called.
+    fn expand_generators(&mut self) -> Result<()> {
+        let now = SystemTime::now();
+        for item in &mut self.0 {
+            let expanded = match item {
+                RequestItem::DataFieldFromFile(key, spec) => generate(spec, now)?
+                    .map(|value| RequestItem::DataField(key.clone(), value_to_string(value))),
+                RequestItem::JsonFieldFromFile(key, spec) => {
+                    generate(spec, now)?.map(|value| RequestItem::JsonField(key.clone(), value))
+                }
+                RequestItem::UrlParamFromFile(key, spec) if !key.is_empty() => generate(spec, now)?
+                    .map(|value| RequestItem::UrlParam(key.clone(), value_to_string(value))),
+                RequestItem::HttpHeader(key, value) if value.starts_with('@') => {
+                    generate(&value[1..], now)?
+                        .map(|value| RequestItem::HttpHeader(key.clone(), value_to_string(value)))
+                }
+                _ => None,
+            };
+            if let Some(expanded) = expanded {
+                *item = expanded;
+            }
+        }
+        Ok(())
+    }
+
     pub fn has_form_files(&self) -> bool {
         self.0
             .iter()
@@ -198,6 +582,7 @@ impl RequestItems {
     pub fn headers(&self) -> Result<(HeaderMap<HeaderValue>, Vec<HeaderName>)> {
         let mut headers = HeaderMap::new();
         let mut headers_to_unset = vec![];
+        let mut cookies = vec![];
         for item in &self.0 {
             match item {
                 RequestItem::HttpHeader(key, value) => {
@@ -209,7 +594,10 @@ impl RequestItems {
                     let key = HeaderName::from_bytes(&key.as_bytes())?;
                     headers_to_unset.push(key);
                 }
+                RequestItem::Cookie(name, value) => cookies.push(format!("{}={}", name, value)),
                 RequestItem::UrlParam(..) => {}
+                RequestItem::UrlParamToUnset(..) => {}
+                RequestItem::UrlParamFromFile(..) => {}
                 RequestItem::DataField(..) => {}
                 RequestItem::DataFieldFromFile(..) => {}
                 RequestItem::JsonField(..) => {}
@@ -217,17 +605,45 @@ impl RequestItems {
                 RequestItem::FormFile { .. } => {}
             }
         }
+        if !cookies.is_empty() {
+            // Add to a Cookie header given with cookie:..., rather than replacing it
+            if let Some(existing) = headers.get(COOKIE) {
+                cookies.insert(0, existing.to_str()?.to_owned());
+            }
+            headers.insert(COOKIE, HeaderValue::from_str(&cookies.join("; "))?);
+        }
         Ok((headers, headers_to_unset))
     }
 
-    pub fn query(&self) -> Vec<(&str, &str)> {
+    pub fn query(&self) -> Result<Vec<(String, String)>> {
         let mut query = vec![];
         for item in &self.0 {
-            if let RequestItem::UrlParam(key, value) = item {
-                query.push((key.as_str(), value.as_str()));
+            match item {
+                RequestItem::UrlParam(key, value) => query.push((key.clone(), value.clone())),
+                RequestItem::UrlParamFromFile(key, file_name) if key.is_empty() => {
+                    let text = fs::read_to_string(file_name)?;
+                    query.extend(parse_params(&text).with_context(|| {
+                        format!("Failed to read query parameters from {:?}", file_name)
+                    })?);
+                }
+                RequestItem::UrlParamFromFile(key, file_name) => {
+                    query.push((key.clone(), fs::read_to_string(file_name)?));
+                }
+                _ => {}
+            }
+        }
+        Ok(query)
+    }
+
+    /// Parameters to remove from the URL, from items like `key==-`.
+    pub fn query_to_unset(&self) -> Vec<String> {
+        let mut query_to_unset = vec![];
+        for item in &self.0 {
+            if let RequestItem::UrlParamToUnset(key) = item {
+                query_to_unset.push(key.clone());
             }
         }
-        query
+        query_to_unset
     }
 
     fn body_as_json(self) -> Result<Body> {
@@ -244,32 +660,43 @@ impl RequestItems {
                     body.insert(key, serde_json::Value::String(value));
                 }
                 RequestItem::DataFieldFromFile(key, value) => {
-                    body.insert(key, serde_json::Value::String(fs::read_to_string(value)?));
+                    // JSON can't hold binary data, so fall back to base64
+                    let value = match String::from_utf8(fs::read(value)?) {
+                        Ok(text) => text,
+                        Err(err) => base64::encode(err.into_bytes()),
+                    };
+                    body.insert(key, serde_json::Value::String(value));
                 }
                 RequestItem::FormFile { .. } => unreachable!(),
                 RequestItem::HttpHeader(..) => {}
                 RequestItem::HttpHeaderToUnset(..) => {}
+                RequestItem::Cookie(..) => {}
                 RequestItem::UrlParam(..) => {}
+                RequestItem::UrlParamToUnset(..) => {}
+                RequestItem::UrlParamFromFile(..) => {}
             }
         }
         Ok(Body::Json(body))
     }
 
     fn body_as_form(self) -> Result<Body> {
-        let mut text_fields = Vec::<(String, String)>::new();
+        let mut text_fields = Vec::<(String, Vec<u8>)>::new();
         for item in self.0 {
             match item {
                 RequestItem::JsonField(..) | RequestItem::JsonFieldFromFile(..) => {
                     return Err(anyhow!("JSON values are not supported in Form fields"));
                 }
-                RequestItem::DataField(key, value) => text_fields.push((key, value)),
+                RequestItem::DataField(key, value) => text_fields.push((key, value.into_bytes())),
                 RequestItem::DataFieldFromFile(key, value) => {
-                    text_fields.push((key, fs::read_to_string(value)?));
+                    text_fields.push((key, fs::read(value)?));
                 }
                 RequestItem::FormFile { .. } => unreachable!(),
                 RequestItem::HttpHeader(..) => {}
                 RequestItem::HttpHeaderToUnset(..) => {}
+                RequestItem::Cookie(..) => {}
                 RequestItem::UrlParam(..) => {}
+                RequestItem::UrlParamToUnset(..) => {}
+                RequestItem::UrlParamFromFile(..) => {}
             }
         }
         Ok(Body::Form(text_fields))
@@ -283,10 +710,13 @@ impl RequestItems {
                     return Err(anyhow!("JSON values are not supported in multipart fields"));
                 }
                 RequestItem::DataField(key, value) => {
-                    form = form.text(key, value);
+                    let (value, field_type) = split_type(&value);
+                    form = form.part(key, text_part(value.as_bytes().to_vec(), field_type)?);
                 }
                 RequestItem::DataFieldFromFile(key, value) => {
-                    form = form.text(key, fs::read_to_string(value)?);
+                    let (file_name, field_type) = split_type(&value);
+                    let value = fs::read(file_name)?;
+                    form = form.part(key, text_part(value, field_type)?);
                 }
                 RequestItem::FormFile {
                     key,
@@ -301,14 +731,102 @@ impl RequestItems {
                 }
                 RequestItem::HttpHeader(..) => {}
                 RequestItem::HttpHeaderToUnset(..) => {}
+                RequestItem::Cookie(..) => {}
                 RequestItem::UrlParam(..) => {}
+                RequestItem::UrlParamToUnset(..) => {}
+                RequestItem::UrlParamFromFile(..) => {}
             }
         }
         Ok(Body::Multipart(form))
     }
 
+    /// Like `body_as_multipart`, but with extra headers on some parts.
+    ///
+    /// reqwest doesn't let us set those, so this does the encoding itself.
+    fn body_as_encoded_multipart(self, part_headers: &[PartHeader]) -> Result<Body> {
+        for header in part_headers {
+            if !self.0.iter().any(|item| match item {
+                RequestItem::DataField(key, _)
+                | RequestItem::DataFieldFromFile(key, _)
+                | RequestItem::FormFile { key, .. } => *key == header.field,
+                _ => false,
+            }) {
+                return Err(anyhow!(
+                    "--part-header: there is no multipart field named {:?}",
+                    header.field
+                ));
+            }
+        }
+
+        let boundary = format!("{:032x}", rand::random::<u128>());
+        let mut data = Vec::new();
+        for item in self.0 {
+            let (key, file_name, content_type, value) = match item {
+                RequestItem::JsonField(..) | RequestItem::JsonFieldFromFile(..) => {
+                    return Err(anyhow!("JSON values are not supported in multipart fields"));
+                }
+                RequestItem::DataField(key, value) => {
+                    let (value, field_type) = split_type(&value);
+                    let field_type = field_type.map(str::to_owned);
+                    (key, None, field_type, value.as_bytes().to_vec())
+                }
+                RequestItem::DataFieldFromFile(key, value) => {
+                    let (file_name, field_type) = split_type(&value);
+                    (
+                        key,
+                        None,
+                        field_type.map(str::to_owned),
+                        fs::read(file_name)?,
+                    )
+                }
+                RequestItem::FormFile {
+                    key,
+                    file_name,
+                    file_type,
+                } => {
+                    let path = Path::new(&file_name);
+                    let base_name = path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned());
+                    (key, base_name, file_type, fs::read(path)?)
+                }
+                RequestItem::HttpHeader(..)
+                | RequestItem::HttpHeaderToUnset(..)
+                | RequestItem::Cookie(..)
+                | RequestItem::UrlParam(..)
+                | RequestItem::UrlParamToUnset(..)
+                | RequestItem::UrlParamFromFile(..) => continue,
+            };
+            data.extend(format!("--{}\r\n", boundary).as_bytes());
+            data.extend(
+                format!(
+                    "Content-Disposition: form-data; name=\"{}\"",
+                    quote_param(&key)
+                )
+                .as_bytes(),
+            );
+            if let Some(file_name) = file_name {
+                data.extend(format!("; filename=\"{}\"", quote_param(&file_name)).as_bytes());
+            }
+            data.extend(b"\r\n");
+            if let Some(content_type) = content_type {
+                data.extend(format!("Content-Type: {}\r\n", content_type).as_bytes());
+            }
+            for header in part_headers.iter().filter(|header| header.field == key) {
+                data.extend(header.name.as_str().as_bytes());
+                data.extend(b": ");
+                data.extend(header.value.as_bytes());
+                data.extend(b"\r\n");
+            }
+            data.extend(b"\r\n");
+            data.extend(value);
+            data.extend(b"\r\n");
+        }
+        data.extend(format!("--{}--\r\n", boundary).as_bytes());
+        Ok(Body::EncodedMultipart { boundary, data })
+    }
+
     fn body_from_file(self) -> Result<Body> {
-        let mut body = None;
         if self
             .0
             .iter()
@@ -318,26 +836,30 @@ impl RequestItems {
                 "Can't use file fields in JSON mode (perhaps you meant --form?)"
             ));
         }
-        for item in self.0 {
-            match item {
+        let file_count = self
+            .0
+            .iter()
+            .filter(|item| matches!(item, RequestItem::FormFile { .. }))
+            .count();
+        let has_data = self.0.iter().any(|item| {
+            matches!(
+                item,
                 RequestItem::DataField(..)
-                | RequestItem::JsonField(..)
-                | RequestItem::DataFieldFromFile(..)
-                | RequestItem::JsonFieldFromFile(..) => {
-                    return Err(anyhow!(
-                        "Request body (from a file) and request data (key=value) cannot be mixed."
-                    ));
-                }
-                RequestItem::FormFile {
-                    key,
+                    | RequestItem::JsonField(..)
+                    | RequestItem::DataFieldFromFile(..)
+                    | RequestItem::JsonFieldFromFile(..)
+            )
+        });
+
+        if file_count == 1 && !has_data {
+            for item in self.0 {
+                if let RequestItem::FormFile {
                     file_name,
                     file_type,
-                } => {
-                    assert!(key.is_empty());
-                    if body.is_some() {
-                        return Err(anyhow!("Can't read request from multiple files"));
-                    }
-                    body = Some(Body::File {
+                    ..
+                } = item
+                {
+                    return Ok(Body::File {
                         file_type: file_type
                             .as_deref()
                             .or_else(|| mime_guess::from_path(&file_name).first_raw())
@@ -346,16 +868,58 @@ impl RequestItems {
                         file_name: file_name.into(),
                     });
                 }
-                RequestItem::HttpHeader(..)
-                | RequestItem::HttpHeaderToUnset(..)
-                | RequestItem::UrlParam(..) => {}
             }
+            unreachable!();
+        }
+
+        // Layer the files on top of each other, in order, and then the
+        // request data on top of that
+        let mut body = serde_json::Value::Object(serde_json::Map::new());
+        let mut data = Vec::new();
+        for item in self.0 {
+            match item {
+                RequestItem::FormFile { file_name, .. } if file_name == "-" => {
+                    return Err(anyhow!(
+                        "Request body (from stdin) and request data (key=value) cannot be mixed"
+                    ));
+                }
+                RequestItem::FormFile { file_name, .. } => {
+                    let text = fs::read_to_string(&file_name)
+                        .with_context(|| format!("Failed to read {:?}", file_name))?;
+                    match serde_json::from_str(&text) {
+                        Ok(value @ serde_json::Value::Object(..)) => merge_json(&mut body, value),
+                        _ if file_count > 1 => {
+                            return Err(anyhow!(
+                                "Can't read request from multiple files, unless they all hold JSON objects ({:?} doesn't)",
+                                file_name
+                            ));
+                        }
+                        _ => {
+                            return Err(anyhow!(
+                                "Request body (from a file) and request data (key=value) cannot be mixed, unless the file holds a JSON object"
+                            ));
+                        }
+                    }
+                }
+                item => data.push(item),
+            }
+        }
+        if let Body::Json(fields) = RequestItems(data).body_as_json()? {
+            merge_json(&mut body, serde_json::Value::Object(fields));
+        }
+        match body {
+            serde_json::Value::Object(body) => Ok(Body::Json(body)),
+            _ => unreachable!(),
         }
-        let body = body.expect("Should have had at least one file field");
-        Ok(body)
     }
 
-    pub fn body(self, request_type: RequestType) -> Result<Body> {
+    pub fn body(self, request_type: RequestType, part_headers: &[PartHeader]) -> Result<Body> {
+        if !part_headers.is_empty() {
+            if !self.is_multipart(request_type) {
+                return Err(anyhow!("--part-header only works with multipart requests"));
+            }
+            return self.body_as_encoded_multipart(part_headers);
+        }
         match request_type {
             RequestType::Multipart => self.body_as_multipart(),
             RequestType::Form if self.has_form_files() => self.body_as_multipart(),
@@ -389,7 +953,10 @@ impl RequestItems {
             match item {
                 RequestItem::HttpHeader(..)
                 | RequestItem::HttpHeaderToUnset(..)
-                | RequestItem::UrlParam(..) => continue,
+                | RequestItem::Cookie(..)
+                | RequestItem::UrlParam(..)
+                | RequestItem::UrlParamToUnset(..)
+                | RequestItem::UrlParamFromFile(..) => continue,
                 RequestItem::DataField(..)
                 | RequestItem::DataFieldFromFile(..)
                 | RequestItem::JsonField(..)
@@ -440,6 +1007,19 @@ mod tests {
         );
         // URL param
         assert_eq!(parse("foo==bar"), UrlParam("foo".into(), "bar".into()));
+        // Unset URL param
+        assert_eq!(parse("foo==-"), UrlParamToUnset("foo".into()));
+        assert_eq!(parse(r"foo==\-"), UrlParam("foo".into(), "-".into()));
+        assert_eq!(parse("foo=="), UrlParam("foo".into(), "".into()));
+        // URL params from file
+        assert_eq!(
+            parse("==@params.json"),
+            UrlParamFromFile("".into(), "params.json".into())
+        );
+        assert_eq!(
+            parse("foo==@bar.txt"),
+            UrlParamFromFile("foo".into(), "bar.txt".into())
+        );
         // Escaped right before separator
         assert_eq!(parse(r"foo\==bar"), DataField("foo=".into(), "bar".into()));
         // Header
@@ -465,6 +1045,12 @@ mod tests {
         );
         // Unset header
         assert_eq!(parse("foobar:"), HttpHeaderToUnset("foobar".into()));
+        // Cookie
+        assert_eq!(
+            parse("foo^=bar=baz"),
+            Cookie("foo".into(), "bar=baz".into())
+        );
+        assert_eq!(parse(r"foo\^=bar"), DataField("foo^".into(), "bar".into()));
         // Empty header
         assert_eq!(parse("foobar;"), HttpHeader("foobar".into(), "".into()));
         // Untyped file
@@ -519,4 +1105,112 @@ mod tests {
         // Empty
         assert_eq!(parse("="), DataField("".into(), "".into()));
     }
+
+    #[test]
+    fn yaml_fields() {
+        let parse = |text: &str| match text.parse::<RequestItem>().unwrap() {
+            RequestItem::JsonField(_, value) => value,
+            item => panic!("{:?}", item),
+        };
+        assert_eq!(
+            parse("foo:={a: 1, b: two, c: [x, 2.5, null], 3: true}"),
+            json!({"a": 1, "b": "two", "c": ["x", 2.5, null], "3": true})
+        );
+        assert_eq!(parse("foo:=[a, b]"), json!(["a", "b"]));
+        assert_eq!(parse("foo:='quoted'"), json!("quoted"));
+        assert_eq!(parse("foo:=~"), json!(null));
+        "foo:=bar".parse::<RequestItem>().unwrap_err();
+        "foo:=.nan".parse::<RequestItem>().unwrap_err();
+        "foo:={a: 1".parse::<RequestItem>().unwrap_err();
+    }
+
+    #[test]
+    fn json_field_hints() {
+        let message = |item: &str| item.parse::<RequestItem>().unwrap_err().message;
+        assert!(message("foo:=").contains("key:='{}'"));
+        assert!(message("foo:=bar").contains("use key=value"));
+        assert!(!message("foo:=[1,").contains("(use"));
+    }
+
+    #[test]
+    fn params_file_parsing() {
+        let params = |text: &str| parse_params(text).unwrap();
+        let pairs = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            params(r#"{"q": "rust", "page": 2, "all": true, "tag": ["a", "b"]}"#),
+            pairs(&[
+                ("q", "rust"),
+                ("page", "2"),
+                ("all", "true"),
+                ("tag", "a"),
+                ("tag", "b")
+            ])
+        );
+        assert_eq!(
+            params("# filters\nq=rust\n\nexport page = 2\nname=\"a b\"\nexpr=a=b\n"),
+            pairs(&[
+                ("q", "rust"),
+                ("page", "2"),
+                ("name", "a b"),
+                ("expr", "a=b")
+            ])
+        );
+        assert!(parse_params(r#"{"q": {"nested": 1}}"#).is_err());
+        assert!(parse_params(r#"{"q": null}"#).is_err());
+        assert!(parse_params("q").is_err());
+    }
+
+    #[test]
+    fn delimited_parsing() {
+        assert_eq!(
+            parse_delimited("a,b,c\r\n1,\"x, \"\"y\"\"\",\n\n", ',').unwrap(),
+            vec![vec!["a", "b", "c"], vec!["1", "x, \"y\"", ""]]
+        );
+        assert_eq!(
+            parse_delimited("a\tb\n\"multi\nline\"\t2", '\t').unwrap(),
+            vec![vec!["a", "b"], vec!["multi\nline", "2"]]
+        );
+        assert!(parse_delimited("a\n\"b", ',').is_err());
+    }
+
+    #[test]
+    fn json_merging() {
+        let mut base = json!({"a": {"b": 1, "c": [1]}, "d": "x"});
+        merge_json(&mut base, json!({"a": {"c": [2], "e": null}, "f": true}));
+        assert_eq!(
+            base,
+            json!({"a": {"b": 1, "c": [2], "e": null}, "d": "x", "f": true})
+        );
+        merge_json(&mut base, json!({"a": 1}));
+        assert_eq!(base, json!({"a": 1, "d": "x", "f": true}));
+    }
+
+    #[test]
+    fn wildcard_matching() {
+        assert!(wildcard_match("*.jpg", "photo.jpg"));
+        assert!(wildcard_match("*.jpg", ".jpg"));
+        assert!(wildcard_match("photo?.jpg", "photo1.jpg"));
+        assert!(wildcard_match("*a*b", "xaxxab"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("\u{00B5}?", "\u{00B5}\u{00B5}"));
+        assert!(!wildcard_match("*.jpg", "photo.jpeg"));
+        assert!(!wildcard_match("photo?.jpg", "photo.jpg"));
+        assert!(!wildcard_match("*a*b", "xaxxabc"));
+    }
+
+    #[test]
+    fn type_splitting() {
+        assert_eq!(split_type("hello"), ("hello", None));
+        assert_eq!(
+            split_type("hello;type=text/markdown"),
+            ("hello", Some("text/markdown"))
+        );
+        assert_eq!(split_type("a;type=b;type=c"), ("a;type=b", Some("c")));
+        assert_eq!(split_type(";type="), ("", Some("")));
+    }
 }
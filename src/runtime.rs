@@ -0,0 +1,29 @@
+// Settings for a run that are worked out once, at its start, from the
+// arguments and the config file. They're kept apart from Cli, which only holds
+// what was parsed, so running doesn't change the arguments it was given.
+
+use crate::cli::{Cli, Deadline, ExitCodeMap};
+use crate::config::Config;
+use crate::utils::Cutoff;
+
+#[derive(Debug, Clone)]
+pub struct Runtime {
+    /// The exit codes for kinds of failure: --exit-code-map only overrides
+    /// the kinds it names in the config file.
+    pub exit_codes: ExitCodeMap,
+    /// When --deadline runs out, counted from the start of the run
+    pub cutoff: Option<Cutoff>,
+}
+
+impl Runtime {
+    pub fn new(args: &Cli, config: &Config) -> Runtime {
+        let mut exit_codes = config.exit_codes.clone();
+        exit_codes
+            .0
+            .extend(args.exit_code_map.clone().unwrap_or_default().0);
+        Runtime {
+            exit_codes,
+            cutoff: args.deadline.map(|Deadline(limit)| Cutoff::start(limit)),
+        }
+    }
+}
@@ -0,0 +1,1185 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, Result};
+use atty::Stream;
+use reqwest::blocking::{Client, Request, Response};
+use reqwest::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, AUTHORIZATION, COOKIE, PROXY_AUTHENTICATE,
+    PROXY_AUTHORIZATION, RANGE, WWW_AUTHENTICATE,
+};
+use reqwest::{Method, StatusCode, Url};
+use terminal_size::{terminal_size, Width};
+
+use crate::audit::{AuditLog, Sent};
+use crate::auth::{answer_challenges, basic_auth, parse_auth, parse_challenges};
+use crate::buffer::Buffer;
+use crate::cache::{Cache, Entry};
+use crate::cli::{AuthType, ByteSize, Cli, Deadline, Print, Timeout, Wrap};
+use crate::config::Config;
+use crate::download::{
+    check_range_response, download_file, get_file_size, request_mirror, save_headers, Collision,
+    DownloadOptions, Split,
+};
+use crate::har::HarArchive;
+use crate::metalink::Checksum;
+#[cfg(feature = "otel")]
+use crate::otel;
+use crate::output::{build_printer, peek_archive, print_page};
+use crate::paginate::Paginate;
+use crate::printer::Printer;
+use crate::proxy::Route;
+use crate::request::{
+    authenticate, build_client, build_request, is_stdin_body, replace_with_json_body, StdinReader,
+};
+use crate::request_items::{Body, RequestItems};
+use crate::runtime::Runtime;
+use crate::template::Metadata;
+use crate::timing::{self, DnsError, Lookup, Timings};
+use crate::trace::Trace;
+use crate::url::{construct_url, has_dot_segments};
+use crate::utils::{parse_retry_after, test_pretend_term, BodyTooLarge, Cutoff};
+use crate::{batch, metalink, status_exit_code, to_curl, RunResult};
+
+/// How many times --respect-retry-after sends a request again.
+const RETRY_AFTER_ATTEMPTS: usize = 3;
+
+/// The longest --respect-retry-after waits, unless --retry-max-wait says otherwise.
+const DEFAULT_RETRY_MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// How long -v --meta spends on the addresses of a host it couldn't connect
+/// to, for all of them together.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A username, and a password unless it's left to be prompted for.
+type Auth = (String, Option<String>);
+
+/// Send the request in `args`, and print the response.
+pub fn send(args: Cli, config: &Config, runtime: &Runtime) -> Result<RunResult> {
+    if args.reject_dot_segments && has_dot_segments(&args.url) {
+        return Err(anyhow!(
+            "The path of {} has . or .. segments, which can't be sent unchanged",
+            args.url
+        ));
+    }
+    if args.curl {
+        to_curl::print_curl_translation(args)?;
+        return Ok(RunResult::exited(0));
+    }
+
+    if let Some(input) = &args.input {
+        return batch::run(&args, input, &config.credentials, runtime).map(RunResult::exited);
+    }
+
+    let Prepared {
+        client,
+        mut request,
+        server_auth,
+        proxy_auth,
+        lookup,
+        resume,
+    } = prepare(&args, config)?;
+    #[cfg(feature = "otel")]
+    let trace_ids = request
+        .headers()
+        .get("traceparent")
+        .and_then(|header| header.to_str().ok())
+        .and_then(otel::parse_traceparent);
+
+    let pager = args
+        .pager
+        .as_ref()
+        .map(|pager| pager.as_deref().unwrap_or("less -FRX"));
+    let wrap = match args.wrap {
+        Some(Wrap::On) => terminal_size().map(|(Width(width), _)| width as usize),
+        Some(Wrap::Width(width)) => Some(width),
+        Some(Wrap::Off) | None => None,
+    };
+    // The listing is the output of --peek, so it goes to stdout
+    let buffer = Buffer::new(
+        args.download && !args.peek,
+        args.output.as_deref(),
+        atty::is(Stream::Stdout) || test_pretend_term(),
+        args.pretty,
+        args.color,
+        pager,
+    )?
+    .with_line_control(args.max_lines, wrap, args.line_numbers);
+    let is_redirect = buffer.is_redirect();
+    let print = match args.print {
+        Some(print) => print,
+        None => Print::new(
+            args.verbose,
+            args.headers,
+            args.body,
+            args.quiet > 0,
+            args.offline,
+            &buffer,
+        ),
+    };
+    let mut pretty = args.pretty.unwrap_or_else(|| buffer.guess_pretty());
+    if let Some(color) = args.color {
+        pretty = pretty.with_color(color);
+    }
+    let mut printer = build_printer(&args, config, pretty, buffer)?;
+
+    // A fresh response from --cache is used instead of sending the request,
+    // and a stale one is revalidated
+    let mut cached = match &args.cache {
+        Some(dir) if !args.offline => Some(Cached::lookup(
+            dir.as_ref().map(PathBuf::from),
+            &mut request,
+        )?),
+        _ => None,
+    };
+
+    if print.request_headers {
+        printer.print_request_headers(&request)?;
+    }
+    if print.request_body {
+        printer.print_request_body(&mut request)?;
+    }
+    if args.offline {
+        return Ok(RunResult::exited(0));
+    }
+
+    let paginate = args
+        .paginate
+        .clone()
+        .map(|paginate| paginate.unwrap_or(Paginate::Link));
+    let method = request.method().clone();
+    let redact = if args.show_secrets {
+        None
+    } else {
+        Some(args.redact.clone())
+    };
+    let trace = match &args.trace {
+        Some(path) => {
+            let trace = Trace::open(path.as_deref().map(Path::new), redact.clone())?;
+            trace.info(&format!(
+                "{} {}, sending {} {}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION"),
+                method,
+                request.url()
+            ))?;
+            Some(trace)
+        }
+        None => None,
+    };
+    let audit = match args.audit_log.as_ref().or(config.audit_log.as_ref()) {
+        Some(path) => Some(AuditLog::open(path, redact.clone())?),
+        None => None,
+    };
+    let har = config
+        .har_dir
+        .as_ref()
+        .map(|dir| HarArchive::new(dir, redact.clone()));
+    let session = Session {
+        args: &args,
+        runtime,
+        client: &client,
+        print,
+        trace: trace.as_ref(),
+        audit: audit.as_ref(),
+        har: har.as_ref(),
+    };
+
+    let (response, mut exchange) = get_response(
+        &session,
+        &mut printer,
+        request,
+        server_auth.as_ref(),
+        proxy_auth.as_ref(),
+        lookup.as_ref(),
+        &mut cached,
+    )?;
+    let connect = exchange.connect();
+    let lookup_time = lookup.as_ref().map(|lookup| lookup.elapsed);
+    let mut metadata =
+        if args.template.is_some() || args.otel_endpoint.is_some() || session.records() {
+            let mut metadata =
+                Metadata::new(method, &response, lookup_time.unwrap_or_default() + connect);
+            metadata.time_namelookup = lookup_time.unwrap_or_default();
+            Some(metadata)
+        } else {
+            None
+        };
+    let mut exit_code = handle_response(
+        &session,
+        &mut printer,
+        &response,
+        &exchange,
+        lookup.as_ref(),
+        is_redirect,
+    )?;
+    let status = response.status();
+    let headers = response.headers().clone();
+
+    let mut body_path = None;
+    let mut next_page = None;
+    if args.download {
+        if let Some(trace) = &trace {
+            if args.peek {
+                trace.info("The body is read by --peek, which isn't traced")?;
+            } else {
+                trace.info("The body is saved by --download, which isn't traced")?;
+            }
+        }
+        if exit_code == 0 {
+            let (size, path) = download(
+                &session,
+                &mut printer,
+                response,
+                &mut exchange,
+                resume,
+                pretty.color(),
+            )?;
+            if let Some(metadata) = &mut metadata {
+                metadata.size_download = size;
+            }
+            body_path = path;
+        }
+    } else {
+        let (size, next) = print_body(
+            &session,
+            &mut printer,
+            response,
+            &exchange,
+            &cached,
+            paginate.as_ref(),
+        )?;
+        if let Some(metadata) = &mut metadata {
+            metadata.size_download = size;
+        }
+        next_page = next.filter(|_| status.is_success());
+        body_path = args.output.clone();
+    }
+
+    let download_time = exchange.response_start.elapsed();
+    if let Some(metadata) = &mut metadata {
+        metadata.time_total = lookup_time.unwrap_or_default() + exchange.request_start.elapsed();
+    }
+    if let (Some(template), Some(metadata)) = (&args.template, &metadata) {
+        printer.print_raw(&template.render(metadata))?;
+    }
+    if let (Some(sent), Some(metadata)) = (&exchange.recorded, &metadata) {
+        session.record(sent, metadata)?;
+    }
+    let timings = Timings {
+        lookup: lookup_time,
+        first_byte: connect,
+        download: download_time,
+    };
+    #[cfg(feature = "otel")]
+    {
+        if let (Some(endpoint), Some(ids), Some(metadata)) =
+            (&args.otel_endpoint, &trace_ids, &metadata)
+        {
+            let start = SystemTime::now() - timings.total();
+            if let Err(err) = otel::export(endpoint, &otel::span(ids, start, &timings, metadata)) {
+                if args.quiet < 2 {
+                    eprintln!(
+                        "{}: warning: couldn't send the span to {}: {:#}",
+                        env!("CARGO_PKG_NAME"),
+                        endpoint,
+                        err
+                    );
+                }
+            }
+        }
+    }
+    if args.meta {
+        if args.json_output {
+            eprintln!("{}", timings.to_json());
+        } else if args.quiet == 0 {
+            printer.print_meta(&timings)?;
+        }
+    }
+    if let (Some(paginate), Some(next)) = (&paginate, next_page) {
+        let first = exchange.sent.as_ref().ok_or_else(|| {
+            anyhow!("--paginate can't send the request again, because its body was streamed")
+        })?;
+        let code = follow_pages(&session, paginate, &mut printer, first, next)?;
+        exit_code = exit_code.max(code);
+    }
+    Ok(RunResult {
+        exit_code,
+        status: Some(status),
+        headers,
+        body_path,
+        timings: Some(timings),
+    })
+}
+
+/// A request that's ready to be sent, and what's needed to send it.
+struct Prepared {
+    client: Client,
+    request: Request,
+    /// The credentials to answer the server's challenge with
+    server_auth: Option<Auth>,
+    /// The credentials to answer the proxy's challenge with
+    proxy_auth: Option<Auth>,
+    /// The host's addresses, if it was looked up to time it
+    lookup: Option<Lookup>,
+    /// Where --resume picks the download up
+    resume: Option<u64>,
+}
+
+/// Build the request in `args`, and the client to send it with.
+fn prepare(args: &Cli, config: &Config) -> Result<Prepared> {
+    let mut request_items = RequestItems::new(args.request_items.clone());
+    request_items.expand(args)?;
+    let query = request_items.query()?;
+    let query_to_unset = request_items.query_to_unset();
+    let (headers, headers_to_unset) = request_items.headers()?;
+    if args.traceparent.is_some() && headers.contains_key("traceparent") {
+        return Err(anyhow!(
+            "--traceparent can't be combined with a traceparent header"
+        ));
+    }
+    let url = construct_url(
+        &args.url,
+        args.default_scheme.as_deref(),
+        &query,
+        &query_to_unset,
+        args.encode_set,
+    )?;
+
+    let stdin_piped = !(args.ignore_stdin
+        || args.password_stdin
+        || args.json_body.is_some()
+        || atty::is(Stream::Stdin)
+        || test_pretend_term());
+    let mut body = request_items.body(args.request_type, &args.part_header)?;
+    if let Some(json_body) = &args.json_body {
+        body = replace_with_json_body(body, json_body)?;
+    }
+    let explicit_stdin = args.request_items.iter().any(is_stdin_body);
+    if stdin_piped && !body.is_empty() && !explicit_stdin {
+        if body.is_multipart() {
+            return Err(anyhow!("Cannot build a multipart request body from stdin"));
+        } else {
+            return Err(anyhow!(
+                "Request body (from stdin) and request data (key=value) cannot be mixed. \
+                Pass --ignore-stdin to ignore standard input."
+            ));
+        }
+    }
+    // Stdin is only sent implicitly if there's nothing else to go on. With
+    // headers or query parameters it takes an explicit @-, so a script whose
+    // stdin is an idle pipe doesn't hang.
+    let ignore_stdin = !stdin_piped || !args.request_items.is_empty();
+
+    // Input may trickle in from a slow generator, so set up the client (which
+    // loads certificates and starts a runtime) in the meantime. Connecting
+    // can't start early: reqwest only opens connections to send a request.
+    let stdin_reader = if ignore_stdin {
+        None
+    } else {
+        Some(StdinReader::spawn())
+    };
+    let route = Route::resolve(&args.proxy, args.system_proxy, &url);
+    let proxy = route.proxy();
+    let proxy_auth = match &args.proxy_auth {
+        Some(auth) => Some(parse_auth(
+            auth.clone(),
+            proxy.and_then(Url::host_str).unwrap_or("<proxy>"),
+            false,
+        )?),
+        None => None,
+    };
+    // Looking the host up before reqwest does is the only way to time it, or
+    // to see the addresses it had to choose from. It's an extra lookup, so
+    // it's only done when the timing is asked for.
+    let timed = args.meta || args.template.is_some() || args.otel_endpoint.is_some();
+    // A proxy looks the host up itself, and the system settings may name one
+    let direct = proxy.is_none() && route != Route::System;
+    let lookup = if timed && direct && !args.offline {
+        timing::lookup(&url)?
+    } else {
+        None
+    };
+    let client = build_client(
+        args,
+        url.scheme() == "https",
+        proxy_auth.as_ref(),
+        lookup.as_ref(),
+    )?;
+    if let Some(stdin_reader) = stdin_reader {
+        let timeout = args.stdin_timeout.as_ref().and_then(Timeout::as_duration);
+        match stdin_reader.finish(timeout)? {
+            Some(buffer) => body = Body::Raw(buffer),
+            None => eprintln!(
+                "{}: warning: nothing on stdin after {}, sending the request without a body. \
+                Pass --ignore-stdin to not wait for it.",
+                env!("CARGO_PKG_NAME"),
+                humantime::format_duration(timeout.unwrap_or_default())
+            ),
+        }
+    }
+    // Only plain HTTP requests get the proxy's 407 back. reqwest handles
+    // CONNECT itself and turns a 407 into an error without the challenge,
+    // so tunnels only get the Basic credentials sent up front.
+    let proxy_auth = match (proxy_auth, proxy) {
+        (Some(auth), Some(proxy))
+            if url.scheme() == "http" && !proxy.scheme().starts_with("socks") =>
+        {
+            Some(auth)
+        }
+        _ => None,
+    };
+
+    let method = args.method.clone().unwrap_or_else(|| body.pick_method());
+    let mut resume: Option<u64> = None;
+
+    let (mut request, mut server_auth) = {
+        let mut request_builder = build_request(&client, args, method, url.clone(), body)?;
+
+        if let Some(range) = args.range {
+            request_builder = request_builder.header(RANGE, range.header_value());
+        }
+
+        if args.resume {
+            if let Some(file_size) = get_file_size(args.output.as_deref()) {
+                request_builder = request_builder.header(RANGE, format!("bytes={}-", file_size));
+                resume = Some(file_size);
+            }
+        }
+
+        let auth = match &args.auth {
+            Some(auth) => Some(parse_auth(
+                auth.clone(),
+                url.host_str().unwrap_or("<host>"),
+                args.password_stdin,
+            )?),
+            None => None,
+        };
+        // Digest credentials, or any with --auth-challenge, wait for the server to ask
+        let server_auth = match (&auth, args.auth_type) {
+            (Some(_), None) if args.auth_challenge && !args.offline => auth.clone(),
+            (Some(_), Some(AuthType::digest)) => auth.clone(),
+            _ => None,
+        };
+        request_builder = authenticate(
+            request_builder,
+            args,
+            auth,
+            server_auth.is_none(),
+            &config.credentials,
+            &url,
+        )?;
+
+        let mut request = request_builder.headers(headers).build()?;
+
+        headers_to_unset.iter().for_each(|h| {
+            request.headers_mut().remove(h);
+        });
+
+        (request, server_auth)
+    };
+
+    // Answering a challenge means sending the body twice, so a streamed one
+    // has to be read into memory. That's only worth it for Digest, which
+    // can't be sent up front.
+    if server_auth.is_some() && request.try_clone().is_none() {
+        if args.auth_type == Some(AuthType::digest) {
+            if let Some(body) = request.body_mut() {
+                body.buffer()?;
+            }
+        } else if let Some((username, password)) = server_auth.take() {
+            let header = basic_auth(&username, password.as_deref().unwrap_or(""));
+            request
+                .headers_mut()
+                .insert(AUTHORIZATION, HeaderValue::from_str(&header)?);
+        }
+    }
+
+    if args.download && args.decompress && args.range.is_none() && args.split.is_none() {
+        // Compression only makes sense if it's undone on the way to disk
+        request.headers_mut().insert(
+            ACCEPT_ENCODING,
+            HeaderValue::from_static("gzip, deflate, br, zstd"),
+        );
+    } else if args.download || args.range.is_some() {
+        // Ranges apply to the encoded body, so it has to be sent as-is
+        request
+            .headers_mut()
+            .insert(ACCEPT_ENCODING, HeaderValue::from_static("identity"));
+    };
+
+    Ok(Prepared {
+        client,
+        request,
+        server_auth,
+        proxy_auth,
+        lookup,
+        resume,
+    })
+}
+
+/// What --cache has for a request.
+struct Cached {
+    cache: Cache,
+    /// The URL and headers the response is stored under. Only GET is cached.
+    key: Option<(Url, HeaderMap)>,
+    /// A fresh response to use instead of sending the request, and its age
+    hit: Option<(Response, Duration)>,
+    /// A stale response the request asks the server to revalidate
+    stale: Option<Entry>,
+}
+
+impl Cached {
+    /// Look `request` up in the cache in `dir`, or the default one. If what's
+    /// there is stale, its validators are added to the request.
+    fn lookup(dir: Option<PathBuf>, request: &mut Request) -> Result<Cached> {
+        let cache = Cache::new(dir)?;
+        let key = match request.method() {
+            &Method::GET => Some((request.url().clone(), request.headers().clone())),
+            _ => None,
+        };
+        let mut hit = None;
+        let mut stale = None;
+        if key.is_some() {
+            if let Some(entry) = cache.lookup(request)? {
+                let now = SystemTime::now();
+                if entry.is_fresh(request.headers(), now) {
+                    let age = entry.age(now);
+                    hit = Some((entry.response(Some(age))?, age));
+                } else if entry.add_validators(request) {
+                    stale = Some(entry);
+                }
+            }
+        }
+        Ok(Cached {
+            cache,
+            key,
+            hit,
+            stale,
+        })
+    }
+}
+
+/// What the stages of [`send`] share once the request is ready to go.
+struct Session<'a> {
+    args: &'a Cli,
+    runtime: &'a Runtime,
+    client: &'a Client,
+    print: Print,
+    trace: Option<&'a Trace>,
+    audit: Option<&'a AuditLog>,
+    har: Option<&'a HarArchive>,
+}
+
+impl Session<'_> {
+    /// Whether requests are recorded, for --audit-log or the HAR archive.
+    fn records(&self) -> bool {
+        self.audit.is_some() || self.har.is_some()
+    }
+
+    fn record(&self, sent: &Sent, metadata: &Metadata) -> Result<()> {
+        if let Some(audit) = self.audit {
+            audit.record(sent, metadata)?;
+        }
+        if let Some(har) = self.har {
+            har.record(sent, metadata)?;
+        }
+        Ok(())
+    }
+
+    /// The timeout for a request that's sent now.
+    fn timeout(&self) -> Result<Option<Duration>> {
+        request_timeout(self.args, self.runtime.cutoff)
+    }
+}
+
+/// How the response to a request was got.
+struct Exchange {
+    /// Where the request was sent, before any redirects
+    orig_url: Url,
+    /// A copy of the request that got the response, if it could be made. --split,
+    /// mirrors and --paginate send more requests like it.
+    sent: Option<Request>,
+    /// The request as it went out, for --audit-log and the HAR archive
+    recorded: Option<Sent>,
+    from_cache: bool,
+    /// When the request was sent, which a response stored in the cache is dated by
+    request_time: SystemTime,
+    request_start: Instant,
+    /// When the response's headers were in
+    response_start: Instant,
+}
+
+impl Exchange {
+    /// How long the response took to start.
+    fn connect(&self) -> Duration {
+        self.response_start.duration_since(self.request_start)
+    }
+}
+
+/// Send the request and get the response, answering the server's and the
+/// proxy's challenge with their credentials, and waiting out a Retry-After
+/// with --respect-retry-after. A fresh response from the cache is used
+/// instead, and a stale one is freshened by a 304.
+fn get_response(
+    session: &Session,
+    printer: &mut Printer,
+    mut request: Request,
+    mut server_challenge: Option<&Auth>,
+    mut proxy_challenge: Option<&Auth>,
+    lookup: Option<&Lookup>,
+    cached: &mut Option<Cached>,
+) -> Result<(Response, Exchange)> {
+    let args = session.args;
+    let print = session.print;
+    let orig_url = request.url().clone();
+    let method = request.method().clone();
+    let mut retries = 0;
+    let mut response;
+    let mut sent;
+    let mut recorded = None;
+    let mut from_cache = false;
+    let request_time = SystemTime::now();
+    let request_start = Instant::now();
+    loop {
+        if let Some((hit, age)) = cached.as_mut().and_then(|cached| cached.hit.take()) {
+            if args.quiet == 0 {
+                eprintln!(
+                    "{}: from the cache, {} old",
+                    env!("CARGO_PKG_NAME"),
+                    humantime::format_duration(age)
+                );
+            }
+            sent = None;
+            response = hit;
+            from_cache = true;
+            break;
+        }
+        sent = match (server_challenge, proxy_challenge, args.download) {
+            (None, None, false) if args.paginate.is_none() && !args.respect_retry_after => None,
+            _ => request.try_clone(),
+        };
+        if let Some(trace) = session.trace {
+            trace.request(&request);
+        }
+        if session.records() {
+            recorded = Some(Sent::capture(&mut request)?);
+        }
+        // Stored under the headers that were really sent, so credentials
+        // from an answered challenge count
+        if let Some((_, headers)) = cached.as_mut().and_then(|cached| cached.key.as_mut()) {
+            *headers = request.headers().clone();
+        }
+        *request.timeout_mut() = session.timeout()?;
+        let result = session.client.execute(request);
+        if let Some(trace) = session.trace {
+            trace.response(result.as_ref().ok())?;
+        }
+        response = match result {
+            Ok(response) => response,
+            Err(err) => {
+                if let (true, Some(lookup)) =
+                    (args.verbose && args.meta && err.is_connect(), lookup)
+                {
+                    print_connect_failures(lookup);
+                }
+                if err.is_connect() {
+                    check_lookup(&err, args)?;
+                }
+                return Err(err.into());
+            }
+        };
+        let mut retry = match sent.as_ref().and_then(Request::try_clone) {
+            Some(retry) => retry,
+            None => break,
+        };
+        if let Some(wait) = retry_after_wait(args, &response, retries) {
+            sleep_before_retry(session.runtime.cutoff, wait)?;
+            retries += 1;
+            if print.request_headers {
+                printer.print_request_headers(&retry)?;
+            }
+            if print.request_body {
+                printer.print_request_body(&mut retry)?;
+            }
+            request = retry;
+            continue;
+        }
+        let (auth, challenge_header, auth_type, answer_header) = match response.status() {
+            StatusCode::UNAUTHORIZED => (
+                server_challenge.take(),
+                WWW_AUTHENTICATE,
+                args.auth_type,
+                AUTHORIZATION,
+            ),
+            // Basic was already sent, so only Digest is worth retrying
+            StatusCode::PROXY_AUTHENTICATION_REQUIRED => (
+                proxy_challenge.take(),
+                PROXY_AUTHENTICATE,
+                Some(AuthType::digest),
+                PROXY_AUTHORIZATION,
+            ),
+            _ => break,
+        };
+        let (username, password) = match auth {
+            Some(auth) => auth,
+            None => break,
+        };
+        let header = answer_challenges(
+            &parse_challenges(response.headers(), challenge_header),
+            auth_type,
+            username,
+            password.as_deref(),
+            retry.method(),
+            retry.url(),
+        )?;
+        match header {
+            Some(header) => retry.headers_mut().insert(answer_header, header),
+            None => break,
+        };
+        if print.request_headers {
+            printer.print_request_headers(&retry)?;
+        }
+        if print.request_body {
+            printer.print_request_body(&mut retry)?;
+        }
+        request = retry;
+    }
+    if let (Some(cached), false) = (cached.as_mut(), from_cache) {
+        let status = response.status();
+        if let (Some(entry), StatusCode::NOT_MODIFIED) = (cached.stale.take(), status) {
+            response = entry.freshen(&cached.cache, &response, request_time)?;
+            from_cache = true;
+            if args.quiet == 0 {
+                eprintln!("{}: from the cache, revalidated", env!("CARGO_PKG_NAME"));
+            }
+        } else if !method.is_safe() && !status.is_client_error() && !status.is_server_error() {
+            // Whatever was stored for the URL may have changed
+            cached.cache.invalidate(&orig_url)?;
+        }
+    }
+    let exchange = Exchange {
+        orig_url,
+        sent,
+        recorded,
+        from_cache,
+        request_time,
+        request_start,
+        response_start: Instant::now(),
+    };
+    Ok((response, exchange))
+}
+
+/// Print what comes before the body of the response, and check the response
+/// against what was asked for. Returns the exit status it calls for.
+fn handle_response(
+    session: &Session,
+    printer: &mut Printer,
+    response: &Response,
+    exchange: &Exchange,
+    lookup: Option<&Lookup>,
+    is_redirect: bool,
+) -> Result<i32> {
+    let args = session.args;
+    if let Some(template) = &args.output_headers {
+        save_headers(response, template, args.output.as_deref())?;
+    }
+    if args.verbose && !exchange.from_cache {
+        let route = Route::resolve(&args.proxy, args.system_proxy, response.url());
+        printer.print_connection(response, &route, lookup)?;
+    }
+    if session.print.response_headers {
+        printer.print_response_headers(response)?;
+        if args.verbose {
+            printer.print_cookies(response)?;
+        }
+    }
+    let status = response.status();
+    if let Some(range) = args.range {
+        if status.is_success() {
+            check_range_response(response, range)?;
+        }
+    }
+    let max_body_size = args.max_body_size.map(|ByteSize(size)| size);
+    if let (Some(limit), Some(length)) = (max_body_size, response.content_length()) {
+        if length > limit {
+            return Err(BodyTooLarge(limit).into());
+        }
+    }
+    let exit_code = status_exit_code(args, &session.runtime.exit_codes, status.as_u16());
+    // With -q the warning is the only sign of what happened
+    let warn_status = match args.quiet {
+        0 => is_redirect,
+        1 => true,
+        _ => false,
+    };
+    if let Some(expected) = args.expect_status {
+        if exit_code != 0 && args.quiet < 2 {
+            eprintln!(
+                "\n{}: warning: expected HTTP {}, got {}\n",
+                env!("CARGO_PKG_NAME"),
+                expected,
+                status
+            );
+        }
+    } else if warn_status && exit_code != 0 {
+        eprintln!("\n{}: warning: HTTP {}\n", env!("CARGO_PKG_NAME"), status);
+    }
+    if args.json_output && exit_code != 0 {
+        eprintln!(
+            "{}",
+            serde_json::json!({
+                "type": "error",
+                "kind": "http-status",
+                "status": status.as_u16(),
+                "message": format!("HTTP {}", status),
+            })
+        );
+    }
+    Ok(exit_code)
+}
+
+/// Save the body of the response to a file for --download, failing over to
+/// mirrors, or list the archive in it for --peek. Returns the number of bytes
+/// received and the file they went to.
+fn download(
+    session: &Session,
+    printer: &mut Printer,
+    response: Response,
+    exchange: &mut Exchange,
+    resume: Option<u64>,
+    color: bool,
+) -> Result<(u64, Option<PathBuf>)> {
+    let args = session.args;
+    let max_body_size = args.max_body_size.map(|ByteSize(size)| size);
+    if args.peek {
+        let size = peek_archive(
+            printer,
+            response,
+            session.print.response_body,
+            max_body_size,
+        )?;
+        return Ok((size, None));
+    }
+    let split = match args.split {
+        Some(parts) if parts > 1 => match exchange.sent.take() {
+            Some(mut request) => {
+                *request.timeout_mut() = session.timeout()?;
+                Some(Split {
+                    parts,
+                    client: session.client.clone(),
+                    request,
+                })
+            }
+            None => None,
+        },
+        _ => None,
+    };
+    // Failing over means resuming into a known file, which is only possible
+    // if it holds the body as sent
+    let mut mirrors = Vec::new();
+    let mut checksum = None;
+    if args.range.is_none() && !args.decompress {
+        if args.output.is_some() && split.is_none() {
+            mirrors = args.mirror.clone();
+            mirrors.extend(metalink::mirrors(response.headers(), response.url()));
+        }
+        checksum = Checksum::from_headers(response.headers());
+    }
+    let collision = if args.no_clobber {
+        Collision::Fail
+    } else if args.overwrite {
+        Collision::Overwrite
+    } else {
+        Collision::Number(args.number_format.clone().unwrap_or_default())
+    };
+    let mut mirrors = mirrors.into_iter().peekable();
+    let options = DownloadOptions {
+        file_name: args.output.clone(),
+        collision: &collision,
+        color,
+        quiet: args.quiet > 0,
+        progress_interval: args.progress_interval,
+        decompress: args.decompress,
+        preserve_mtime: !args.ignore_last_modified,
+        keep_partial: args.resume || mirrors.peek().is_some(),
+        max_size: max_body_size,
+    };
+    let orig_url = &exchange.orig_url;
+    let mut result = download_file(response, orig_url, resume, split, &options);
+    while let Err(err) = result {
+        let (mirror, request, path) = match (mirrors.next(), &exchange.sent, &args.output) {
+            (Some(mirror), Some(request), Some(path)) => (mirror, request, path),
+            _ => return Err(err),
+        };
+        if args.quiet < 2 {
+            eprintln!(
+                "{}: warning: {:#}, trying {}",
+                env!("CARGO_PKG_NAME"),
+                err,
+                mirror
+            );
+        }
+        let keep_partial = args.resume || mirrors.peek().is_some();
+        let timeout = session.timeout()?;
+        result = match request_mirror(session.client, request, &mirror, path, timeout) {
+            Ok((response, resume)) => {
+                let options = DownloadOptions {
+                    file_name: Some(path.clone()),
+                    // What's there is the start of this download
+                    collision: &Collision::Overwrite,
+                    decompress: false,
+                    keep_partial,
+                    ..options
+                };
+                download_file(response, orig_url, resume, None, &options)
+            }
+            Err(err) => Err(err),
+        };
+    }
+    let transfer = result?;
+    let transfer_time = exchange.response_start.elapsed();
+    if let (Some(checksum), Some(path)) = (checksum, &transfer.path) {
+        if let Err(err) = checksum.verify(path) {
+            fs::remove_file(path)?;
+            return Err(err.context(format!("Removed {:?}", path)));
+        }
+    }
+    if args.json_output || args.quiet == 0 {
+        transfer.print_summary(exchange.connect(), transfer_time, args.json_output);
+    }
+    Ok((transfer.received, transfer.path))
+}
+
+/// Print the body of the response, storing the response in the cache on the
+/// way. Returns the size of the body and the next page, for --paginate.
+fn print_body(
+    session: &Session,
+    printer: &mut Printer,
+    response: Response,
+    exchange: &Exchange,
+    cached: &Option<Cached>,
+    paginate: Option<&Paginate>,
+) -> Result<(u64, Option<Url>)> {
+    let max_body_size = session.args.max_body_size.map(|ByteSize(size)| size);
+    let response = match cached {
+        Some(Cached {
+            cache,
+            key: Some((url, headers)),
+            ..
+        }) if !exchange.from_cache => {
+            cache.store(url, headers, exchange.request_time, response, max_body_size)?
+        }
+        _ => response,
+    };
+    print_page(
+        printer,
+        response,
+        session.print.response_body,
+        paginate,
+        max_body_size,
+        session.trace,
+    )
+}
+
+/// Find out whether a connection failed because its host couldn't be looked
+/// up, or its proxy's host. reqwest only says so in the message of a private
+/// error, so the host is looked up again here.
+fn check_lookup(err: &reqwest::Error, args: &Cli) -> Result<(), DnsError> {
+    let url = match err.url() {
+        Some(url) => url,
+        None => return Ok(()),
+    };
+    match Route::resolve(&args.proxy, args.system_proxy, url) {
+        // The proxy isn't known
+        Route::System => Ok(()),
+        route => timing::lookup(route.proxy().unwrap_or(url)).map(drop),
+    }
+}
+
+/// Say why each address of the host failed, since reqwest only reports the
+/// last connection error.
+fn print_connect_failures(lookup: &Lookup) {
+    for (addr, result) in lookup.probe(PROBE_TIMEOUT) {
+        match result {
+            Ok(()) => eprintln!(
+                "{}: {} accepts connections when tried again",
+                env!("CARGO_PKG_NAME"),
+                addr
+            ),
+            Err(err) => eprintln!("{}: {}: {}", env!("CARGO_PKG_NAME"), addr, err),
+        }
+    }
+}
+
+/// Wait before sending a request again, but not past --deadline.
+fn sleep_before_retry(cutoff: Option<Cutoff>, wait: Duration) -> Result<()> {
+    match cutoff {
+        Some(cutoff) => thread::sleep(cutoff.limit(Some(wait))?),
+        None => thread::sleep(wait),
+    }
+    Ok(())
+}
+
+/// How long to wait before sending a request again, if --respect-retry-after
+/// is used and the response is a 429 or 503 with a Retry-After header.
+/// `retries` is how many times the request was already sent again.
+fn retry_after_wait(args: &Cli, response: &Response, retries: usize) -> Option<Duration> {
+    if !args.respect_retry_after
+        || retries >= RETRY_AFTER_ATTEMPTS
+        || !matches!(
+            response.status(),
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+        )
+    {
+        return None;
+    }
+    let wait = parse_retry_after(response.headers(), SystemTime::now())?;
+    let max_wait = args
+        .retry_max_wait
+        .map_or(DEFAULT_RETRY_MAX_WAIT, |Deadline(max_wait)| max_wait);
+    let wait = wait.min(max_wait);
+    if args.quiet < 2 {
+        eprintln!(
+            "{}: warning: HTTP {}, sending the request again in {}",
+            env!("CARGO_PKG_NAME"),
+            response.status(),
+            humantime::format_duration(wait)
+        );
+    }
+    Some(wait)
+}
+
+/// The timeout for a request that's sent now: --timeout, cut short to what's
+/// left of --deadline.
+pub fn request_timeout(args: &Cli, cutoff: Option<Cutoff>) -> Result<Option<Duration>> {
+    let timeout = args.timeout.as_ref().and_then(|t| t.as_duration());
+    match cutoff {
+        Some(cutoff) => Ok(Some(cutoff.limit(timeout)?)),
+        None => Ok(timeout),
+    }
+}
+
+/// Request and print the pages after the first one for --paginate, until
+/// there's no next page. Returns the highest exit status.
+fn follow_pages(
+    session: &Session,
+    paginate: &Paginate,
+    printer: &mut Printer,
+    first: &Request,
+    mut next: Url,
+) -> Result<i32> {
+    let Session {
+        args,
+        runtime,
+        client,
+        print,
+        trace,
+        ..
+    } = *session;
+    let max_body_size = args.max_body_size.map(|ByteSize(size)| size);
+    let mut seen = HashSet::new();
+    seen.insert(first.url().clone());
+    let mut count = 1;
+    let mut exit_code = 0;
+    loop {
+        if args.max_pages.map_or(false, |max| count >= max) {
+            return Ok(exit_code);
+        }
+        if !seen.insert(next.clone()) {
+            if args.quiet < 2 {
+                eprintln!(
+                    "{}: warning: the next page is {} again, stopping",
+                    env!("CARGO_PKG_NAME"),
+                    next
+                );
+            }
+            return Ok(exit_code);
+        }
+        let mut page = first.try_clone().expect("the request was cloned before");
+        // Like redirects, don't hand credentials to another origin
+        if next.origin() != first.url().origin() {
+            page.headers_mut().remove(AUTHORIZATION);
+            page.headers_mut().remove(COOKIE);
+        }
+        *page.url_mut() = next;
+        let method = page.method().clone();
+        let request_start = Instant::now();
+        let mut retries = 0;
+        let (response, sent) = loop {
+            let mut request = page.try_clone().expect("the request was cloned before");
+            if print.request_headers {
+                printer.print_request_headers(&request)?;
+            }
+            if print.request_body {
+                printer.print_request_body(&mut request)?;
+            }
+            if let Some(trace) = trace {
+                trace.request(&request);
+            }
+            let sent = if session.records() {
+                Some(Sent::capture(&mut request)?)
+            } else {
+                None
+            };
+            *request.timeout_mut() = session.timeout()?;
+            let response = client.execute(request);
+            if let Some(trace) = trace {
+                trace.response(response.as_ref().ok())?;
+            }
+            let response = response?;
+            match retry_after_wait(args, &response, retries) {
+                Some(wait) => sleep_before_retry(runtime.cutoff, wait)?,
+                None => break (response, sent),
+            }
+            retries += 1;
+        };
+        let mut metadata = if args.template.is_some() || sent.is_some() {
+            Some(Metadata::new(method, &response, request_start.elapsed()))
+        } else {
+            None
+        };
+        if print.response_headers {
+            printer.print_response_headers(&response)?;
+        }
+        let status = response.status();
+        exit_code = exit_code.max(status_exit_code(args, &runtime.exit_codes, status.as_u16()));
+        let (size, found) = print_page(
+            printer,
+            response,
+            print.response_body,
+            Some(paginate),
+            max_body_size,
+            trace,
+        )?;
+        if let Some(metadata) = &mut metadata {
+            metadata.size_download = size;
+            metadata.time_total = request_start.elapsed();
+        }
+        if let (Some(template), Some(metadata)) = (&args.template, &metadata) {
+            printer.print_raw(&template.render(metadata))?;
+        }
+        if let (Some(sent), Some(metadata)) = (&sent, &metadata) {
+            session.record(sent, metadata)?;
+        }
+        count += 1;
+        if !status.is_success() {
+            if args.quiet < 2 {
+                eprintln!(
+                    "{}: warning: HTTP {} for page {}, stopping",
+                    env!("CARGO_PKG_NAME"),
+                    status,
+                    count
+                );
+            }
+            return Ok(exit_code);
+        }
+        next = match found {
+            Some(url) => url,
+            None => return Ok(exit_code),
+        };
+    }
+}
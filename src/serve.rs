@@ -0,0 +1,265 @@
+// A small HTTP/1.1 server for --serve, which prints every request it
+// receives and sends back the same reply to each. It handles one connection
+// at a time, so the output of requests doesn't get mixed up.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use atty::Stream;
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, EXPECT, TRANSFER_ENCODING,
+};
+use reqwest::StatusCode;
+
+use crate::buffer::Buffer;
+use crate::cli::Cli;
+use crate::config::Config;
+use crate::output::build_printer;
+use crate::printer::Printer;
+use crate::utils::test_pretend_term;
+
+const DEFAULT_PORT: u16 = 8080;
+
+/// Requests with larger headers or bodies are refused.
+const MAX_HEAD_SIZE: u64 = 64 * 1024;
+const MAX_BODY_SIZE: u64 = 64 * 1024 * 1024;
+
+/// A stalled client would hold up every request after it.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Received {
+    request_line: String,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+pub fn serve(args: &Cli, config: &Config) -> Result<i32> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, args.port.unwrap_or(DEFAULT_PORT)))
+        .context("Failed to start the server")?;
+    if args.quiet < 2 {
+        eprintln!(
+            "Listening on http://{}, stop with Ctrl-C",
+            listener.local_addr()?
+        );
+    }
+
+    // Stream to the terminal right away instead of paging
+    let buffer = Buffer::new(
+        false,
+        None,
+        atty::is(Stream::Stdout) || test_pretend_term(),
+        args.pretty,
+        args.color,
+        None,
+    )?;
+    let mut pretty = args.pretty.unwrap_or_else(|| buffer.guess_pretty());
+    if let Some(color) = args.color {
+        pretty = pretty.with_color(color);
+    }
+    let mut printer = build_printer(args, config, pretty, buffer)?;
+    let reply = reply(args);
+
+    for stream in listener.incoming() {
+        let result = stream
+            .map_err(anyhow::Error::from)
+            .and_then(|stream| handle(stream, &reply, args, &mut printer));
+        if let Err(err) = result {
+            if args.quiet < 2 {
+                eprintln!("{}: warning: {:#}", env!("CARGO_PKG_NAME"), err);
+            }
+        }
+    }
+    Ok(0)
+}
+
+/// The response sent to every request, without its body for HEAD requests.
+fn reply(args: &Cli) -> (Vec<u8>, Vec<u8>) {
+    let status = args.reply_status.unwrap_or(StatusCode::OK);
+    let body = args.reply_body.clone().unwrap_or_default();
+    let content_type = if serde_json::from_str::<serde_json::Value>(&body).is_ok() {
+        "application/json"
+    } else {
+        "text/plain; charset=utf-8"
+    };
+    let head = format!(
+        "HTTP/1.1 {}\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    (head.into_bytes(), body.into_bytes())
+}
+
+fn handle(
+    stream: TcpStream,
+    reply: &(Vec<u8>, Vec<u8>),
+    args: &Cli,
+    printer: &mut Printer,
+) -> Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let received = match receive(&mut reader, &mut writer)? {
+        Some(received) => received,
+        None => return Ok(()),
+    };
+
+    let (head, body) = reply;
+    writer.write_all(head)?;
+    if !received.request_line.starts_with("HEAD ") {
+        writer.write_all(body)?;
+    }
+    writer.flush()?;
+
+    if args.quiet == 0 {
+        if !args.body {
+            printer.print_raw_request_headers(&received.request_line, &received.headers)?;
+        }
+        if !args.headers && !received.body.is_empty() {
+            printer.print_raw_request_body(&received.headers, &received.body)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read a request. Returns `None` if the client hung up without sending one.
+fn receive(reader: &mut impl BufRead, writer: &mut impl Write) -> Result<Option<Received>> {
+    let mut head = reader.by_ref().take(MAX_HEAD_SIZE);
+    let mut request_line = String::new();
+    if head.read_line(&mut request_line)? == 0 {
+        return Ok(None);
+    }
+    let request_line = request_line.trim_end().to_string();
+    if request_line.split(' ').count() != 3 {
+        return Err(anyhow!("Malformed request line {:?}", request_line));
+    }
+
+    let mut headers = HeaderMap::new();
+    loop {
+        let mut line = String::new();
+        if head.read_line(&mut line)? == 0 {
+            return Err(anyhow!("The request headers were cut off"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let index = line
+            .find(':')
+            .ok_or_else(|| anyhow!("Malformed header {:?}", line))?;
+        headers.append(
+            HeaderName::from_bytes(line[..index].trim().as_bytes())?,
+            HeaderValue::from_str(line[index + 1..].trim())?,
+        );
+    }
+
+    let expects_continue = headers.get(EXPECT).map_or(false, |value| {
+        value.as_bytes().eq_ignore_ascii_case(b"100-continue")
+    });
+    if expects_continue {
+        writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        writer.flush()?;
+    }
+
+    let chunked = headers
+        .get(TRANSFER_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| {
+            value.to_ascii_lowercase().ends_with("chunked")
+        });
+    let body = if chunked {
+        read_chunked(reader)?
+    } else {
+        let length = match headers.get(CONTENT_LENGTH) {
+            Some(length) => length
+                .to_str()
+                .ok()
+                .and_then(|length| length.trim().parse().ok())
+                .ok_or_else(|| anyhow!("Invalid Content-Length {:?}", length))?,
+            None => 0,
+        };
+        if length > MAX_BODY_SIZE {
+            return Err(anyhow!("The request body is too large ({} bytes)", length));
+        }
+        let mut body = Vec::new();
+        reader.by_ref().take(length).read_to_end(&mut body)?;
+        if body.len() as u64 != length {
+            return Err(anyhow!("The request body was cut off"));
+        }
+        body
+    };
+
+    Ok(Some(Received {
+        request_line,
+        headers,
+        body,
+    }))
+}
+
+fn read_chunked(reader: &mut impl BufRead) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.by_ref().take(MAX_HEAD_SIZE).read_line(&mut line)?;
+        // Chunk extensions come after a semicolon
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = u64::from_str_radix(size, 16)
+            .map_err(|_| anyhow!("Invalid chunk size {:?}", line.trim_end()))?;
+        if size == 0 {
+            break;
+        }
+        if body.len() as u64 + size > MAX_BODY_SIZE {
+            return Err(anyhow!("The request body is too large"));
+        }
+        let start = body.len();
+        reader.by_ref().take(size).read_to_end(&mut body)?;
+        if ((body.len() - start) as u64) != size {
+            return Err(anyhow!("The request body was cut off"));
+        }
+        reader.read_line(&mut String::new())?;
+    }
+    // Skip trailers
+    loop {
+        let mut line = String::new();
+        if reader.by_ref().take(MAX_HEAD_SIZE).read_line(&mut line)? == 0
+            || line.trim_end().is_empty()
+        {
+            break;
+        }
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(request: &[u8]) -> Result<Option<Received>> {
+        receive(&mut &request[..], &mut std::io::sink())
+    }
+
+    #[test]
+    fn requests() {
+        let received =
+            parse(b"POST /hook?x=1 HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello")
+                .unwrap()
+                .unwrap();
+        assert_eq!(received.request_line, "POST /hook?x=1 HTTP/1.1");
+        assert_eq!(received.headers["host"], "a");
+        assert_eq!(received.body, b"hello");
+
+        let received = parse(
+            b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;ext\r\nhello\r\n1\r\n!\r\n0\r\nx-trailer: 1\r\n\r\n",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(received.body, b"hello!");
+
+        assert!(parse(b"").unwrap().is_none());
+        assert!(parse(b"GET /\r\n\r\n").is_err());
+        assert!(parse(b"GET / HTTP/1.1\r\nbad header\r\n\r\n").is_err());
+        assert!(parse(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhel").is_err());
+    }
+}
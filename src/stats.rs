@@ -0,0 +1,228 @@
+// --repeat: latency statistics for the requests from --input, and the same
+// numbers in Prometheus' text format for --metrics-file
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+
+/// The outcomes of a batch of requests.
+#[derive(Debug, Default)]
+pub struct Stats {
+    /// Seconds taken by each request that got a response
+    times: Vec<f64>,
+    statuses: BTreeMap<u16, usize>,
+    /// Requests that failed without a response, by kind of failure
+    errors: BTreeMap<String, usize>,
+}
+
+const HISTOGRAM_BUCKETS: usize = 10;
+const HISTOGRAM_WIDTH: usize = 40;
+
+impl Stats {
+    pub fn add_response(&mut self, status: u16, seconds: f64) {
+        self.times.push(seconds);
+        *self.statuses.entry(status).or_default() += 1;
+    }
+
+    pub fn add_error(&mut self, kind: &str) {
+        *self.errors.entry(kind.to_string()).or_default() += 1;
+    }
+
+    fn total(&self) -> usize {
+        self.times.len() + self.errors.values().sum::<usize>()
+    }
+
+    /// Requests that failed, or got a 4xx or 5xx response.
+    fn failures(&self) -> usize {
+        let error_statuses: usize = self.statuses.range(400..).map(|(_, count)| count).sum();
+        error_statuses + self.errors.values().sum::<usize>()
+    }
+
+    fn sorted_times(&self) -> Vec<f64> {
+        let mut times = self.times.clone();
+        times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        times
+    }
+
+    /// The nearest-rank percentile of `sorted`.
+    fn percentile(sorted: &[f64], percent: f64) -> f64 {
+        let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+        sorted[rank.max(1) - 1]
+    }
+
+    pub fn to_prometheus(&self) -> String {
+        let prefix = env!("CARGO_PKG_NAME");
+        let mut text = String::new();
+        let _ = writeln!(
+            text,
+            "# HELP {}_responses_total Responses received, by status code.\n\
+             # TYPE {}_responses_total counter",
+            prefix, prefix
+        );
+        for (status, count) in &self.statuses {
+            let _ = writeln!(
+                text,
+                "{}_responses_total{{status=\"{}\"}} {}",
+                prefix, status, count
+            );
+        }
+        let _ = writeln!(
+            text,
+            "# HELP {}_errors_total Requests that failed without a response, by kind.\n\
+             # TYPE {}_errors_total counter",
+            prefix, prefix
+        );
+        for (kind, count) in &self.errors {
+            let _ = writeln!(
+                text,
+                "{}_errors_total{{kind=\"{}\"}} {}",
+                prefix, kind, count
+            );
+        }
+        let _ = writeln!(
+            text,
+            "# HELP {}_request_duration_seconds How long requests took until the body was read.\n\
+             # TYPE {}_request_duration_seconds summary",
+            prefix, prefix
+        );
+        let sorted = self.sorted_times();
+        if !sorted.is_empty() {
+            for &quantile in &[0.5, 0.9, 0.99] {
+                let _ = writeln!(
+                    text,
+                    "{}_request_duration_seconds{{quantile=\"{}\"}} {}",
+                    prefix,
+                    quantile,
+                    Stats::percentile(&sorted, quantile * 100.0)
+                );
+            }
+        }
+        let _ = writeln!(
+            text,
+            "{}_request_duration_seconds_sum {}\n{}_request_duration_seconds_count {}",
+            prefix,
+            sorted.iter().sum::<f64>(),
+            prefix,
+            sorted.len()
+        );
+        text
+    }
+}
+
+fn millis(seconds: f64) -> String {
+    format!("{:.2} ms", seconds * 1000.0)
+}
+
+fn counts<K: fmt::Display>(counts: &BTreeMap<K, usize>) -> String {
+    counts
+        .iter()
+        .map(|(key, count)| format!("{} {}", key, count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total();
+        writeln!(
+            f,
+            "requests: {}, failed: {} ({:.1}%)",
+            total,
+            self.failures(),
+            self.failures() as f64 * 100.0 / total.max(1) as f64
+        )?;
+        if !self.statuses.is_empty() {
+            writeln!(f, "statuses: {}", counts(&self.statuses))?;
+        }
+        if !self.errors.is_empty() {
+            writeln!(f, "errors: {}", counts(&self.errors))?;
+        }
+        let sorted = self.sorted_times();
+        let (min, max) = match (sorted.first(), sorted.last()) {
+            (Some(&min), Some(&max)) => (min, max),
+            _ => return Ok(()),
+        };
+        write!(
+            f,
+            "latency: min {}, p50 {}, p90 {}, p99 {}, max {}",
+            millis(min),
+            millis(Stats::percentile(&sorted, 50.0)),
+            millis(Stats::percentile(&sorted, 90.0)),
+            millis(Stats::percentile(&sorted, 99.0)),
+            millis(max)
+        )?;
+
+        // Equal buckets from the fastest to the slowest request
+        let width = (max - min) / HISTOGRAM_BUCKETS as f64;
+        let mut buckets = [0; HISTOGRAM_BUCKETS];
+        for &time in &sorted {
+            let index = if width > 0.0 {
+                ((time - min) / width) as usize
+            } else {
+                0
+            };
+            buckets[index.min(HISTOGRAM_BUCKETS - 1)] += 1;
+        }
+        let largest = *buckets.iter().max().unwrap();
+        for (index, &count) in buckets.iter().enumerate() {
+            if width == 0.0 && index > 0 {
+                break;
+            }
+            let bar = (count * HISTOGRAM_WIDTH + largest - 1) / largest;
+            write!(
+                f,
+                "\n{:>10} {:<width$} {}",
+                millis(min + width * (index + 1) as f64),
+                "#".repeat(bar),
+                count,
+                width = HISTOGRAM_WIDTH
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats() -> Stats {
+        let mut stats = Stats::default();
+        for millis in 1..=100 {
+            let status = if millis % 25 == 0 { 503 } else { 200 };
+            stats.add_response(status, millis as f64 / 1000.0);
+        }
+        stats.add_error("timeout");
+        stats
+    }
+
+    #[test]
+    fn summary() {
+        let summary = stats().to_string();
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(lines[0], "requests: 101, failed: 5 (5.0%)");
+        assert_eq!(lines[1], "statuses: 200 96, 503 4");
+        assert_eq!(lines[2], "errors: timeout 1");
+        assert_eq!(
+            lines[3],
+            "latency: min 1.00 ms, p50 50.00 ms, p90 90.00 ms, p99 99.00 ms, max 100.00 ms"
+        );
+        assert_eq!(lines.len(), 4 + HISTOGRAM_BUCKETS);
+        assert!(lines[4].starts_with("  10.90 ms ####"));
+        assert!(lines[4].ends_with(" 10"));
+
+        let mut stats = Stats::default();
+        stats.add_response(200, 0.002);
+        assert!(stats
+            .to_string()
+            .ends_with("\n   2.00 ms ######################################## 1"));
+    }
+
+    #[test]
+    fn prometheus() {
+        let text = stats().to_prometheus();
+        assert!(text.contains("\nxh_responses_total{status=\"503\"} 4\n"));
+        assert!(text.contains("\nxh_errors_total{kind=\"timeout\"} 1\n"));
+        assert!(text.contains("\nxh_request_duration_seconds{quantile=\"0.99\"} 0.099\n"));
+        assert!(text.ends_with("xh_request_duration_seconds_count 100\n"));
+    }
+}
@@ -0,0 +1,147 @@
+// Lays out CSV as an aligned table, for text/csv responses and --table
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+use termcolor::{ColorSpec, WriteColor};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::buffer::Buffer;
+
+const SEPARATOR: &str = "  ";
+
+/// Parse CSV into rows of cells, or return `None` if it isn't valid CSV.
+///
+/// Rows don't have to be the same length.
+pub fn parse(text: &str) -> Option<Vec<Vec<String>>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let rows = reader
+        .records()
+        .map(|record| Some(record.ok()?.iter().map(str::to_string).collect()))
+        .collect::<Option<Vec<Vec<String>>>>()?;
+    if rows.is_empty() {
+        None
+    } else {
+        Some(rows)
+    }
+}
+
+/// Print rows with their columns aligned, treating the first row as the header.
+///
+/// Cells wider than `max_width` are cut short. Columns that only hold numbers
+/// are aligned to the right.
+pub fn print(
+    rows: &[Vec<String>],
+    out: &mut Buffer,
+    color: bool,
+    max_width: Option<usize>,
+) -> io::Result<()> {
+    let rows: Vec<Vec<Cow<str>>> = rows
+        .iter()
+        .map(|row| row.iter().map(|cell| fit(cell, max_width)).collect())
+        .collect();
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| {
+            rows.iter()
+                .filter_map(|row| row.get(column))
+                .map(|cell| cell.width())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let numeric: Vec<bool> = (0..columns)
+        .map(|column| {
+            let mut cells = rows[1..].iter().filter_map(|row| row.get(column));
+            cells.clone().any(|cell| !cell.is_empty())
+                && cells.all(|cell| cell.is_empty() || cell.trim().parse::<f64>().is_ok())
+        })
+        .collect();
+
+    for (i, row) in rows.iter().enumerate() {
+        let mut line = String::new();
+        for column in 0..columns {
+            let cell = row.get(column).map_or("", |cell| cell);
+            let padding = " ".repeat(widths[column] - cell.width());
+            if column > 0 {
+                line.push_str(SEPARATOR);
+            }
+            if numeric[column] && i > 0 {
+                line.push_str(&padding);
+                line.push_str(cell);
+            } else {
+                line.push_str(cell);
+                line.push_str(&padding);
+            }
+        }
+        let line = line.trim_end();
+        if i == 0 && color {
+            out.set_color(ColorSpec::new().set_bold(true))?;
+            out.write_all(line.as_bytes())?;
+            out.reset()?;
+        } else {
+            out.write_all(line.as_bytes())?;
+        }
+        out.write_all(b"\n")?;
+        if i == 0 && rows.len() > 1 {
+            let rule: Vec<String> = widths.iter().map(|&width| "─".repeat(width)).collect();
+            writeln!(out, "{}", rule.join(SEPARATOR))?;
+        }
+    }
+    Ok(())
+}
+
+/// Put a cell on a single line and cut it down to `max_width` columns.
+fn fit(cell: &str, max_width: Option<usize>) -> Cow<'_, str> {
+    let cell = if cell.contains(['\n', '\r']) {
+        Cow::Owned(cell.replace("\r\n", " ").replace(['\n', '\r'], " "))
+    } else {
+        Cow::Borrowed(cell)
+    };
+    match max_width {
+        Some(max_width) if cell.width() > max_width => {
+            let mut fitted = String::new();
+            let mut width = 0;
+            for c in cell.chars() {
+                width += c.width().unwrap_or(0);
+                // Leave room for the ellipsis
+                if width >= max_width {
+                    break;
+                }
+                fitted.push(c);
+            }
+            fitted.push('…');
+            Cow::Owned(fitted)
+        }
+        _ => cell,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fitting() {
+        assert_eq!(fit("hello", None), "hello");
+        assert_eq!(fit("hello", Some(5)), "hello");
+        assert_eq!(fit("hello", Some(4)), "hel…");
+        assert_eq!(fit("日本語", Some(5)), "日本…");
+        assert_eq!(fit("a\r\nb\nc", None), "a b c");
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(
+            parse("a,\"b,c\"\n1\n"),
+            Some(vec![
+                vec!["a".to_string(), "b,c".to_string()],
+                vec!["1".to_string()]
+            ])
+        );
+        assert_eq!(parse(""), None);
+    }
+}
@@ -0,0 +1,266 @@
+// --template, a format string with variables that's printed after each
+// response, like curl's --write-out.
+
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use reqwest::blocking::Response;
+use reqwest::header::{HeaderMap, HeaderName};
+use reqwest::{Method, StatusCode, Url, Version};
+use structopt::clap::{Error, ErrorKind, Result};
+
+const VARIABLES: &[&str] = &[
+    "status",
+    "reason",
+    "http_version",
+    "method",
+    "url",
+    "remote_address",
+    "time_namelookup",
+    "time_starttransfer",
+    "time_total",
+    "size_download",
+    "header.NAME",
+];
+
+#[derive(Debug, PartialEq, Clone)]
+enum Part {
+    Text(String),
+    Variable(Variable),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Variable {
+    Status,
+    Reason,
+    HttpVersion,
+    Method,
+    Url,
+    RemoteAddress,
+    TimeNamelookup,
+    TimeStarttransfer,
+    TimeTotal,
+    SizeDownload,
+    Header(HeaderName),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Template(Vec<Part>);
+
+/// What a template can show about a request and its response.
+pub struct Metadata {
+    pub method: Method,
+    pub status: StatusCode,
+    pub version: Version,
+    pub url: Url,
+    pub remote_addr: Option<SocketAddr>,
+    pub headers: HeaderMap,
+    /// The time the DNS lookup took, if there was one
+    pub time_namelookup: Duration,
+    /// The time until the response headers arrived
+    pub time_starttransfer: Duration,
+    pub time_total: Duration,
+    /// The bytes of the body that were read
+    pub size_download: u64,
+}
+
+impl Metadata {
+    pub fn new(method: Method, response: &Response, time_starttransfer: Duration) -> Metadata {
+        Metadata {
+            method,
+            status: response.status(),
+            version: response.version(),
+            url: response.url().clone(),
+            remote_addr: response.remote_addr(),
+            headers: response.headers().clone(),
+            time_namelookup: Duration::default(),
+            time_starttransfer,
+            time_total: time_starttransfer,
+            size_download: 0,
+        }
+    }
+}
+
+impl Template {
+    pub fn render(&self, metadata: &Metadata) -> String {
+        let mut output = String::new();
+        for part in &self.0 {
+            match part {
+                Part::Text(text) => output.push_str(text),
+                Part::Variable(variable) => output.push_str(&variable.render(metadata)),
+            }
+        }
+        output
+    }
+}
+
+impl Variable {
+    fn render(&self, metadata: &Metadata) -> String {
+        match self {
+            Variable::Status => metadata.status.as_str().to_string(),
+            Variable::Reason => metadata
+                .status
+                .canonical_reason()
+                .unwrap_or_default()
+                .to_string(),
+            Variable::HttpVersion => format!("{:?}", metadata.version),
+            Variable::Method => metadata.method.to_string(),
+            Variable::Url => metadata.url.to_string(),
+            Variable::RemoteAddress => metadata
+                .remote_addr
+                .map(|addr| addr.to_string())
+                .unwrap_or_default(),
+            Variable::TimeNamelookup => format!("{:.6}", metadata.time_namelookup.as_secs_f64()),
+            Variable::TimeStarttransfer => {
+                format!("{:.6}", metadata.time_starttransfer.as_secs_f64())
+            }
+            Variable::TimeTotal => format!("{:.6}", metadata.time_total.as_secs_f64()),
+            Variable::SizeDownload => metadata.size_download.to_string(),
+            Variable::Header(name) => {
+                let values: Vec<_> = metadata
+                    .headers
+                    .get_all(name)
+                    .iter()
+                    .map(|value| String::from_utf8_lossy(value.as_bytes()))
+                    .collect();
+                values.join(", ")
+            }
+        }
+    }
+}
+
+impl FromStr for Variable {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Variable> {
+        let variable = match name {
+            "status" => Variable::Status,
+            "reason" => Variable::Reason,
+            "http_version" => Variable::HttpVersion,
+            "method" => Variable::Method,
+            "url" => Variable::Url,
+            "remote_address" => Variable::RemoteAddress,
+            "time_namelookup" => Variable::TimeNamelookup,
+            "time_starttransfer" => Variable::TimeStarttransfer,
+            "time_total" => Variable::TimeTotal,
+            "size_download" => Variable::SizeDownload,
+            _ if name.starts_with("header.") => {
+                let header = &name["header.".len()..];
+                Variable::Header(HeaderName::from_bytes(header.as_bytes()).map_err(|_| {
+                    Error::with_description(
+                        &format!("Invalid header name in --template: {:?}", header),
+                        ErrorKind::InvalidValue,
+                    )
+                })?)
+            }
+            _ => {
+                return Err(Error::with_description(
+                    &format!(
+                        "Unknown variable {{{}}} in --template, expected one of: {}",
+                        name,
+                        VARIABLES.join(", ")
+                    ),
+                    ErrorKind::InvalidValue,
+                ))
+            }
+        };
+        Ok(variable)
+    }
+}
+
+impl FromStr for Template {
+    type Err = Error;
+
+    /// Parse a template like `{status} {header.content-type}\n`. Braces are
+    /// escaped by doubling them, and `\n`, `\t` and `\\` are understood
+    /// because shells don't make that easy.
+    fn from_str(template: &str) -> Result<Template> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or_else(|| {
+                        Error::with_description(
+                            "Unclosed { in --template, write {{ for a literal brace",
+                            ErrorKind::InvalidValue,
+                        )
+                    })?;
+                    let variable = rest[..end].trim().parse()?;
+                    chars = rest[end + 1..].chars();
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Variable(variable));
+                }
+                '}' => {
+                    return Err(Error::with_description(
+                        "Unmatched } in --template, write }} for a literal brace",
+                        ErrorKind::InvalidValue,
+                    ))
+                }
+                '\\' => {
+                    let escaped = match chars.as_str().chars().next() {
+                        Some('n') => Some('\n'),
+                        Some('t') => Some('\t'),
+                        Some('\\') => Some('\\'),
+                        _ => None,
+                    };
+                    match escaped {
+                        Some(escaped) => {
+                            chars.next();
+                            text.push(escaped);
+                        }
+                        None => text.push('\\'),
+                    }
+                }
+                _ => text.push(ch),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template(parts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing() {
+        let template: Template = r"{status} {{x}} {header.Content-Type}\n".parse().unwrap();
+        assert_eq!(
+            template,
+            Template(vec![
+                Part::Variable(Variable::Status),
+                Part::Text(" {x} ".to_string()),
+                Part::Variable(Variable::Header(HeaderName::from_static("content-type"))),
+                Part::Text("\n".to_string()),
+            ])
+        );
+        assert_eq!(
+            "a\\tb\\\\n\\x".parse::<Template>().unwrap(),
+            Template(vec![Part::Text("a\tb\\n\\x".to_string())])
+        );
+        assert!("{status".parse::<Template>().is_err());
+        assert!("status}".parse::<Template>().is_err());
+        assert!("{header.a b}".parse::<Template>().is_err());
+        let error = "{size}".parse::<Template>().unwrap_err();
+        assert!(error
+            .message
+            .contains("Unknown variable {size} in --template, expected one of: status,"));
+    }
+}
@@ -0,0 +1,209 @@
+// --meta: how long the parts of an exchange took
+//
+// reqwest doesn't report when a connection was established or when its TLS
+// handshake finished, so those are part of the wait for the first byte. The
+// DNS lookup is timed by doing it here and handing the addresses to reqwest,
+// which also lets --verbose show them.
+
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use reqwest::Url;
+use serde_json::{json, Value};
+
+/// The addresses of a host, and how long it took to find them.
+pub struct Lookup {
+    pub host: String,
+    pub addrs: Vec<SocketAddr>,
+    pub elapsed: Duration,
+}
+
+/// A host couldn't be looked up.
+///
+/// hyper's own resolver errors are private, so a failed connection is looked
+/// into here to tell this apart from other connection errors.
+#[derive(Debug)]
+pub struct DnsError {
+    pub host: String,
+    pub source: io::Error,
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to look up {}", self.host)
+    }
+}
+
+impl std::error::Error for DnsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Look up the host of `url` with the system resolver, like reqwest would.
+///
+/// There's nothing to look up for an IP address, so that gives `Ok(None)`.
+pub fn lookup(url: &Url) -> Result<Option<Lookup>, DnsError> {
+    let (host, port) = match (url.domain(), url.port_or_known_default()) {
+        (Some(host), Some(port)) => (host.to_string(), port),
+        _ => return Ok(None),
+    };
+    let start = Instant::now();
+    let addrs: Vec<SocketAddr> = match (host.as_str(), port).to_socket_addrs() {
+        Ok(addrs) => addrs.collect(),
+        Err(source) => return Err(DnsError { host, source }),
+    };
+    if addrs.is_empty() {
+        return Err(DnsError {
+            host,
+            source: io::Error::new(io::ErrorKind::NotFound, "no addresses found"),
+        });
+    }
+    Ok(Some(Lookup {
+        host,
+        addrs,
+        elapsed: start.elapsed(),
+    }))
+}
+
+impl Lookup {
+    /// Try to connect to each address, to find out why they failed when
+    /// reqwest only reports the last error. It all takes at most `limit`, and
+    /// the addresses there's no time left for are left out.
+    pub fn probe(&self, limit: Duration) -> Vec<(SocketAddr, io::Result<()>)> {
+        let start = Instant::now();
+        let mut results = Vec::new();
+        for addr in &self.addrs {
+            let left = limit.saturating_sub(start.elapsed());
+            if left == Duration::ZERO {
+                break;
+            }
+            results.push((*addr, TcpStream::connect_timeout(addr, left).map(|_| ())));
+        }
+        results
+    }
+}
+
+impl fmt::Display for Lookup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let addrs: Vec<String> = self
+            .addrs
+            .iter()
+            .map(|addr| addr.ip().to_string())
+            .collect();
+        write!(
+            f,
+            "dns: {} resolved to {} in {:.2} ms",
+            self.host,
+            addrs.join(", "),
+            self.elapsed.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+/// The timings of a request and its response.
+#[derive(Debug, Default)]
+pub struct Timings {
+    pub lookup: Option<Duration>,
+    /// From sending the request until the response headers arrived
+    pub first_byte: Duration,
+    pub download: Duration,
+}
+
+impl Timings {
+    pub fn total(&self) -> Duration {
+        self.lookup.unwrap_or_default() + self.first_byte + self.download
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "type": "meta",
+            "dns_lookup_seconds": self.lookup.map(|lookup| lookup.as_secs_f64()),
+            "first_byte_seconds": self.first_byte.as_secs_f64(),
+            "download_seconds": self.download.as_secs_f64(),
+            "total_seconds": self.total().as_secs_f64(),
+        })
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let millis = |duration: Duration| format!("{:.2} ms", duration.as_secs_f64() * 1000.0);
+        if let Some(lookup) = self.lookup {
+            writeln!(f, "dns-lookup: {}", millis(lookup))?;
+        }
+        writeln!(f, "time-to-first-byte: {}", millis(self.first_byte))?;
+        writeln!(f, "body-download: {}", millis(self.download))?;
+        write!(f, "total: {}", millis(self.total()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let timings = Timings {
+            lookup: Some(Duration::from_micros(1500)),
+            first_byte: Duration::from_millis(40),
+            download: Duration::from_millis(8),
+        };
+        assert_eq!(
+            timings.to_string(),
+            "dns-lookup: 1.50 ms\ntime-to-first-byte: 40.00 ms\nbody-download: 8.00 ms\n\
+             total: 49.50 ms"
+        );
+        assert_eq!(timings.to_json()["total_seconds"], json!(0.0495));
+
+        let timings = Timings::default();
+        assert!(!timings.to_string().contains("dns-lookup"));
+        assert_eq!(timings.to_json()["dns_lookup_seconds"], Value::Null);
+    }
+
+    #[test]
+    fn lookup_display() {
+        let lookup = Lookup {
+            host: "example.com".to_string(),
+            addrs: vec![
+                "[2606:2800:220:1::1946]:443".parse().unwrap(),
+                "93.184.216.34:443".parse().unwrap(),
+            ],
+            elapsed: Duration::from_micros(2500),
+        };
+        assert_eq!(
+            lookup.to_string(),
+            "dns: example.com resolved to 2606:2800:220:1::1946, 93.184.216.34 in 2.50 ms"
+        );
+    }
+
+    #[test]
+    fn probe_time_is_capped() {
+        let lookup = Lookup {
+            host: "localhost".to_string(),
+            addrs: vec!["127.0.0.1:9".parse().unwrap()],
+            elapsed: Duration::default(),
+        };
+        assert!(lookup.probe(Duration::ZERO).is_empty());
+    }
+
+    #[test]
+    fn addresses_need_no_lookup() {
+        assert!(lookup(&Url::parse("http://127.0.0.1:8000").unwrap())
+            .unwrap()
+            .is_none());
+        assert!(lookup(&Url::parse("http://[::1]:8000").unwrap())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn failed_lookup() {
+        let err = lookup(&Url::parse("http://nonexistent.invalid").unwrap())
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "Failed to look up nonexistent.invalid");
+    }
+}
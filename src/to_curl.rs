@@ -4,9 +4,12 @@ use anyhow::{anyhow, Result};
 use reqwest::Method;
 
 use crate::{
-    cli::{Cli, Verify},
+    cli::{AuthType, ByteSize, Cli, Deadline, EncodeSet, Proxy, Verify},
+    redact::{is_secret, redact_value, MASK},
+    request::replace_with_json_body,
     request_items::{
-        Body, RequestItem, RequestItems, FORM_CONTENT_TYPE, JSON_ACCEPT, JSON_CONTENT_TYPE,
+        encode_form, Body, RequestItem, RequestItems, FORM_CONTENT_TYPE, JSON_ACCEPT,
+        JSON_CONTENT_TYPE,
     },
     url::construct_url,
 };
@@ -92,20 +95,61 @@ impl std::fmt::Display for Command {
 }
 
 pub fn translate(args: Cli) -> Result<Command> {
-    let request_items = RequestItems::new(args.request_items);
-    let query = request_items.query();
+    let mut request_items = RequestItems::new(args.request_items.clone());
+    request_items.expand(&args)?;
+    let query = request_items.query()?;
+    let query_to_unset = request_items.query_to_unset();
     let (headers, headers_to_unset) = request_items.headers()?;
-    let url = construct_url(&args.url, args.default_scheme.as_deref(), query)?;
+    let url = construct_url(
+        &args.url,
+        args.default_scheme.as_deref(),
+        &query,
+        &query_to_unset,
+        args.encode_set,
+    )?;
 
     let mut cmd = Command::new(args.curl_long);
+    let mask = !args.show_secrets;
+    let mut masked = false;
 
     let ignored = &[
-        (args.offline, "--offline"),          // No equivalent
-        (args.body, "-b/--body"),             // Already the default
-        (args.print.is_some(), "-p/--print"), // No straightforward equivalent
-        (args.quiet, "-q/--quiet"),           // No equivalent, -s/--silent suppresses other stuff
-        (args.pretty.is_some(), "--pretty"),  // No equivalent
+        (args.offline, "--offline"),                           // No equivalent
+        (args.body, "-b/--body"),                              // Already the default
+        (args.print.is_some(), "-p/--print"),                  // No straightforward equivalent
+        (args.quiet > 0, "-q/--quiet"), // No equivalent, -s/--silent suppresses other stuff
+        (args.pretty.is_some(), "--pretty"), // No equivalent
         (args.style.is_some(), "-s/--style"), // No equivalent
+        (args.color.is_some(), "--color"), // No equivalent
+        (!args.format_options.is_empty(), "--format-options"), // No equivalent
+        (args.pager.is_some(), "--pager"), // No equivalent
+        (args.progress_interval.is_some(), "--progress-interval"), // No equivalent
+        (args.json_lines, "--json-lines"), // No equivalent
+        (args.force_text, "--force-text"), // No equivalent
+        (args.force_binary, "--force-binary"), // No equivalent
+        (args.response_charset.is_some(), "--response-charset"), // No equivalent
+        (args.render_markdown, "--render-markdown"), // No equivalent
+        (args.table, "--table"),        // No equivalent
+        (args.column_width.is_some(), "--column-width"), // No equivalent
+        (args.max_lines.is_some(), "--max-lines"), // No equivalent
+        (args.wrap.is_some(), "--wrap"), // No equivalent
+        (args.line_numbers, "--line-numbers"), // No equivalent
+        (args.password_stdin, "--password-stdin"), // curl prompts instead
+        (args.stdin_timeout.is_some(), "--stdin-timeout"), // No equivalent
+        (args.system_proxy, "--system-proxy"), // No equivalent
+        (args.template.is_some(), "--template"), // -w/--write-out has other variables
+        (args.traceparent.is_some(), "--traceparent"), // A fixed header would reuse the trace ID
+        (args.otel_endpoint.is_some(), "--otel-endpoint"), // No equivalent
+        (args.meta, "--meta"),          // -w/--write-out can show the timings
+        (args.trace.is_some(), "--trace"), // curl's needs a file and shows more
+        (args.audit_log.is_some(), "--audit-log"), // No equivalent
+        (args.paginate.is_some(), "--paginate"), // No equivalent
+        (args.retry_max_wait.is_some(), "--retry-max-wait"), // --retry-max-time limits the total
+        (args.cache.is_some(), "--cache"), // No equivalent
+        (!args.mirror.is_empty(), "--mirror"), // No equivalent
+        (args.peek, "--peek"),          // No equivalent
+        (args.no_clobber, "--no-clobber"), // curl's numbers the file instead
+        (args.number_format.is_some(), "--number-format"), // No equivalent
+        (args.json_output, "--json-output"), // No equivalent
     ];
 
     for (present, flag) in ignored {
@@ -118,6 +162,13 @@ pub fn translate(args: Cli) -> Result<Command> {
     // - .ignore_stdin: assumed by default
     //   (to send stdin, --data-binary @- -H 'Content-Type: application/octet-stream')
     // - .curl and .curl_long: you are here
+    // - .overwrite: curl always overwrites
+    // - .var and .var_file: the placeholders are already filled in
+    // - .urls_from: a command is printed for each URL
+    // - .request_file: the request it describes was merged into the others
+    // - .exit_code_map: curl has its own exit codes
+    // - .reject_dot_segments: paths with . or .. segments are refused before we get here
+    // - .strict_url: the arguments were already checked
 
     // Output options
     if args.verbose {
@@ -142,6 +193,20 @@ pub fn translate(args: Cli) -> Result<Command> {
         cmd.push("--max-redirects");
         cmd.push(num.to_string());
     }
+    if let Some(Deadline(limit)) = args.deadline {
+        cmd.flag("-m", "--max-time");
+        cmd.push(limit.as_secs_f64().to_string());
+    }
+    if args.respect_retry_after {
+        // Also retries timeouts and other transient errors, and only waits for
+        // Retry-After on 429 and 503 too
+        cmd.push("--retry");
+        cmd.push("3");
+    }
+    if let Some(ByteSize(size)) = args.max_body_size {
+        cmd.push("--max-filesize");
+        cmd.push(size.to_string());
+    }
     if let Some(filename) = args.output {
         let filename = filename.to_str().ok_or_else(|| anyhow!("Invalid UTF-8"))?;
         cmd.flag("-o", "--output");
@@ -149,10 +214,26 @@ pub fn translate(args: Cli) -> Result<Command> {
     } else if args.download {
         cmd.flag("-O", "--remote-name");
     }
+    if args.download && !args.ignore_last_modified {
+        cmd.flag("-R", "--remote-time");
+    }
+    if let Some(filename) = args.output_headers {
+        let filename = filename.to_str().ok_or_else(|| anyhow!("Invalid UTF-8"))?;
+        if filename.contains('%') {
+            cmd.warn("Ignored --output-headers, curl can't fill in its placeholders".to_string());
+        } else {
+            cmd.flag("-D", "--dump-header");
+            cmd.push(filename);
+        }
+    }
     if args.resume {
         cmd.flag("-C", "--continue-at");
         cmd.push("-"); // Tell curl to guess, like we do
     }
+    if let Some(range) = args.range {
+        cmd.flag("-r", "--range");
+        cmd.push(range.to_string());
+    }
     match args.verify.unwrap_or(Verify::Yes) {
         Verify::CustomCaBundle(filename) => {
             cmd.push("--cacert");
@@ -166,6 +247,11 @@ pub fn translate(args: Cli) -> Result<Command> {
         Verify::Yes => {}
     }
     if let Some(cert) = args.cert {
+        let extension = cert.extension().and_then(|ext| ext.to_str());
+        if let Some("p12") | Some("pfx") = extension.map(str::to_ascii_lowercase).as_deref() {
+            cmd.push("--cert-type");
+            cmd.push("P12");
+        }
         cmd.flag("-E", "--cert");
         // TODO: as bytes?
         cmd.push(cert.to_string_lossy());
@@ -174,19 +260,45 @@ pub fn translate(args: Cli) -> Result<Command> {
         cmd.push("--key");
         cmd.push(keyfile.to_string_lossy());
     }
+    if let Some(passphrase) = args.cert_key_pass {
+        cmd.push("--pass");
+        if mask {
+            cmd.push(MASK);
+            masked = true;
+        } else {
+            cmd.push(passphrase);
+        }
+    }
     for proxy in args.proxy {
+        let mut url = match &proxy {
+            Proxy::All(url) | Proxy::Http(url) | Proxy::Https(url) => url.clone(),
+        };
+        if mask && url.password().is_some() {
+            let _ = url.set_password(Some(MASK));
+            masked = true;
+        }
         match proxy {
-            crate::cli::Proxy::All(proxy) => {
+            Proxy::All(_) => {
                 cmd.flag("-x", "--proxy");
-                cmd.push(proxy.into_string());
+                cmd.push(url.to_string());
             }
-            crate::cli::Proxy::Http(proxy) => {
+            Proxy::Http(_) => {
                 // These don't seem to have corresponding flags
-                cmd.env("http_proxy", proxy.into_string());
+                cmd.env("http_proxy", url.to_string());
             }
-            crate::cli::Proxy::Https(proxy) => {
-                cmd.env("https_proxy", proxy.into_string());
+            Proxy::Https(_) => {
+                cmd.env("https_proxy", url.to_string());
+            }
+        }
+    }
+    if let Some(proxy_auth) = args.proxy_auth {
+        cmd.flag("-U", "--proxy-user");
+        match mask_password(&proxy_auth) {
+            Some(proxy_auth) if mask => {
+                cmd.push(proxy_auth);
+                masked = true;
             }
+            _ => cmd.push(proxy_auth),
         }
     }
 
@@ -230,6 +342,10 @@ pub fn translate(args: Cli) -> Result<Command> {
         cmd.flag("-H", "--header");
         if value.is_empty() {
             cmd.push(format!("{};", header));
+        } else if mask && is_secret(header, &args.redact) {
+            let value = redact_value(header, value);
+            cmd.push(format!("{}: {}", header, value.to_str()?));
+            masked = true;
         } else {
             cmd.push(format!("{}: {}", header, value.to_str()?));
         }
@@ -238,66 +354,120 @@ pub fn translate(args: Cli) -> Result<Command> {
         cmd.flag("-H", "--header");
         cmd.push(format!("{}:", header));
     }
-    if let Some(auth) = args.auth {
+    if args.auth_type == Some(AuthType::negotiate) {
+        cmd.push("--negotiate");
+        // curl needs a user to enable authentication, even a blank one
+        cmd.flag("-u", "--user");
+        cmd.push(":");
+    } else if let Some(auth) = args.auth {
+        if args.auth_type == Some(AuthType::digest) {
+            cmd.push("--digest");
+        } else if args.auth_type.is_none() && args.auth_challenge {
+            cmd.push("--anyauth");
+        }
         // curl implements this flag the same way, including password prompt
         cmd.flag("-u", "--user");
-        cmd.push(auth);
+        match mask_password(&auth) {
+            Some(auth) if mask => {
+                cmd.push(auth);
+                masked = true;
+            }
+            _ => cmd.push(auth),
+        }
     }
     if let Some(token) = args.bearer {
         cmd.push("--oauth2-bearer");
-        cmd.push(token);
+        if mask {
+            cmd.push(MASK);
+            masked = true;
+        } else {
+            cmd.push(token);
+        }
     }
 
     if request_items.is_multipart(args.request_type) {
         // We can't use .body() here because we can't look inside the multipart
         // form after construction and we don't want to actually read the files
         for item in request_items.0 {
-            match item {
+            let (key, mut field) = match item {
                 RequestItem::JsonField(..) | RequestItem::JsonFieldFromFile(..) => {
                     return Err(anyhow!("JSON values are not supported in multipart fields"));
                 }
                 RequestItem::DataField(key, value) => {
-                    cmd.flag("-F", "--form");
-                    cmd.push(format!("{}={}", key, value));
+                    let field = format!("{}={}", key, value);
+                    (key, field)
                 }
                 RequestItem::DataFieldFromFile(key, value) => {
-                    cmd.flag("-F", "--form");
-                    cmd.push(format!("{}=<{}", key, value));
+                    let field = format!("{}=<{}", key, value);
+                    (key, field)
                 }
                 RequestItem::FormFile {
                     key,
                     file_name,
                     file_type,
                 } => {
-                    cmd.flag("-F", "--form");
-                    if let Some(file_type) = file_type {
-                        cmd.push(format!("{}=@{};type={}", key, file_name, file_type));
+                    let field = if let Some(file_type) = file_type {
+                        format!("{}=@{};type={}", key, file_name, file_type)
                     } else {
-                        cmd.push(format!("{}=@{}", key, file_name));
-                    }
+                        format!("{}=@{}", key, file_name)
+                    };
+                    (key, field)
                 }
-                RequestItem::HttpHeader(..) => {}
-                RequestItem::HttpHeaderToUnset(..) => {}
-                RequestItem::UrlParam(..) => {}
+                RequestItem::HttpHeader(..) => continue,
+                RequestItem::HttpHeaderToUnset(..) => continue,
+                RequestItem::Cookie(..) => continue,
+                RequestItem::UrlParam(..) => continue,
+                RequestItem::UrlParamToUnset(..) => continue,
+                RequestItem::UrlParamFromFile(..) => continue,
+            };
+            for header in args.part_header.iter().filter(|header| header.field == key) {
+                // New in curl 7.51.0 (November 2 2016)
+                field.push_str(&format!(
+                    ";headers=\"{}: {}\"",
+                    header.name,
+                    String::from_utf8_lossy(header.value.as_bytes())
+                ));
             }
+            cmd.flag("-F", "--form");
+            cmd.push(field);
         }
     } else {
-        match request_items.body(args.request_type)? {
+        let mut body = request_items.body(args.request_type, &args.part_header)?;
+        if let Some(json_body) = &args.json_body {
+            body = replace_with_json_body(body, json_body)?;
+        }
+        match body {
             Body::Form(items) => {
                 if items.is_empty() {
                     // Force the header
                     cmd.header("content-type", FORM_CONTENT_TYPE);
                 }
                 for (key, value) in items {
-                    // More faithful than -F, but doesn't have a short version
-                    // New in curl 7.18.0 (January 28 2008), *probably* old enough
-                    // Otherwise passing --multipart helps
-                    cmd.push("--data-urlencode");
-                    // Encoding this is tricky: --data-urlencode expects name
-                    // to be encoded but not value and doesn't take strings
-                    let mut encoded = serde_urlencoded::to_string(&[(key, "")])?;
-                    encoded.push_str(&value);
-                    cmd.push(encoded);
+                    match String::from_utf8(value) {
+                        Ok(value) if args.encode_set == EncodeSet::strict => {
+                            // More faithful than -F, but doesn't have a short version
+                            // New in curl 7.18.0 (January 28 2008), *probably* old enough
+                            // Otherwise passing --multipart helps
+                            cmd.push("--data-urlencode");
+                            // Encoding this is tricky: --data-urlencode expects name
+                            // to be encoded but not value and doesn't take strings
+                            let mut encoded = serde_urlencoded::to_string(&[(key, "")])?;
+                            encoded.push_str(&value);
+                            cmd.push(encoded);
+                        }
+                        value => {
+                            // Binary data can't be passed as an argument, and
+                            // curl only knows the strict encoding, so encode
+                            // it ourselves
+                            let value =
+                                value.map_or_else(|err| err.into_bytes(), String::into_bytes);
+                            cmd.flag("-d", "--data");
+                            cmd.push(String::from_utf8_lossy(&encode_form(
+                                &[(key, value)],
+                                args.encode_set,
+                            )));
+                        }
+                    }
                 }
             }
             Body::Json(map) if !map.is_empty() => {
@@ -313,8 +483,14 @@ pub fn translate(args: Cli) -> Result<Command> {
                 cmd.header("accept", JSON_ACCEPT);
             }
             Body::Json(..) => {}
-            Body::Multipart { .. } => unreachable!(),
-            Body::Raw(..) => unreachable!(),
+            Body::Multipart(..) | Body::EncodedMultipart { .. } => unreachable!(),
+            // We don't read stdin, so this can only come from --json-body
+            Body::Raw(data) => {
+                cmd.header("content-type", JSON_CONTENT_TYPE);
+                cmd.header("accept", JSON_ACCEPT);
+                cmd.flag("-d", "--data");
+                cmd.push(String::from_utf8(data)?);
+            }
             Body::File {
                 file_name,
                 file_type,
@@ -330,9 +506,20 @@ pub fn translate(args: Cli) -> Result<Command> {
         }
     }
 
+    if masked {
+        cmd.warn("Credentials are masked, use --show-secrets to include them".to_string());
+    }
+
     Ok(cmd)
 }
 
+/// Mask the password in `USER:PASS`, keeping the user name to tell commands
+/// apart. Without a password curl prompts for one, so there's nothing to mask.
+fn mask_password(auth: &str) -> Option<String> {
+    let index = auth.find(':')?;
+    Some(format!("{}:{}", &auth[..index], MASK))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -366,6 +553,16 @@ mod tests {
                 "curl 'https://httpbin.org/get?x=3'",
                 "curl https://httpbin.org/get?x=3",
             ),
+            (
+                "xh --deadline=1m30s httpbin.org/get",
+                "curl -m 90 'http://httpbin.org/get'",
+                "curl -m 90 http://httpbin.org/get",
+            ),
+            (
+                "xh --max-body-size=1M httpbin.org/get",
+                "curl --max-filesize 1048576 'http://httpbin.org/get'",
+                "curl --max-filesize 1048576 http://httpbin.org/get",
+            ),
             (
                 "xh -h httpbin.org/get",
                 "curl -I -X GET 'http://httpbin.org/get'",
@@ -381,6 +578,11 @@ mod tests {
                 "http_proxy='localhost:1080' curl 'http://httpbin.org/get'",
                 "http_proxy=localhost:1080 curl http://httpbin.org/get",
             ),
+            (
+                "xh --proxy all:localhost:1080 --proxy-auth user:pass httpbin.org/get",
+                "curl -x 'localhost:1080' -U 'user:********' 'http://httpbin.org/get'",
+                "curl -x localhost:1080 -U user:******** http://httpbin.org/get",
+            ),
             (
                 "xh --proxy all:localhost:1080 httpbin.org/get",
                 "curl -x 'localhost:1080' 'http://httpbin.org/get'",
@@ -408,8 +610,38 @@ mod tests {
             ),
             (
                 "xh --bearer foobar post httpbin.org/post",
-                "curl -X POST 'http://httpbin.org/post' --oauth2-bearer foobar",
-                "curl -X POST http://httpbin.org/post --oauth2-bearer foobar",
+                "curl -X POST 'http://httpbin.org/post' --oauth2-bearer '********'",
+                "curl -X POST http://httpbin.org/post --oauth2-bearer ********",
+            ),
+            (
+                "xh -A negotiate httpbin.org/get",
+                "curl 'http://httpbin.org/get' --negotiate -u ':'",
+                "curl http://httpbin.org/get --negotiate -u :",
+            ),
+            (
+                "xh --auth-challenge -a user:pass httpbin.org/get",
+                "curl 'http://httpbin.org/get' --anyauth -u 'user:********'",
+                "curl http://httpbin.org/get --anyauth -u user:********",
+            ),
+            (
+                "xh -A digest -a user:pass httpbin.org/get",
+                "curl 'http://httpbin.org/get' --digest -u 'user:********'",
+                "curl http://httpbin.org/get --digest -u user:********",
+            ),
+            (
+                "xh --show-secrets -a user:pass httpbin.org/get",
+                "curl 'http://httpbin.org/get' -u 'user:pass'",
+                "curl http://httpbin.org/get -u user:pass",
+            ),
+            (
+                "xh httpbin.org/get Cookie:a=b X-Api-Key:abc X-Other:def --redact X-Api-Key",
+                "curl 'http://httpbin.org/get' -H 'cookie: a=********' -H 'x-api-key: ********' -H 'x-other: def'",
+                "curl http://httpbin.org/get -H \"cookie: a=********\" -H \"x-api-key: ********\" -H \"x-other: def\"",
+            ),
+            (
+                "xh --cert=client.p12 --cert-key-pass=secret https://example.com",
+                "curl --cert-type P12 -E client.p12 --pass '********' 'https://example.com/'",
+                "curl --cert-type P12 -E client.p12 --pass ******** https://example.com/",
             ),
             (
                 "xh httpbin.org/get foo:Bar baz; user-agent:",
@@ -418,18 +650,53 @@ mod tests {
             ),
             (
                 "xh -d httpbin.org/get",
-                "curl -L -O 'http://httpbin.org/get'",
-                "curl -L -O http://httpbin.org/get",
+                "curl -L -O -R 'http://httpbin.org/get'",
+                "curl -L -O -R http://httpbin.org/get",
             ),
             (
                 "xh -d -o foobar --continue httpbin.org/get",
-                "curl -L -o foobar -C - 'http://httpbin.org/get'",
-                "curl -L -o foobar -C - http://httpbin.org/get",
+                "curl -L -o foobar -R -C - 'http://httpbin.org/get'",
+                "curl -L -o foobar -R -C - http://httpbin.org/get",
+            ),
+            (
+                "xh -d -o foobar --ignore-last-modified httpbin.org/get",
+                "curl -L -o foobar 'http://httpbin.org/get'",
+                "curl -L -o foobar http://httpbin.org/get",
             ),
             (
                 "xh --curl-long -d -o foobar --continue httpbin.org/get",
-                "curl --location --output foobar --continue-at - 'http://httpbin.org/get'",
-                "curl --location --output foobar --continue-at - http://httpbin.org/get",
+                "curl --location --output foobar --remote-time --continue-at - 'http://httpbin.org/get'",
+                "curl --location --output foobar --remote-time --continue-at - http://httpbin.org/get",
+            ),
+            (
+                "xh --range=-500 httpbin.org/get",
+                "curl -r -500 'http://httpbin.org/get'",
+                "curl -r -500 http://httpbin.org/get",
+            ),
+            (
+                "xh post httpbin.org/post --json-body={}",
+                "curl -X POST 'http://httpbin.org/post' -H 'content-type: application/json' -H 'accept: application/json, */*;q=0.5' -d '{}'",
+                r#"curl -X POST http://httpbin.org/post -H "content-type: application/json" -H "accept: application/json, */*;q=0.5" -d {}"#,
+            ),
+            (
+                "xh -f --encode-set=minimal httpbin.org/post a=b:c,d q==x:y",
+                "curl 'http://httpbin.org/post?q=x:y' -d 'a=b:c,d'",
+                "curl http://httpbin.org/post?q=x:y -d a=b:c,d",
+            ),
+            (
+                "xh -m httpbin.org/post doc@doc.json --part-header=doc:content-id:<doc1>",
+                r#"curl 'http://httpbin.org/post' -F 'doc=@doc.json;headers="content-id: <doc1>"'"#,
+                r#"curl http://httpbin.org/post -F "doc=@doc.json;headers=\"content-id: <doc1>\"""#,
+            ),
+            (
+                "xh httpbin.org/get?a=1&utm_source=x utm_source==-",
+                "curl 'http://httpbin.org/get?a=1'",
+                "curl http://httpbin.org/get?a=1",
+            ),
+            (
+                "xh httpbin.org/get -o body.bin --output-headers=headers.txt",
+                "curl -o body.bin -D headers.txt 'http://httpbin.org/get'",
+                "curl -o body.bin -D headers.txt http://httpbin.org/get",
             ),
             (
                 "xh httpbin.org/post @foo.txt",
@@ -0,0 +1,283 @@
+// --trace, a log of everything that's sent and received, with timestamps.
+//
+// reqwest doesn't let us see the connection itself, so this shows the bytes
+// that xh hands to it and gets back: TLS, HTTP/2 framing and compression
+// happen out of sight. Only the response says which HTTP version was used, so
+// a request is written out once its response arrives.
+
+use std::fs::File;
+use std::io::{self, stderr, stdout, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use reqwest::blocking::{Request, Response};
+use reqwest::header::{HeaderMap, HeaderName};
+use reqwest::Version;
+
+use crate::printer::request_headers;
+use crate::redact::redact_headers;
+use crate::utils::looks_binary;
+
+/// Which way data went, with curl's markers.
+#[derive(Debug, Clone, Copy)]
+enum Direction {
+    Send,
+    Recv,
+}
+
+impl Direction {
+    fn marker(self) -> &'static str {
+        match self {
+            Direction::Send => "=> Send",
+            Direction::Recv => "<= Recv",
+        }
+    }
+}
+
+/// A handle on the trace output. Clones write to the same place.
+#[derive(Clone)]
+pub struct Trace {
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+    /// Headers to mask, or `None` for --show-secrets
+    redact: Option<Vec<HeaderName>>,
+    /// The request that was sent last, until there's a response to it
+    pending: Arc<Mutex<Option<Pending>>>,
+}
+
+/// A request that's been sent but not written out yet.
+struct Pending {
+    time: String,
+    /// The method and the path, without the HTTP version
+    target: String,
+    version: Version,
+    headers: HeaderMap,
+    /// `None` if the body was streamed, which isn't traced
+    body: Option<Vec<u8>>,
+}
+
+impl Trace {
+    /// Trace to `path`, to stdout for `-`, or to stderr without a path.
+    pub fn open(path: Option<&Path>, redact: Option<Vec<HeaderName>>) -> Result<Trace> {
+        let out: Box<dyn Write + Send> = match path {
+            None => Box::new(stderr()),
+            Some(path) if path == Path::new("-") => Box::new(stdout()),
+            Some(path) => Box::new(
+                File::create(path)
+                    .with_context(|| format!("Failed to create the trace file {:?}", path))?,
+            ),
+        };
+        Ok(Trace {
+            out: Arc::new(Mutex::new(out)),
+            redact,
+            pending: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    pub fn info(&self, message: &str) -> io::Result<()> {
+        self.info_at(&timestamp(), message)
+    }
+
+    fn info_at(&self, time: &str, message: &str) -> io::Result<()> {
+        self.write(&format!("{} == Info: {}\n", time, message))
+    }
+
+    /// Note a request that's about to be sent. It's written out by
+    /// [`Trace::response`].
+    pub fn request(&self, request: &Request) {
+        let url = request.url();
+        let query = url
+            .query()
+            .map_or(String::new(), |query| format!("?{}", query));
+        let pending = Pending {
+            time: timestamp(),
+            target: format!("{} {}{}", request.method(), url.path(), query),
+            version: request.version(),
+            headers: request_headers(request),
+            body: match request.body() {
+                Some(body) => body.as_bytes().map(<[u8]>::to_vec),
+                None => Some(Vec::new()),
+            },
+        };
+        *lock(&self.pending) = Some(pending);
+    }
+
+    /// Write out the request that was sent, and the head of its response if
+    /// it got one.
+    pub fn response(&self, response: Option<&Response>) -> io::Result<()> {
+        if let Some(pending) = lock(&self.pending).take() {
+            let version = response.map_or(pending.version, Response::version);
+            let head = format!("{} {:?}", pending.target, version);
+            self.head(&pending.time, Direction::Send, head, pending.headers)?;
+            match pending.body {
+                Some(body) if body.is_empty() => {}
+                Some(body) => self.data(&pending.time, Direction::Send, &body)?,
+                None => self.info_at(
+                    &pending.time,
+                    "The request body is streamed, which isn't traced",
+                )?,
+            }
+        }
+        if let Some(response) = response {
+            let head = format!("{:?} {}", response.version(), response.status());
+            self.head(
+                &timestamp(),
+                Direction::Recv,
+                head,
+                response.headers().clone(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Wrap a response body to trace it as it's read.
+    pub fn reader<R: Read>(&self, inner: R) -> TraceReader<R> {
+        TraceReader {
+            inner,
+            trace: self.clone(),
+        }
+    }
+
+    fn head(
+        &self,
+        time: &str,
+        direction: Direction,
+        first_line: String,
+        mut headers: HeaderMap,
+    ) -> io::Result<()> {
+        if let Some(redact) = &self.redact {
+            redact_headers(&mut headers, redact);
+        }
+        let mut text = first_line + "\r\n";
+        for (name, value) in &headers {
+            text.push_str(name.as_str());
+            text.push_str(": ");
+            text.push_str(&String::from_utf8_lossy(value.as_bytes()));
+            text.push_str("\r\n");
+        }
+        text.push_str("\r\n");
+        self.write(&format!(
+            "{} {} header, {} bytes\n{}",
+            time,
+            direction.marker(),
+            text.len(),
+            text.replace("\r\n", "\n")
+        ))
+    }
+
+    fn data(&self, time: &str, direction: Direction, data: &[u8]) -> io::Result<()> {
+        let mut text = format!(
+            "{} {} data, {} bytes\n",
+            time,
+            direction.marker(),
+            data.len()
+        );
+        if is_binary(data) {
+            text.push_str(&hex_dump(data));
+        } else {
+            text.push_str(&String::from_utf8_lossy(data));
+            if !text.ends_with('\n') {
+                text.push('\n');
+            }
+        }
+        self.write(&text)
+    }
+
+    fn write(&self, text: &str) -> io::Result<()> {
+        let mut out = lock(&self.out);
+        out.write_all(text.as_bytes())?;
+        out.flush()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // A panic elsewhere shouldn't also lose the trace
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+pub struct TraceReader<R> {
+    inner: R,
+    trace: Trace,
+}
+
+impl<R: Read> Read for TraceReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.trace.data(&timestamp(), Direction::Recv, &buf[..n])?;
+        }
+        Ok(n)
+    }
+}
+
+fn is_binary(data: &[u8]) -> bool {
+    match std::str::from_utf8(data) {
+        Ok(text) => looks_binary(text),
+        // A character cut off at the end of a chunk is fine
+        Err(err) if err.error_len().is_none() => {
+            looks_binary(&String::from_utf8_lossy(&data[..err.valid_up_to()]))
+        }
+        Err(_) => true,
+    }
+}
+
+/// Sixteen bytes a line, in hex and then as text, like curl does it.
+fn hex_dump(data: &[u8]) -> String {
+    let mut dump = String::new();
+    for (index, line) in data.chunks(16).enumerate() {
+        dump.push_str(&format!("{:04x}:", index * 16));
+        for byte in line {
+            dump.push_str(&format!(" {:02x}", byte));
+        }
+        for _ in line.len()..16 {
+            dump.push_str("   ");
+        }
+        dump.push(' ');
+        for &byte in line {
+            dump.push(if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            });
+        }
+        dump.push('\n');
+    }
+    dump
+}
+
+/// The time of day in UTC, to the microsecond.
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = now.as_secs() % 86400;
+    format!(
+        "{:02}:{:02}:{:02}.{:06}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        now.subsec_micros()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_data() {
+        assert_eq!(
+            hex_dump(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\x01"),
+            "0000: 89 50 4e 47 0d 0a 1a 0a 00 00 00 0d 49 48 44 52 .PNG........IHDR\n\
+             0010: 00 01                                           ..\n"
+        );
+        assert!(is_binary(b"\x89PNG\r\n\x1a\n\0\0\0"));
+        assert!(!is_binary(b"{\"a\": 1}\n"));
+        // Cut off in the middle of "é"
+        assert!(!is_binary(&"café".as_bytes()[..4]));
+    }
+}
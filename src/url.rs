@@ -1,14 +1,44 @@
 // Would be slightly cleaner to return a ParseError, but reqwest doesn't
 // export that type
 use anyhow::Result;
+use percent_encoding::{percent_encode, AsciiSet, CONTROLS};
 use reqwest::Url;
 
+use crate::cli::EncodeSet;
 use crate::regex;
 
+/// Characters that would otherwise end or alter a parameter, plus those
+/// that the `url` crate would encode anyway.
+const MINIMAL: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'&')
+    .add(b'+')
+    .add(b'<')
+    .add(b'=')
+    .add(b'>');
+
+/// Percent-encode the name or value of a URL parameter or form field.
+pub fn encode_component(input: &[u8], encode_set: EncodeSet) -> Vec<u8> {
+    match encode_set {
+        EncodeSet::strict => form_urlencoded::byte_serialize(input)
+            .flat_map(str::bytes)
+            .collect(),
+        EncodeSet::minimal => percent_encode(input, MINIMAL)
+            .flat_map(str::bytes)
+            .collect(),
+        EncodeSet::none => input.to_vec(),
+    }
+}
+
 pub fn construct_url(
     url: &str,
     default_scheme: Option<&str>,
-    query: Vec<(&str, &str)>,
+    query: &[(String, String)],
+    query_to_unset: &[String],
+    encode_set: EncodeSet,
 ) -> Result<Url> {
     let mut default_scheme = default_scheme.unwrap_or("http://").to_string();
     if !default_scheme.ends_with("://") {
@@ -21,13 +51,149 @@ pub fn construct_url(
     } else {
         url.parse()?
     };
-    if !query.is_empty() {
+    if !query.is_empty() || !query_to_unset.is_empty() {
         // If we run this even without adding pairs it adds a `?`, hence
         // the .is_empty() check
-        let mut pairs = url.query_pairs_mut();
+        let mut query_string = Vec::new();
+        // Keep the pairs we don't remove exactly as they were written
+        for pair in url.query().unwrap_or("").split('&') {
+            let name = form_urlencoded::parse(pair.as_bytes())
+                .next()
+                .map(|(name, _)| name);
+            let unset = name.map_or(false, |name| query_to_unset.iter().any(|n| *n == name));
+            if !pair.is_empty() && !unset {
+                if !query_string.is_empty() {
+                    query_string.push(b'&');
+                }
+                query_string.extend(pair.as_bytes());
+            }
+        }
         for (name, value) in query {
-            pairs.append_pair(name, value);
+            if !query_string.is_empty() {
+                query_string.push(b'&');
+            }
+            query_string.extend(encode_component(name.as_bytes(), encode_set));
+            query_string.push(b'=');
+            query_string.extend(encode_component(value.as_bytes(), encode_set));
+        }
+        if query_string.is_empty() {
+            url.set_query(None);
+        } else {
+            // Our input was valid UTF-8 and encoding can't break that
+            url.set_query(Some(&String::from_utf8_lossy(&query_string)));
         }
     }
     Ok(url)
 }
+
+/// Whether the path in `url` has `.` or `..` segments. The `url` crate
+/// resolves them while parsing, and reqwest only takes a parsed `Url`, so
+/// there's no way to send them as they were typed.
+pub fn has_dot_segments(url: &str) -> bool {
+    let rest = match url.find("://") {
+        Some(index) => &url[index + 3..],
+        None => url,
+    };
+    let path = match rest.find('/') {
+        Some(index) => &rest[index..],
+        None => return false,
+    };
+    let path = match path.find(['?', '#']) {
+        Some(index) => &path[..index],
+        None => path,
+    };
+    path.split(['/', '\\']).any(|segment| {
+        let segment = segment.to_ascii_lowercase().replace("%2e", ".");
+        segment == "." || segment == ".."
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_encoding() {
+        let query = vec![
+            ("q".to_string(), "a:b,c d&e=f+ü".to_string()),
+            ("x".to_string(), "1".to_string()),
+        ];
+        let expected = vec![
+            (
+                EncodeSet::strict,
+                "http://example.org/?q=a%3Ab%2Cc+d%26e%3Df%2B%C3%BC&x=1",
+            ),
+            (
+                EncodeSet::minimal,
+                "http://example.org/?q=a:b,c%20d%26e%3Df%2B%C3%BC&x=1",
+            ),
+            (
+                EncodeSet::none,
+                "http://example.org/?q=a:b,c%20d&e=f+%C3%BC&x=1",
+            ),
+        ];
+        for (encode_set, url) in expected {
+            assert_eq!(
+                construct_url("example.org", None, &query, &[], encode_set)
+                    .unwrap()
+                    .as_str(),
+                url
+            );
+        }
+        assert_eq!(
+            construct_url(
+                "example.org/?a=1",
+                None,
+                &[("b".to_string(), "2".to_string())],
+                &[],
+                EncodeSet::strict
+            )
+            .unwrap()
+            .as_str(),
+            "http://example.org/?a=1&b=2"
+        );
+    }
+
+    #[test]
+    fn query_unsetting() {
+        let url = |query: &[(String, String)], unset: &[&str]| {
+            let unset: Vec<String> = unset.iter().map(|name| name.to_string()).collect();
+            construct_url(
+                "example.org/?utm_source=x&a=%20b&utm_medium=y&utm_source=z",
+                None,
+                query,
+                &unset,
+                EncodeSet::strict,
+            )
+            .unwrap()
+            .to_string()
+        };
+        assert_eq!(
+            url(&[], &["utm_source", "utm_medium"]),
+            "http://example.org/?a=%20b"
+        );
+        assert_eq!(
+            url(
+                &[("c".to_string(), "d".to_string())],
+                &["utm_source", "utm_medium", "a"]
+            ),
+            "http://example.org/?c=d"
+        );
+        assert_eq!(
+            url(&[], &["utm_source", "utm_medium", "a"]),
+            "http://example.org/"
+        );
+    }
+
+    #[test]
+    fn dot_segments() {
+        assert!(has_dot_segments("example.org/a/../b"));
+        assert!(has_dot_segments("http://example.org/./b?x=1"));
+        assert!(has_dot_segments(":3000/a/%2E%2e"));
+        assert!(has_dot_segments("example.org/a/.%2e/b"));
+        assert!(!has_dot_segments("example.org/a/..b/.c"));
+        assert!(!has_dot_segments("example.org//a/%2Fb"));
+        assert!(!has_dot_segments("example.org/a?path=../b"));
+        assert!(!has_dot_segments("example.org"));
+    }
+}
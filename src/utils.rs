@@ -1,9 +1,14 @@
 use std::{
+    convert::TryFrom,
     env::var_os,
-    io::{self, Write},
+    fmt,
+    io::{self, Read, Write},
+    process::Command,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use chrono::DateTime;
+use reqwest::header::{HeaderMap, CONTENT_TYPE, RETRY_AFTER};
 
 /// Whether to make some things more deterministic for the benefit of tests
 pub fn test_mode() -> bool {
@@ -21,12 +26,40 @@ pub fn test_default_color() -> bool {
     var_os("XH_TEST_MODE_COLOR").is_some()
 }
 
+/// How many characters to look at when guessing whether a body is binary.
+const SNIFF_CHARS: usize = 1024;
+
+/// Guess whether a body is binary by looking at its first chunk, whatever its
+/// content type claims.
+///
+/// This goes by the decoded text, so bytes that aren't valid in the body's
+/// encoding show up as replacement characters and count against it.
+pub fn looks_binary(text: &str) -> bool {
+    let mut total = 0;
+    let mut suspicious = 0;
+    for c in text.chars().take(SNIFF_CHARS) {
+        total += 1;
+        match c {
+            '\0' => return true,
+            '\t' | '\n' | '\r' | '\x0c' | '\x1b' => {}
+            '\u{fffd}' => suspicious += 1,
+            c if c.is_control() => suspicious += 1,
+            _ => {}
+        }
+    }
+    suspicious * 10 > total
+}
+
 pub enum ContentType {
     Json,
+    /// Newline-delimited JSON, with a document on each line.
+    JsonLines,
     Html,
     Xml,
     JavaScript,
     Css,
+    Markdown,
+    Csv,
     Text,
     UrlencodedForm,
     Multipart,
@@ -47,7 +80,12 @@ pub fn get_content_type(headers: &HeaderMap) -> ContentType {
         .get(CONTENT_TYPE)
         .and_then(|value| value.to_str().ok())
         .and_then(|content_type| {
-            if content_type.contains("json") {
+            if content_type.contains("ndjson")
+                || content_type.contains("jsonl")
+                || content_type.contains("json-seq")
+            {
+                Some(ContentType::JsonLines)
+            } else if content_type.contains("json") {
                 Some(ContentType::Json)
             } else if content_type.contains("html") {
                 Some(ContentType::Html)
@@ -61,6 +99,10 @@ pub fn get_content_type(headers: &HeaderMap) -> ContentType {
                 Some(ContentType::JavaScript)
             } else if content_type.contains("css") {
                 Some(ContentType::Css)
+            } else if content_type.contains("csv") {
+                Some(ContentType::Csv)
+            } else if content_type.contains("markdown") {
+                Some(ContentType::Markdown)
             } else if content_type.contains("text") {
                 // We later check if this one's JSON
                 // HTTPie checks for "json", "javascript" and "text" in one place:
@@ -122,6 +164,177 @@ pub fn copy_largebuf(reader: &mut impl io::Read, writer: &mut impl Write) -> io:
     }
 }
 
+/// A response body turned out larger than --max-body-size.
+#[derive(Debug)]
+pub struct BodyTooLarge(pub u64);
+
+impl BodyTooLarge {
+    pub fn into_io_error(self) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, self)
+    }
+}
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Response body is larger than the --max-body-size of {} bytes",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for BodyTooLarge {}
+
+/// The --deadline ran out.
+#[derive(Debug)]
+pub struct DeadlineExceeded(pub Duration);
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Gave up after the --deadline of {}",
+            humantime::format_duration(self.0)
+        )
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+/// When the --deadline runs out. Every request gets what's left of it as its
+/// timeout, so the run stops there with an error instead of going on.
+#[derive(Debug, Clone, Copy)]
+pub struct Cutoff {
+    pub limit: Duration,
+    at: Instant,
+}
+
+impl Cutoff {
+    pub fn start(limit: Duration) -> Cutoff {
+        Cutoff {
+            limit,
+            at: Instant::now() + limit,
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        Instant::now() >= self.at
+    }
+
+    /// `timeout` cut short to what's left, or an error if nothing is.
+    pub fn limit(&self, timeout: Option<Duration>) -> Result<Duration, DeadlineExceeded> {
+        let left = self.at.saturating_duration_since(Instant::now());
+        if left == Duration::ZERO {
+            return Err(DeadlineExceeded(self.limit));
+        }
+        Ok(timeout.map_or(left, |timeout| timeout.min(left)))
+    }
+}
+
+/// Find an error of type `E` among the causes of `err`.
+///
+/// This also looks inside io::Errors, whose `source()` skips the error they
+/// wrap. They can be wrapped several times over.
+pub fn find_cause<E: std::error::Error + 'static>(err: &anyhow::Error) -> Option<&E> {
+    err.chain().find_map(|mut cause| loop {
+        if let Some(found) = cause.downcast_ref::<E>() {
+            return Some(found);
+        }
+        cause = cause.downcast_ref::<io::Error>()?.get_ref()?;
+    })
+}
+
+/// A reader that fails with BodyTooLarge once it has given out more than
+/// `limit` bytes.
+pub struct SizeLimit<R> {
+    inner: R,
+    limit: Option<u64>,
+    read: u64,
+}
+
+impl<R: Read> SizeLimit<R> {
+    pub fn new(inner: R, limit: Option<u64>) -> Self {
+        SizeLimit {
+            inner,
+            limit,
+            read: 0,
+        }
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.read
+    }
+}
+
+impl<R: Read> Read for SizeLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        match self.limit {
+            Some(limit) if self.read > limit => Err(BodyTooLarge(limit).into_io_error()),
+            _ => Ok(n),
+        }
+    }
+}
+
+/// Build a command from a string like `less -R`, splitting it on whitespace.
+pub fn parse_command(command: &str) -> io::Result<Command> {
+    let mut parts = command.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Empty command"))?;
+    let mut command = Command::new(program);
+    command.args(parts);
+    Ok(command)
+}
+
 pub fn valid_json(text: &str) -> bool {
     serde_json::from_str::<serde::de::IgnoredAny>(text).is_ok()
 }
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parse the Retry-After header, which holds a number of seconds or a date.
+/// A date that's already past means there's no need to wait.
+pub fn parse_retry_after(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    let date = UNIX_EPOCH + Duration::from_secs(u64::try_from(date.timestamp()).ok()?);
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn retry_after() {
+        // Wed, 21 Oct 2015 07:28:00 GMT
+        let now = UNIX_EPOCH + Duration::from_secs(1_445_412_480);
+        let parse = |value| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, HeaderValue::from_static(value));
+            parse_retry_after(&headers, now)
+        };
+        assert_eq!(parse("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse("Wed, 21 Oct 2015 07:30:30 GMT"),
+            Some(Duration::from_secs(150))
+        );
+        assert_eq!(
+            parse("Wed, 21 Oct 2015 07:00:00 GMT"),
+            Some(Duration::from_secs(0))
+        );
+        assert_eq!(parse("-1"), None);
+        assert_eq!(parse("soon"), None);
+        assert_eq!(parse_retry_after(&HeaderMap::new(), now), None);
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+
+use crate::cli::Cli;
+use crate::regex;
+use crate::request_items::RequestItem;
+
+/// Values for `{{name}}` placeholders, from --var-file and --var.
+#[derive(Default)]
+pub struct Vars(HashMap<String, String>);
+
+impl Vars {
+    /// Collect the variables of a command line. Returns `None` if there
+    /// aren't any, so that text with braces is left alone.
+    pub fn from_args(args: &Cli) -> Result<Option<Vars>> {
+        if args.var.is_empty() && args.var_file.is_none() {
+            return Ok(None);
+        }
+        let mut vars = HashMap::new();
+        if let Some(path) = &args.var_file {
+            vars = read_var_file(path)
+                .with_context(|| format!("Failed to read variables from {}", path.display()))?;
+        }
+        for var in &args.var {
+            vars.insert(var.name.clone(), var.value.clone());
+        }
+        Ok(Some(Vars(vars)))
+    }
+
+    /// Replace every placeholder in `text`. Undefined variables are an error
+    /// rather than being sent as is.
+    pub fn substitute(&self, text: &str) -> Result<String> {
+        let placeholder = regex!(r"\{\{\s*([A-Za-z_][A-Za-z0-9_.-]*)\s*\}\}");
+        let mut out = String::with_capacity(text.len());
+        let mut last = 0;
+        for caps in placeholder.captures_iter(text) {
+            let whole = caps.get(0).unwrap();
+            let name = &caps[1];
+            let value = self.0.get(name).ok_or_else(|| {
+                anyhow!(
+                    "Undefined variable {{{{{}}}}}, set it with --var {}=VALUE",
+                    name,
+                    name
+                )
+            })?;
+            out.push_str(&text[last..whole.start()]);
+            out.push_str(value);
+            last = whole.end();
+        }
+        out.push_str(&text[last..]);
+        Ok(out)
+    }
+
+    /// Fill in the placeholders in the URLs and the values of request items.
+    pub fn apply(&self, args: &mut Cli) -> Result<()> {
+        args.url = self.substitute(&args.url)?;
+        for url in &mut args.more_urls {
+            *url = self.substitute(url)?;
+        }
+        for item in &mut args.request_items {
+            match item {
+                RequestItem::HttpHeader(_, value)
+                | RequestItem::Cookie(_, value)
+                | RequestItem::UrlParam(_, value)
+                | RequestItem::UrlParamFromFile(_, value)
+                | RequestItem::DataField(_, value)
+                | RequestItem::DataFieldFromFile(_, value)
+                | RequestItem::JsonFieldFromFile(_, value) => *value = self.substitute(value)?,
+                RequestItem::JsonField(_, value) => self.substitute_json(value)?,
+                RequestItem::FormFile { file_name, .. } => {
+                    *file_name = self.substitute(file_name)?
+                }
+                RequestItem::HttpHeaderToUnset(_) | RequestItem::UrlParamToUnset(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Substitute inside the strings of a raw JSON value, so a placeholder
+    /// can't change the structure of the value.
+    fn substitute_json(&self, value: &mut Value) -> Result<()> {
+        match value {
+            Value::String(text) => *text = self.substitute(text)?,
+            Value::Array(values) => {
+                for value in values {
+                    self.substitute_json(value)?;
+                }
+            }
+            Value::Object(map) => {
+                for value in map.values_mut() {
+                    self.substitute_json(value)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Read a JSON object of variables. Numbers and booleans are turned into
+/// strings.
+fn read_var_file(path: &Path) -> Result<HashMap<String, String>> {
+    let text = fs::read_to_string(path)?;
+    let object = match serde_json::from_str(&text)? {
+        Value::Object(object) => object,
+        _ => return Err(anyhow!("Expected a JSON object")),
+    };
+    let mut vars = HashMap::new();
+    for (name, value) in object {
+        let value = match value {
+            Value::String(value) => value,
+            Value::Number(_) | Value::Bool(_) => value.to_string(),
+            _ => return Err(anyhow!("{:?} should be a string, number or boolean", name)),
+        };
+        vars.insert(name, value);
+    }
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitution() {
+        let mut map = HashMap::new();
+        map.insert("host".to_string(), "staging.example.com".to_string());
+        map.insert("api.version".to_string(), "v2".to_string());
+        let vars = Vars(map);
+
+        assert_eq!(
+            vars.substitute("https://{{host}}/{{ api.version }}/users")
+                .unwrap(),
+            "https://staging.example.com/v2/users"
+        );
+        assert_eq!(vars.substitute("{{}} {{-x}}").unwrap(), "{{}} {{-x}}");
+        assert!(vars.substitute("{{token}}").is_err());
+
+        let mut value = serde_json::json!({"url": "{{host}}", "tags": ["{{api.version}}", 1]});
+        vars.substitute_json(&mut value).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"url": "staging.example.com", "tags": ["v2", 1]})
+        );
+    }
+}
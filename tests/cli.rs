@@ -1,15 +1,17 @@
 #![cfg(feature = "integration-tests")]
 use std::{
     fs::File,
-    fs::{read_to_string, OpenOptions},
-    io::{Seek, SeekFrom, Write},
-    process::Command,
-    time::Duration,
+    fs::{metadata, read, read_to_string, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    net::TcpListener,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant, UNIX_EPOCH},
 };
 
 use assert_cmd::prelude::*;
 use httpmock::{Method::*, MockServer};
-use indoc::indoc;
+use indoc::{formatdoc, indoc};
 use predicate::str::contains;
 use predicates::prelude::*;
 use serde_json::json;
@@ -163,6 +165,43 @@ fn query_param() {
     mock.assert();
 }
 
+#[test]
+fn query_params_from_file() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, _then| {
+        when.query_param("foo", "bar")
+            .query_param("page", "2")
+            .query_param("q", "rust");
+    });
+
+    let mut params_file = tempfile::NamedTempFile::new().unwrap();
+    writeln!(params_file, r#"{{"foo": "bar", "page": 2}}"#).unwrap();
+
+    get_command()
+        .arg(server.base_url())
+        .arg(format!("==@{}", params_file.path().to_string_lossy()))
+        .arg("q==rust")
+        .assert()
+        .success();
+    mock.assert();
+}
+
+#[test]
+fn cookie_items() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, _then| {
+        when.header("cookie", "theme=dark; session=abc; lang=en");
+    });
+    get_command()
+        .arg(server.base_url())
+        .arg("session^=abc")
+        .arg("cookie:theme=dark")
+        .arg("lang^=en")
+        .assert()
+        .success();
+    mock.assert();
+}
+
 #[test]
 fn json_param() {
     let server = MockServer::start();
@@ -194,7 +233,7 @@ fn verbose() {
         .arg(server.base_url())
         .arg("x=y")
         .assert()
-        .stdout(indoc! {r#"
+        .stdout(formatdoc! {r#"
         POST / HTTP/1.1
         accept: application/json, */*;q=0.5
         accept-encoding: gzip, br
@@ -204,11 +243,13 @@ fn verbose() {
         host: http.mock
         user-agent: xh/0.0.0 (test mode)
 
-        {
+        {{
             "x": "y"
-        }
+        }}
+
 
 
+        remote-address: {}
 
         HTTP/1.1 200 OK
         content-length: 6
@@ -216,10 +257,55 @@ fn verbose() {
         x-foo: Bar
 
         a body
-        "#});
+        "#, server.address()});
     mock.assert();
 }
 
+#[test]
+fn header_order() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.header("date", "N/A")
+            .header("X-B", "1")
+            .header("X-A", "2");
+    });
+
+    // The mock server sends its headers in its own order, with the
+    // content-length last
+    get_command()
+        .arg("--print=h")
+        .arg("--format-options=headers.sort:true,headers.sort:false")
+        .arg(server.base_url())
+        .assert()
+        .stdout(indoc! {r#"
+        HTTP/1.1 200 OK
+        x-a: 2
+        x-b: 1
+        date: N/A
+        content-length: 0
+
+        "#});
+    get_command()
+        .arg("--print=h")
+        .arg(server.base_url())
+        .assert()
+        .stdout(indoc! {r#"
+        HTTP/1.1 200 OK
+        content-length: 0
+        date: N/A
+        x-a: 2
+        x-b: 1
+
+        "#});
+    get_command()
+        .arg("--format-options=headers.sort:maybe")
+        .arg(server.base_url())
+        .assert()
+        .failure()
+        .stderr(contains("expected true or false"));
+    mock.assert_hits(2);
+}
+
 #[test]
 fn download() {
     let dir = tempdir().unwrap();
@@ -239,1012 +325,3909 @@ fn download() {
     assert_eq!(read_to_string(&outfile).unwrap(), "file contents\n");
 }
 
-fn get_proxy_command(
-    protocol_to_request: &str,
-    protocol_to_proxy: &str,
-    proxy_url: &str,
-) -> Command {
-    let mut cmd = get_command();
-    cmd.arg("--pretty=format")
-        .arg("--check-status")
-        .arg(format!("--proxy={}:{}", protocol_to_proxy, proxy_url))
-        .arg("GET")
-        .arg(format!("{}://example.test/get", protocol_to_request));
-    cmd
-}
-
 #[test]
-fn proxy_http_proxy() {
+fn download_split() {
+    let dir = tempdir().unwrap();
     let server = MockServer::start();
-
-    let mock = server.mock(|when, then| {
-        when.method(GET).header("host", "example.test");
-        then.status(200);
+    let segments = [
+        ("bytes=4-7", "bytes 4-7/10", "4567"),
+        ("bytes=8-9", "bytes 8-9/10", "89"),
+    ];
+    let range_mocks: Vec<_> = segments
+        .iter()
+        .map(|&(range, content_range, body)| {
+            server.mock(|when, then| {
+                when.path("/split").header("Range", range);
+                then.status(206)
+                    .header("Content-Range", content_range)
+                    .body(body);
+            })
+        })
+        .collect();
+    server.mock(|when, then| {
+        when.path("/split");
+        then.header("Accept-Ranges", "bytes").body("0123456789");
+    });
+    server.mock(|when, then| {
+        when.path("/no-ranges");
+        then.body("0123456789");
     });
 
-    get_proxy_command("http", "http", &server.base_url())
+    let outfile = dir.path().join("outfile");
+    get_command()
+        .args(&["--download", "--split=3", "--output"])
+        .arg(&outfile)
+        .arg(server.url("/split"))
         .assert()
         .success();
+    for mock in &range_mocks {
+        mock.assert();
+    }
+    assert_eq!(read_to_string(&outfile).unwrap(), "0123456789");
 
-    mock.assert();
-}
-
-#[test]
-fn proxy_https_proxy() {
-    let server = MockServer::start();
-
-    let mock = server.mock(|when, then| {
-        when.method(CONNECT);
-        then.status(502);
-    });
-
-    get_proxy_command("https", "https", &server.base_url())
+    // Without Accept-Ranges it's a normal download
+    get_command()
+        .args(&["--download", "--split=3", "--output"])
+        .arg(&outfile)
+        .arg(server.url("/no-ranges"))
         .assert()
-        .stderr(predicate::str::contains("unsuccessful tunnel"))
-        .failure();
-    mock.assert();
+        .success();
+    assert_eq!(read_to_string(&outfile).unwrap(), "0123456789");
 }
 
 #[test]
-fn download_generated_filename() {
+fn download_decompress() {
     let dir = tempdir().unwrap();
     let server = MockServer::start();
+    // "hello" in a brotli stream that stores it uncompressed
+    let body = b"\x40\x00\x10hello\x03";
     server.mock(|_when, then| {
-        then.header("Content-Type", "application/json").body("file");
+        then.header("Content-Encoding", "br").body(body);
     });
 
+    let outfile = dir.path().join("outfile");
     get_command()
-        .arg("--download")
-        .arg(server.url("/foo/bar/"))
-        .current_dir(&dir)
-        .assert();
+        .args(&["--download", "--output"])
+        .arg(&outfile)
+        .arg(server.base_url())
+        .assert()
+        .success();
+    assert_eq!(read(&outfile).unwrap(), body);
 
     get_command()
-        .arg("--download")
-        .arg(server.url("/foo/bar/"))
-        .current_dir(&dir)
-        .assert();
-
-    assert_eq!(read_to_string(dir.path().join("bar.json")).unwrap(), "file");
-    assert_eq!(
-        read_to_string(dir.path().join("bar.json-1")).unwrap(),
-        "file"
-    );
+        .args(&["--download", "--decompress", "--output"])
+        .arg(&outfile)
+        .arg(server.base_url())
+        .assert()
+        .success();
+    assert_eq!(read_to_string(&outfile).unwrap(), "hello");
 }
 
 #[test]
-fn download_supplied_filename() {
+fn download_peek() {
     let dir = tempdir().unwrap();
     let server = MockServer::start();
-    let mock = server.mock(|_when, then| {
-        then.header("Content-Disposition", r#"attachment; filename="foo.bar""#)
-            .body("file");
+    // A zip with "hello" stored in a.txt, followed by an empty index
+    let body = b"PK\x03\x04\x0a\x00\x00\x00\x00\x00\x00\x60\xc1\x52\x86\xa6\x10\x36\
+                 \x05\x00\x00\x00\x05\x00\x00\x00\x05\x00\x00\x00a.txthello\
+                 PK\x05\x06\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+    server.mock(|when, then| {
+        when.path("/archive.zip");
+        then.body(body);
+    });
+    server.mock(|when, then| {
+        when.path("/page.html");
+        then.body("<!doctype html>");
     });
 
     get_command()
-        .arg("--download")
-        .arg(server.base_url())
         .current_dir(&dir)
-        .assert();
-    mock.assert();
-    assert_eq!(read_to_string(dir.path().join("foo.bar")).unwrap(), "file");
+        .args(&["--download", "--peek", "--body"])
+        .arg(server.url("/archive.zip"))
+        .assert()
+        .stdout("           5  2021-06-01 12:00  a.txt\n")
+        .success();
+    assert_eq!(dir.path().read_dir().unwrap().count(), 0);
+
+    get_command()
+        .args(&["--download", "--peek"])
+        .arg(server.url("/page.html"))
+        .assert()
+        .stderr(contains("isn't a gzip, tar or zip archive"))
+        .failure();
 }
 
 #[test]
-fn download_supplied_unquoted_filename() {
+fn download_last_modified() {
     let dir = tempdir().unwrap();
     let server = MockServer::start();
-    let mock = server.mock(|_when, then| {
-        then.header("Content-Disposition", r#"attachment; filename=foo bar baz"#)
+    server.mock(|_when, then| {
+        then.header("Last-Modified", "Wed, 21 Oct 2015 07:28:00 GMT")
             .body("file");
     });
+    let last_modified = UNIX_EPOCH + Duration::from_secs(1_445_412_480);
 
+    let outfile = dir.path().join("outfile");
     get_command()
-        .arg("--download")
+        .args(&["--download", "--output"])
+        .arg(&outfile)
         .arg(server.base_url())
-        .current_dir(&dir)
-        .assert();
-    mock.assert();
-    assert_eq!(
-        read_to_string(dir.path().join("foo bar baz")).unwrap(),
-        "file"
-    );
-}
-
-#[test]
-fn decode() {
-    let server = MockServer::start();
-    let mock = server.mock(|_when, then| {
-        then.header("Content-Type", "text/plain; charset=latin1")
-            .body(b"\xe9");
-    });
+        .assert()
+        .success();
+    let modified = metadata(&outfile).unwrap().modified().unwrap();
+    assert_eq!(modified, last_modified);
 
     get_command()
-        .arg("--print=b")
+        .args(&["--download", "--ignore-last-modified", "--output"])
+        .arg(&outfile)
         .arg(server.base_url())
         .assert()
-        .stdout("é\n");
-    mock.assert();
+        .success();
+    let modified = metadata(&outfile).unwrap().modified().unwrap();
+    assert!(modified > last_modified);
 }
 
 #[test]
-fn proxy_all_proxy() {
-    let server = MockServer::start();
-
-    let mock = server.mock(|when, then| {
-        when.method(CONNECT);
-        then.status(502);
+fn download_truncated() {
+    let dir = tempdir().unwrap();
+    // httpmock always sends the whole body, so this closes the connection early
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/file", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = [0; 4096];
+            assert!(stream.read(&mut request).unwrap() > 0);
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nabc")
+                .unwrap();
+        }
     });
 
-    get_proxy_command("https", "all", &server.base_url())
+    let outfile = dir.path().join("outfile");
+    get_command()
+        .args(&["--download", "--output"])
+        .arg(&outfile)
+        .arg(&url)
         .assert()
-        .stderr(predicate::str::contains("unsuccessful tunnel"))
-        .failure();
-    mock.assert();
+        .failure()
+        .stderr(contains("Download incomplete, removed"));
+    assert!(!outfile.exists());
 
-    get_proxy_command("http", "all", &server.base_url())
+    get_command()
+        .args(&["--download", "--continue", "--output"])
+        .arg(&outfile)
+        .arg(&url)
         .assert()
-        .failure();
-    mock.assert();
+        .failure()
+        .stderr(contains("Download incomplete, kept"));
+    assert_eq!(read_to_string(&outfile).unwrap(), "abc");
 }
 
 #[test]
-fn streaming_decode() {
-    let server = MockServer::start();
-    let mock = server.mock(|_when, then| {
-        then.header("Content-Type", "text/plain; charset=latin1")
-            .body(b"\xe9");
+fn deadline() {
+    let dir = tempdir().unwrap();
+    // Send part of the body, then stall
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/file", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        let mut streams = Vec::new();
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = [0; 4096];
+            assert!(stream.read(&mut request).unwrap() > 0);
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nabc")
+                .unwrap();
+            streams.push(stream);
+        }
     });
 
+    let outfile = dir.path().join("outfile");
     get_command()
-        .arg("--print=b")
-        .arg("--stream")
-        .arg(server.base_url())
+        .args(&["--download", "--deadline=0.5", "--output"])
+        .arg(&outfile)
+        .arg(&url)
         .assert()
-        .stdout("é\n");
-    mock.assert();
-}
+        .code(2)
+        .stderr(contains("Gave up after the --deadline of 500ms"));
+    assert!(!outfile.exists());
 
-#[test]
-fn only_decode_for_terminal() {
+    get_command()
+        .args(&["--deadline=0.5", &url])
+        .assert()
+        .code(2);
+
+    // The wait before a retry is cut short too
     let server = MockServer::start();
-    let mock = server.mock(|_when, then| {
-        then.header("Content-Type", "text/plain; charset=latin1")
-            .body(b"\xe9");
+    server.mock(|_when, then| {
+        then.status(503).header("Retry-After", "60");
     });
-
-    let output = redirecting_command()
-        .arg(server.base_url())
+    let start = Instant::now();
+    get_command()
+        .args([
+            "--deadline=0.5",
+            "--respect-retry-after",
+            &server.base_url(),
+        ])
         .assert()
-        .get_output()
-        .stdout
-        .clone();
-    assert_eq!(&output, b"\xe9"); // .stdout() doesn't support byte slices
-    mock.assert();
+        .code(2)
+        .stderr(contains("Gave up after the --deadline of 500ms"));
+    assert!(start.elapsed() < Duration::from_secs(10));
 }
 
 #[test]
-fn do_decode_if_formatted() {
+fn max_body_size() {
     let server = MockServer::start();
-    let mock = server.mock(|_when, then| {
-        then.header("Content-Type", "text/plain; charset=latin1")
-            .body(b"\xe9");
+    server.mock(|_when, then| {
+        then.body("hello world");
     });
+    get_command()
+        .args(&["--body", "--max-body-size=5", &server.base_url()])
+        .assert()
+        .code(7)
+        .stdout("")
+        .stderr(contains("larger than the --max-body-size of 5 bytes"));
+    get_command()
+        .args(&["--body", "--max-body-size=1K", &server.base_url()])
+        .assert()
+        .success()
+        .stdout("hello world\n");
 
-    redirecting_command()
-        .arg("--pretty=all")
-        .arg(server.base_url())
+    // Without a Content-Length it's only found out while reading
+    let dir = tempdir().unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/file", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = [0; 4096];
+            assert!(stream.read(&mut request).unwrap() > 0);
+            let _ = stream.write_all(
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+            );
+        }
+    });
+    get_command()
+        .args(&["--max-body-size=8", &url])
         .assert()
-        .stdout("é");
-    mock.assert();
+        .code(7);
+    let outfile = dir.path().join("outfile");
+    get_command()
+        .args(&["--download", "--max-body-size=8", "--output"])
+        .arg(&outfile)
+        .arg(&url)
+        .assert()
+        .code(7)
+        .stderr(contains("Download incomplete, removed"));
+    assert!(!outfile.exists());
 }
 
 #[test]
-fn never_decode_if_binary() {
+fn exit_code_map() {
     let server = MockServer::start();
-    let mock = server.mock(|_when, then| {
-        // this mimetype with a charset may actually be incoherent
-        then.header("Content-Type", "application/octet-stream; charset=latin1")
-            .body(b"\xe9");
+    server.mock(|when, then| {
+        when.path("/missing");
+        then.status(404);
+    });
+    server.mock(|when, then| {
+        when.path("/broken");
+        then.status(500);
     });
+    let config_dir = tempdir().unwrap();
+    std::fs::write(
+        config_dir.path().join("config.json"),
+        r#"{"exit_codes": {"4xx": 10, "5xx": 11}}"#,
+    )
+    .unwrap();
 
-    let output = redirecting_command()
-        .arg("--pretty=all")
-        .arg(server.base_url())
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(&["--check-status", &server.url("/missing")])
         .assert()
-        .get_output()
-        .stdout
-        .clone();
-    assert_eq!(&output, b"\xe9");
-    mock.assert();
+        .code(10);
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(&[
+            "--check-status",
+            "--exit-code-map=5xx=20",
+            &server.url("/broken"),
+        ])
+        .assert()
+        .code(20);
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(&[&server.url("/missing")])
+        .assert()
+        .success();
+
+    // A server that doesn't speak TLS, and one that never answers
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut streams = Vec::new();
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n");
+            streams.push(stream);
+        }
+    });
+    get_command()
+        .args(&["--exit-code-map=tls=35", &format!("https://{}", addr)])
+        .assert()
+        .code(35);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut streams = Vec::new();
+        for stream in listener.incoming() {
+            streams.push(stream.unwrap());
+        }
+    });
+    get_command()
+        .args(&[
+            "--exit-code-map=timeout=28",
+            "--timeout=0.5",
+            &addr.to_string(),
+        ])
+        .assert()
+        .code(28);
+    get_command()
+        .args(&[
+            "--exit-code-map=timeout=28",
+            "--deadline=0.5",
+            &addr.to_string(),
+        ])
+        .assert()
+        .code(28);
 }
 
 #[test]
-fn binary_detection() {
+fn json_output_errors() {
     let server = MockServer::start();
-    let mock = server.mock(|_when, then| {
-        then.body(b"foo\0bar");
+    server.mock(|when, then| {
+        when.path("/missing");
+        then.status(404);
     });
+    get_command()
+        .args(&["--json-output", "--check-status", &server.url("/missing")])
+        .assert()
+        .code(4)
+        .stderr(contains(
+            r#"{"type":"error","kind":"http-status","status":404,"message":"HTTP 404 Not Found"}"#,
+        ));
 
+    // Nothing listens on a port that was just freed
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
     get_command()
-        .arg("--print=b")
-        .arg(server.base_url())
+        .args(&[
+            "--json-output",
+            "--exit-code-map=connect=7",
+            &format!("http://127.0.0.1:{}", port),
+        ])
         .assert()
-        .stdout(indoc! {r#"
-        +-----------------------------------------+
-        | NOTE: binary data not shown in terminal |
-        +-----------------------------------------+
+        .code(7)
+        .stderr(contains(r#""kind":"connect""#))
+        .stderr(contains("Error:").not());
 
-        "#});
-    mock.assert();
+    // .invalid names never resolve
+    get_command()
+        .args([
+            "--json-output",
+            "--exit-code-map=dns=6",
+            "http://nonexistent.invalid",
+        ])
+        .assert()
+        .code(6)
+        .stderr(contains(r#""kind":"dns""#));
 }
 
 #[test]
-fn streaming_binary_detection() {
+fn download_mirror() {
+    let dir = tempdir().unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/file", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = [0; 4096];
+            assert!(stream.read(&mut request).unwrap() > 0);
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\
+                    Repr-Digest: sha-256=:LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=:\r\n\r\nhel",
+                )
+                .unwrap();
+        }
+    });
     let server = MockServer::start();
-    let mock = server.mock(|_when, then| {
-        then.body(b"foo\0bar");
+    let mirror = server.mock(|when, then| {
+        when.path("/good").header("range", "bytes=3-");
+        then.status(206)
+            .header("Content-Range", "bytes 3-4/5")
+            .body("lo");
+    });
+    server.mock(|when, then| {
+        when.path("/bad");
+        then.body("jello");
     });
 
+    let outfile = dir.path().join("outfile");
     get_command()
-        .arg("--print=b")
-        .arg("--stream")
-        .arg(server.base_url())
+        .args(&["--download", "--output"])
+        .arg(&outfile)
+        .arg(format!("--mirror={}", server.url("/good")))
+        .arg(&url)
         .assert()
-        .stdout(indoc! {r#"
-        +-----------------------------------------+
-        | NOTE: binary data not shown in terminal |
-        +-----------------------------------------+
+        .success()
+        .stderr(contains("trying http://"));
+    mirror.assert();
+    assert_eq!(read_to_string(&outfile).unwrap(), "hello");
 
-        "#});
-    mock.assert();
+    get_command()
+        .args(&["--download", "--output"])
+        .arg(&outfile)
+        .arg(format!("--mirror={}", server.url("/bad")))
+        .arg(&url)
+        .assert()
+        .failure()
+        .stderr(contains("SHA-256 checksum mismatch"));
+    assert!(!outfile.exists());
+}
+
+fn get_proxy_command(
+    protocol_to_request: &str,
+    protocol_to_proxy: &str,
+    proxy_url: &str,
+) -> Command {
+    let mut cmd = get_command();
+    cmd.arg("--pretty=format")
+        .arg("--check-status")
+        .arg(format!("--proxy={}:{}", protocol_to_proxy, proxy_url))
+        .arg("GET")
+        .arg(format!("{}://example.test/get", protocol_to_request));
+    cmd
 }
 
 #[test]
-fn request_binary_detection() {
-    let mut binary_file = tempfile().unwrap();
-    binary_file.write_all(b"foo\0bar").unwrap();
-    binary_file.seek(SeekFrom::Start(0)).unwrap();
-    redirecting_command()
-        .arg("--print=B")
-        .arg("--offline")
-        .arg(":")
-        .stdin(binary_file)
-        .assert()
-        .stdout(indoc! {r#"
-        +-----------------------------------------+
-        | NOTE: binary data not shown in terminal |
-        +-----------------------------------------+
+fn proxy_http_proxy() {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(GET).header("host", "example.test");
+        then.status(200);
+    });
 
+    get_proxy_command("http", "http", &server.base_url())
+        .assert()
+        .success();
 
-        "#});
+    mock.assert();
 }
 
 #[test]
-fn timeout() {
+fn proxy_https_proxy() {
     let server = MockServer::start();
-    let mock = server.mock(|_, then| {
-        then.status(200).delay(Duration::from_secs_f32(0.5));
+
+    let mock = server.mock(|when, then| {
+        when.method(CONNECT);
+        then.status(502);
     });
 
-    get_command()
-        .arg("--timeout=0.1")
-        .arg(server.base_url())
+    get_proxy_command("https", "https", &server.base_url())
         .assert()
-        .failure()
-        .stderr(predicates::str::contains("operation timed out"));
-
+        .stderr(predicate::str::contains("unsuccessful tunnel"))
+        .failure();
     mock.assert();
 }
 
 #[test]
-fn timeout_no_limit() {
+fn proxy_auth() {
     let server = MockServer::start();
-    let mock = server.mock(|_, then| {
-        then.status(200).delay(Duration::from_secs_f32(0.5));
+    let plain = server.mock(|when, then| {
+        when.method(GET)
+            .header("Proxy-Authorization", "Basic dXNlcjpwYXNz");
+        then.status(200);
+    });
+    let tunnel = server.mock(|when, then| {
+        when.method(CONNECT)
+            .header("Proxy-Authorization", "Basic dXNlcjpwYXNz");
+        then.status(502);
     });
 
-    get_command()
-        .arg("--timeout=0")
-        .arg(server.base_url())
+    get_proxy_command("http", "all", &server.base_url())
+        .arg("--proxy-auth=user:pass")
         .assert()
         .success();
+    plain.assert();
 
-    mock.assert();
-}
+    get_proxy_command("https", "all", &server.base_url())
+        .arg("--proxy-auth=user:pass")
+        .assert()
+        .stderr(predicate::str::contains("unsuccessful tunnel"))
+        .failure();
+    tunnel.assert();
 
-#[test]
-fn timeout_invalid() {
     get_command()
-        .arg("--timeout=-0.01")
-        .arg("--offline")
-        .arg(":")
+        .args(&["--proxy-auth=user:pass", ":"])
         .assert()
         .failure()
-        .stderr(predicates::str::contains(
-            "Invalid seconds as connection timeout",
-        ));
+        .stderr(contains("--proxy-auth requires --proxy"));
 }
 
 #[test]
-fn last_supplied_proxy_wins() {
-    let first_server = MockServer::start();
-    let first_mock = first_server.mock(|when, then| {
-        when.method(GET).header("host", "example.test");
-        then.status(500);
-    });
-    let second_server = MockServer::start();
-    let second_mock = second_server.mock(|when, then| {
-        when.method(GET).header("host", "example.test");
+fn proxy_digest_auth() {
+    let server = MockServer::start();
+    let authenticated = server.mock(|when, then| {
+        when.matches(|req| {
+            let headers = req.headers.as_deref().unwrap_or(&[]);
+            headers.iter().any(|(name, value)| {
+                name.eq_ignore_ascii_case("proxy-authorization")
+                    && value.starts_with(
+                        "Digest username=\"user\", realm=\"proxy\", nonce=\"abc\", \
+                         uri=\"/get\", algorithm=MD5, response=",
+                    )
+            })
+        });
         then.status(200);
     });
+    let challenge = server.mock(|_when, then| {
+        then.status(407).header(
+            "Proxy-Authenticate",
+            "Digest realm=\"proxy\", nonce=\"abc\", qop=\"auth\"",
+        );
+    });
 
-    let mut cmd = get_command();
-    cmd.args(&[
-        format!("--proxy=http:{}", first_server.base_url()).as_str(),
-        format!("--proxy=http:{}", second_server.base_url()).as_str(),
-        "GET",
-        "http://example.test",
-    ])
-    .assert()
-    .success();
-
-    first_mock.assert_hits(0);
-    second_mock.assert();
-}
-
-#[test]
-fn proxy_multiple_valid_proxies() {
-    let mut cmd = get_command();
-    cmd.arg("--offline")
-        .arg("--pretty=format")
-        .arg("--proxy=http:https://127.0.0.1:8000")
-        .arg("--proxy=https:socks5://127.0.0.1:8000")
-        .arg("--proxy=all:http://127.0.0.1:8000")
-        .arg("GET")
-        .arg("http://httpbin.org/get");
-
-    cmd.assert().success();
+    get_proxy_command("http", "http", &server.base_url())
+        .arg("--proxy-auth=user:pass")
+        .assert()
+        .success();
+    authenticated.assert();
+    challenge.assert();
 }
 
 #[test]
-fn check_status() {
+fn trace() {
     let server = MockServer::start();
     let mock = server.mock(|_when, then| {
-        then.status(404);
+        then.header("date", "N/A")
+            .body(b"\x89PNG\r\n\x1a\n\0\0\0\r".as_ref());
     });
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("trace.log");
 
-    get_command()
-        .arg("--check-status")
-        .arg(server.base_url())
+    redirecting_command()
+        .arg(format!("--trace={}", path.display()))
+        .args(&[
+            "--print=b",
+            "--ignore-stdin",
+            &server.base_url(),
+            "Authorization:Bearer secret",
+            "x=y",
+        ])
         .assert()
-        .code(4)
+        .success()
+        .stdout(b"\x89PNG\r\n\x1a\n\0\0\0\r".as_ref())
         .stderr("");
     mock.assert();
+
+    let trace = read_to_string(&path).unwrap();
+    // Leave out the timestamps, like "14:03:27.123456 "
+    let trace: Vec<&str> = trace
+        .lines()
+        .map(|line| match line.get(..16) {
+            Some(time) if time.as_bytes()[2] == b':' && time.ends_with(' ') => &line[16..],
+            _ => line,
+        })
+        .collect();
+    assert!(trace[0].starts_with("== Info: xh "));
+    assert!(trace[1].starts_with("=> Send header, "));
+    assert_eq!(trace[2], "POST / HTTP/1.1");
+    assert!(trace.contains(&"authorization: Bearer ********"));
+    assert!(!trace.iter().any(|line| line.contains("secret")));
+    assert_eq!(
+        &trace[trace.len() - 9..],
+        &[
+            "=> Send data, 9 bytes",
+            "{\"x\":\"y\"}",
+            "<= Recv header, 50 bytes",
+            "HTTP/1.1 200 OK",
+            "date: N/A",
+            "content-length: 12",
+            "",
+            "<= Recv data, 12 bytes",
+            "0000: 89 50 4e 47 0d 0a 1a 0a 00 00 00 0d             .PNG........",
+        ][..]
+    );
+
+    // A body read from a file is streamed, so it's left out
+    let body = dir.path().join("body.txt");
+    std::fs::write(&body, "from a file").unwrap();
+    redirecting_command()
+        .arg(format!("--trace={}", path.display()))
+        .args(["--print=b", "--ignore-stdin", &server.base_url()])
+        .arg(format!("@{}", body.display()))
+        .assert()
+        .success();
+    let trace = read_to_string(&path).unwrap();
+    assert!(trace.contains("== Info: The request body is streamed, which isn't traced\n"));
+    assert!(!trace.contains("from a file"));
 }
 
 #[test]
-fn user_password_auth() {
+fn audit_log() {
     let server = MockServer::start();
-    let mock = server.mock(|when, _then| {
-        when.header("Authorization", "Basic dXNlcjpwYXNz");
+    let mock = server.mock(|_when, then| {
+        then.status(201).header("date", "N/A").body("created");
     });
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    std::fs::write(
+        dir.path().join("config.json"),
+        serde_json::json!({ "audit_log": path }).to_string(),
+    )
+    .unwrap();
 
     get_command()
-        .arg("--auth=user:pass")
-        .arg(server.base_url())
-        .assert();
-    mock.assert();
+        .env("XH_CONFIG_DIR", dir.path())
+        .args(&[&server.base_url(), "Authorization:Bearer secret", "x=y"])
+        .assert()
+        .success();
+    get_command()
+        .arg(format!("--audit-log={}", path.display()))
+        .args(&[
+            "--show-secrets",
+            &server.base_url(),
+            "Authorization:Bearer secret",
+        ])
+        .assert()
+        .success();
+    mock.assert_hits(2);
+
+    let log = read_to_string(&path).unwrap();
+    let records: Vec<serde_json::Value> = log
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 2);
+    let request = &records[0]["request"];
+    assert_eq!(request["method"], "POST");
+    assert_eq!(request["body"], r#"{"x":"y"}"#);
+    assert!(request["headers"]
+        .as_array()
+        .unwrap()
+        .contains(&serde_json::json!(["authorization", "Bearer ********"])));
+    assert_eq!(records[0]["response"]["status"], 201);
+    assert_eq!(records[0]["response"]["body_size"], 7);
+    assert!(!log.lines().next().unwrap().contains("secret"));
+    assert!(log.lines().nth(1).unwrap().contains("Bearer secret"));
 }
 
 #[test]
-fn netrc_env_user_password_auth() {
+fn paginate() {
     let server = MockServer::start();
-    let mock = server.mock(|when, _then| {
-        when.header("Authorization", "Basic dXNlcjpwYXNz");
+    let first = server.mock(|when, then| {
+        when.path("/items").query_param("page", "1");
+        then.header("Link", r#"</items?page=2>; rel="next""#)
+            .body("one\n");
+    });
+    let second = server.mock(|when, then| {
+        when.path("/items").query_param("page", "2");
+        then.header("Link", r#"</items?page=3>; rel="next""#)
+            .body("two\n");
+    });
+    let third = server.mock(|when, then| {
+        when.path("/items").query_param("page", "3");
+        then.body("three\n");
     });
 
-    let mut netrc = tempfile::NamedTempFile::new().unwrap();
-    writeln!(
-        netrc,
-        "machine {}\nlogin user\npassword pass",
-        server.host()
-    )
-    .unwrap();
-
-    get_command()
-        .env("NETRC", netrc.path())
-        .arg(server.base_url())
-        .assert();
-    mock.assert();
+    redirecting_command()
+        .args(&["--paginate", &server.url("/items?page=1")])
+        .assert()
+        .success()
+        .stdout("one\ntwo\nthree\n");
+    redirecting_command()
+        .args(&["--paginate", "--max-pages=2", &server.url("/items?page=1")])
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+    first.assert_hits(2);
+    second.assert_hits(2);
+    third.assert_hits(1);
 }
 
 #[test]
-fn netrc_file_user_password_auth() {
-    for netrc_file in [".netrc", "_netrc"].iter() {
-        let server = MockServer::start();
+fn paginate_json_cursor() {
+    let server = MockServer::start();
+    let first = server.mock(|when, then| {
+        when.query_param("limit", "1")
+            .query_param("cursor", "start");
+        then.header("Content-Type", "application/json")
+            .body(r#"{"items": [1], "next": "abc"}"#);
+    });
+    let second = server.mock(|when, then| {
+        when.query_param("limit", "1").query_param("cursor", "abc");
+        then.header("Content-Type", "application/json")
+            .body(r#"{"items": [2], "next": null}"#);
+    });
+
+    get_command()
+        .args(&[
+            "--paginate=next:cursor",
+            "--print=b",
+            &server.base_url(),
+            "limit==1",
+            "cursor==start",
+        ])
+        .assert()
+        .success()
+        .stdout(indoc! {r#"
+            {
+                "items": [
+                    1
+                ],
+                "next": "abc"
+            }
+
+
+            {
+                "items": [
+                    2
+                ],
+                "next": null
+            }
+
+
+        "#});
+    first.assert();
+    second.assert();
+}
+
+#[test]
+fn respect_retry_after() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.status(429).header("Retry-After", "0");
+    });
+
+    get_command()
+        .args(&["--print=", "--respect-retry-after", &server.base_url()])
+        .assert()
+        .success()
+        .stderr(
+            "xh: warning: HTTP 429 Too Many Requests, sending the request again in 0s\n".repeat(3),
+        );
+    mock.assert_hits(4);
+
+    get_command()
+        .args(&["--print=", &server.base_url()])
+        .assert()
+        .success()
+        .stderr("");
+    mock.assert_hits(5);
+}
+
+#[test]
+fn template() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.status(201).header("x-foo", "Bar").body("a body");
+    });
+
+    redirecting_command()
+        .args(&[
+            "--print=b",
+            r"--template={method} {status} {reason} {header.X-Foo} {size_download}\n",
+            "post",
+            &server.base_url(),
+        ])
+        .assert()
+        .success()
+        .stdout("a bodyPOST 201 Created Bar 6\n");
+
+    // Nothing else is printed with --quiet
+    redirecting_command()
+        .args(&[
+            "-q",
+            "--template={{{url}}} {header.missing}",
+            &server.url("/x"),
+        ])
+        .assert()
+        .success()
+        .stdout(format!("{{{}}} ", server.url("/x")));
+    mock.assert_hits(2);
+
+    redirecting_command()
+        .args(&["--template={size}", ":"])
+        .assert()
+        .failure()
+        .stderr(contains("Unknown variable {size} in --template"));
+}
+
+#[test]
+fn meta() {
+    let server = MockServer::start();
+    server.mock(|_when, then| {
+        then.body("a body");
+    });
+    // localhost has to be looked up, unlike the mock server's own address
+    let url = format!("localhost:{}", server.port());
+
+    redirecting_command()
+        .args(&["--print=b", "--meta", &url])
+        .assert()
+        .success()
+        .stdout(contains("a body\ndns-lookup: "))
+        .stdout(contains("\ntime-to-first-byte: "))
+        .stdout(contains("\nbody-download: "))
+        .stdout(contains("\ntotal: "));
+
+    let output = redirecting_command()
+        .args(&["-q", "--meta", "--json-output", &url])
+        .output()
+        .unwrap();
+    assert!(output.stdout.is_empty());
+    let meta: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(meta["type"], "meta");
+    assert!(meta["dns_lookup_seconds"].is_f64());
+    assert!(
+        meta["total_seconds"].as_f64().unwrap() >= meta["first_byte_seconds"].as_f64().unwrap()
+    );
+
+    redirecting_command()
+        .args(&["-q", "--template={time_namelookup}", &server.base_url()])
+        .assert()
+        .success()
+        .stdout("0.000000");
+}
+
+#[test]
+fn verbose_dns() {
+    let server = MockServer::start();
+    server.mock(|_when, then| {
+        then.body("ok");
+    });
+    get_command()
+        .args(["-v", "--meta", &format!("localhost:{}", server.port())])
+        .assert()
+        .success()
+        .stdout(
+            predicates::str::is_match("\ndns: localhost resolved to [^\n]*127\\.0\\.0\\.1")
+                .unwrap(),
+        );
+
+    // The host is only looked up separately with --meta
+    get_command()
+        .args(["-v", &format!("localhost:{}", server.port())])
+        .assert()
+        .success()
+        .stdout(contains("dns:").not());
+
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    get_command()
+        .args(["-v", "--meta", &format!("localhost:{}", port)])
+        .assert()
+        .failure()
+        .stderr(contains(format!("xh: 127.0.0.1:{}: ", port)));
+}
+
+#[test]
+fn traceparent() {
+    let output = get_command()
+        .args(&[
+            "--offline",
+            "--print=H",
+            "--traceparent",
+            "--tracestate=congo=t61rcWkgMzE",
+            ":",
+        ])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let trace_id = stderr.strip_prefix("xh: trace id ").unwrap().trim_end();
+    assert_eq!(trace_id.len(), 32);
+    assert!(stdout.contains(&format!("traceparent: 00-{}-", trace_id)));
+    assert!(stdout.contains("-01\ntracestate: congo=t61rcWkgMzE\n"));
+
+    get_command()
+        .args(&["--offline", "--traceparent=not-sampled", ":"])
+        .assert()
+        .stdout(
+            predicates::str::is_match("traceparent: 00-[0-9a-f]{32}-[0-9a-f]{16}-00\n").unwrap(),
+        );
+
+    get_command()
+        .args(&["--offline", "--traceparent", ":", "traceparent:00-1-2-01"])
+        .assert()
+        .failure()
+        .stderr(contains("can't be combined with a traceparent header"));
+}
+
+#[cfg(not(feature = "otel"))]
+#[test]
+fn otel_endpoint_needs_feature() {
+    get_command()
+        .args(&["--offline", "--otel-endpoint=http://localhost:4318", ":"])
+        .assert()
+        .failure()
+        .stderr(contains("needs xh to be built with the otel feature"));
+}
+
+#[test]
+fn reject_dot_segments() {
+    get_command()
+        .args(&["--offline", "--print=H", ":/static/../admin"])
+        .assert()
+        .success()
+        .stdout(contains("GET /admin HTTP/1.1"))
+        .stderr("");
+
+    get_command()
+        .args([
+            "--offline",
+            "--reject-dot-segments",
+            ":/static/%2e%2e/admin",
+        ])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "The path of :/static/%2e%2e/admin has . or .. segments, which can't be sent unchanged",
+        ));
+
+    get_command()
+        .args([
+            "--offline",
+            "--print=H",
+            "--reject-dot-segments",
+            ":/a//b/c%2Fd?x=../y",
+        ])
+        .assert()
+        .success()
+        .stdout(contains("GET /a//b/c%2Fd?x=../y HTTP/1.1"))
+        .stderr("");
+}
+
+#[test]
+fn internationalized_domain_name() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.header("host", "xn--bcher-kva.example");
+        then.status(200);
+    });
+
+    get_command()
+        .arg(format!("--proxy=http:{}", server.base_url()))
+        .args(&["-v", "--check-status", "http://bücher.example"])
+        .assert()
+        .success()
+        .stdout(contains("host: xn--bcher-kva.example (bücher.example)"));
+    mock.assert();
+}
+
+#[test]
+fn proxy_from_environment() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.method(GET).header("host", "example.test");
+        then.status(200);
+    });
+
+    get_command()
+        .env("http_proxy", server.address().to_string())
+        .args(&["-v", "--check-status", "http://example.test/get"])
+        .assert()
+        .success()
+        .stdout(contains(format!(
+            "proxy: {} (from http_proxy)",
+            server.url("/")
+        )));
+    mock.assert();
+
+    // The bypassed request goes to a host that doesn't exist
+    get_command()
+        .env("HTTP_PROXY", server.base_url())
+        .env("NO_PROXY", "localhost, .test")
+        .args(&["-v", "http://example.test/get"])
+        .assert()
+        .failure();
+    mock.assert_hits(1);
+
+    get_command()
+        .env("all_proxy", server.base_url())
+        .env("no_proxy", "127.0.0.0/8")
+        .args(&["-v", &server.url("/get")])
+        .assert()
+        .stdout(contains(
+            "proxy: none (all_proxy is set, but no_proxy matches \"127.0.0.0/8\")",
+        ));
+}
+
+#[test]
+fn download_generated_filename() {
+    let dir = tempdir().unwrap();
+    let server = MockServer::start();
+    server.mock(|_when, then| {
+        then.header("Content-Type", "application/json").body("file");
+    });
+
+    get_command()
+        .arg("--download")
+        .arg(server.url("/foo/bar/"))
+        .current_dir(&dir)
+        .assert();
+
+    get_command()
+        .arg("--download")
+        .arg(server.url("/foo/bar/"))
+        .current_dir(&dir)
+        .assert();
+
+    assert_eq!(read_to_string(dir.path().join("bar.json")).unwrap(), "file");
+    assert_eq!(
+        read_to_string(dir.path().join("bar.json-1")).unwrap(),
+        "file"
+    );
+}
+
+#[test]
+fn download_summary() {
+    let dir = tempdir().unwrap();
+    let server = MockServer::start();
+    server.mock(|_when, then| {
+        then.body("file");
+    });
+
+    let outfile = dir.path().join("outfile");
+    get_command()
+        .args(&["--download", "--output"])
+        .arg(&outfile)
+        .arg(server.base_url())
+        .assert()
+        .success()
+        .stderr(contains("in less than a second").and(contains("Saved to")));
+
+    let output = get_command()
+        .args(&["--download", "--quiet", "--json-output", "--output"])
+        .arg(&outfile)
+        .arg(server.base_url())
+        .output()
+        .unwrap();
+    let summary: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(summary["type"], "download");
+    assert_eq!(summary["bytes"], 4);
+    assert_eq!(summary["path"], outfile.to_str().unwrap());
+    assert!(summary["connect_seconds"].is_f64());
+}
+
+#[test]
+fn download_filename_collision() {
+    let dir = tempdir().unwrap();
+    let server = MockServer::start();
+    server.mock(|_when, then| {
+        then.header("Content-Disposition", r#"attachment; filename="foo.bar""#)
+            .body("new");
+    });
+    std::fs::write(dir.path().join("foo.bar"), "old").unwrap();
+
+    get_command()
+        .args(&["--download", "--number-format=%b (%n)%e"])
+        .arg(server.base_url())
+        .current_dir(&dir)
+        .assert()
+        .success();
+    assert_eq!(
+        read_to_string(dir.path().join("foo (1).bar")).unwrap(),
+        "new"
+    );
+
+    get_command()
+        .args(&["--download", "--no-clobber"])
+        .arg(server.base_url())
+        .current_dir(&dir)
+        .assert()
+        .failure()
+        .stderr(contains("\"foo.bar\" already exists"));
+    get_command()
+        .args(&["--download", "--no-clobber", "--output=foo.bar"])
+        .arg(server.base_url())
+        .current_dir(&dir)
+        .assert()
+        .failure();
+    assert_eq!(read_to_string(dir.path().join("foo.bar")).unwrap(), "old");
+
+    get_command()
+        .args(&["--download", "--overwrite"])
+        .arg(server.base_url())
+        .current_dir(&dir)
+        .assert()
+        .success();
+    assert_eq!(read_to_string(dir.path().join("foo.bar")).unwrap(), "new");
+}
+
+#[test]
+fn download_supplied_filename() {
+    let dir = tempdir().unwrap();
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.header("Content-Disposition", r#"attachment; filename="foo.bar""#)
+            .body("file");
+    });
+
+    get_command()
+        .arg("--download")
+        .arg(server.base_url())
+        .current_dir(&dir)
+        .assert();
+    mock.assert();
+    assert_eq!(read_to_string(dir.path().join("foo.bar")).unwrap(), "file");
+}
+
+#[test]
+fn download_supplied_unquoted_filename() {
+    let dir = tempdir().unwrap();
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.header("Content-Disposition", r#"attachment; filename=foo bar baz"#)
+            .body("file");
+    });
+
+    get_command()
+        .arg("--download")
+        .arg(server.base_url())
+        .current_dir(&dir)
+        .assert();
+    mock.assert();
+    assert_eq!(
+        read_to_string(dir.path().join("foo bar baz")).unwrap(),
+        "file"
+    );
+}
+
+#[test]
+fn decode() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.header("Content-Type", "text/plain; charset=latin1")
+            .body(b"\xe9");
+    });
+
+    get_command()
+        .arg("--print=b")
+        .arg(server.base_url())
+        .assert()
+        .stdout("é\n");
+    mock.assert();
+}
+
+#[test]
+fn proxy_all_proxy() {
+    let server = MockServer::start();
+
+    let mock = server.mock(|when, then| {
+        when.method(CONNECT);
+        then.status(502);
+    });
+
+    get_proxy_command("https", "all", &server.base_url())
+        .assert()
+        .stderr(predicate::str::contains("unsuccessful tunnel"))
+        .failure();
+    mock.assert();
+
+    get_proxy_command("http", "all", &server.base_url())
+        .assert()
+        .failure();
+    mock.assert();
+}
+
+#[test]
+fn streaming_decode() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.header("Content-Type", "text/plain; charset=latin1")
+            .body(b"\xe9");
+    });
+
+    get_command()
+        .arg("--print=b")
+        .arg("--stream")
+        .arg(server.base_url())
+        .assert()
+        .stdout("é\n");
+    mock.assert();
+}
+
+#[test]
+fn only_decode_for_terminal() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.header("Content-Type", "text/plain; charset=latin1")
+            .body(b"\xe9");
+    });
+
+    let output = redirecting_command()
+        .arg(server.base_url())
+        .assert()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(&output, b"\xe9"); // .stdout() doesn't support byte slices
+    mock.assert();
+}
+
+#[test]
+fn do_decode_if_formatted() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.header("Content-Type", "text/plain; charset=latin1")
+            .body(b"\xe9");
+    });
+
+    redirecting_command()
+        .arg("--pretty=all")
+        .arg(server.base_url())
+        .assert()
+        .stdout("é");
+    mock.assert();
+}
+
+#[test]
+fn never_decode_if_binary() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        // this mimetype with a charset may actually be incoherent
+        then.header("Content-Type", "application/octet-stream; charset=latin1")
+            .body(b"\xe9");
+    });
+
+    let output = redirecting_command()
+        .arg("--pretty=all")
+        .arg(server.base_url())
+        .assert()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(&output, b"\xe9");
+    mock.assert();
+}
+
+#[test]
+fn large_binary_body_passed_through() {
+    let body: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.header("Content-Type", "application/octet-stream")
+            .body(&body);
+    });
+
+    let output = redirecting_command()
+        .arg(server.base_url())
+        .assert()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(output, body);
+    mock.assert();
+}
+
+#[test]
+fn binary_detection() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.body(b"foo\0bar");
+    });
+
+    get_command()
+        .arg("--print=b")
+        .arg(server.base_url())
+        .assert()
+        .stdout(indoc! {r#"
+        +-----------------------------------------+
+        | NOTE: binary data not shown in terminal |
+        +-----------------------------------------+
+
+        "#});
+    mock.assert();
+}
+
+#[test]
+fn streaming_binary_detection() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.body(b"foo\0bar");
+    });
+
+    get_command()
+        .arg("--print=b")
+        .arg("--stream")
+        .arg(server.base_url())
+        .assert()
+        .stdout(indoc! {r#"
+        +-----------------------------------------+
+        | NOTE: binary data not shown in terminal |
+        +-----------------------------------------+
+
+        "#});
+    mock.assert();
+}
+
+#[test]
+fn binary_detection_overrides() {
+    let server = MockServer::start();
+    let control = server.mock(|when, then| {
+        when.path("/control");
+        then.body(b"\x01\x02\x03\x04\x05ab");
+    });
+    let nul = server.mock(|when, then| {
+        when.path("/nul");
+        then.body(b"foo\0bar");
+    });
+    let json = server.mock(|when, then| {
+        when.path("/json");
+        then.header("Content-Type", "application/octet-stream")
+            .body(r#"{"a":1}"#);
+    });
+    let suppressed = indoc! {r#"
+        +-----------------------------------------+
+        | NOTE: binary data not shown in terminal |
+        +-----------------------------------------+
+
+    "#};
+
+    get_command()
+        .arg("--print=b")
+        .arg(server.url("/control"))
+        .assert()
+        .stdout(suppressed);
+    get_command()
+        .arg("--print=b")
+        .arg("--force-text")
+        .arg(server.url("/nul"))
+        .assert()
+        .stdout("foo\0bar\n");
+    get_command()
+        .arg("--print=b")
+        .arg("--force-binary")
+        .arg(server.url("/json"))
+        .assert()
+        .stdout(suppressed);
+    redirecting_command()
+        .arg("--print=b")
+        .arg("--pretty=format")
+        .arg("--force-text")
+        .arg(server.url("/json"))
+        .assert()
+        .stdout("{\n    \"a\": 1\n}\n\n");
+    control.assert();
+    nul.assert();
+    json.assert_hits(2);
+}
+
+#[test]
+fn body_line_controls() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.path("/");
+        then.body("first line\nsecond\nthird\n");
+    });
+
+    get_command()
+        .arg("--print=b")
+        .arg("--max-lines=2")
+        .arg(server.base_url())
+        .assert()
+        .stdout("first line\nsecond\n[output truncated after 2 lines]\n");
+    get_command()
+        .arg("--print=b")
+        .arg("--wrap=5")
+        .arg("--line-numbers")
+        .arg(server.base_url())
+        .assert()
+        .stdout("    1 | first\n      |  line\n    2 | secon\n      | d\n    3 | third\n\n");
+    get_command()
+        .arg("--print=b")
+        .arg("--max-lines=3")
+        .arg(server.base_url())
+        .assert()
+        .stdout("first line\nsecond\nthird\n\n");
+    mock.assert_hits(3);
+}
+
+#[test]
+fn multipart_response() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.path("/");
+        then.header("Content-Type", "multipart/mixed; boundary=xyz")
+            .body(
+                &b"preamble\r\n\
+                --xyz\r\n\
+                Content-Type: application/json\r\n\
+                \r\n\
+                {\"a\":1}\r\n\
+                --xyz\r\n\
+                Content-Type: application/octet-stream\r\n\
+                Content-Range: bytes 0-3/10\r\n\
+                \r\n\
+                \x00\x01\x02\x03\r\n\
+                --xyz\r\n\
+                \r\n\
+                plain\r\n\
+                --xyz--\r\n"[..],
+            );
+    });
+
+    get_command()
+        .arg("--print=b")
+        .arg(server.base_url())
+        .assert()
+        .stdout(indoc! {r#"
+            --xyz
+            content-type: application/json
+
+            {
+                "a": 1
+            }
+
+
+            --xyz
+            content-range: bytes 0-3/10
+            content-type: application/octet-stream
+
+            [binary data, 4 bytes not shown]
+            --xyz
+
+            plain
+            --xyz--
+        "#});
+    mock.assert();
+}
+
+#[test]
+fn response_charset() {
+    let server = MockServer::start();
+    let header = server.mock(|when, then| {
+        when.path("/header");
+        then.header("Content-Type", "text/plain; charset=iso-8859-1")
+            .body(b"caf\xe9 au lait, s'il vous pla\xeet");
+    });
+    let meta = server.mock(|when, then| {
+        when.path("/meta");
+        then.header("Content-Type", "text/html")
+            .body(b"<meta charset=\"shift_jis\">\x93\xfa\x96\x7b");
+    });
+
+    get_command()
+        .arg("--print=b")
+        .arg(server.url("/header"))
+        .assert()
+        .stdout("caf\u{e9} au lait, s'il vous pla\u{ee}t\n");
+    get_command()
+        .arg("--print=b")
+        .arg(server.url("/meta"))
+        .assert()
+        .stdout("<meta charset=\"shift_jis\">\u{65e5}\u{672c}\n");
+    get_command()
+        .arg("--print=b")
+        .arg("--response-charset=utf-8")
+        .arg(server.url("/header"))
+        .assert()
+        .stdout("caf\u{fffd} au lait, s'il vous pla\u{fffd}t\n");
+    header.assert_hits(2);
+    meta.assert();
+}
+
+#[test]
+fn markdown_rendering() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.path("/");
+        then.header("Content-Type", "text/markdown")
+            .body("## Fixed\n\n* **bold**\n\n```json\n[1]\n```\n");
+    });
+
+    get_command()
+        .args(&["--print=b", "--pretty=all", "--render-markdown"])
+        .arg(server.base_url())
+        .assert()
+        .stdout(
+            "\x1b[0m\x1b[1m## \x1b[0m\x1b[0m\x1b[1mFixed\x1b[0m\n\n\
+             • \x1b[0m\x1b[1mbold\x1b[0m\n\n    \
+             \x1b[0m[\x1b[0m\x1b[34m1\x1b[0m]\n\x1b[0m\n",
+        );
+    get_command()
+        .args(&["--print=b", "--pretty=all"])
+        .arg(server.base_url())
+        .assert()
+        .stdout("## Fixed\n\n* **bold**\n\n```json\n[1]\n```\n\n");
+    mock.assert_hits(2);
+}
+
+#[test]
+fn minified_css() {
+    let server = MockServer::start();
+    let minified = "a{color:red}".repeat(20);
+    let mock = server.mock(|_when, then| {
+        then.header("Content-Type", "text/css").body(&minified);
+    });
+
+    get_command()
+        .args(&["--print=b", "--pretty=format", &server.base_url()])
+        .assert()
+        .stdout(format!(
+            "{}\n\n",
+            "a {\n    color: red\n}\n".repeat(20).trim_end()
+        ));
+    get_command()
+        .args(&["--print=b", "--pretty=none", &server.base_url()])
+        .assert()
+        .stdout(format!("{}\n", minified));
+    mock.assert_hits(2);
+}
+
+#[test]
+fn problem_details() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.status(403)
+            .header("Content-Type", "application/problem+json")
+            .body(r#"{"type": "https://example.com/probs/out-of-credit", "title": "Out of credit", "detail": "Your balance is 30, but that costs 50."}"#);
+    });
+
+    get_command()
+        .args(&["--print=b", &server.base_url()])
+        .assert()
+        .stdout(indoc! {r#"
+            Out of credit
+              type      https://example.com/probs/out-of-credit
+              detail    Your balance is 30, but that costs 50.
+
+            {
+                "type": "https://example.com/probs/out-of-credit",
+                "title": "Out of credit",
+                "detail": "Your balance is 30, but that costs 50."
+            }
+
+
+        "#});
+    mock.assert();
+}
+
+#[test]
+fn csv_table() {
+    let server = MockServer::start();
+    let csv = server.mock(|when, then| {
+        when.path("/csv");
+        then.header("Content-Type", "text/csv")
+            .body("name,qty\napple,3\nbanana,12\n");
+    });
+    let plain = server.mock(|when, then| {
+        when.path("/plain");
+        then.body("name,note\napple,\"crisp, red\"\n");
+    });
+
+    get_command()
+        .arg("--print=b")
+        .arg(server.url("/csv"))
+        .assert()
+        .stdout(indoc! {"
+            name    qty
+            ──────  ───
+            apple     3
+            banana   12
+
+        "});
+    get_command()
+        .args(&["--print=b", "--table", "--column-width=6"])
+        .arg(server.url("/plain"))
+        .assert()
+        .stdout(indoc! {"
+            name   note
+            ─────  ──────
+            apple  crisp…
+
+        "});
+    redirecting_command()
+        .arg(server.url("/csv"))
+        .assert()
+        .stdout("name,qty\napple,3\nbanana,12\n");
+    csv.assert_hits(2);
+    plain.assert();
+}
+
+#[test]
+fn request_binary_detection() {
+    let mut binary_file = tempfile().unwrap();
+    binary_file.write_all(b"foo\0bar").unwrap();
+    binary_file.seek(SeekFrom::Start(0)).unwrap();
+    redirecting_command()
+        .arg("--print=B")
+        .arg("--offline")
+        .arg(":")
+        .stdin(binary_file)
+        .assert()
+        .stdout(indoc! {r#"
+        +-----------------------------------------+
+        | NOTE: binary data not shown in terminal |
+        +-----------------------------------------+
+
+
+        "#});
+}
+
+#[test]
+fn timestamp_generators() {
+    get_command()
+        .arg("--print=HB")
+        .arg("--offline")
+        .arg(":")
+        .arg("date:@http-date")
+        .arg("created=@now")
+        .arg("expires:=@unix+2h")
+        .assert()
+        .stdout(
+            predicate::str::is_match(r"date: \w{3}, \d{2} \w{3} \d{4} \d{2}:\d{2}:\d{2} GMT")
+                .unwrap(),
+        )
+        .stdout(
+            predicate::str::is_match(r#""created": "\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}Z""#)
+                .unwrap(),
+        )
+        .stdout(predicate::str::is_match(r#""expires": \d+"#).unwrap());
+}
+
+#[test]
+fn output_headers() {
+    let server = MockServer::start();
+    let mock = server.mock(|_, then| {
+        then.status(201).header("x-foo", "bar").body("body");
+    });
+    let dir = tempdir().unwrap();
+
+    get_command()
+        .current_dir(&dir)
+        .arg(server.base_url())
+        .arg("--output=body.txt")
+        .arg("--output-headers=%o.%s.headers")
+        .assert()
+        .success();
+    mock.assert();
+
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("body.txt")).unwrap(),
+        "body"
+    );
+    let headers = std::fs::read_to_string(dir.path().join("body.txt.201.headers")).unwrap();
+    assert!(headers.starts_with("HTTP/1.1 201 Created\n"));
+    assert!(headers.contains("x-foo: bar\n"));
+}
+
+#[test]
+fn timeout() {
+    let server = MockServer::start();
+    let mock = server.mock(|_, then| {
+        then.status(200).delay(Duration::from_secs_f32(0.5));
+    });
+
+    get_command()
+        .arg("--timeout=0.1")
+        .arg(server.base_url())
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("operation timed out"));
+
+    mock.assert();
+}
+
+#[test]
+fn timeout_no_limit() {
+    let server = MockServer::start();
+    let mock = server.mock(|_, then| {
+        then.status(200).delay(Duration::from_secs_f32(0.5));
+    });
+
+    get_command()
+        .arg("--timeout=0")
+        .arg(server.base_url())
+        .assert()
+        .success();
+
+    mock.assert();
+}
+
+#[test]
+fn timeout_invalid() {
+    get_command()
+        .arg("--timeout=-0.01")
+        .arg("--offline")
+        .arg(":")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Invalid seconds as connection timeout",
+        ));
+}
+
+#[test]
+fn last_supplied_proxy_wins() {
+    let first_server = MockServer::start();
+    let first_mock = first_server.mock(|when, then| {
+        when.method(GET).header("host", "example.test");
+        then.status(500);
+    });
+    let second_server = MockServer::start();
+    let second_mock = second_server.mock(|when, then| {
+        when.method(GET).header("host", "example.test");
+        then.status(200);
+    });
+
+    let mut cmd = get_command();
+    cmd.args(&[
+        format!("--proxy=http:{}", first_server.base_url()).as_str(),
+        format!("--proxy=http:{}", second_server.base_url()).as_str(),
+        "GET",
+        "http://example.test",
+    ])
+    .assert()
+    .success();
+
+    first_mock.assert_hits(0);
+    second_mock.assert();
+}
+
+#[test]
+fn proxy_multiple_valid_proxies() {
+    let mut cmd = get_command();
+    cmd.arg("--offline")
+        .arg("--pretty=format")
+        .arg("--proxy=http:https://127.0.0.1:8000")
+        .arg("--proxy=https:socks5://127.0.0.1:8000")
+        .arg("--proxy=all:http://127.0.0.1:8000")
+        .arg("GET")
+        .arg("http://httpbin.org/get");
+
+    cmd.assert().success();
+}
+
+#[test]
+fn check_status() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.status(404);
+    });
+
+    get_command()
+        .arg("--check-status")
+        .arg(server.base_url())
+        .assert()
+        .code(4)
+        .stderr("");
+    mock.assert();
+}
+
+#[test]
+fn multiple_urls() {
+    let server = MockServer::start();
+    let found = server.mock(|when, then| {
+        when.path("/found").header("x-trace", "1");
+        then.body("found\n");
+    });
+    let missing = server.mock(|when, then| {
+        when.path("/missing").header("x-trace", "1");
+        then.status(404);
+    });
+
+    get_command()
+        .args(&["--check-status", "--body"])
+        .arg(server.url("/missing"))
+        .arg(server.url("/found"))
+        .arg("x-trace:1")
+        .assert()
+        .code(4)
+        .stdout(
+            predicate::str::is_match(
+                "(?s)^==> http://.*/missing <==\n.*\n==> http://.*/found <==\nfound",
+            )
+            .unwrap(),
+        );
+    found.assert();
+    missing.assert();
+}
+
+#[test]
+fn urls_from_stdin() {
+    let server = MockServer::start();
+    let get = server.mock(|when, then| {
+        when.method(GET).path("/a").header("x-trace", "1");
+        then.body("a\n");
+    });
+    let delete = server.mock(|when, then| {
+        when.method(DELETE).path("/b").header("x-trace", "1");
+        then.status(204);
+    });
+
+    let mut urls = tempfile().unwrap();
+    writeln!(urls, "# checked nightly\n{}\n", server.url("/a")).unwrap();
+    writeln!(urls, "delete {}", server.url("/b")).unwrap();
+    urls.seek(SeekFrom::Start(0)).unwrap();
+    get_command()
+        .args(&["--urls-from=-", "--body", "x-trace:1"])
+        .stdin(urls)
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::is_match("(?s)^==> http://.*/a <==\na\n.*==> DELETE http://.*/b <==")
+                .unwrap(),
+        );
+    get.assert();
+    delete.assert();
+}
+
+#[test]
+fn serve() {
+    let mut server = redirecting_command()
+        .args([
+            "--serve",
+            "--port=0",
+            "--reply-status=201",
+            r#"--reply-body={"ok":true}"#,
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(server.stderr.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let url = line
+        .split_whitespace()
+        .find(|word| word.starts_with("http://"))
+        .unwrap()
+        .trim_end_matches(',')
+        .to_string();
+
+    get_command()
+        .args(&["--check-status", "--body", "post"])
+        .arg(format!("{}/hook", url))
+        .arg("name=xh")
+        .assert()
+        .success()
+        .stdout(contains("\"ok\": true"));
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+    let mut output = String::new();
+    server
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut output)
+        .unwrap();
+    assert!(output.starts_with("POST /hook HTTP/1.1\n"), "{}", output);
+    assert!(output.contains(r#"{"name":"xh"}"#), "{}", output);
+}
+
+#[test]
+fn run_collection() {
+    let server = MockServer::start();
+    let login = server.mock(|when, then| {
+        when.method(POST)
+            .path("/login")
+            .json_body(json!({"user": "alice"}));
+        then.status(200);
+    });
+    let missing = server.mock(|when, then| {
+        when.method(GET).path("/missing");
+        then.status(404);
+    });
+
+    let dir = tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("requests.toml"),
+        indoc! {r#"
+            [login]
+            method = "post"
+            url = "{{base}}/login"
+            items = ["user={{user}}"]
+
+            [missing]
+            url = "{{base}}/missing"
+            status = 404
+
+            [wrong]
+            url = "{{base}}/missing"
+            status = 200
+        "#},
+    )
+    .unwrap();
+
+    get_command()
+        .current_dir(dir.path())
+        .args(["--run=login", "--run=missing", "--var=user=alice"])
+        .arg(format!("--var=base={}", server.base_url()))
+        .assert()
+        .success()
+        .stdout(contains("==> login <==").and(contains("==> missing <==")));
+    login.assert();
+    missing.assert();
+
+    get_command()
+        .current_dir(dir.path())
+        .args(["--run-all", "--var=user=alice", "--body"])
+        .arg(format!("--var=base={}", server.base_url()))
+        .assert()
+        .code(1)
+        .stderr(contains("expected HTTP 200, got 404 Not Found"));
+
+    get_command()
+        .current_dir(dir.path())
+        .args(["--run=logout"])
+        .assert()
+        .failure()
+        .stderr(contains("No request named \"logout\" in requests.toml"));
+}
+
+#[test]
+fn vars() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, then| {
+        when.path("/v1/users")
+            .query_param("env", "staging")
+            .header("authorization", "Bearer secret")
+            .json_body(json!({"tags": ["staging"]}));
+        then.status(200);
+    });
+    let mut var_file = tempfile::NamedTempFile::new().unwrap();
+    write!(var_file, r#"{{"version": 1, "env": "prod"}}"#).unwrap();
+
+    get_command()
+        .arg("--var-file")
+        .arg(var_file.path())
+        .args(&["--var", "env=staging", "--var=token=secret"])
+        .arg(format!("{}/v{{{{version}}}}/users", server.base_url()))
+        .args(&[
+            "env=={{env}}",
+            "Authorization:Bearer {{ token }}",
+            r#"tags:=["{{env}}"]"#,
+        ])
+        .assert()
+        .success();
+    mock.assert();
+
+    get_command()
+        .args(&["--offline", "--var", "a=b", ":/{{missing}}"])
+        .assert()
+        .failure()
+        .stderr(contains("Undefined variable {{missing}}"));
+}
+
+#[test]
+fn user_password_auth() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, _then| {
+        when.header("Authorization", "Basic dXNlcjpwYXNz");
+    });
+
+    get_command()
+        .arg("--auth=user:pass")
+        .arg(server.base_url())
+        .assert();
+    mock.assert();
+}
+
+#[test]
+fn challenge_auth() {
+    let server = MockServer::start();
+    let authenticated = server.mock(|when, then| {
+        when.header("Authorization", "Basic dXNlcjpwYXNz");
+        then.status(200);
+    });
+    let challenge = server.mock(|_when, then| {
+        then.status(401)
+            .header("WWW-Authenticate", "Basic realm=\"xh\"");
+    });
+
+    get_command()
+        .args(&["--auth=user:pass", "--auth-challenge"])
+        .arg(server.base_url())
+        .assert()
+        .success();
+    authenticated.assert();
+    challenge.assert();
+}
+
+#[test]
+fn digest_auth() {
+    let server = MockServer::start();
+    let authenticated = server.mock(|when, then| {
+        when.method(POST).body(r#"{"a":"b"}"#).matches(|req| {
+            let headers = req.headers.as_deref().unwrap_or(&[]);
+            headers.iter().any(|(name, value)| {
+                name.eq_ignore_ascii_case("authorization")
+                    && value.starts_with(
+                        "Digest username=\"user\", realm=\"xh\", nonce=\"abc\", \
+                         uri=\"/?x=y\", algorithm=MD5, response=",
+                    )
+            })
+        });
+        then.status(200);
+    });
+    let challenge = server.mock(|_when, then| {
+        then.status(401).header(
+            "WWW-Authenticate",
+            "Basic realm=\"xh\", Digest realm=\"xh\", nonce=\"abc\", qop=\"auth\"",
+        );
+    });
+
+    get_command()
+        .args(&["--auth=user:pass", "--auth-challenge", "post"])
+        .arg(server.url("/?x=y"))
+        .arg("a=b")
+        .assert()
+        .success();
+    authenticated.assert();
+    challenge.assert();
+}
+
+#[test]
+fn netrc_env_user_password_auth() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, _then| {
+        when.header("Authorization", "Basic dXNlcjpwYXNz");
+    });
+
+    let mut netrc = tempfile::NamedTempFile::new().unwrap();
+    writeln!(
+        netrc,
+        "machine {}\nlogin user\npassword pass",
+        server.host()
+    )
+    .unwrap();
+
+    get_command()
+        .env("NETRC", netrc.path())
+        .arg(server.base_url())
+        .assert();
+    mock.assert();
+}
+
+#[test]
+fn netrc_file_user_password_auth() {
+    for netrc_file in [".netrc", "_netrc"].iter() {
+        let server = MockServer::start();
         let mock = server.mock(|when, _then| {
             when.header("Authorization", "Basic dXNlcjpwYXNz");
         });
 
-        let homedir = tempfile::TempDir::new().unwrap();
-        let netrc_path = homedir.path().join(netrc_file);
-        let mut netrc = File::create(&netrc_path).unwrap();
-        writeln!(
-            netrc,
-            "machine {}\nlogin user\npassword pass",
-            server.host()
-        )
-        .unwrap();
+        let homedir = tempfile::TempDir::new().unwrap();
+        let netrc_path = homedir.path().join(netrc_file);
+        let mut netrc = File::create(&netrc_path).unwrap();
+        writeln!(
+            netrc,
+            "machine {}\nlogin user\npassword pass",
+            server.host()
+        )
+        .unwrap();
+
+        netrc.flush().unwrap();
+
+        get_command()
+            .env("HOME", homedir.path())
+            .env("XH_TEST_MODE_WIN_HOME_DIR", homedir.path())
+            .arg(server.base_url())
+            .assert();
+
+        mock.assert();
+
+        drop(netrc);
+        homedir.close().unwrap();
+    }
+}
+
+#[test]
+fn check_status_warning() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.status(501);
+    });
+
+    redirecting_command()
+        .arg("--check-status")
+        .arg(server.base_url())
+        .assert()
+        .code(5)
+        .stderr("\nxh: warning: HTTP 501 Not Implemented\n\n");
+    mock.assert();
+}
+
+#[test]
+fn quiet_levels() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.status(404).body("not found");
+    });
+
+    get_command()
+        .arg("-q")
+        .arg("--check-status")
+        .arg(server.base_url())
+        .assert()
+        .code(4)
+        .stdout("")
+        .stderr("\nxh: warning: HTTP 404 Not Found\n\n");
+    get_command()
+        .arg("-qq")
+        .arg("--check-status")
+        .arg(server.base_url())
+        .assert()
+        .code(4)
+        .stdout("")
+        .stderr("");
+    get_command()
+        .arg("-qq")
+        .arg(server.base_url())
+        .arg("foo:=bar")
+        .assert()
+        .failure()
+        .stderr(contains("error"));
+    mock.assert_hits(2);
+}
+
+#[test]
+fn json_lines_formatting() {
+    let server = MockServer::start();
+    let ndjson = server.mock(|when, then| {
+        when.path("/ndjson");
+        then.header("Content-Type", "application/x-ndjson")
+            .body("{\"id\":1}\n{\"id\":2}\n");
+    });
+    let plain = server.mock(|when, then| {
+        when.path("/plain");
+        then.header("Content-Type", "text/plain")
+            .body("{\"id\":1}\n{\"id\":2}\n");
+    });
+    let expected = indoc! {r#"
+        {
+            "id": 1
+        }
+
+        {
+            "id": 2
+        }
+
+
+    "#};
+
+    get_command()
+        .arg("--print=b")
+        .arg("--pretty=format")
+        .arg(server.url("/ndjson"))
+        .assert()
+        .stdout(expected);
+    get_command()
+        .arg("--print=b")
+        .arg("--pretty=format")
+        .arg("--json-lines")
+        .arg(server.url("/plain"))
+        .assert()
+        .stdout(expected);
+    ndjson.assert();
+    plain.assert();
+}
+
+#[test]
+fn color_when_redirected() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.header("Content-Type", "application/json")
+            .body(r#"{"id":1}"#);
+    });
+
+    redirecting_command()
+        .arg("--print=b")
+        .arg("--color=always")
+        .arg(server.base_url())
+        .assert()
+        .stdout(contains("\x1b["));
+    get_command()
+        .arg("--print=b")
+        .arg("--color=never")
+        .arg(server.base_url())
+        .assert()
+        .stdout(contains("\x1b[").not());
+    mock.assert_hits(2);
+}
+
+#[test]
+fn pager() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.body("paged\n");
+    });
+
+    get_command()
+        .arg("--print=b")
+        .arg("--pager=cat")
+        .arg(server.base_url())
+        .assert()
+        .stdout("paged\n\n");
+    get_command()
+        .arg("--print=b")
+        .arg("--pager=/nonexistent/pager")
+        .arg(server.base_url())
+        .assert()
+        .failure()
+        .stderr(contains("Failed to start pager"));
+    mock.assert_hits(1);
+}
+
+#[test]
+fn external_formatter() {
+    let config_dir = tempdir().unwrap();
+    std::fs::write(
+        config_dir.path().join("config.json"),
+        r#"{"formatters": {"application/x-shout": "tr a-z A-Z", "application/x-fail": "false"}}"#,
+    )
+    .unwrap();
+    let server = MockServer::start();
+    let shout = server.mock(|when, then| {
+        when.path("/shout");
+        then.header("Content-Type", "application/x-shout")
+            .body("hello");
+    });
+    let fail = server.mock(|when, then| {
+        when.path("/fail");
+        then.header("Content-Type", "application/x-fail")
+            .body("hello");
+    });
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .arg("--print=b")
+        .arg(server.url("/shout"))
+        .assert()
+        .stdout("HELLO\n");
+    redirecting_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .arg(server.url("/shout"))
+        .assert()
+        .stdout("hello");
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .arg("--print=b")
+        .arg(server.url("/fail"))
+        .assert()
+        .stdout("hello\n")
+        .stderr(contains("formatter \"false\" failed"));
+    shout.assert_hits(2);
+    fail.assert();
+}
+
+#[test]
+fn config_credentials() {
+    let server = MockServer::start();
+    let config_dir = tempdir().unwrap();
+    std::fs::write(
+        config_dir.path().join("config.json"),
+        format!(
+            r#"{{"credentials": {{
+                "http://127.0.0.1:{}": {{"type": "bearer", "token": {{"env": "XH_TEST_TOKEN"}}}},
+                "http://127.0.0.1": {{"type": "basic", "username": "user", "password": {{"command": "echo hunter2"}}}},
+                "localhost": {{"type": "basic", "username": "user", "password": "secure"}}
+            }}}}"#,
+            server.port()
+        ),
+    )
+    .unwrap();
+    let bearer = server.mock(|when, _then| {
+        when.header("Authorization", "Bearer secret");
+    });
+    let basic = server.mock(|when, _then| {
+        when.header("Authorization", "Basic dXNlcjpwYXNz");
+    });
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .env("XH_TEST_TOKEN", "secret")
+        .arg(server.base_url())
+        .assert()
+        .success();
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .arg("--auth=user:pass")
+        .arg(server.base_url())
+        .assert()
+        .success();
+    bearer.assert();
+    basic.assert();
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(&[
+            "--offline",
+            "--print=H",
+            "--show-secrets",
+            "http://127.0.0.1/",
+        ])
+        .assert()
+        .stdout(contains("authorization: Basic dXNlcjpodW50ZXIy\n"));
+    // A key without a scheme is only for HTTPS
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(["--offline", "--print=H", "http://localhost/"])
+        .assert()
+        .stdout(contains("authorization").not());
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .env_remove("XH_TEST_TOKEN")
+        .args(&["--offline", &server.base_url()])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Failed to read the environment variable XH_TEST_TOKEN",
+        ));
+}
+
+#[test]
+fn custom_syntaxes() {
+    let config_dir = tempdir().unwrap();
+    std::fs::create_dir(config_dir.path().join("syntaxes")).unwrap();
+    std::fs::write(
+        config_dir.path().join("syntaxes/edn.sublime-syntax"),
+        indoc! {"
+            %YAML 1.2
+            ---
+            name: EDN
+            file_extensions: [edn]
+            scope: source.edn
+            contexts:
+              main:
+                - match: ':[a-z]+'
+                  scope: constant.other.keyword
+        "},
+    )
+    .unwrap();
+    std::fs::write(
+        config_dir.path().join("config.json"),
+        r#"{"syntaxes": {"application/x-config": "edn"}}"#,
+    )
+    .unwrap();
+    let server = MockServer::start();
+    let edn = server.mock(|when, then| {
+        when.path("/edn");
+        then.header("Content-Type", "application/edn")
+            .body("{:a 1}");
+    });
+    let mapped = server.mock(|when, then| {
+        when.path("/mapped");
+        then.header("Content-Type", "application/x-config")
+            .body("{:a 1}");
+    });
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .arg("--print=b")
+        .arg("--pretty=colors")
+        .arg(server.url("/edn"))
+        .assert()
+        .stdout("\x1b[0m{\x1b[0m\x1b[34m:a\x1b[0m 1}\x1b[0m\n");
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .arg("--print=b")
+        .arg("--pretty=colors")
+        .arg(server.url("/mapped"))
+        .assert()
+        .stdout("\x1b[0m{\x1b[0m\x1b[34m:a\x1b[0m 1}\x1b[0m\n");
+    edn.assert();
+    mapped.assert();
+
+    std::fs::write(
+        config_dir.path().join("config.json"),
+        r#"{"syntaxes": {"application/x-config": "nope"}}"#,
+    )
+    .unwrap();
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .arg("--pretty=colors")
+        .arg("--offline")
+        .arg(":")
+        .assert()
+        .failure()
+        .stderr(contains("Unknown syntax \"nope\" for application/x-config"));
+}
+
+#[test]
+fn builtin_styles() {
+    let config_dir = tempdir().unwrap();
+    std::fs::write(
+        config_dir.path().join("config.json"),
+        r#"{"style": "light"}"#,
+    )
+    .unwrap();
+
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(&["--pretty=colors", "--print=B", "--offline", ":", "a=1"])
+        .assert()
+        .stdout(
+            "\x1b[0m{\x1b[0m\x1b[34m\"\x1b[0m\x1b[34ma\x1b[0m\x1b[34m\"\x1b[0m:\x1b[0m\x1b[32m\"1\"\x1b[0m}\x1b[0m\n\n",
+        );
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .args(&["--style=contrast", "--pretty=colors", "--print=B", "--offline", ":", "a=1"])
+        .assert()
+        .stdout(
+            "\x1b[0m{\x1b[0m\x1b[1m\x1b[38;5;14m\"\x1b[0m\x1b[1m\x1b[38;5;14ma\x1b[0m\x1b[1m\x1b[38;5;14m\"\x1b[0m:\x1b[0m\x1b[38;5;10m\"1\"\x1b[0m}\x1b[0m\n\n",
+        );
+}
+
+#[test]
+fn invalid_config() {
+    let config_dir = tempdir().unwrap();
+    std::fs::write(config_dir.path().join("config.json"), "{").unwrap();
+    get_command()
+        .env("XH_CONFIG_DIR", config_dir.path())
+        .arg("--offline")
+        .arg(":")
+        .assert()
+        .failure()
+        .stderr(contains("Invalid config file"));
+}
+
+#[test]
+fn user_auth() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, _then| {
+        when.header("Authorization", "Basic dXNlcjo=");
+    });
+
+    get_command()
+        .arg("--auth=user:")
+        .arg(server.base_url())
+        .assert();
+    mock.assert();
+}
+
+#[test]
+fn bearer_auth() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, _then| {
+        when.header("Authorization", "Bearer SomeToken");
+    });
+
+    get_command()
+        .arg("--bearer=SomeToken")
+        .arg(server.base_url())
+        .assert();
+    mock.assert();
+}
+
+#[test]
+fn negotiate_auth_without_credentials() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, _then| {});
+
+    get_command()
+        .args(&["--auth-type=negotiate", "--auth=:"])
+        .arg(server.base_url())
+        .env("KRB5CCNAME", "FILE:/nonexistent/xh-test-ccache")
+        .assert()
+        .failure()
+        .stderr(contains("Negotiate authentication failed"));
+    mock.assert_hits(0);
+}
+
+#[test]
+fn password_from_stdin() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, _then| {
+        when.header("Authorization", "Basic dXNlcjpzZWNyZXQ=");
+    });
+
+    let mut password_file = tempfile().unwrap();
+    password_file.write_all(b"secret\n").unwrap();
+    password_file.seek(SeekFrom::Start(0)).unwrap();
+    get_command()
+        .args(&["--auth=user", "--password-stdin"])
+        .arg(server.base_url())
+        .stdin(password_file)
+        .assert()
+        .success();
+    mock.assert();
+
+    get_command()
+        .args(&["--auth=user:pass", "--password-stdin", ":"])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "--password-stdin can't be combined with a password in --auth",
+        ));
+}
+
+#[test]
+fn verbose_set_cookie() {
+    let server = MockServer::start();
+    server.mock(|_when, then| {
+        then.header("set-cookie", "id=secret; Path=/; HttpOnly; SameSite=None");
+    });
+    get_command()
+        .args(&["--verbose", "--show-secrets", &server.base_url()])
+        .assert()
+        .stdout(contains(indoc! {"
+            cookie: id=secret
+            expires: at the end of the session
+            path: /
+            flags: HttpOnly, SameSite=None
+            warning: SameSite=None without Secure is rejected by browsers
+
+        "}));
+
+    get_command()
+        .args(&["--print=h", &server.base_url()])
+        .assert()
+        .stdout(contains("\ncookie: ").not());
+}
+
+#[test]
+fn verbose_hides_credentials() {
+    get_command()
+        .args(&["--offline", "--print=H", "--auth=user:pass", ":"])
+        .assert()
+        .stdout(contains("authorization: Basic ********\n"));
+
+    get_command()
+        .args(&[
+            "--offline",
+            "--print=H",
+            "--auth=user:pass",
+            "--show-secrets",
+            ":",
+        ])
+        .assert()
+        .stdout(contains("authorization: Basic dXNlcjpwYXNz\n"));
+
+    get_command()
+        .args(&["--offline", "--print=H", ":", "authorization:secret"])
+        .assert()
+        .stdout(contains("authorization: ********\n"));
+
+    get_command()
+        .args(&["--offline", "--print=H", "--redact=X-Api-Key", ":"])
+        .args(&["cookie:a=b; c=d", "x-api-key:secret"])
+        .assert()
+        .stdout(contains("cookie: a=********; c=********\n"))
+        .stdout(contains("x-api-key: ********\n"));
+
+    let server = MockServer::start();
+    server.mock(|_when, then| {
+        then.header("set-cookie", "id=secret; Path=/");
+    });
+    get_command()
+        .args(&["--print=h", &server.base_url()])
+        .assert()
+        .stdout(contains("set-cookie: id=********; Path=/\n"));
+
+    get_command()
+        .args(&["--curl", "--bearer=secret", ":"])
+        .assert()
+        .stdout(contains("--oauth2-bearer '********'"))
+        .stderr(contains("use --show-secrets to include them"));
+}
+
+// TODO: test implicit download filenames
+// For this we have to pretend the output is a tty
+// This intersects with both #41 and #59
+
+#[test]
+fn verify_default_yes() {
+    get_command()
+        .arg("-v")
+        .arg("--pretty=format")
+        .arg("get")
+        .arg("https://self-signed.badssl.com")
+        .assert()
+        .failure()
+        .stdout(predicates::str::contains("GET / HTTP/1.1"))
+        .stderr(predicates::str::contains("UnknownIssuer"));
+}
+
+#[test]
+fn verify_explicit_yes() {
+    get_command()
+        .arg("-v")
+        .arg("--pretty=format")
+        .arg("--verify=yes")
+        .arg("get")
+        .arg("https://self-signed.badssl.com")
+        .assert()
+        .failure()
+        .stdout(predicates::str::contains("GET / HTTP/1.1"))
+        .stderr(predicates::str::contains("UnknownIssuer"));
+}
+
+#[test]
+fn verify_no() {
+    get_command()
+        .arg("-v")
+        .arg("--pretty=format")
+        .arg("--verify=no")
+        .arg("get")
+        .arg("https://self-signed.badssl.com")
+        .assert()
+        .stdout(predicates::str::contains("GET / HTTP/1.1"))
+        .stdout(predicates::str::contains("HTTP/1.1 200 OK"))
+        .stdout(predicates::str::contains("subject: CN=*.badssl.com"))
+        .stderr(predicates::str::is_empty());
+}
+
+#[test]
+fn verify_valid_file() {
+    get_command()
+        .arg("-v")
+        .arg("--pretty=format")
+        .arg("--verify=tests/fixtures/certs/wildcard-self-signed.pem")
+        .arg("get")
+        .arg("https://self-signed.badssl.com")
+        .assert()
+        .stdout(predicates::str::contains("GET / HTTP/1.1"))
+        .stdout(predicates::str::contains("HTTP/1.1 200 OK"))
+        .stderr(predicates::str::is_empty());
+}
+
+#[test]
+fn cert_without_key() {
+    get_command()
+        .arg("-v")
+        .arg("--pretty=format")
+        .arg("get")
+        .arg("https://client.badssl.com")
+        .assert()
+        .stdout(predicates::str::contains(
+            "400 No required SSL certificate was sent",
+        ))
+        .stderr(predicates::str::is_empty());
+}
+
+#[test]
+fn cert_with_key() {
+    get_command()
+        .arg("-v")
+        .arg("--pretty=format")
+        .arg("--cert=tests/fixtures/certs/client.badssl.com.crt")
+        .arg("--cert-key=tests/fixtures/certs/client.badssl.com.key")
+        .arg("get")
+        .arg("https://client.badssl.com")
+        .assert()
+        .stdout(predicates::str::contains("HTTP/1.1 200 OK"))
+        .stdout(predicates::str::contains("client-authenticated"))
+        .stderr(predicates::str::is_empty());
+}
+
+#[test]
+fn encrypted_cert_keys() {
+    get_command()
+        .arg("--offline")
+        .arg("--cert=tests/fixtures/certs/client.badssl.com.p12")
+        .arg("--cert-key-pass=badssl.com")
+        .arg("https://client.badssl.com")
+        .assert()
+        .success();
+
+    get_command()
+        .arg("--offline")
+        .arg("--cert=tests/fixtures/certs/client.badssl.com.crt")
+        .arg("--cert-key=tests/fixtures/certs/client.badssl.com.encrypted.key")
+        .arg("https://client.badssl.com")
+        .env("XH_CERT_KEY_PASS", "badssl.com")
+        .assert()
+        .success();
+
+    get_command()
+        .arg("--offline")
+        .arg("--cert=tests/fixtures/certs/client.badssl.com.legacy.p12")
+        .arg("--cert-key-pass=hunter2")
+        .arg("https://client.badssl.com")
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Wrong passphrase for tests/fixtures/certs/client.badssl.com.legacy.p12",
+        ));
+
+    get_command()
+        .arg("--offline")
+        .arg("--cert=tests/fixtures/certs/client.badssl.com.legacy.p12")
+        .arg("--cert-key-pass=badssl.com")
+        .arg("https://client.badssl.com")
+        .assert()
+        .failure()
+        .stderr(contains("legacy RC2 or 3DES encryption aren't supported"));
+}
+
+#[cfg(unix)]
+#[test]
+fn pkcs11_cert_without_module() {
+    get_command()
+        .arg("--offline")
+        .arg("--cert=pkcs11:token=YubiKey?module-path=/nonexistent/libykcs11.so")
+        .arg("https://client.badssl.com")
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Failed to load the PKCS#11 module /nonexistent/libykcs11.so",
+        ));
+}
+
+#[test]
+fn forced_json() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, _then| {
+        when.method(GET)
+            .header("content-type", "application/json")
+            .header("accept", "application/json, */*;q=0.5");
+    });
+    get_command()
+        .arg("--json")
+        .arg(server.base_url())
+        .assert()
+        .success();
+    mock.assert();
+}
+
+#[test]
+fn forced_form() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, _then| {
+        when.method(GET)
+            .header("content-type", "application/x-www-form-urlencoded");
+    });
+    get_command()
+        .arg("--form")
+        .arg(server.base_url())
+        .assert()
+        .success();
+    mock.assert();
+}
+
+#[test]
+fn forced_multipart() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, _then| {
+        when.method(POST).header_exists("content-type").body("");
+    });
+    get_command()
+        .arg("--multipart")
+        .arg(server.base_url())
+        .assert()
+        .success();
+    mock.assert();
+}
+
+#[test]
+fn formatted_json_output() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.header("content-type", "application/json")
+            .body(r#"{"":0}"#);
+    });
+    get_command()
+        .arg("--print=b")
+        .arg(server.base_url())
+        .assert()
+        .stdout(indoc! {r#"
+        {
+            "": 0
+        }
+
+
+        "#});
+    mock.assert();
+}
+
+#[test]
+fn inferred_json_output() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.header("content-type", "text/plain").body(r#"{"":0}"#);
+    });
+    get_command()
+        .arg("--print=b")
+        .arg(server.base_url())
+        .assert()
+        .stdout(indoc! {r#"
+        {
+            "": 0
+        }
+
+
+        "#});
+    mock.assert();
+}
+
+#[test]
+fn inferred_json_javascript_output() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        then.header("content-type", "application/javascript")
+            .body(r#"{"":0}"#);
+    });
+    get_command()
+        .arg("--print=b")
+        .arg(server.base_url())
+        .assert()
+        .stdout(indoc! {r#"
+        {
+            "": 0
+        }
+
 
-        netrc.flush().unwrap();
+        "#});
+    mock.assert();
+}
 
-        get_command()
-            .env("HOME", homedir.path())
-            .env("XH_TEST_MODE_WIN_HOME_DIR", homedir.path())
-            .arg(server.base_url())
-            .assert();
+#[test]
+fn inferred_nonjson_output() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        // Trailing comma makes it invalid JSON, though formatting would still work
+        then.header("content-type", "text/plain").body(r#"{"":0,}"#);
+    });
+    get_command()
+        .arg("--print=b")
+        .arg(server.base_url())
+        .assert()
+        .stdout(indoc! {r#"
+        {"":0,}
+        "#});
+    mock.assert();
+}
 
-        mock.assert();
+#[test]
+fn noninferred_json_output() {
+    let server = MockServer::start();
+    let mock = server.mock(|_when, then| {
+        // Valid JSON, but not declared as text
+        then.header("content-type", "application/octet-stream")
+            .body(r#"{"":0}"#);
+    });
+    get_command()
+        .arg("--print=b")
+        .arg(server.base_url())
+        .assert()
+        .stdout(indoc! {r#"
+        {"":0}
+        "#});
+    mock.assert();
+}
 
-        drop(netrc);
-        homedir.close().unwrap();
-    }
+#[test]
+fn mixed_stdin_request_items() {
+    let input_file = tempfile().unwrap();
+    redirecting_command()
+        .arg("--offline")
+        .arg(":")
+        .arg("x=3")
+        .stdin(input_file.try_clone().unwrap())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Request body (from stdin) and request data (key=value) cannot be mixed",
+        ));
+    redirecting_command()
+        .args(["--offline", ":", "x=3", "@-"])
+        .stdin(input_file)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Request body (from stdin) and request data (key=value) cannot be mixed",
+        ));
 }
 
 #[test]
-fn check_status_warning() {
+fn explicit_stdin_body() {
     let server = MockServer::start();
-    let mock = server.mock(|_when, then| {
-        then.status(501);
+    let mock = server.mock(|when, _| {
+        when.header("content-type", "text/csv")
+            .header("x-source", "stdin")
+            .body("a,b\n");
+    });
+    let mut input_file = tempfile().unwrap();
+    input_file.write_all(b"a,b\n").unwrap();
+    input_file.seek(SeekFrom::Start(0)).unwrap();
+    redirecting_command()
+        .args(&[&server.base_url(), "x-source:stdin", "@-;type=text/csv"])
+        .stdin(input_file)
+        .assert()
+        .success();
+    mock.assert();
+}
+
+#[test]
+fn stdin_timeout() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, _| {
+        when.method(GET);
     });
+    // The pipe stays open without anything being written to it, until the
+    // child is waited on
+    let run = |args: &[&str]| {
+        let mut child = redirecting_command()
+            .arg("--stdin-timeout=0.2")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stderr = String::new();
+        child
+            .stderr
+            .take()
+            .unwrap()
+            .read_to_string(&mut stderr)
+            .unwrap();
+        (child.wait().unwrap().code(), stderr)
+    };
+
+    let (code, stderr) = run(&[&server.base_url()]);
+    assert_eq!(code, Some(0));
+    assert!(
+        stderr.contains("nothing on stdin after 200ms"),
+        "{}",
+        stderr
+    );
+    mock.assert();
+
+    let (code, stderr) = run(&[&server.base_url(), "@-"]);
+    assert_eq!(code, Some(1));
+    assert!(
+        stderr.contains("Nothing on stdin after 200ms"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn multipart_stdin() {
+    let input_file = tempfile().unwrap();
+    redirecting_command()
+        .arg("--offline")
+        .arg("--multipart")
+        .arg(":")
+        .stdin(input_file)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Cannot build a multipart request body from stdin",
+        ));
+}
 
+#[test]
+fn default_json_for_raw_body() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, _then| {
+        when.header("content-type", "application/json");
+    });
+    let input_file = tempfile().unwrap();
     redirecting_command()
-        .arg("--check-status")
         .arg(server.base_url())
+        .stdin(input_file)
         .assert()
-        .code(5)
-        .stderr("\nxh: warning: HTTP 501 Not Implemented\n\n");
+        .success();
     mock.assert();
 }
 
 #[test]
-fn user_auth() {
+fn body_from_file() {
     let server = MockServer::start();
-    let mock = server.mock(|when, _then| {
-        when.header("Authorization", "Basic dXNlcjo=");
+    let mock = server.mock(|when, _| {
+        when.header("content-type", "text/plain")
+            .body("Hello world\n");
     });
 
+    let dir = tempfile::tempdir().unwrap();
+    let filename = dir.path().join("input.txt");
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(&filename)
+        .unwrap()
+        .write_all(b"Hello world\n")
+        .unwrap();
+
     get_command()
-        .arg("--auth=user:")
         .arg(server.base_url())
-        .assert();
+        .arg(format!("@{}", filename.to_string_lossy()))
+        .assert()
+        .success();
+
     mock.assert();
 }
 
 #[test]
-fn bearer_auth() {
+fn body_from_file_with_explicit_mimetype() {
     let server = MockServer::start();
-    let mock = server.mock(|when, _then| {
-        when.header("Authorization", "Bearer SomeToken");
+    let mock = server.mock(|when, _| {
+        when.header("content-type", "image/png")
+            .body("Hello world\n");
     });
 
+    let dir = tempfile::tempdir().unwrap();
+    let filename = dir.path().join("input.txt");
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(&filename)
+        .unwrap()
+        .write_all(b"Hello world\n")
+        .unwrap();
+
     get_command()
-        .arg("--bearer=SomeToken")
         .arg(server.base_url())
-        .assert();
+        .arg(format!("@{};type=image/png", filename.to_string_lossy()))
+        .assert()
+        .success();
+
     mock.assert();
 }
 
-// TODO: test implicit download filenames
-// For this we have to pretend the output is a tty
-// This intersects with both #41 and #59
+#[test]
+fn body_from_file_with_fallback_mimetype() {
+    let server = MockServer::start();
+    let mock = server.mock(|when, _| {
+        when.header("content-type", "application/json")
+            .body("Hello world\n");
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    let filename = dir.path().join("input");
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(&filename)
+        .unwrap()
+        .write_all(b"Hello world\n")
+        .unwrap();
+
+    get_command()
+        .arg(server.base_url())
+        .arg(format!("@{}", filename.to_string_lossy()))
+        .assert()
+        .success();
+
+    mock.assert();
+}
 
 #[test]
-fn verify_default_yes() {
+fn no_double_file_body() {
+    let mut foo = tempfile::NamedTempFile::new().unwrap();
+    write!(foo, "foo").unwrap();
+    let mut bar = tempfile::NamedTempFile::new().unwrap();
+    write!(bar, "bar").unwrap();
     get_command()
-        .arg("-v")
-        .arg("--pretty=format")
-        .arg("get")
-        .arg("https://self-signed.badssl.com")
+        .arg(":")
+        .arg(format!("@{}", foo.path().to_string_lossy()))
+        .arg(format!("@{}", bar.path().to_string_lossy()))
         .assert()
         .failure()
-        .stdout(predicates::str::contains("GET / HTTP/1.1"))
-        .stderr(predicates::str::contains("UnknownIssuer"));
+        .stderr(predicate::str::contains(
+            "Can't read request from multiple files",
+        ));
 }
 
 #[test]
-fn verify_explicit_yes() {
+fn missing_merged_file() {
+    let mut base = tempfile::NamedTempFile::new().unwrap();
+    write!(base, "{{}}").unwrap();
     get_command()
-        .arg("-v")
-        .arg("--pretty=format")
-        .arg("--verify=yes")
-        .arg("get")
-        .arg("https://self-signed.badssl.com")
+        .arg(":")
+        .arg(format!("@{}", base.path().to_string_lossy()))
+        .arg("@/nonexistent/prod.json")
         .assert()
         .failure()
-        .stdout(predicates::str::contains("GET / HTTP/1.1"))
-        .stderr(predicates::str::contains("UnknownIssuer"));
+        .stderr(predicate::str::contains(
+            "Failed to read \"/nonexistent/prod.json\"",
+        ));
 }
 
 #[test]
-fn verify_no() {
+fn print_body_from_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let filename = dir.path().join("input");
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(&filename)
+        .unwrap()
+        .write_all(b"Hello world\n")
+        .unwrap();
+
     get_command()
-        .arg("-v")
-        .arg("--pretty=format")
-        .arg("--verify=no")
-        .arg("get")
-        .arg("https://self-signed.badssl.com")
+        .arg("--offline")
+        .arg(":")
+        .arg(format!("@{}", filename.to_string_lossy()))
         .assert()
-        .stdout(predicates::str::contains("GET / HTTP/1.1"))
-        .stdout(predicates::str::contains("HTTP/1.1 200 OK"))
-        .stderr(predicates::str::is_empty());
+        .success()
+        .stdout(predicate::str::contains("Hello world"));
 }
 
 #[test]
-fn verify_valid_file() {
-    get_command()
-        .arg("-v")
-        .arg("--pretty=format")
-        .arg("--verify=tests/fixtures/certs/wildcard-self-signed.pem")
-        .arg("get")
-        .arg("https://self-signed.badssl.com")
+fn colored_headers() {
+    color_command()
+        .arg("--offline")
+        .arg(":")
         .assert()
-        .stdout(predicates::str::contains("GET / HTTP/1.1"))
-        .stdout(predicates::str::contains("HTTP/1.1 200 OK"))
-        .stderr(predicates::str::is_empty());
+        .success()
+        // Color
+        .stdout(predicate::str::contains("\x1b[4m"))
+        // Reset
+        .stdout(predicate::str::contains("\x1b[0m"));
 }
 
 #[test]
-fn cert_without_key() {
-    get_command()
-        .arg("-v")
-        .arg("--pretty=format")
-        .arg("get")
-        .arg("https://client.badssl.com")
+fn colored_body() {
+    color_command()
+        .arg("--offline")
+        .arg(":")
+        .arg("x:=3")
         .assert()
-        .stdout(predicates::str::contains(
-            "400 No required SSL certificate was sent",
-        ))
-        .stderr(predicates::str::is_empty());
+        .success()
+        .stdout(predicate::str::contains("\x1b[34m3\x1b[0m"));
 }
 
 #[test]
-fn cert_with_key() {
-    get_command()
-        .arg("-v")
-        .arg("--pretty=format")
-        .arg("--cert=tests/fixtures/certs/client.badssl.com.crt")
-        .arg("--cert-key=tests/fixtures/certs/client.badssl.com.key")
-        .arg("get")
-        .arg("https://client.badssl.com")
+fn force_color_pipe() {
+    redirecting_command()
+        .arg("--ignore-stdin")
+        .arg("--offline")
+        .arg("--pretty=colors")
+        .arg(":")
+        .arg("x:=3")
         .assert()
-        .stdout(predicates::str::contains("HTTP/1.1 200 OK"))
-        .stdout(predicates::str::contains("client-authenticated"))
-        .stderr(predicates::str::is_empty());
+        .success()
+        .stdout(predicate::str::contains("\x1b[34m3\x1b[0m"));
 }
 
 #[test]
-fn forced_json() {
+fn request_json_keys_order_is_preserved() {
     let server = MockServer::start();
-    let mock = server.mock(|when, _then| {
-        when.method(GET)
-            .header("content-type", "application/json")
-            .header("accept", "application/json, */*;q=0.5");
+    let mock = server.mock(|when, _| {
+        when.body(r#"{"name":"ali","age":24}"#);
     });
+
     get_command()
-        .arg("--json")
+        .arg("get")
         .arg(server.base_url())
-        .assert()
-        .success();
+        .arg("name=ali")
+        .arg("age:=24")
+        .assert();
     mock.assert();
 }
 
 #[test]
-fn forced_form() {
+fn data_field_from_file() {
     let server = MockServer::start();
-    let mock = server.mock(|when, _then| {
-        when.method(GET)
-            .header("content-type", "application/x-www-form-urlencoded");
+    let mock = server.mock(|when, _| {
+        when.body(r#"{"ids":"[1,2,3]"}"#);
     });
+
+    let mut text_file = tempfile::NamedTempFile::new().unwrap();
+    write!(text_file, "[1,2,3]").unwrap();
+
     get_command()
-        .arg("--form")
         .arg(server.base_url())
-        .assert()
-        .success();
+        .arg(format!("ids=@{}", text_file.path().to_string_lossy()))
+        .assert();
     mock.assert();
 }
 
 #[test]
-fn forced_multipart() {
+fn data_field_from_file_in_form_mode() {
     let server = MockServer::start();
-    let mock = server.mock(|when, _then| {
-        when.method(POST).header_exists("content-type").body("");
+    let mock = server.mock(|when, _| {
+        when.body(r#"message=hello+world"#);
     });
+
+    let mut text_file = tempfile::NamedTempFile::new().unwrap();
+    write!(text_file, "hello world").unwrap();
+
     get_command()
-        .arg("--multipart")
         .arg(server.base_url())
-        .assert()
-        .success();
+        .arg("--form")
+        .arg(format!("message=@{}", text_file.path().to_string_lossy()))
+        .assert();
     mock.assert();
 }
 
 #[test]
-fn formatted_json_output() {
+fn json_field_from_file() {
     let server = MockServer::start();
-    let mock = server.mock(|_when, then| {
-        then.header("content-type", "application/json")
-            .body(r#"{"":0}"#);
+    let mock = server.mock(|when, _| {
+        when.body(r#"{"ids":[1,2,3]}"#);
     });
-    get_command()
-        .arg("--print=b")
-        .arg(server.base_url())
-        .assert()
-        .stdout(indoc! {r#"
-        {
-            "": 0
-        }
 
+    let mut json_file = tempfile::NamedTempFile::new().unwrap();
+    writeln!(json_file, "[1,2,3]").unwrap();
 
-        "#});
+    get_command()
+        .arg(server.base_url())
+        .arg(format!("ids:=@{}", json_file.path().to_string_lossy()))
+        .assert();
     mock.assert();
 }
 
 #[test]
-fn inferred_json_output() {
+fn accept_encoding_not_modifiable_in_download_mode() {
     let server = MockServer::start();
-    let mock = server.mock(|_when, then| {
-        then.header("content-type", "text/plain").body(r#"{"":0}"#);
+    let mock = server.mock(|when, then| {
+        when.header("accept-encoding", "identity");
+        then.body(r#"{"ids":[1,2,3]}"#);
     });
+
+    let dir = tempdir().unwrap();
     get_command()
-        .arg("--print=b")
+        .current_dir(&dir)
         .arg(server.base_url())
-        .assert()
-        .stdout(indoc! {r#"
-        {
-            "": 0
-        }
-
-
-        "#});
+        .arg("--download")
+        .arg("accept-encoding:gzip")
+        .assert();
     mock.assert();
 }
 
 #[test]
-fn inferred_json_javascript_output() {
+fn range_request() {
     let server = MockServer::start();
-    let mock = server.mock(|_when, then| {
-        then.header("content-type", "application/javascript")
-            .body(r#"{"":0}"#);
+    let mock = server.mock(|when, then| {
+        when.header("Range", "bytes=0-3")
+            .header("Accept-Encoding", "identity");
+        then.status(206)
+            .header("Content-Range", "bytes 0-3/10")
+            .body("file");
     });
+
     get_command()
         .arg("--print=b")
+        .arg("--range=0-3")
         .arg(server.base_url())
         .assert()
-        .stdout(indoc! {r#"
-        {
-            "": 0
-        }
-
-
-        "#});
+        .success()
+        .stdout("file\n");
     mock.assert();
 }
 
 #[test]
-fn inferred_nonjson_output() {
+fn range_request_not_honored() {
     let server = MockServer::start();
-    let mock = server.mock(|_when, then| {
-        // Trailing comma makes it invalid JSON, though formatting would still work
-        then.header("content-type", "text/plain").body(r#"{"":0,}"#);
+    let mock = server.mock(|when, then| {
+        when.header("Range", "bytes=-4");
+        then.body("whole file");
     });
+
     get_command()
-        .arg("--print=b")
+        .arg("--range=-4")
         .arg(server.base_url())
         .assert()
-        .stdout(indoc! {r#"
-        {"":0,}
-        "#});
+        .failure()
+        .stderr(contains("Server did not honor the range request"));
     mock.assert();
 }
 
 #[test]
-fn noninferred_json_output() {
+fn format_max_size() {
     let server = MockServer::start();
     let mock = server.mock(|_when, then| {
-        // Valid JSON, but not declared as text
-        then.header("content-type", "application/octet-stream")
-            .body(r#"{"":0}"#);
+        then.header("Content-Type", "application/json")
+            .body(r#"{"foo": "bar"}"#);
     });
+
     get_command()
         .arg("--print=b")
+        .arg("--pretty=format")
+        .arg("--format-max-size=10")
         .arg(server.base_url())
         .assert()
-        .stdout(indoc! {r#"
-        {"":0}
-        "#});
-    mock.assert();
-}
+        .stdout("{\"foo\": \"bar\"}\n")
+        .stderr(contains("printing it without formatting"));
 
-#[test]
-fn mixed_stdin_request_items() {
-    let input_file = tempfile().unwrap();
-    redirecting_command()
-        .arg("--offline")
-        .arg(":")
-        .arg("x=3")
-        .stdin(input_file)
+    get_command()
+        .arg("--print=b")
+        .arg("--pretty=format")
+        .arg("--format-max-size=1K")
+        .arg(server.base_url())
         .assert()
-        .failure()
-        .stderr(predicate::str::contains(
-            "Request body (from stdin) and request data (key=value) cannot be mixed",
-        ));
-}
+        .stdout(indoc! {r#"
+        {
+            "foo": "bar"
+        }
 
-#[test]
-fn multipart_stdin() {
-    let input_file = tempfile().unwrap();
-    redirecting_command()
-        .arg("--offline")
-        .arg("--multipart")
-        .arg(":")
-        .stdin(input_file)
-        .assert()
-        .failure()
-        .stderr(predicate::str::contains(
-            "Cannot build a multipart request body from stdin",
-        ));
+
+        "#})
+        .stderr("");
+    mock.assert_hits(2);
 }
 
 #[test]
-fn default_json_for_raw_body() {
+fn input_file_requests() {
     let server = MockServer::start();
-    let mock = server.mock(|when, _then| {
-        when.header("content-type", "application/json");
+    let get_mock = server.mock(|when, then| {
+        when.method(GET).path("/get");
+        then.body("hello");
     });
-    let input_file = tempfile().unwrap();
-    redirecting_command()
-        .arg(server.base_url())
-        .stdin(input_file)
+    let post_mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/post")
+            .header("X-Foo", "bar")
+            .json_body(json!({"a": "b"}));
+        then.status(201);
+    });
+
+    let mut input = tempfile::NamedTempFile::new().unwrap();
+    writeln!(input, "{}", json!({"url": server.url("/get")})).unwrap();
+    writeln!(input).unwrap();
+    writeln!(
+        input,
+        "{}",
+        json!({"method": "post", "url": server.url("/post"), "items": ["a=b", "X-Foo:bar"]})
+    )
+    .unwrap();
+    writeln!(input, "not json").unwrap();
+    let output = redirecting_command()
+        .arg("--parallel=2")
+        .arg(format!("--input={}", input.path().display()))
         .assert()
-        .success();
-    mock.assert();
+        .code(1)
+        .get_output()
+        .stdout
+        .clone();
+
+    let mut reports: Vec<serde_json::Value> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    reports.sort_by_key(|report| report["line"].as_u64());
+    assert_eq!(reports.len(), 3);
+    assert_eq!(reports[0]["method"], "GET");
+    assert_eq!(reports[0]["status"], 200);
+    assert_eq!(reports[0]["size"], 5);
+    assert_eq!(reports[1]["line"], 3);
+    assert_eq!(reports[1]["method"], "POST");
+    assert_eq!(reports[1]["status"], 201);
+    assert_eq!(reports[2]["line"], 4);
+    assert!(reports[2]["error"].is_string());
+    get_mock.assert();
+    post_mock.assert();
 }
 
 #[test]
-fn body_from_file() {
+fn input_file_repeat() {
     let server = MockServer::start();
-    let mock = server.mock(|when, _| {
-        when.header("content-type", "text/plain")
-            .body("Hello world\n");
+    let mock = server.mock(|_when, then| {
+        then.body("hello");
     });
+    let mut input = tempfile::NamedTempFile::new().unwrap();
+    writeln!(input, "{}", json!({"url": server.base_url()})).unwrap();
+    let dir = tempdir().unwrap();
+    let metrics = dir.path().join("metrics.prom");
 
-    let dir = tempfile::tempdir().unwrap();
-    let filename = dir.path().join("input.txt");
-    OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(&filename)
-        .unwrap()
-        .write_all(b"Hello world\n")
-        .unwrap();
+    let output = redirecting_command()
+        .args(&["--repeat=3", "--parallel=2"])
+        .arg(format!("--input={}", input.path().display()))
+        .arg(format!("--metrics-file={}", metrics.display()))
+        .assert()
+        .success()
+        .stderr(contains(
+            "requests: 3, failed: 0 (0.0%)\nstatuses: 200 3\nlatency: min ",
+        ))
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(String::from_utf8(output).unwrap().lines().count(), 3);
+    mock.assert_hits(3);
+
+    let metrics = read_to_string(&metrics).unwrap();
+    assert!(metrics.contains("\nxh_responses_total{status=\"200\"} 3\n"));
+    assert!(metrics.ends_with("\nxh_request_duration_seconds_count 3\n"));
+
+    get_command()
+        .args(&["--repeat=2", ":"])
+        .assert()
+        .failure()
+        .stderr(contains("--repeat only works with --input"));
+}
 
+#[test]
+fn input_file_conflicts_with_url() {
     get_command()
-        .arg(server.base_url())
-        .arg(format!("@{}", filename.to_string_lossy()))
+        .args(&["--input=-", "example.org"])
         .assert()
-        .success();
+        .failure()
+        .stderr(contains("--input can't be combined with a URL"));
+}
 
-    mock.assert();
+#[test]
+fn format_max_size_request_body() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    write!(file, r#"{{"foo": "bar"}}"#).unwrap();
+
+    get_command()
+        .arg("--offline")
+        .arg("--print=B")
+        .arg("--pretty=format")
+        .arg("--format-max-size=10")
+        .arg(":")
+        .arg(format!("@{}", file.path().display()))
+        .assert()
+        .stdout("{\"foo\": \"bar\"}\n\n")
+        .stderr(contains("request body is larger than"));
 }
 
 #[test]
-fn body_from_file_with_explicit_mimetype() {
+fn streamed_json_is_colored_like_buffered_json() {
     let server = MockServer::start();
-    let mock = server.mock(|when, _| {
-        when.header("content-type", "image/png")
-            .body("Hello world\n");
+    let mock = server.mock(|_when, then| {
+        then.header("Content-Type", "application/json")
+            .body(r#"{"foo": [1, "two", {"three": null}], "bar": true}"#);
     });
 
-    let dir = tempfile::tempdir().unwrap();
-    let filename = dir.path().join("input.txt");
-    OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(&filename)
+    let buffered = color_command()
+        .arg("--print=b")
+        .arg(server.base_url())
+        .output()
         .unwrap()
-        .write_all(b"Hello world\n")
-        .unwrap();
-
-    get_command()
+        .stdout;
+    let streamed = color_command()
+        .arg("--print=b")
+        .arg("--stream")
         .arg(server.base_url())
-        .arg(format!("@{};type=image/png", filename.to_string_lossy()))
-        .assert()
-        .success();
-
-    mock.assert();
+        .output()
+        .unwrap()
+        .stdout;
+    assert!(String::from_utf8_lossy(&buffered).contains("\x1b[34m1\x1b[0m"));
+    assert_eq!(
+        String::from_utf8_lossy(&streamed).trim_end(),
+        String::from_utf8_lossy(&buffered).trim_end()
+    );
+    mock.assert_hits(2);
 }
 
 #[test]
-fn body_from_file_with_fallback_mimetype() {
+fn highlight_max_size() {
     let server = MockServer::start();
-    let mock = server.mock(|when, _| {
-        when.header("content-type", "application/json")
-            .body("Hello world\n");
+    let mock = server.mock(|_when, then| {
+        then.header("Content-Type", "application/json")
+            .body("[1, 2, 3]");
     });
 
-    let dir = tempfile::tempdir().unwrap();
-    let filename = dir.path().join("input");
-    OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(&filename)
-        .unwrap()
-        .write_all(b"Hello world\n")
-        .unwrap();
-
-    get_command()
+    color_command()
+        .arg("--print=b")
+        .arg("--pretty=colors")
+        .arg("--highlight-max-size=4")
         .arg(server.base_url())
-        .arg(format!("@{}", filename.to_string_lossy()))
         .assert()
-        .success();
+        .stdout(contains("\x1b[34m1\x1b[0m"))
+        .stdout(contains("\x1b[0m2, 3]"))
+        .stderr(contains("only highlighting the start"));
 
-    mock.assert();
+    // Only a terminal gets told
+    redirecting_command()
+        .args(&["--print=b", "--pretty=colors", "--highlight-max-size=4"])
+        .arg(server.base_url())
+        .assert()
+        .stderr("");
+    mock.assert_hits(2);
 }
 
 #[test]
-fn no_double_file_body() {
+fn multipart_text_field_type() {
     get_command()
+        .arg("--offline")
+        .arg("--print=B")
+        .arg("--multipart")
         .arg(":")
-        .arg("@foo")
-        .arg("@bar")
+        .arg("desc=hello;type=text/markdown")
+        .arg("plain=hi")
         .assert()
-        .failure()
-        .stderr(predicate::str::contains(
-            "Can't read request from multiple files",
+        .stdout(contains(
+            indoc! {r#"
+            Content-Disposition: form-data; name="desc"
+            Content-Type: text/markdown
+        "#}
+            .replace('\n', "\r\n"),
+        ))
+        .stdout(contains("\r\n\r\nhello\r\n"))
+        .stdout(contains(
+            "Content-Disposition: form-data; name=\"plain\"\r\n\r\nhi",
         ));
 }
 
 #[test]
-fn print_body_from_file() {
-    let dir = tempfile::tempdir().unwrap();
-    let filename = dir.path().join("input");
-    OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(&filename)
-        .unwrap()
-        .write_all(b"Hello world\n")
-        .unwrap();
+fn multipart_part_header() {
+    get_command()
+        .arg("--offline")
+        .arg("--print=HB")
+        .arg("--multipart")
+        .arg(":")
+        .arg("desc=hello;type=text/plain")
+        .arg("plain=hi")
+        .arg("--part-header=desc:Content-Transfer-Encoding:binary")
+        .arg("--part-header=desc:Content-ID:<desc>")
+        .assert()
+        .stdout(contains("content-type: multipart/form-data; boundary="))
+        .stdout(contains(
+            indoc! {r#"
+            Content-Disposition: form-data; name="desc"
+            Content-Type: text/plain
+            content-transfer-encoding: binary
+            content-id: <desc>
+
+            hello
+        "#}
+            .replace('\n', "\r\n"),
+        ))
+        .stdout(contains(
+            "Content-Disposition: form-data; name=\"plain\"\r\n\r\nhi",
+        ));
 
     get_command()
         .arg("--offline")
+        .arg("--multipart")
         .arg(":")
-        .arg(format!("@{}", filename.to_string_lossy()))
+        .arg("plain=hi")
+        .arg("--part-header=other:Content-ID:<x>")
         .assert()
-        .success()
-        .stdout(predicate::str::contains("Hello world"));
+        .failure()
+        .stderr(contains("no multipart field named \"other\""));
 }
 
 #[test]
-fn colored_headers() {
-    color_command()
+fn multipart_body_elision() {
+    let dir = tempdir().unwrap();
+    let binary = dir.path().join("image.bin");
+    std::fs::write(&binary, b"\x89PNG\0\x01").unwrap();
+    let large = dir.path().join("large.txt");
+    std::fs::write(&large, "x".repeat(1500)).unwrap();
+
+    get_command()
         .arg("--offline")
+        .arg("--print=B")
+        .arg("--multipart")
         .arg(":")
+        .arg("name=value")
+        .arg(format!("image@{}", binary.display()))
+        .arg(format!("large@{}", large.display()))
         .assert()
-        .success()
-        // Color
-        .stdout(predicate::str::contains("\x1b[4m"))
-        // Reset
-        .stdout(predicate::str::contains("\x1b[0m"));
+        .stdout(contains("name=\"name\"\r\n\r\nvalue\r\n--"))
+        .stdout(contains(
+            "filename=\"image.bin\"\r\n\r\n[binary data, 6 bytes not shown]\r\n--",
+        ))
+        .stdout(contains(format!(
+            "filename=\"large.txt\"\r\n\r\n{}\n[476 more bytes not shown]\r\n--",
+            "x".repeat(1024)
+        )));
 }
 
 #[test]
-fn colored_body() {
-    color_command()
+fn multipart_file_glob() {
+    let dir = tempdir().unwrap();
+    for name in &["b.txt", "a.txt", "c.md", ".hidden.txt"] {
+        File::create(dir.path().join(name)).unwrap();
+    }
+
+    get_command()
         .arg("--offline")
+        .arg("--print=B")
+        .arg("--form")
+        .arg("--glob-brackets")
         .arg(":")
-        .arg("x:=3")
+        .arg(format!("files@{}", dir.path().join("*.txt").display()))
         .assert()
-        .success()
-        .stdout(predicate::str::contains("\x1b[34m3\x1b[0m"));
-}
+        .stdout(contains(r#"name="files[]"; filename="a.txt""#))
+        .stdout(contains(r#"name="files[]"; filename="b.txt""#))
+        .stdout(contains("c.md").not())
+        .stdout(contains("hidden").not());
 
-#[test]
-fn force_color_pipe() {
-    redirecting_command()
-        .arg("--ignore-stdin")
+    get_command()
         .arg("--offline")
-        .arg("--pretty=colors")
+        .arg("--form")
         .arg(":")
-        .arg("x:=3")
+        .arg(format!("files@{}", dir.path().join("*.png").display()))
         .assert()
-        .success()
-        .stdout(predicate::str::contains("\x1b[34m3\x1b[0m"));
+        .failure()
+        .stderr(contains("No files match"));
 }
 
 #[test]
-fn request_json_keys_order_is_preserved() {
+fn binary_data_field_from_file() {
     let server = MockServer::start();
-    let mock = server.mock(|when, _| {
-        when.body(r#"{"name":"ali","age":24}"#);
+    let json_mock = server.mock(|when, _| {
+        when.path("/json").body(r#"{"blob":"/wBhIGI="}"#);
     });
+    let form_mock = server.mock(|when, _| {
+        when.path("/form").body("blob=%FF%00a+b");
+    });
+
+    let mut binary_file = tempfile::NamedTempFile::new().unwrap();
+    binary_file.write_all(b"\xff\x00a b").unwrap();
+    let item = format!("blob=@{}", binary_file.path().to_string_lossy());
 
+    get_command().arg(server.url("/json")).arg(&item).assert();
     get_command()
-        .arg("get")
-        .arg(server.base_url())
-        .arg("name=ali")
-        .arg("age:=24")
+        .arg("--form")
+        .arg(server.url("/form"))
+        .arg(&item)
         .assert();
-    mock.assert();
+    json_mock.assert();
+    form_mock.assert();
 }
 
 #[test]
-fn data_field_from_file() {
+fn fields_from_csv() {
     let server = MockServer::start();
     let mock = server.mock(|when, _| {
-        when.body(r#"{"ids":"[1,2,3]"}"#);
+        when.json_body(json!({"name": "Smith, Jo", "age": "40", "city": "Oslo"}));
     });
 
-    let mut text_file = tempfile::NamedTempFile::new().unwrap();
-    write!(text_file, "[1,2,3]").unwrap();
+    let dir = tempdir().unwrap();
+    let csv = dir.path().join("fields.csv");
+    std::fs::write(&csv, "name,age,city\n\"Smith, Jo\",41,Bergen\n").unwrap();
 
     get_command()
         .arg(server.base_url())
-        .arg(format!("ids=@{}", text_file.path().to_string_lossy()))
-        .assert();
+        .arg(format!("--fields-from={}", csv.to_string_lossy()))
+        .arg("age=40")
+        .arg("city=Oslo")
+        .assert()
+        .success();
     mock.assert();
+
+    std::fs::write(&csv, "name\na\nb\n").unwrap();
+    get_command()
+        .arg("--offline")
+        .arg(":")
+        .arg(format!("--fields-from={}", csv.to_string_lossy()))
+        .assert()
+        .failure()
+        .stderr(contains("Expected a single row of values, found 2"));
 }
 
 #[test]
-fn data_field_from_file_in_form_mode() {
+fn layered_json_files() {
     let server = MockServer::start();
     let mock = server.mock(|when, _| {
-        when.body(r#"message=hello+world"#);
+        when.json_body(json!({
+            "name": "app",
+            "db": {"host": "prod.example.org", "port": 5432},
+            "debug": false
+        }));
     });
 
-    let mut text_file = tempfile::NamedTempFile::new().unwrap();
-    write!(text_file, "hello world").unwrap();
+    let mut base = tempfile::NamedTempFile::new().unwrap();
+    write!(
+        base,
+        r#"{{"name": "app", "db": {{"host": "localhost", "port": 5432}}, "debug": true}}"#
+    )
+    .unwrap();
+    let mut env = tempfile::NamedTempFile::new().unwrap();
+    write!(env, r#"{{"db": {{"host": "prod.example.org"}}}}"#).unwrap();
 
     get_command()
         .arg(server.base_url())
-        .arg("--form")
-        .arg(format!("message=@{}", text_file.path().to_string_lossy()))
-        .assert();
+        .arg(format!("@{}", base.path().to_string_lossy()))
+        .arg(format!("@{}", env.path().to_string_lossy()))
+        .arg("debug:=false")
+        .assert()
+        .success();
     mock.assert();
 }
 
 #[test]
-fn json_field_from_file() {
+fn json_body() {
     let server = MockServer::start();
     let mock = server.mock(|when, _| {
-        when.body(r#"{"ids":[1,2,3]}"#);
+        when.method(POST)
+            .header("Content-Type", "application/json")
+            .body("{}");
     });
 
-    let mut json_file = tempfile::NamedTempFile::new().unwrap();
-    writeln!(json_file, "[1,2,3]").unwrap();
-
     get_command()
+        .arg("--json-body={}")
         .arg(server.base_url())
-        .arg(format!("ids:=@{}", json_file.path().to_string_lossy()))
-        .assert();
+        .assert()
+        .success();
     mock.assert();
+
+    get_command()
+        .arg("--json-body={}")
+        .arg(server.base_url())
+        .arg("x=y")
+        .assert()
+        .failure()
+        .stderr(contains("cannot be mixed"));
 }
 
 #[test]
-fn accept_encoding_not_modifiable_in_download_mode() {
+fn run_in_process() {
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.path("/item");
+        then.status(201).header("X-Id", "7").body("{\"id\": 7}");
+    });
+    let dir = tempdir().unwrap();
+    let outfile = dir.path().join("item.json");
+
+    let args = xh::cli::Cli::from_iter_safe(&[
+        "xh".as_ref(),
+        "--ignore-stdin".as_ref(),
+        "--output".as_ref(),
+        outfile.as_os_str(),
+        server.url("/item").as_ref(),
+    ])
+    .unwrap();
+    let result = xh::run(args).unwrap();
+    assert_eq!(result.exit_code, 0);
+    assert_eq!(result.status.unwrap().as_u16(), 201);
+    assert_eq!(result.headers["x-id"], "7");
+    assert_eq!(result.body_path.as_deref(), Some(outfile.as_path()));
+    assert_eq!(read_to_string(&outfile).unwrap(), "{\"id\": 7}");
+    assert!(result.timings.is_some());
+}
+
+#[test]
+fn request_file() {
     let server = MockServer::start();
     let mock = server.mock(|when, then| {
-        when.header("accept-encoding", "identity");
-        then.body(r#"{"ids":[1,2,3]}"#);
+        when.method(PUT)
+            .path("/items/1")
+            .query_param("dry_run", "true")
+            .header("X-Api-Key", "1234")
+            .json_body(json!({"name": "other", "tags": ["cli"]}));
+        then.status(201);
     });
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("item.yaml");
+    std::fs::write(
+        &path,
+        formatdoc! {r#"
+            method: put
+            url: {}
+            headers:
+              X-Api-Key: "{{{{key}}}}"
+            query:
+              dry_run: true
+            body:
+              name: xh
+              tags: [cli]
+            expect:
+              status: 200
+        "#, server.url("/items/1")},
+    )
+    .unwrap();
+
+    // The response isn't the expected 200
+    get_command()
+        .arg("--request-file")
+        .arg(&path)
+        .args(&["--var=key=1234", "name=other"])
+        .assert()
+        .code(1);
+    mock.assert();
+}
 
+#[test]
+fn cache() {
+    let server = MockServer::start();
+    let fresh = server.mock(|when, then| {
+        when.method(GET).path("/fresh");
+        then.header("Cache-Control", "max-age=60").body("fresh");
+    });
+    let mut revalidated = server.mock(|when, then| {
+        when.method(GET).path("/etag");
+        then.header("Cache-Control", "no-cache")
+            .header("ETag", "\"v1\"")
+            .body("stored");
+    });
     let dir = tempdir().unwrap();
+    let cache = format!("--cache={}", dir.path().display());
+
+    for _ in 0..2 {
+        get_command()
+            .args(&["--body", &cache, &server.url("/fresh")])
+            .assert()
+            .stdout("fresh\n");
+    }
     get_command()
-        .current_dir(&dir)
+        .args(&["--body", &cache, &server.url("/fresh")])
+        .assert()
+        .stderr(contains("xh: from the cache"));
+    fresh.assert_hits(1);
+
+    // A request with credentials isn't answered with what others got
+    get_command()
+        .args(["--body", &cache, &server.url("/fresh"), "Cookie:session=a"])
+        .assert()
+        .stdout("fresh\n");
+    fresh.assert_hits(2);
+
+    get_command()
+        .args(&["--body", &cache, &server.url("/etag")])
+        .assert()
+        .stdout("stored\n");
+    revalidated.assert();
+    revalidated.delete();
+    let not_modified = server.mock(|when, then| {
+        when.method(GET)
+            .path("/etag")
+            .header("If-None-Match", "\"v1\"");
+        then.status(304);
+    });
+    get_command()
+        .args(&["--body", &cache, &server.url("/etag")])
+        .assert()
+        .stdout("stored\n")
+        .stderr(contains("from the cache, revalidated"));
+    not_modified.assert();
+
+    // Changing the resource drops what was stored for it
+    server.mock(|when, then| {
+        when.method(DELETE).path("/fresh");
+        then.status(204);
+    });
+    get_command()
+        .args(&[&cache, "DELETE", &server.url("/fresh")])
+        .assert()
+        .success();
+    get_command()
+        .args(&["--body", &cache, &server.url("/fresh")])
+        .assert()
+        .stdout("fresh\n");
+    fresh.assert_hits(3);
+}
+
+#[test]
+fn cache_after_digest_challenge() {
+    let server = MockServer::start();
+    let authenticated = server.mock(|when, then| {
+        when.method(GET).matches(|req| {
+            let headers = req.headers.as_deref().unwrap_or(&[]);
+            headers.iter().any(|(name, value)| {
+                name.eq_ignore_ascii_case("authorization") && value.starts_with("Digest ")
+            })
+        });
+        then.header("Cache-Control", "max-age=60").body("secret");
+    });
+    let challenge = server.mock(|_when, then| {
+        then.status(401)
+            .header("WWW-Authenticate", "Digest realm=\"xh\", nonce=\"abc\"")
+            .body("denied");
+    });
+    let dir = tempdir().unwrap();
+    let cache = format!("--cache={}", dir.path().display());
+
+    get_command()
+        .args(["--body", &cache, "--auth=user:pass", "--auth-challenge"])
         .arg(server.base_url())
-        .arg("--download")
-        .arg("accept-encoding:gzip")
-        .assert();
-    mock.assert();
+        .assert()
+        .stdout("secret\n");
+    authenticated.assert();
+
+    // The response was meant for the user who answered the challenge
+    get_command()
+        .args(["--body", &cache])
+        .arg(server.base_url())
+        .assert()
+        .stdout("denied\n")
+        .stderr(contains("from the cache").not());
+    authenticated.assert_hits(1);
+    challenge.assert_hits(2);
 }